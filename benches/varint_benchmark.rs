@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::time::Duration;
-use vstp::encoding::{decode_varint, encode_varint, varint_len};
+use vstp::{decode_varint, encode_varint};
 
 fn varint_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("varint");
@@ -17,10 +17,10 @@ fn varint_benchmark(c: &mut Criterion) {
     });
 
     group.bench_function("decode_small", |b| {
-        let encoded: Vec<_> = (0..128u64).map(|i| encode_varint(i)).collect();
+        let encoded: Vec<_> = (0..128u64).map(encode_varint).collect();
         b.iter(|| {
             for bytes in &encoded {
-                black_box(decode_varint(bytes));
+                let _ = black_box(decode_varint(bytes));
             }
         })
     });
@@ -36,11 +36,11 @@ fn varint_benchmark(c: &mut Criterion) {
 
     group.bench_function("decode_large", |b| {
         let encoded: Vec<_> = ((u64::MAX - 128)..u64::MAX)
-            .map(|i| encode_varint(i))
+            .map(encode_varint)
             .collect();
         b.iter(|| {
             for bytes in &encoded {
-                black_box(decode_varint(bytes));
+                let _ = black_box(decode_varint(bytes));
             }
         })
     });
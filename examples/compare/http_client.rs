@@ -118,12 +118,9 @@ fn main() -> std::io::Result<()> {
             
             // Read response
             let mut response = vec![0u8; 1024];
-            match stream.read(&mut response) {
-                Ok(size) => {
-                    let response_str = String::from_utf8_lossy(&response[..size]);
-                    println!("📥 Response: {}", response_str.lines().next().unwrap_or(""));
-                }
-                Err(_) => {}
+            if let Ok(size) = stream.read(&mut response) {
+                let response_str = String::from_utf8_lossy(&response[..size]);
+                println!("📥 Response: {}", response_str.lines().next().unwrap_or(""));
             }
         }
     }
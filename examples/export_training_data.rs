@@ -2,16 +2,17 @@
 //! 
 //! Run with: cargo run --example export_training_data
 //! 
-//! This will collect data from a running VSTP server and export it to CSV format
+//! This will collect data from a running VSTP server and export it to CSV
+//! format: one row per connection in `vstp_training_data.csv` and one row per
+//! frame in `vstp_training_sequences.csv`
 
-use std::fs::File;
-use std::io::Write;
 use std::sync::Arc;
 use vstp::{
-    security::ai::{AnomalyDetector, AttackPattern},
+    security::ai::AnomalyDetector,
     security::ai::detector::DetectorConfig,
+    security::ai::{DetectionMode, ExportOptions, FrameLog},
     tcp::{VstpTcpClient, VstpTcpServer},
-    types::{Frame, FrameType, SessionId},
+    types::{Frame, SessionId},
 };
 use tokio::time::{sleep, Duration};
 
@@ -22,16 +23,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("📊 VSTP Training Data Exporter");
     println!("===============================\n");
     
-    // Create detector in learning mode (collects data without blocking)
+    // Create detector in shadow mode (scores and labels data without blocking)
     let config = DetectorConfig {
         enabled: true,
         min_confidence: 0.3, // Lower threshold to collect more data
         auto_block_critical: false, // Don't block, just collect
-        learning_mode: true, // Learning mode - collect all data
+        learning_mode: false, // Score traffic so exports are labelled
         min_samples: 1, // Start collecting immediately
     };
     
-    let detector = Arc::new(AnomalyDetector::new(config));
+    // Log every frame for sequence models as well
+    let frame_log = FrameLog::open("vstp_training_sequences.csv", ExportOptions::default()).await?;
+    let detector = Arc::new(
+        AnomalyDetector::new(config)
+            .with_mode(DetectionMode::Shadow)
+            .with_frame_log(frame_log),
+    );
     let detector_clone = detector.clone();
     
     // Start server
@@ -85,7 +92,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // High data ratio (exfiltration pattern)
     let mut exfil_client = VstpTcpClient::connect("127.0.0.1:8080").await?;
     exfil_client.send_hello().await?;
-    for i in 0..150 {
+    for _i in 0..150 {
         let data = vec![0u8; 500]; // Large data frames
         exfil_client.send_data(data).await?;
         sleep(Duration::from_millis(50)).await;
@@ -97,34 +104,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Export data to CSV
     println!("\n📝 Exporting data to CSV...");
-    export_to_csv(&detector, "vstp_training_data.csv").await?;
+    let rows = detector
+        .export_connections("vstp_training_data.csv", ExportOptions::default())
+        .await?;
+    detector.flush_frame_log().await?;
     
     server_handle.abort();
     
-    println!("\n✅ Data exported to: vstp_training_data.csv");
+    println!("\n✅ {} connection(s) exported to: vstp_training_data.csv", rows);
+    println!("   Frame sequences exported to: vstp_training_sequences.csv");
     println!("   You can now use this CSV to train your ML model!");
     
     Ok(())
 }
-
-async fn export_to_csv(
-    detector: &AnomalyDetector,
-    filename: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = File::create(filename)?;
-    
-    // Write CSV header
-    writeln!(file, "session_id,frame_count,byte_count,connection_duration_seconds,frames_per_second,bytes_per_second,avg_frame_size,max_frame_size,min_frame_size,hello_frames,data_frames,ack_frames,data_frame_ratio,avg_inter_arrival_time_ms,crc_errors,protocol_errors,error_rate,is_anomaly,threat_type")?;
-    
-    // Get all connection stats
-    let connections = detector.get_connection_stats(1).await; // This needs to be implemented to get all
-    
-    // For now, we'll create a simple export function
-    // You'll need to implement get_all_connections() in the detector
-    
-    // Placeholder - you'll need to iterate through all sessions
-    // and export their stats
-    
-    Ok(())
-}
-
@@ -1,20 +1,40 @@
 use bytes::BytesMut;
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::core::frame::{encode_frame, try_decode_frame};
+use crate::core::frame::{
+    encode_frame, encode_frame_with_mac, try_decode_frame, try_decode_frame_with_mac,
+};
 use crate::core::types::{Frame, VstpError};
+use crate::security::mac::FrameKeyring;
 
 /// Tokio codec for VSTP frames
 pub struct VstpFrameCodec {
     max_frame_size: usize,
+    keyring: Option<FrameKeyring>,
 }
 
 impl VstpFrameCodec {
     pub fn new(max_frame_size: usize) -> Self {
-        Self { max_frame_size }
+        Self {
+            max_frame_size,
+            keyring: None,
+        }
     }
 
-    pub fn default() -> Self {
+    /// Sign outgoing frames and reject incoming frames that do not verify
+    pub fn with_mac(mut self, keyring: FrameKeyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Turn frame authentication on or off
+    pub fn set_mac(&mut self, keyring: Option<FrameKeyring>) {
+        self.keyring = keyring;
+    }
+}
+
+impl Default for VstpFrameCodec {
+    fn default() -> Self {
         Self::new(8 * 1024 * 1024) // 8MB default
     }
 }
@@ -24,7 +44,10 @@ impl Decoder for VstpFrameCodec {
     type Error = VstpError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        try_decode_frame(src, self.max_frame_size)
+        match &self.keyring {
+            Some(keyring) => try_decode_frame_with_mac(src, self.max_frame_size, keyring),
+            None => try_decode_frame(src, self.max_frame_size),
+        }
     }
 }
 
@@ -32,7 +55,10 @@ impl Encoder<Frame> for VstpFrameCodec {
     type Error = VstpError;
 
     fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let encoded = encode_frame(&item)?;
+        let encoded = match &self.keyring {
+            Some(keyring) => encode_frame_with_mac(&item, keyring)?,
+            None => encode_frame(&item)?,
+        };
         dst.extend_from_slice(&encoded);
         Ok(())
     }
@@ -79,4 +105,26 @@ mod tests {
         let decoded = codec.decode(&mut buf).unwrap().unwrap();
         assert_eq!(frame, decoded);
     }
+
+    #[test]
+    fn test_codec_mac() {
+        let keyring = FrameKeyring::new("k1", b"session key".to_vec());
+        let mut sender = VstpFrameCodec::default().with_mac(keyring.clone());
+        let mut receiver = VstpFrameCodec::default().with_mac(keyring);
+        let mut buf = BytesMut::new();
+
+        let frame = Frame::new(FrameType::Data)
+            .with_header("test", "value")
+            .with_payload(b"hello".to_vec());
+        sender.encode(frame.clone(), &mut buf).unwrap();
+        let decoded = receiver.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.payload, frame.payload);
+        assert_eq!(decoded.get_header("test"), Some("value"));
+        assert_eq!(decoded.get_header("mac-key"), Some("k1"));
+
+        // Unsigned frames are refused once authentication is on
+        let mut plain = VstpFrameCodec::default();
+        plain.encode(frame, &mut buf).unwrap();
+        assert!(matches!(receiver.decode(&mut buf), Err(VstpError::ForgedFrame(_))));
+    }
 }
@@ -63,7 +63,7 @@ pub fn decode_varint(buf: &[u8]) -> Result<(u64, usize), VstpError> {
 pub fn varint_len(value: u64) -> usize {
     match value {
         0 => 1,
-        v => (64 - v.leading_zeros() as usize + 6) / 7,
+        v => (64 - v.leading_zeros() as usize).div_ceil(7),
     }
 }
 
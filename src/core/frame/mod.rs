@@ -3,5 +3,5 @@ mod parser;
 mod types;
 
 pub use builder::FrameBuilder;
-pub use parser::{encode_frame, try_decode_frame};
+pub use parser::{encode_frame, encode_frame_with_mac, try_decode_frame, try_decode_frame_with_mac};
 pub use types::*;
@@ -3,33 +3,88 @@ use bytes::{BufMut, Bytes, BytesMut};
 use crc_any::CRC;
 
 use crate::core::types::{Flags, Frame, FrameType, Header, VstpError, VSTP_MAGIC, VSTP_VERSION};
+use crate::security::mac::{FrameKeyring, MAC_HEADER, MAC_KEY_HEADER};
 
 /// Encode a VSTP frame into bytes according to the wire format specification
 pub fn encode_frame(frame: &Frame) -> Result<Bytes, VstpError> {
+    encode(frame, None)
+}
+
+/// Encode a VSTP frame with an authentication tag from the keyring's active key
+pub fn encode_frame_with_mac(frame: &Frame, keyring: &FrameKeyring) -> Result<Bytes, VstpError> {
+    encode(frame, Some(keyring))
+}
+
+/// Write header: [KEY_LEN (1B)] [VALUE_LEN (1B)] [KEY] [VALUE]
+fn put_header(header_data: &mut BytesMut, key: &[u8], value: &[u8]) -> Result<(), VstpError> {
+    if key.len() > 255 {
+        return Err(VstpError::Protocol("Header key too long".to_string()));
+    }
+    if value.len() > 255 {
+        return Err(VstpError::Protocol("Header value too long".to_string()));
+    }
+    header_data.put_u8(key.len() as u8);
+    header_data.put_u8(value.len() as u8);
+    header_data.put_slice(key);
+    header_data.put_slice(value);
+    Ok(())
+}
+
+/// Bytes covered by a frame's authentication tag: everything but the tag
+/// itself, the magic and the CRC
+fn mac_input(version: u8, typ: u8, flags: u8, headers: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(7 + headers.len() + payload.len());
+    data.extend_from_slice(&[version, typ, flags]);
+    data.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+    data.extend_from_slice(headers);
+    data.extend_from_slice(payload);
+    data
+}
+
+fn encode(frame: &Frame, keyring: Option<&FrameKeyring>) -> Result<Bytes, VstpError> {
     let mut buf = BytesMut::new();
+    let mut flags = frame.flags;
+    if keyring.is_some() {
+        flags.insert(Flags::MAC);
+    }
 
     // Fixed header: [MAGIC (2B)] [VER (1B)] [TYPE (1B)] [FLAGS (1B)]
     buf.put_slice(&VSTP_MAGIC);
     buf.put_u8(frame.version);
     buf.put_u8(frame.typ as u8);
-    buf.put_u8(frame.flags.bits());
+    buf.put_u8(flags.bits());
 
     // Encode headers first to calculate total header length
     let mut header_data = BytesMut::new();
     for header in &frame.headers {
-        // Validate header key length
-        if header.key.len() > 255 {
-            return Err(VstpError::Protocol("Header key too long".to_string()));
-        }
-        if header.value.len() > 255 {
-            return Err(VstpError::Protocol("Header value too long".to_string()));
+        // Re-signing a decoded frame replaces its old key ID and tag
+        if keyring.is_some()
+            && (header.key == MAC_KEY_HEADER.as_bytes() || header.key == MAC_HEADER.as_bytes())
+        {
+            continue;
         }
+        put_header(&mut header_data, &header.key, &header.value)?;
+    }
 
-        // Write header: [KEY_LEN (1B)] [VALUE_LEN (1B)] [KEY] [VALUE]
-        header_data.put_u8(header.key.len() as u8);
-        header_data.put_u8(header.value.len() as u8);
-        header_data.put_slice(&header.key);
-        header_data.put_slice(&header.value);
+    // The tag goes last and covers every header before it
+    if let Some(keyring) = keyring {
+        let key_id = keyring.active_key_id();
+        put_header(&mut header_data, MAC_KEY_HEADER.as_bytes(), key_id.as_bytes())?;
+        let data = mac_input(
+            frame.version,
+            frame.typ as u8,
+            flags.bits(),
+            &header_data,
+            &frame.payload,
+        );
+        let (signed_with, tag) = keyring.sign(&data);
+        if signed_with != key_id {
+            return Err(VstpError::Protocol("Key rotated while signing".to_string()));
+        }
+        put_header(&mut header_data, MAC_HEADER.as_bytes(), &tag)?;
+    }
+    if header_data.len() > u16::MAX as usize {
+        return Err(VstpError::Protocol("Headers too long".to_string()));
     }
 
     // Write header length (little-endian) and payload length (big-endian)
@@ -63,6 +118,28 @@ pub fn encode_frame(frame: &Frame) -> Result<Bytes, VstpError> {
 pub fn try_decode_frame(
     buf: &mut BytesMut,
     max_frame_size: usize,
+) -> Result<Option<Frame>, VstpError> {
+    decode(buf, max_frame_size, None)
+}
+
+/// Try to decode a VSTP frame, verifying its authentication tag against the
+/// keyring
+///
+/// Frames without a tag, signed with an unknown key or altered in transit are
+/// rejected with [`VstpError::ForgedFrame`]. The tag header is removed from
+/// the returned frame; the `mac-key` header is kept.
+pub fn try_decode_frame_with_mac(
+    buf: &mut BytesMut,
+    max_frame_size: usize,
+    keyring: &FrameKeyring,
+) -> Result<Option<Frame>, VstpError> {
+    decode(buf, max_frame_size, Some(keyring))
+}
+
+fn decode(
+    buf: &mut BytesMut,
+    max_frame_size: usize,
+    keyring: Option<&FrameKeyring>,
 ) -> Result<Option<Frame>, VstpError> {
     // Need at least 11 bytes for fixed header + lengths
     if buf.len() < 11 {
@@ -71,7 +148,7 @@ pub fn try_decode_frame(
 
     // Check magic bytes
     if buf[0] != VSTP_MAGIC[0] || buf[1] != VSTP_MAGIC[1] {
-        return Err(VstpError::Protocol("Invalid magic bytes".to_string()));
+        return Err(VstpError::InvalidMagic([buf[0], buf[1]]));
     }
 
     // Parse fixed header
@@ -81,7 +158,10 @@ pub fn try_decode_frame(
 
     // Validate version
     if version != VSTP_VERSION {
-        return Err(VstpError::Protocol("Unsupported version".to_string()));
+        return Err(VstpError::InvalidVersion {
+            expected: VSTP_VERSION,
+            got: version,
+        });
     }
 
     // Parse lengths
@@ -93,7 +173,10 @@ pub fn try_decode_frame(
 
     // Check size limits
     if total_size > max_frame_size {
-        return Err(VstpError::Protocol("Frame too large".to_string()));
+        return Err(VstpError::FrameTooLarge {
+            size: total_size,
+            limit: max_frame_size,
+        });
     }
 
     // Check if we have enough data
@@ -129,14 +212,16 @@ pub fn try_decode_frame(
         0x06 => FrameType::Bye,
         0x07 => FrameType::Ack,
         0x08 => FrameType::Err,
-        _ => return Err(VstpError::Protocol("Invalid frame type".to_string())),
+        other => return Err(VstpError::InvalidFrameType(other)),
     };
 
     // Parse headers
     let mut headers = Vec::new();
     let mut header_pos = 11; // Start after fixed header
+    let mut last_header_start = header_pos;
 
     while header_pos < 11 + header_len {
+        last_header_start = header_pos;
         if header_pos + 2 > frame_data.len() {
             return Err(VstpError::Protocol("Incomplete header length".to_string()));
         }
@@ -162,10 +247,39 @@ pub fn try_decode_frame(
     let payload_end = payload_start + payload_len;
     let payload = frame_data[payload_start..payload_end].to_vec();
 
+    if let Some(keyring) = keyring {
+        if flags & Flags::MAC.bits() == 0 {
+            return Err(VstpError::ForgedFrame("frame is not authenticated".to_string()));
+        }
+        let tag = match headers.pop() {
+            Some(header) if header.key == MAC_HEADER.as_bytes() => header.value,
+            _ => return Err(VstpError::ForgedFrame("missing authentication tag".to_string())),
+        };
+        let key_id = headers
+            .iter()
+            .find(|h| h.key == MAC_KEY_HEADER.as_bytes())
+            .and_then(|h| std::str::from_utf8(&h.value).ok())
+            .ok_or_else(|| VstpError::ForgedFrame("missing key id".to_string()))?;
+        let data = mac_input(
+            version,
+            frame_type,
+            flags,
+            &frame_data[11..last_header_start],
+            &payload,
+        );
+        if !keyring.verify(key_id, &data, &tag) {
+            return Err(VstpError::ForgedFrame(format!(
+                "tag does not verify with key {:?}",
+                key_id
+            )));
+        }
+    }
+
     Ok(Some(Frame {
         version,
         typ,
-        flags: Flags::from_bits(flags).unwrap_or(Flags::empty()),
+        // Unknown bits are kept so receivers can tell they were set
+        flags: Flags::from_bits_retain(flags),
         headers,
         payload,
     }))
@@ -209,4 +323,98 @@ mod tests {
 
         assert_eq!(frame, decoded);
     }
+
+    fn reseal_crc(bytes: &mut [u8]) {
+        let len = bytes.len();
+        let mut crc = CRC::crc32();
+        crc.digest(&bytes[..len - 4]);
+        let value = crc.get_crc() as u32;
+        bytes[len - 4..].copy_from_slice(&value.to_be_bytes());
+    }
+
+    #[test]
+    fn test_mac_roundtrip_and_forgery() {
+        let keyring = FrameKeyring::new("k1", b"per-session key".to_vec());
+        let frame = Frame::new(FrameType::Data)
+            .with_header("msg-id", "7")
+            .with_payload(b"transfer 10 coins".to_vec());
+
+        let encoded = encode_frame_with_mac(&frame, &keyring).unwrap();
+        let mut buf = BytesMut::from(&encoded[..]);
+        let decoded = try_decode_frame_with_mac(&mut buf, 1024, &keyring)
+            .unwrap()
+            .unwrap();
+        assert!(decoded.flags.contains(Flags::MAC));
+        assert_eq!(decoded.get_header("msg-id"), Some("7"));
+        assert_eq!(decoded.get_header(MAC_HEADER), None);
+        assert_eq!(decoded.payload, frame.payload);
+
+        // Tampering with the payload and fixing up the CRC is caught
+        let mut tampered = encoded.to_vec();
+        let last_payload_byte = tampered.len() - 5;
+        tampered[last_payload_byte] ^= 1;
+        reseal_crc(&mut tampered);
+        let mut buf = BytesMut::from(&tampered[..]);
+        assert!(try_decode_frame(&mut buf.clone(), 1024).is_ok());
+        assert!(matches!(
+            try_decode_frame_with_mac(&mut buf, 1024, &keyring),
+            Err(VstpError::ForgedFrame(_))
+        ));
+
+        // So is a frame signed with a key the receiver does not hold
+        let other = FrameKeyring::new("k1", b"attacker key".to_vec());
+        let forged = encode_frame_with_mac(&frame, &other).unwrap();
+        let mut buf = BytesMut::from(&forged[..]);
+        assert!(matches!(
+            try_decode_frame_with_mac(&mut buf, 1024, &keyring),
+            Err(VstpError::ForgedFrame(_))
+        ));
+    }
+
+    #[test]
+    fn test_mac_key_rotation() {
+        let keyring = FrameKeyring::new("k1", b"old key".to_vec());
+        let frame = Frame::new(FrameType::Data).with_payload(b"hi".to_vec());
+        let before = encode_frame_with_mac(&frame, &keyring).unwrap();
+
+        keyring.rotate("k2", b"new key".to_vec());
+        let after = encode_frame_with_mac(&frame, &keyring).unwrap();
+
+        for encoded in [before, after.clone()] {
+            let mut buf = BytesMut::from(&encoded[..]);
+            assert!(try_decode_frame_with_mac(&mut buf, 1024, &keyring).is_ok());
+        }
+        let mut buf = BytesMut::from(&after[..]);
+        let decoded = try_decode_frame_with_mac(&mut buf, 1024, &keyring)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.get_header(MAC_KEY_HEADER), Some("k2"));
+    }
+
+    #[test]
+    fn test_decode_errors_and_unknown_flags() {
+        let encoded = encode_frame(&Frame::new(FrameType::Ping)).unwrap().to_vec();
+        let decode = |bytes: &[u8]| try_decode_frame(&mut BytesMut::from(bytes), 1024);
+
+        let mut bad = encoded.clone();
+        bad[0] = b'X';
+        assert!(matches!(decode(&bad), Err(VstpError::InvalidMagic(_))));
+
+        let mut bad = encoded.clone();
+        bad[3] = 0x7f;
+        reseal_crc(&mut bad);
+        assert!(matches!(decode(&bad), Err(VstpError::InvalidFrameType(0x7f))));
+
+        let mut bad = encoded.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        assert!(matches!(decode(&bad), Err(VstpError::CrcMismatch { .. })));
+
+        let mut flagged = encoded.clone();
+        flagged[4] = 0b1000_0001;
+        reseal_crc(&mut flagged);
+        let frame = decode(&flagged).unwrap().unwrap();
+        assert!(frame.flags.contains(Flags::REQ_ACK));
+        assert_eq!(frame.flags.unknown_bits(), 0b1000_0000);
+    }
 }
@@ -22,6 +22,12 @@ pub enum VstpError {
     #[error("CRC mismatch: expected {expected}, got {got}")]
     CrcMismatch { expected: u32, got: u32 },
 
+    #[error("Forged frame: {0}")]
+    ForgedFrame(String),
+
+    #[error("Replayed frame: {0}")]
+    ReplayedFrame(String),
+
     #[error("Incomplete frame: need {needed} more bytes")]
     Incomplete { needed: usize },
 
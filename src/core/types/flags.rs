@@ -5,7 +5,16 @@ bitflags! {
     pub struct Flags: u8 {
         const REQ_ACK = 0b0000_0001;  // Request acknowledgment
         const CRC     = 0b0000_0010;  // CRC checksum present
+        const ENC     = 0b0000_0100;  // Encrypted datagram
+        const MAC     = 0b0000_1000;  // Authentication tag present
         const FRAG    = 0b0001_0000;  // Fragmented frame
         const COMP    = 0b0010_0000;  // Compressed payload
     }
 }
+
+impl Flags {
+    /// Bits that are set but belong to no known flag
+    pub fn unknown_bits(&self) -> u8 {
+        self.bits() & !Self::all().bits()
+    }
+}
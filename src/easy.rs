@@ -1,4 +1,5 @@
 use crate::core::types::{Flags, Frame, FrameType, VstpError};
+use crate::security::sealed::{SealIdentity, SealKey};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::sync::{mpsc, Mutex};
@@ -96,8 +97,44 @@ impl VstpClient {
         Ok(())
     }
 
+    /// Send data encrypted to `recipient` and signed by `identity`
+    ///
+    /// Servers and relays on the way can forward the frame and check who
+    /// sent it, but only the holder of the recipient's keys can read it.
+    pub async fn send_sealed<T: Serialize>(
+        &self,
+        data: T,
+        identity: &SealIdentity,
+        recipient: &SealKey,
+    ) -> Result<(), VstpError> {
+        let payload = serde_json::to_vec(&data)
+            .map_err(|e| VstpError::Protocol(format!("Serialization error: {}", e)))?;
+        let frame = Frame::sealed(&payload, identity, recipient)?
+            .with_header("content-type", "application/json");
+        self.send_raw(frame).await
+    }
+
+    /// Receive a sealed message addressed to `identity`, returning it with
+    /// the sender's verified signing key
+    pub async fn receive_sealed<T: DeserializeOwned>(
+        &self,
+        identity: &SealIdentity,
+    ) -> Result<(T, SealKey), VstpError> {
+        let frame = self.receive_frame().await?;
+        let opened = frame.open_sealed(identity)?;
+        let data = serde_json::from_slice(&opened.payload)
+            .map_err(|e| VstpError::Protocol(format!("Deserialization error: {}", e)))?;
+        Ok((data, opened.sender))
+    }
+
     /// Receive data and automatically deserialize it
     pub async fn receive<T: DeserializeOwned>(&self) -> Result<T, VstpError> {
+        let frame = self.receive_frame().await?;
+        serde_json::from_slice(frame.payload())
+            .map_err(|e| VstpError::Protocol(format!("Deserialization error: {}", e)))
+    }
+
+    async fn receive_frame(&self) -> Result<Frame, VstpError> {
         let mut inner = self.inner.lock().await;
         let frame = match &mut *inner {
             ClientType::Tcp(client) => tokio::time::timeout(self.timeout, client.recv())
@@ -113,9 +150,7 @@ impl VstpClient {
                 frame
             }
         };
-
-        serde_json::from_slice(frame.payload())
-            .map_err(|e| VstpError::Protocol(format!("Deserialization error: {}", e)))
+        Ok(frame)
     }
 
     /// Send data and wait for acknowledgment
@@ -167,6 +202,7 @@ enum ServerType {
 
 struct ServerMessage {
     data: Vec<u8>,
+    #[allow(dead_code)]
     client_addr: SocketAddr,
     response_tx: mpsc::Sender<Vec<u8>>,
 }
@@ -228,9 +264,9 @@ impl VstpServer {
                                 let (response_tx, mut response_rx) = mpsc::channel(1);
 
                                 // Try to deserialize and handle the message
-                                match serde_json::from_slice::<T>(&frame.payload()) {
+                                match serde_json::from_slice::<T>(frame.payload()) {
                                     Ok(_data) => {
-                                        if let Err(_) = tokio::time::timeout(
+                                        if tokio::time::timeout(
                                             timeout,
                                             tx.send(ServerMessage {
                                                 data: frame.payload().to_vec(),
@@ -239,6 +275,7 @@ impl VstpServer {
                                             }),
                                         )
                                         .await
+                                        .is_err()
                                         {
                                             break;
                                         }
@@ -246,7 +283,7 @@ impl VstpServer {
                                         if let Some(response) = response_rx.recv().await {
                                             let response_frame =
                                                 Frame::new(FrameType::Data).with_payload(response);
-                                            if let Err(_) = client.send(response_frame).await {
+                                            if client.send(response_frame).await.is_err() {
                                                 break;
                                             }
                                         }
@@ -276,9 +313,9 @@ impl VstpServer {
                         let (response_tx, mut response_rx) = mpsc::channel(1);
 
                         // Try to deserialize and handle the message
-                        match serde_json::from_slice::<T>(&frame.payload()) {
+                        match serde_json::from_slice::<T>(frame.payload()) {
                             Ok(_data) => {
-                                if let Err(_) = tokio::time::timeout(
+                                if tokio::time::timeout(
                                     timeout,
                                     tx.send(ServerMessage {
                                         data: frame.payload().to_vec(),
@@ -287,6 +324,7 @@ impl VstpServer {
                                     }),
                                 )
                                 .await
+                                .is_err()
                                 {
                                     break;
                                 }
@@ -312,16 +350,12 @@ impl VstpServer {
         while let Some(msg) = self.message_rx.recv().await {
             let handler = handler.clone();
             tokio::spawn(async move {
-                match serde_json::from_slice::<T>(&msg.data) {
-                    Ok(data) => match handler(data).await {
-                        Ok(response) => {
-                            if let Ok(response_data) = serde_json::to_vec(&response) {
-                                let _ = msg.response_tx.send(response_data).await;
-                            }
+                if let Ok(data) = serde_json::from_slice::<T>(&msg.data) {
+                    if let Ok(response) = handler(data).await {
+                        if let Ok(response_data) = serde_json::to_vec(&response) {
+                            let _ = msg.response_tx.send(response_data).await;
                         }
-                        Err(_) => (),
-                    },
-                    Err(_) => (),
+                    }
                 }
             });
         }
@@ -441,6 +475,43 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_sealed_through_relay() -> Result<(), VstpError> {
+        let relay = crate::transport::tcp::VstpTcpServer::bind("127.0.0.1:8086").await?;
+        tokio::spawn(async move {
+            let mut conn = relay.accept().await?;
+            while let Some(frame) = conn.recv().await? {
+                // The relay can check the sender but not read the payload
+                assert!(frame.verify_sealed().is_ok());
+                assert!(!frame.payload.windows(6).any(|w| w == b"secret"));
+                conn.send(frame).await?;
+            }
+            Ok::<_, VstpError>(())
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let alice = SealIdentity::generate();
+        let bob = SealIdentity::generate();
+        let client = VstpClient::connect_tcp("127.0.0.1:8086").await?;
+
+        let msg = TestMessage {
+            content: "secret".to_string(),
+        };
+        client
+            .send_sealed(msg.clone(), &alice, &bob.public_keys().encryption)
+            .await?;
+        let (response, sender): (TestMessage, _) = client.receive_sealed(&bob).await?;
+        assert_eq!(response, msg);
+        assert_eq!(sender, alice.public_keys().signing);
+
+        client
+            .send_sealed(msg, &alice, &bob.public_keys().encryption)
+            .await?;
+        assert!(client.receive_sealed::<TestMessage>(&alice).await.is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_multiple_clients() -> Result<(), VstpError> {
         let server = VstpServer::bind_tcp("127.0.0.1:8085").await?;
@@ -53,7 +53,9 @@ pub mod utils;
 
 // Re-export commonly used types
 pub use core::encoding::{decode_varint, encode_varint, varint_len};
-pub use core::frame::{encode_frame, try_decode_frame};
+pub use core::frame::{
+    encode_frame, encode_frame_with_mac, try_decode_frame, try_decode_frame_with_mac,
+};
 pub use core::types::{Flags, Frame, FrameType, Header, SessionId, VstpError};
 
 // Re-export transport modules
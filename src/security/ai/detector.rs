@@ -1,14 +1,30 @@
 //! Main anomaly detection engine
 
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, warn, error};
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{debug, error, info, warn};
 
 use crate::core::types::{Frame, SessionId, VstpError};
 
-use super::monitor::{TrafficMonitor, ErrorType};
-use super::models::BaselineModel;
-use super::patterns::{ThreatDetection, ThreatLevel};
+use super::ensemble::{DetectionModel, Ensemble};
+use super::export::{self, ConnectionRecord, ExportOptions, FrameLog, Label};
+use super::monitor::{ConnectionStats, TrafficMonitor, ErrorType};
+use super::models::BaselineConfig;
+use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
+use super::response::{Responder, ResponseAction, ResponsePolicy};
+use super::segments::{BaselineSnapshot, SegmentInput, SegmentedBaseline, Segmentation};
+use super::sinks::{ThreatForwarder, ThreatSink};
+use super::store::{MemoryThreatStore, ThreatStore};
+use crate::security::blocklist::{BanRecord, Blocklist, IpNet};
+use crate::security::replay::ReplayRejection;
+
+/// Threats a subscriber may fall behind by before missing some
+pub const EVENT_CAPACITY: usize = 1024;
 
 /// Configuration for the anomaly detector
 #[derive(Debug, Clone)]
@@ -19,9 +35,12 @@ pub struct DetectorConfig {
     pub min_confidence: f64,
     /// Auto-block on critical threats
     pub auto_block_critical: bool,
-    /// Learning mode (collects data without blocking)
+    /// Start in [`DetectionMode::Learning`] rather than
+    /// [`DetectionMode::Enforcing`]
     pub learning_mode: bool,
-    /// Minimum samples before detection starts
+    /// Baseline samples needed before a baseline, global or per segment, is
+    /// used for scoring; each connection contributes at most one sample per
+    /// second
     pub min_samples: u64,
 }
 
@@ -37,13 +56,59 @@ impl Default for DetectorConfig {
     }
 }
 
+impl DetectorConfig {
+    /// Mode a detector with this configuration starts in
+    pub fn mode(&self) -> DetectionMode {
+        if self.learning_mode {
+            DetectionMode::Learning
+        } else {
+            DetectionMode::Enforcing
+        }
+    }
+}
+
+/// What the detector does with the traffic it sees
+///
+/// A deployment typically starts in `Learning` until the baselines hold
+/// [`DetectorConfig::min_samples`] samples, runs in `Shadow` while the
+/// threats it would act on are reviewed, then switches to `Enforcing`.
+/// Bans already in force are honoured in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DetectionMode {
+    /// Learn baselines but report nothing
+    Learning,
+    /// Report and store threats but never block
+    Shadow,
+    /// Report threats, block critical ones if
+    /// [`DetectorConfig::auto_block_critical`] is set and carry out the
+    /// response policy
+    Enforcing,
+}
+
+impl DetectionMode {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => DetectionMode::Learning,
+            1 => DetectionMode::Shadow,
+            _ => DetectionMode::Enforcing,
+        }
+    }
+}
+
 /// Main anomaly detection engine
 pub struct AnomalyDetector {
     monitor: Arc<TrafficMonitor>,
-    baseline: Arc<RwLock<BaselineModel>>,
+    ensemble: Arc<RwLock<Ensemble>>,
+    principals: Arc<RwLock<HashMap<SessionId, String>>>,
     config: DetectorConfig,
-    threat_history: Arc<RwLock<Vec<ThreatDetection>>>,
-    blocked_sessions: Arc<RwLock<Vec<SessionId>>>,
+    mode: AtomicU8,
+    store: Arc<dyn ThreatStore>,
+    blocked_sessions: Arc<RwLock<HashSet<SessionId>>>,
+    blocklist: Blocklist,
+    frame_log: Option<FrameLog>,
+    responder: Option<Responder>,
+    events: broadcast::Sender<ThreatDetection>,
 }
 
 impl AnomalyDetector {
@@ -51,16 +116,210 @@ impl AnomalyDetector {
     pub fn new(config: DetectorConfig) -> Self {
         Self {
             monitor: Arc::new(TrafficMonitor::default()),
-            baseline: Arc::new(RwLock::new(BaselineModel::new())),
+            ensemble: Arc::new(RwLock::new(Ensemble::new(SegmentedBaseline::new(
+                baseline_config(&config),
+                Segmentation::Global,
+            )))),
+            principals: Arc::new(RwLock::new(HashMap::new())),
+            mode: AtomicU8::new(config.mode() as u8),
             config,
-            threat_history: Arc::new(RwLock::new(Vec::new())),
-            blocked_sessions: Arc::new(RwLock::new(Vec::new())),
+            store: Arc::new(MemoryThreatStore::default()),
+            blocked_sessions: Arc::new(RwLock::new(HashSet::new())),
+            blocklist: Blocklist::default(),
+            frame_log: None,
+            responder: None,
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
-    /// Create with default configuration
-    pub fn default() -> Self {
-        Self::new(DetectorConfig::default())
+    /// Keep a separate baseline per segment as well as the global one
+    ///
+    /// Connections in a segment with fewer than
+    /// [`DetectorConfig::min_samples`] samples are scored against the global
+    /// baseline.
+    pub fn with_segmentation(mut self, segmentation: Segmentation) -> Self {
+        let rules = SegmentedBaseline::new(baseline_config(&self.config), segmentation);
+        self.ensemble_mut().set_rules(rules);
+        self
+    }
+
+    /// Start in `mode` instead of the one [`DetectorConfig::learning_mode`]
+    /// picks
+    pub fn with_mode(self, mode: DetectionMode) -> Self {
+        self.set_mode(mode);
+        self
+    }
+
+    pub fn mode(&self) -> DetectionMode {
+        DetectionMode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    /// Switch mode, for example from learning to enforcing once the
+    /// baselines are ready
+    pub fn set_mode(&self, mode: DetectionMode) {
+        let previous = DetectionMode::from_u8(self.mode.swap(mode as u8, Ordering::Relaxed));
+        if previous != mode {
+            info!("Anomaly detector switched from {:?} to {:?} mode", previous, mode);
+        }
+    }
+
+    /// Whether the global baseline has the samples it needs to score
+    /// connections
+    pub async fn baseline_ready(&self) -> bool {
+        self.ensemble.read().await.rules().global().is_ready()
+    }
+
+    /// Save what the baselines have learned to `path`, as JSON
+    ///
+    /// The file is written next to `path` and renamed over it, so a crash
+    /// leaves the previous snapshot intact.
+    pub async fn save_baseline(&self, path: impl AsRef<Path>) -> Result<(), VstpError> {
+        let path = path.as_ref();
+        let snapshot = self.ensemble.read().await.rules().snapshot();
+        let json = serde_json::to_vec_pretty(&snapshot)
+            .map_err(|e| VstpError::Protocol(format!("Failed to encode baseline: {}", e)))?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        debug!("Saved baseline to {}", path.display());
+        Ok(())
+    }
+
+    /// Replace the baselines with a snapshot saved by
+    /// [`save_baseline`](Self::save_baseline), returning `false` if there is
+    /// no file at `path`
+    ///
+    /// Load a snapshot taken under the same [`Segmentation`], after
+    /// [`with_segmentation`](Self::with_segmentation).
+    pub async fn load_baseline(&self, path: impl AsRef<Path>) -> Result<bool, VstpError> {
+        let path = path.as_ref();
+        let json = match tokio::fs::read(path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: BaselineSnapshot = serde_json::from_slice(&json).map_err(|e| {
+            VstpError::Protocol(format!("Invalid baseline in {}: {}", path.display(), e))
+        })?;
+        let samples = snapshot.global.sample_count;
+        self.ensemble.write().await.rules_mut().restore(snapshot);
+        info!("Loaded baseline of {} samples from {}", samples, path.display());
+        Ok(true)
+    }
+
+    /// Run another detection model alongside the rules
+    ///
+    /// `weight`, between 0 and 1, scales the confidence of the threats the
+    /// model reports. A model with the same name as one already added
+    /// replaces it.
+    pub fn with_model(mut self, model: impl DetectionModel + 'static, weight: f64) -> Self {
+        self.ensemble_mut().add_model(Box::new(model), weight);
+        self
+    }
+
+    /// Change the weight of a detection model; the built-in rules are
+    /// called `rules`
+    pub fn with_model_weight(mut self, name: &str, weight: f64) -> Self {
+        if !self.ensemble_mut().set_weight(name, weight) {
+            warn!("No detection model named {:?} to weigh", name);
+        }
+        self
+    }
+
+    /// Names and weights of the detection models, rules first
+    pub async fn model_weights(&self) -> Vec<(String, f64)> {
+        self.ensemble.read().await.weights()
+    }
+
+    fn ensemble_mut(&mut self) -> &mut Ensemble {
+        Arc::get_mut(&mut self.ensemble)
+            .expect("detector is not shared while it is being built")
+            .get_mut()
+    }
+
+    /// Record the authenticated principal of a session, for
+    /// [`Segmentation::Principal`]
+    pub async fn set_principal(&self, session_id: SessionId, principal: impl Into<String>) {
+        self.principals
+            .write()
+            .await
+            .insert(session_id, principal.into());
+    }
+
+    /// Keys of the segments that have a baseline of their own
+    pub async fn baseline_segments(&self) -> Vec<String> {
+        self.ensemble.read().await.rules().segment_keys()
+    }
+
+    /// Ban blocked peers' addresses on `blocklist` instead of a private one
+    ///
+    /// Share one blocklist between detectors to apply bans across servers.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// The blocklist that blocked sessions' peer addresses are banned on
+    pub fn blocklist(&self) -> Blocklist {
+        self.blocklist.clone()
+    }
+
+    /// Keep threat history and bans in `store` instead of in memory
+    ///
+    /// Call [`restore`](Self::restore) afterwards to reapply the bans the
+    /// store already holds.
+    pub fn with_store(mut self, store: Arc<dyn ThreatStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Log every analyzed frame to `log` as training data
+    pub fn with_frame_log(mut self, log: FrameLog) -> Self {
+        self.frame_log = Some(log);
+        self
+    }
+
+    /// Respond to threats as `policy` says
+    ///
+    /// Actions are carried out only in [`DetectionMode::Enforcing`]; in
+    /// shadow mode they are logged instead. Bans and throttles are applied
+    /// by the detector, while the server owning a session picks up the rest
+    /// through [`take_responses`](Self::take_responses).
+    pub fn with_response_policy(mut self, policy: ResponsePolicy) -> Self {
+        self.responder = Some(Responder::new(policy));
+        self
+    }
+
+    /// Receive every threat the detector records from now on
+    ///
+    /// A subscriber more than [`EVENT_CAPACITY`] threats behind misses the
+    /// oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<ThreatDetection> {
+        self.events.subscribe()
+    }
+
+    /// Deliver every threat the detector records from now on to `sink`,
+    /// until the returned forwarder is dropped
+    pub fn forward_to(&self, sink: Arc<dyn ThreatSink>) -> ThreatForwarder {
+        ThreatForwarder::start(self.subscribe(), sink)
+    }
+
+    /// Reapply the bans held by the store to the blocklist, returning how
+    /// many were still in force
+    pub async fn restore(&self) -> Result<usize, VstpError> {
+        let bans = self.store.active_bans().await?;
+        let mut restored = 0;
+        for ban in &bans {
+            if ban.is_active() {
+                self.blocklist.apply(ban);
+                restored += 1;
+            }
+        }
+        if restored > 0 {
+            info!("Restored {} ban(s) from the threat store", restored);
+        }
+        Ok(restored)
     }
 
     /// Analyze a frame for anomalies
@@ -84,6 +343,12 @@ impl AnomalyDetector {
                 ));
             }
         }
+        if self.blocklist.is_blocked(peer_addr.ip()) {
+            return Err(VstpError::Protocol(format!(
+                "Peer {} is blocked due to security threat",
+                peer_addr.ip()
+            )));
+        }
 
         // Record frame in monitor
         self.monitor.record_frame(session_id, peer_addr, frame, frame_size).await;
@@ -97,16 +362,45 @@ impl AnomalyDetector {
             }
         };
 
-        // Update baseline model
-        {
-            let mut baseline = self.baseline.write().await;
-            baseline.update_baseline(&stats);
+        let principal = self.principals.read().await.get(&session_id).cloned();
+        let mut ensemble = self.ensemble.write().await;
+        let key = ensemble.rules().key(&SegmentInput {
+            session_id,
+            peer_addr,
+            principal: principal.as_deref(),
+            frame,
+        });
+        let mut features = stats.feature_vector().with_segment(key);
+        features.observed_at = std::time::Instant::now();
+
+        // Update the models, then score the connection with them unless
+        // still learning
+        ensemble.learn(&features);
+        let threats = if self.mode() == DetectionMode::Learning {
+            Vec::new()
+        } else {
+            ensemble.score(&features)
+        };
+        drop(ensemble);
+
+        let threats = self.report(session_id, threats).await;
+
+        if let Some(log) = &self.frame_log {
+            if let Err(e) = log.record(&stats, &threats).await {
+                error!("Failed to log frame to {}: {}", log.path().display(), e);
+            }
         }
 
-        // Detect anomalies
-        let baseline = self.baseline.read().await;
-        let mut threats = baseline.detect_anomalies(&stats);
+        Ok(threats)
+    }
 
+    /// Keep the threats confident enough to report, tag them with the
+    /// session and record them
+    async fn report(
+        &self,
+        session_id: SessionId,
+        mut threats: Vec<ThreatDetection>,
+    ) -> Vec<ThreatDetection> {
         // Filter by confidence threshold
         threats.retain(|t| {
             t.confidence >= self.config.min_confidence
@@ -117,56 +411,233 @@ impl AnomalyDetector {
             threat.session_id = Some(session_id);
         }
 
-        // Log and store threats
         for threat in &threats {
-            match threat.threat_level {
-                ThreatLevel::Critical => {
-                    error!(
-                        "🚨 CRITICAL THREAT DETECTED: {:?} - {} (confidence: {:.2}%)",
-                        threat.pattern,
-                        threat.description,
-                        threat.confidence * 100.0
-                    );
-                    
-                    if self.config.auto_block_critical {
-                        self.block_session(session_id).await;
-                    }
-                }
-                ThreatLevel::High => {
-                    warn!(
-                        "⚠️  HIGH THREAT: {:?} - {} (confidence: {:.2}%)",
-                        threat.pattern,
-                        threat.description,
-                        threat.confidence * 100.0
-                    );
-                }
-                ThreatLevel::Medium => {
-                    warn!(
-                        "⚠️  MEDIUM THREAT: {:?} - {} (confidence: {:.2}%)",
-                        threat.pattern,
-                        threat.description,
-                        threat.confidence * 100.0
-                    );
-                }
-                _ => {
-                    debug!(
-                        "Threat detected: {:?} - {} (confidence: {:.2}%)",
-                        threat.pattern,
-                        threat.description,
-                        threat.confidence * 100.0
-                    );
-                }
+            self.record_threat(session_id, threat).await;
+        }
+        threats
+    }
+
+    /// Report a frame that could not be received
+    ///
+    /// CRC mismatches and malformed, unknown or forged frames are counted
+    /// against the session, or against the peer's IP address if it has no
+    /// session yet, and the connection is scored again so a high error rate
+    /// is caught. Failures that say nothing about the peer, such as I/O
+    /// errors, are ignored.
+    ///
+    /// Threats from a peer without a session have no session to respond on;
+    /// critical ones get the peer's address banned when
+    /// [`DetectorConfig::auto_block_critical`] is set.
+    pub async fn report_decode_error(
+        &self,
+        session_id: Option<SessionId>,
+        peer_addr: std::net::SocketAddr,
+        error: &VstpError,
+    ) -> Vec<ThreatDetection> {
+        match (ErrorType::from_error(error), session_id) {
+            (Some(error_type), Some(session_id)) => {
+                self.report_error(session_id, peer_addr, error_type).await
             }
+            (Some(error_type), None) => self.report_sessionless_error(peer_addr, error_type).await,
+            (None, _) => Vec::new(),
+        }
+    }
 
-            // Store in history
-            let mut history = self.threat_history.write().await;
-            history.push(threat.clone());
-            if history.len() > 1000 {
-                history.remove(0);
+    async fn report_sessionless_error(
+        &self,
+        peer_addr: std::net::SocketAddr,
+        error_type: ErrorType,
+    ) -> Vec<ThreatDetection> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        let stats = self
+            .monitor
+            .record_sessionless_error(peer_addr, error_type)
+            .await;
+        if self.mode() == DetectionMode::Learning {
+            return Vec::new();
+        }
+        let mut features = stats.feature_vector();
+        features.observed_at = std::time::Instant::now();
+        let mut threats = self.ensemble.read().await.score(&features);
+        threats.retain(|t| t.confidence >= self.config.min_confidence);
+
+        let ip = peer_addr.ip();
+        for threat in &mut threats {
+            threat.indicators.push(format!("Peer {} has no session", ip));
+        }
+        for threat in &threats {
+            log_threat(threat);
+            if threat.threat_level == ThreatLevel::Critical
+                && self.config.auto_block_critical
+                && self.mode() == DetectionMode::Enforcing
+            {
+                let reason = format!("peer {} blocked without a session", ip);
+                self.ban_peer(ip, None, reason).await;
             }
+            self.publish(threat).await;
         }
+        threats
+    }
 
-        Ok(threats)
+    /// Count an error against a session and score the session again
+    /// against the global baseline
+    pub async fn report_error(
+        &self,
+        session_id: SessionId,
+        peer_addr: std::net::SocketAddr,
+        error_type: ErrorType,
+    ) -> Vec<ThreatDetection> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        self.monitor
+            .record_peer_error(session_id, peer_addr, error_type)
+            .await;
+        if self.mode() == DetectionMode::Learning {
+            return Vec::new();
+        }
+        let Some(stats) = self.monitor.get_connection_stats(session_id).await else {
+            return Vec::new();
+        };
+        let mut features = stats.feature_vector();
+        features.observed_at = std::time::Instant::now();
+        let threats = self.ensemble.read().await.score(&features);
+        self.report(session_id, threats).await
+    }
+
+    /// Report a frame rejected by replay protection
+    ///
+    /// A duplicate or stale sequence number is proof rather than a
+    /// statistical guess, so it is recorded with full confidence.
+    pub async fn report_replay(
+        &self,
+        session_id: SessionId,
+        rejection: &ReplayRejection,
+    ) -> Option<ThreatDetection> {
+        if !self.config.enabled || self.mode() == DetectionMode::Learning {
+            return None;
+        }
+        let threat = ThreatDetection::new(
+            AttackPattern::ReplayAttack,
+            ThreatLevel::from_score(1.0),
+            1.0,
+            format!("Replayed frame rejected: {}", rejection),
+        )
+        .with_session_id(session_id)
+        .with_indicator(rejection.to_string());
+        self.record_threat(session_id, &threat).await;
+        Some(threat)
+    }
+
+    /// Report a frame refused by the authorization policy
+    pub async fn report_unauthorized(
+        &self,
+        session_id: SessionId,
+        reason: &str,
+    ) -> Option<ThreatDetection> {
+        if !self.config.enabled || self.mode() == DetectionMode::Learning {
+            return None;
+        }
+        // The denial itself is certain, but a misconfigured client looks the
+        // same as a probing one, so it is not treated as critical
+        let threat = ThreatDetection::new(
+            AttackPattern::UnauthorizedAccess,
+            ThreatLevel::High,
+            1.0,
+            format!("Frame denied by policy: {}", reason),
+        )
+        .with_session_id(session_id)
+        .with_indicator(reason.to_string());
+        self.record_threat(session_id, &threat).await;
+        Some(threat)
+    }
+
+    /// Log and store a threat, blocking the session if it is critical
+    async fn record_threat(&self, session_id: SessionId, threat: &ThreatDetection) {
+        log_threat(threat);
+        if threat.threat_level == ThreatLevel::Critical
+            && self.config.auto_block_critical
+            && self.mode() == DetectionMode::Enforcing
+        {
+            self.block_session(session_id).await;
+        }
+
+        if let Some(action) = self
+            .responder
+            .as_ref()
+            .and_then(|responder| responder.decide(session_id, threat))
+        {
+            self.respond(session_id, threat, action).await;
+        }
+
+        self.publish(threat).await;
+    }
+
+    /// Store a threat and pass it to subscribers
+    async fn publish(&self, threat: &ThreatDetection) {
+        if let Err(e) = self.store.record_threat(threat).await {
+            error!("Failed to store threat: {}", e);
+        }
+        // Nobody may be subscribed
+        let _ = self.events.send(threat.clone());
+    }
+
+    /// Carry out the response policy's action for a threat
+    async fn respond(&self, session_id: SessionId, threat: &ThreatDetection, action: ResponseAction) {
+        let Some(responder) = &self.responder else {
+            return;
+        };
+        if self.mode() != DetectionMode::Enforcing {
+            info!(
+                "{:?} mode: would respond to {:?} on session {} with {:?}",
+                self.mode(),
+                threat.pattern,
+                session_id,
+                action
+            );
+            return;
+        }
+
+        info!(
+            "Responding to {:?} on session {} with {:?}",
+            threat.pattern, session_id, action
+        );
+        if let ResponseAction::Ban(ttl) = action {
+            self.ban_session(session_id, ttl).await;
+        }
+        responder.apply(session_id, action);
+    }
+
+    /// Take the response actions the server owning a session has yet to
+    /// carry out, such as warning the peer or closing the session
+    pub fn take_responses(&self, session_id: SessionId) -> Vec<ResponseAction> {
+        self.responder
+            .as_ref()
+            .map(|responder| responder.take(session_id))
+            .unwrap_or_default()
+    }
+
+    /// How long to hold back a throttled session's next frame
+    ///
+    /// Each call reserves a slot, so frames handled concurrently are spaced
+    /// out as well. Stream transports wait this long before reading on;
+    /// datagram transports use [`throttle_admit`](Self::throttle_admit).
+    pub fn throttle_delay(&self, session_id: SessionId) -> Option<Duration> {
+        self.responder
+            .as_ref()
+            .and_then(|responder| responder.throttle_delay(session_id))
+    }
+
+    /// Whether a throttled session's frame arriving now should be handled
+    ///
+    /// For datagram transports, which cannot hold a peer back: frames that
+    /// arrive before the session's next slot should be dropped.
+    pub fn throttle_admit(&self, session_id: SessionId) -> bool {
+        self.responder
+            .as_ref()
+            .is_none_or(|responder| responder.throttle_admit(session_id))
     }
 
     /// Record an error for analysis
@@ -175,18 +646,60 @@ impl AnomalyDetector {
     }
 
     /// Block a session
+    ///
+    /// If the monitor has seen the session, its peer address is also banned
+    /// so the client cannot simply reconnect; repeat offenders get longer
+    /// bans.
     pub async fn block_session(&self, session_id: SessionId) {
-        let mut blocked = self.blocked_sessions.write().await;
-        if !blocked.contains(&session_id) {
-            blocked.push(session_id);
-            warn!("Session {} has been blocked due to security threat", session_id);
+        self.ban_session(session_id, None).await;
+    }
+
+    /// Block a session and ban its peer address for `ttl`, or for an
+    /// escalating time if `None`
+    async fn ban_session(&self, session_id: SessionId, ttl: Option<Duration>) {
+        if !self.blocked_sessions.write().await.insert(session_id) {
+            return;
+        }
+        warn!("Session {} has been blocked due to security threat", session_id);
+
+        if let Some(stats) = self.monitor.get_connection_stats(session_id).await {
+            let reason = format!("session {} blocked", session_id);
+            self.ban_peer(stats.peer_addr.ip(), ttl, reason).await;
         }
     }
 
+    /// Ban a peer address for `ttl`, or for an escalating time if `None`
+    async fn ban_peer(&self, ip: IpAddr, ttl: Option<Duration>, reason: String) {
+        let ttl = match ttl {
+            Some(ttl) => {
+                self.blocklist.block(IpNet::from(ip), Some(ttl), reason);
+                ttl
+            }
+            None => self.blocklist.ban(ip, reason),
+        };
+        warn!("Peer {} banned for {:?}", ip, ttl);
+
+        if let Some(ban) = self.blocklist.lookup(ip) {
+            if let Err(e) = self.store.record_ban(&BanRecord::from(&ban)).await {
+                error!("Failed to store ban on {}: {}", ip, e);
+            }
+        }
+    }
+
+    /// Lift the ban on exactly `net`, in the blocklist and the store
+    pub async fn unban(&self, net: IpNet) -> Result<(), VstpError> {
+        self.blocklist.unblock(net);
+        self.store.lift_ban(net).await?;
+        debug!("Ban on {} has been lifted", net);
+        Ok(())
+    }
+
     /// Unblock a session
+    ///
+    /// Any ban on the session's peer address stays in place; lift it through
+    /// [`blocklist`](Self::blocklist).
     pub async fn unblock_session(&self, session_id: SessionId) {
-        let mut blocked = self.blocked_sessions.write().await;
-        blocked.retain(|&id| id != session_id);
+        self.blocked_sessions.write().await.remove(&session_id);
         debug!("Session {} has been unblocked", session_id);
     }
 
@@ -196,37 +709,169 @@ impl AnomalyDetector {
         blocked.contains(&session_id)
     }
 
+    /// Check if a peer address is banned
+    pub fn is_peer_blocked(&self, ip: IpAddr) -> bool {
+        self.blocklist.is_blocked(ip)
+    }
+
+    /// Forget a session that has closed
+    ///
+    /// Its blocked status is no longer needed once the connection is gone;
+    /// the ban on its peer address carries over to new connections.
+    pub async fn end_session(&self, session_id: SessionId) {
+        self.blocked_sessions.write().await.remove(&session_id);
+        self.ensemble.write().await.end_session(session_id);
+        self.principals.write().await.remove(&session_id);
+        if let Some(responder) = &self.responder {
+            responder.end_session(session_id);
+        }
+        if let Some(log) = &self.frame_log {
+            if let Err(e) = log.end_session(session_id).await {
+                error!("Failed to flush frame log {}: {}", log.path().display(), e);
+            }
+        }
+    }
+
     /// Get threat history
     pub async fn get_threat_history(&self, limit: usize) -> Vec<ThreatDetection> {
-        let history = self.threat_history.read().await;
-        history.iter().rev().take(limit).cloned().collect()
+        self.store.recent_threats(limit).await.unwrap_or_else(|e| {
+            error!("Failed to read threat history: {}", e);
+            Vec::new()
+        })
     }
 
     /// Get recent threats for a session
     pub async fn get_session_threats(&self, session_id: SessionId) -> Vec<ThreatDetection> {
-        let history = self.threat_history.read().await;
-        history.iter()
-            .rev()
-            .filter(|t| t.session_id == Some(session_id))
-            .take(10)
-            .cloned()
-            .collect()
+        self.store
+            .session_threats(session_id, 10)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to read threat history: {}", e);
+                Vec::new()
+            })
     }
 
     /// Get connection statistics
-    pub async fn get_connection_stats(&self, session_id: SessionId) -> Option<super::monitor::ConnectionStats> {
+    pub async fn get_connection_stats(&self, session_id: SessionId) -> Option<ConnectionStats> {
         self.monitor.get_connection_stats(session_id).await
     }
 
+    /// Statistics of every connection the monitor tracks
+    pub async fn get_all_connections(&self) -> Vec<ConnectionStats> {
+        self.monitor.get_all_connections().await
+    }
+
+    /// Error records of peers that sent undecodable datagrams without a
+    /// session
+    pub async fn get_sessionless_stats(&self) -> Vec<ConnectionStats> {
+        self.monitor.get_sessionless_stats().await
+    }
+
+    /// Every tracked connection as a row of training data, scored against
+    /// the global baseline and, if `labels` is set, labelled from the
+    /// threat history
+    pub async fn connection_records(&self, labels: bool) -> Vec<ConnectionRecord> {
+        let connections = self.get_all_connections().await;
+        let mut records: Vec<ConnectionRecord> = {
+            let ensemble = self.ensemble.read().await;
+            let baseline = ensemble.rules().global();
+            connections
+                .iter()
+                .filter(|stats| stats.frame_count > 0)
+                .map(|stats| ConnectionRecord::new(stats, Some(baseline)))
+                .collect()
+        };
+        if labels {
+            for record in &mut records {
+                let threats = self.get_session_threats(record.session_id).await;
+                record.label = Some(Label::from_threats(&threats));
+            }
+        }
+        records.sort_by_key(|record| record.session_id);
+        records
+    }
+
+    /// Write every tracked connection to `path`, replacing the file, and
+    /// return how many were written
+    ///
+    /// Use [`Snapshots`](super::export::Snapshots) to export periodically.
+    pub async fn export_connections(
+        &self,
+        path: impl AsRef<Path>,
+        options: ExportOptions,
+    ) -> Result<usize, VstpError> {
+        let records = self.connection_records(options.labels).await;
+        export::write_records(path.as_ref(), &records, options.format, false).await?;
+        Ok(records.len())
+    }
+
+    /// Write buffered frame log records to the file
+    pub async fn flush_frame_log(&self) -> Result<(), VstpError> {
+        match &self.frame_log {
+            Some(log) => log.flush().await,
+            None => Ok(()),
+        }
+    }
+
     /// Cleanup old data
     pub async fn cleanup(&self) {
         self.monitor.cleanup_old_connections().await;
+        self.blocklist.purge_expired();
+        if let Some(responder) = &self.responder {
+            responder.purge_expired();
+        }
+    }
+}
+
+/// Log a threat at a level matching its severity
+fn log_threat(threat: &ThreatDetection) {
+    match threat.threat_level {
+        ThreatLevel::Critical => {
+            error!(
+                "🚨 CRITICAL THREAT DETECTED: {:?} - {} (confidence: {:.2}%)",
+                threat.pattern,
+                threat.description,
+                threat.confidence * 100.0
+            );
+        }
+        ThreatLevel::High => {
+            warn!(
+                "⚠️  HIGH THREAT: {:?} - {} (confidence: {:.2}%)",
+                threat.pattern,
+                threat.description,
+                threat.confidence * 100.0
+            );
+        }
+        ThreatLevel::Medium => {
+            warn!(
+                "⚠️  MEDIUM THREAT: {:?} - {} (confidence: {:.2}%)",
+                threat.pattern,
+                threat.description,
+                threat.confidence * 100.0
+            );
+        }
+        _ => {
+            debug!(
+                "Threat detected: {:?} - {} (confidence: {:.2}%)",
+                threat.pattern,
+                threat.description,
+                threat.confidence * 100.0
+            );
+        }
+    }
+}
+
+fn baseline_config(config: &DetectorConfig) -> BaselineConfig {
+    BaselineConfig {
+        min_samples: config.min_samples,
+        ..Default::default()
     }
 }
 
 impl Default for AnomalyDetector {
+    /// Create with default configuration
     fn default() -> Self {
-        Self::default()
+        Self::new(DetectorConfig::default())
     }
 }
 
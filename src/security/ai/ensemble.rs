@@ -0,0 +1,287 @@
+//! Pluggable detection models and the weighted ensemble that runs them
+//!
+//! A [`DetectionModel`] learns from and scores [`FeatureVector`]s. The
+//! detector always runs the hand-written rules, scored against the
+//! (segmented) baseline, and any further models added with
+//! [`AnomalyDetector::with_model`](super::AnomalyDetector::with_model).
+//!
+//! Each model has a weight between 0 and 1 that scales the confidence of
+//! what it reports; a weight of 0 disables it. A down-weighted threat's level
+//! is capped at what its scaled confidence warrants, so a weak model cannot
+//! trigger responses meant for high threats. When several models report
+//! the same [`AttackPattern`](super::patterns::AttackPattern) their confidences are combined as independent
+//! evidence, `1 - (1 - a)(1 - b)`, so agreement raises confidence and level
+//! while a single model keeps its own score.
+
+use tracing::warn;
+
+use crate::core::types::SessionId;
+
+use super::monitor::FeatureVector;
+use super::patterns::{ThreatDetection, ThreatLevel};
+use super::segments::SegmentedBaseline;
+
+/// A model that learns normal traffic and scores connections against it
+pub trait DetectionModel: Send + Sync {
+    /// Short name shown in threat indicators and used to change weights
+    fn name(&self) -> &str;
+
+    /// Learn from a connection's current features
+    ///
+    /// Called for every analyzed frame, before [`score`](Self::score);
+    /// models decide for themselves how often to take a sample.
+    fn learn(&mut self, features: &FeatureVector);
+
+    /// Threats raised by a connection's current features
+    fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection>;
+
+    /// Forget a closed connection
+    fn end_session(&mut self, _session_id: SessionId) {}
+}
+
+struct Member {
+    model: Box<dyn DetectionModel>,
+    weight: f64,
+}
+
+/// The rules plus any other models, with a weight each
+pub struct Ensemble {
+    rules: SegmentedBaseline,
+    rules_weight: f64,
+    models: Vec<Member>,
+}
+
+impl Ensemble {
+    /// An ensemble running only the rules, at full weight
+    pub fn new(rules: SegmentedBaseline) -> Self {
+        Self {
+            rules,
+            rules_weight: 1.0,
+            models: Vec::new(),
+        }
+    }
+
+    /// The rules model and its baselines
+    pub fn rules(&self) -> &SegmentedBaseline {
+        &self.rules
+    }
+
+    pub fn rules_mut(&mut self) -> &mut SegmentedBaseline {
+        &mut self.rules
+    }
+
+    /// Replace the rules model, keeping its weight
+    pub fn set_rules(&mut self, rules: SegmentedBaseline) {
+        self.rules = rules;
+    }
+
+    /// Add a model, replacing any model of the same name
+    ///
+    /// The name `rules` belongs to the built-in rules; change their weight
+    /// with [`set_weight`](Self::set_weight).
+    pub fn add_model(&mut self, model: Box<dyn DetectionModel>, weight: f64) {
+        let weight = weight.clamp(0.0, 1.0);
+        if model.name() == self.rules.name() {
+            warn!("Ignoring detection model named {:?}: the name is reserved", model.name());
+            return;
+        }
+        self.models.retain(|member| member.model.name() != model.name());
+        self.models.push(Member { model, weight });
+    }
+
+    /// Change the weight of the model called `name`, returning whether
+    /// there is one
+    pub fn set_weight(&mut self, name: &str, weight: f64) -> bool {
+        let weight = weight.clamp(0.0, 1.0);
+        if name == self.rules.name() {
+            self.rules_weight = weight;
+            return true;
+        }
+        match self.models.iter_mut().find(|m| m.model.name() == name) {
+            Some(member) => {
+                member.weight = weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names and weights of the models, rules first
+    pub fn weights(&self) -> Vec<(String, f64)> {
+        self.members()
+            .map(|(model, weight)| (model.name().to_string(), weight))
+            .collect()
+    }
+
+    fn members(&self) -> impl Iterator<Item = (&dyn DetectionModel, f64)> {
+        std::iter::once((&self.rules as &dyn DetectionModel, self.rules_weight)).chain(
+            self.models
+                .iter()
+                .map(|member| (member.model.as_ref(), member.weight)),
+        )
+    }
+
+    /// Let every model learn from a feature vector
+    ///
+    /// Models with a weight of 0 keep learning, so they are ready if their
+    /// weight is raised.
+    pub fn learn(&mut self, features: &FeatureVector) {
+        self.rules.learn(features);
+        for member in &mut self.models {
+            member.model.learn(features);
+        }
+    }
+
+    /// Score a feature vector with every weighted model, merging threats of
+    /// the same pattern
+    pub fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        let mut merged: Vec<ThreatDetection> = Vec::new();
+        for (model, weight) in self.members() {
+            if weight <= 0.0 {
+                continue;
+            }
+            for threat in model.score(features) {
+                let indicator = format!("Model: {} ({:.2})", model.name(), threat.confidence);
+                let mut threat = threat.with_indicator(indicator);
+                if weight < 1.0 {
+                    threat.confidence *= weight;
+                    threat.threat_level =
+                        threat.threat_level.min(ThreatLevel::from_score(threat.confidence));
+                }
+                match merged.iter_mut().find(|t| t.pattern == threat.pattern) {
+                    Some(existing) => merge(existing, threat),
+                    None => merged.push(threat),
+                }
+            }
+        }
+        merged
+    }
+
+    /// Forget a closed connection in every model
+    pub fn end_session(&mut self, session_id: SessionId) {
+        self.rules.remove_session(session_id);
+        for member in &mut self.models {
+            member.model.end_session(session_id);
+        }
+    }
+}
+
+/// Fold `other` into `existing`, which reports the same pattern
+fn merge(existing: &mut ThreatDetection, other: ThreatDetection) {
+    debug_assert_eq!(existing.pattern, other.pattern);
+    let combined = 1.0 - (1.0 - existing.confidence) * (1.0 - other.confidence);
+    if other.confidence > existing.confidence {
+        existing.description = other.description;
+    }
+    existing.confidence = combined.clamp(0.0, 1.0);
+    existing.threat_level = existing
+        .threat_level
+        .max(other.threat_level)
+        .max(ThreatLevel::from_score(existing.confidence));
+    existing.indicators.extend(other.indicators);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::ai::models::BaselineConfig;
+    use crate::security::ai::monitor::ConnectionStats;
+    use crate::security::ai::patterns::AttackPattern;
+    use crate::security::ai::segments::Segmentation;
+
+    /// Reports a fixed threat for every vector
+    struct Fixed {
+        name: &'static str,
+        pattern: AttackPattern,
+        confidence: f64,
+    }
+
+    impl DetectionModel for Fixed {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn learn(&mut self, _features: &FeatureVector) {}
+
+        fn score(&self, _features: &FeatureVector) -> Vec<ThreatDetection> {
+            vec![ThreatDetection::new(
+                self.pattern,
+                ThreatLevel::from_score(self.confidence),
+                self.confidence,
+                format!("{} fired", self.name),
+            )]
+        }
+    }
+
+    fn fixed(name: &'static str, pattern: AttackPattern, confidence: f64) -> Box<Fixed> {
+        Box::new(Fixed {
+            name,
+            pattern,
+            confidence,
+        })
+    }
+
+    fn ensemble() -> Ensemble {
+        Ensemble::new(SegmentedBaseline::new(BaselineConfig::default(), Segmentation::Global))
+    }
+
+    fn vector() -> FeatureVector {
+        ConnectionStats::new(1, "127.0.0.1:1".parse().unwrap()).feature_vector()
+    }
+
+    #[test]
+    fn test_weights_scale_and_agreement_combines() {
+        let mut ensemble = ensemble();
+        ensemble.add_model(fixed("a", AttackPattern::AnomalousTraffic, 0.6), 1.0);
+        ensemble.add_model(fixed("b", AttackPattern::AnomalousTraffic, 0.8), 0.5);
+        ensemble.add_model(fixed("c", AttackPattern::ConnectionFlood, 0.9), 0.0);
+
+        let threats = ensemble.score(&vector());
+        assert_eq!(threats.len(), 1);
+        let threat = &threats[0];
+        // 1 - (1 - 0.6)(1 - 0.4)
+        assert!((threat.confidence - 0.76).abs() < 1e-9, "{}", threat.confidence);
+        assert_eq!(threat.threat_level, ThreatLevel::High);
+        assert_eq!(threat.description, "a fired");
+        assert!(threat.indicators.contains(&"Model: a (0.60)".to_string()));
+        assert!(threat.indicators.contains(&"Model: b (0.80)".to_string()));
+
+        assert!(ensemble.set_weight("c", 1.0));
+        assert!(!ensemble.set_weight("missing", 1.0));
+        let patterns: Vec<_> = ensemble.score(&vector()).iter().map(|t| t.pattern).collect();
+        assert_eq!(
+            patterns,
+            vec![AttackPattern::AnomalousTraffic, AttackPattern::ConnectionFlood]
+        );
+    }
+
+    #[test]
+    fn test_weights_cap_threat_level() {
+        let mut ensemble = ensemble();
+        ensemble.add_model(fixed("a", AttackPattern::ConnectionFlood, 0.95), 0.4);
+        let threats = ensemble.score(&vector());
+        assert!((threats[0].confidence - 0.38).abs() < 1e-9);
+        assert_eq!(threats[0].threat_level, ThreatLevel::Low);
+
+        // Full weight keeps the level the model chose
+        ensemble.set_weight("a", 1.0);
+        assert_eq!(ensemble.score(&vector())[0].threat_level, ThreatLevel::Critical);
+    }
+
+    #[test]
+    fn test_models_by_name() {
+        let mut ensemble = ensemble();
+        ensemble.add_model(fixed("a", AttackPattern::AnomalousTraffic, 0.6), 1.0);
+        ensemble.add_model(fixed("a", AttackPattern::AnomalousTraffic, 0.6), 0.3);
+        ensemble.add_model(fixed("rules", AttackPattern::AnomalousTraffic, 0.6), 1.0);
+        assert!(ensemble.set_weight("rules", 0.2));
+        assert_eq!(
+            ensemble.weights(),
+            vec![("rules".to_string(), 0.2), ("a".to_string(), 0.3)]
+        );
+
+        ensemble.set_weight("a", 0.0);
+        ensemble.learn(&vector());
+        assert!(ensemble.score(&vector()).is_empty());
+    }
+}
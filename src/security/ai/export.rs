@@ -0,0 +1,918 @@
+//! Training data export
+//!
+//! Writes what the detector sees in the two layouts described in
+//! `ML_TRAINING_DATA_SCHEMA.md`:
+//!
+//! - [`ConnectionRecord`]: one row per connection, the aggregated format,
+//!   with the columns listed in `CSV_COLUMNS_FOR_AI.md`. Write them once
+//!   with [`AnomalyDetector::export_connections`] or on a timer with
+//!   [`Snapshots`].
+//! - [`FrameRecord`]: one row per frame, the sequence format for sequence
+//!   models, written as frames arrive by a [`FrameLog`] given to
+//!   [`AnomalyDetector::with_frame_log`].
+//!
+//! Both can be written as CSV or JSON Lines, and can be labelled from the
+//! threats the detector reported for each connection.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::core::types::{FrameType, SessionId, VstpError};
+
+use super::detector::AnomalyDetector;
+use super::models::{BaselineModel, Feature};
+use super::monitor::{ConnectionStats, SEQUENCE_FEATURE_COUNT};
+use super::patterns::{ThreatDetection, ThreatLevel};
+
+/// File layout of exported records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values with a header line
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// How records are exported
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Add `is_anomaly` and `threat_type` columns from the threat history
+    pub labels: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Csv,
+            labels: true,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn with_format(mut self, format: ExportFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+}
+
+/// Label columns of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Label {
+    /// 1 if any threat was reported, else 0
+    pub is_anomaly: u8,
+    /// [`AttackPattern::label`](super::patterns::AttackPattern::label) of
+    /// the most severe threat, or `normal`
+    pub threat_type: &'static str,
+}
+
+impl Label {
+    pub fn normal() -> Self {
+        Self {
+            is_anomaly: 0,
+            threat_type: "normal",
+        }
+    }
+
+    /// Label for a connection that raised `threats`, after the most severe
+    /// and then most confident of them
+    pub fn from_threats(threats: &[ThreatDetection]) -> Self {
+        let mut running = RunningLabel::default();
+        running.observe(threats);
+        running.label()
+    }
+
+    fn values(&self) -> [String; 2] {
+        [self.is_anomaly.to_string(), self.threat_type.to_string()]
+    }
+}
+
+/// Label of a connection kept up to date as it raises threats, without
+/// holding on to them
+#[derive(Debug, Clone, Copy, Default)]
+struct RunningLabel {
+    /// Level, confidence and pattern label of the most severe threat so far
+    worst: Option<(ThreatLevel, f64, &'static str)>,
+}
+
+impl RunningLabel {
+    fn observe(&mut self, threats: &[ThreatDetection]) {
+        for threat in threats {
+            let worse = self.worst.is_none_or(|(level, confidence, _)| {
+                threat
+                    .threat_level
+                    .cmp(&level)
+                    .then(threat.confidence.total_cmp(&confidence))
+                    .is_ge()
+            });
+            if worse {
+                self.worst = Some((threat.threat_level, threat.confidence, threat.pattern.label()));
+            }
+        }
+    }
+
+    fn label(&self) -> Label {
+        match self.worst {
+            Some((_, _, threat_type)) => Label {
+                is_anomaly: 1,
+                threat_type,
+            },
+            None => Label::normal(),
+        }
+    }
+}
+
+const LABEL_COLUMNS: [&str; 2] = ["is_anomaly", "threat_type"];
+
+/// A row of exported training data
+pub trait ExportRecord: Serialize {
+    /// Column names, in order, without the label columns
+    fn columns() -> &'static [&'static str];
+
+    /// Column values, in the order of [`columns`](Self::columns)
+    fn values(&self) -> Vec<String>;
+
+    fn label(&self) -> Option<Label>;
+}
+
+/// Turns records into lines of CSV or JSON Lines
+///
+/// The CSV header is written before the first record, with the label
+/// columns if that record is labelled.
+#[derive(Debug, Clone)]
+pub struct RecordEncoder {
+    format: ExportFormat,
+    header_written: bool,
+}
+
+impl RecordEncoder {
+    pub fn new(format: ExportFormat) -> Self {
+        Self {
+            format,
+            header_written: false,
+        }
+    }
+
+    /// An encoder for a file that already holds records, so writes no header
+    pub fn continuing(format: ExportFormat) -> Self {
+        Self {
+            format,
+            header_written: true,
+        }
+    }
+
+    /// Encode one record, with the header first if it is still due
+    pub fn encode<R: ExportRecord>(&mut self, record: &R) -> Result<Vec<u8>, VstpError> {
+        let mut out = Vec::new();
+        match self.format {
+            ExportFormat::Csv => {
+                let label = record.label();
+                if !self.header_written {
+                    let mut header: Vec<&str> = R::columns().to_vec();
+                    if label.is_some() {
+                        header.extend(LABEL_COLUMNS);
+                    }
+                    out.extend(header.join(",").as_bytes());
+                    out.push(b'\n');
+                    self.header_written = true;
+                }
+                let mut values = record.values();
+                if let Some(label) = label {
+                    values.extend(label.values());
+                }
+                let fields: Vec<String> = values.iter().map(|v| csv_field(v)).collect();
+                out.extend(fields.join(",").as_bytes());
+            }
+            ExportFormat::JsonLines => {
+                out = serde_json::to_vec(record).map_err(|e| {
+                    VstpError::Protocol(format!("Failed to encode record: {}", e))
+                })?;
+            }
+        }
+        out.push(b'\n');
+        Ok(out)
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn flag(value: bool) -> u8 {
+    u8::from(value)
+}
+
+/// One connection in the aggregated format
+///
+/// Counts and sizes cover the connection's lifetime; timing statistics
+/// cover its last [`TIMING_HISTORY`](super::monitor::TIMING_HISTORY) gaps.
+/// Z-scores are against the detector's global baseline and are 0 until it
+/// has samples; the baseline does not track gaps, so
+/// `inter_arrival_z_score` is always 0.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionRecord {
+    /// When the record was taken, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub session_id: SessionId,
+    pub frame_count: u64,
+    pub byte_count: u64,
+    pub connection_duration_seconds: f64,
+    pub frames_per_second: f64,
+    pub bytes_per_second: f64,
+    pub avg_frame_size: f64,
+    pub max_frame_size: usize,
+    pub min_frame_size: usize,
+    pub frame_size_std: f64,
+    pub frame_size_variance: f64,
+    pub hello_frames: u64,
+    pub data_frames: u64,
+    pub ack_frames: u64,
+    pub ping_frames: u64,
+    pub pong_frames: u64,
+    pub bye_frames: u64,
+    pub error_frames: u64,
+    pub data_frame_ratio: f64,
+    /// Share of frames that are neither data nor acknowledgements
+    pub control_frame_ratio: f64,
+    pub ack_frame_ratio: f64,
+    pub avg_inter_arrival_time_ms: f64,
+    pub min_inter_arrival_time_ms: f64,
+    pub max_inter_arrival_time_ms: f64,
+    pub inter_arrival_std_ms: f64,
+    pub inter_arrival_variance_ms: f64,
+    /// 1 minus the gaps' coefficient of variation, clamped to 0..1
+    pub timing_consistency_score: f64,
+    pub crc_errors: u64,
+    pub protocol_errors: u64,
+    pub suspicious_flags: u64,
+    pub total_errors: u64,
+    pub error_rate: f64,
+    pub crc_error_rate: f64,
+    pub protocol_error_rate: f64,
+    pub peer_ip: String,
+    pub peer_port: u16,
+    pub is_localhost: u8,
+    pub frame_size_z_score: f64,
+    pub fps_z_score: f64,
+    pub bps_z_score: f64,
+    pub inter_arrival_z_score: f64,
+    /// Positive burstiness; the three pattern scores add up to 1
+    pub burst_score: f64,
+    /// Negative burstiness, for arrivals more regular than random
+    pub steady_score: f64,
+    /// 1 minus the magnitude of burstiness, for random arrivals
+    pub irregular_score: f64,
+    pub connection_established: u8,
+    pub connection_closed_gracefully: u8,
+    pub has_ping_pong: u8,
+    pub uses_ack: u8,
+    #[serde(flatten)]
+    pub label: Option<Label>,
+}
+
+impl ConnectionRecord {
+    /// Record for a connection, scored against `baseline` if given
+    pub fn new(stats: &ConnectionStats, baseline: Option<&BaselineModel>) -> Self {
+        let count = |typ: FrameType| stats.frame_types.get(&typ).copied().unwrap_or(0);
+        let frames = stats.frame_count;
+        let ratio = |n: u64| if frames > 0 { n as f64 / frames as f64 } else { 0.0 };
+        let traffic = stats.features();
+        let gaps_ms = stats
+            .inter_arrival_times
+            .iter()
+            .map(|gap| gap.as_secs_f64() * 1000.0);
+        let min_gap = gaps_ms.clone().fold(f64::INFINITY, f64::min);
+        let max_gap = gaps_ms.fold(0.0, f64::max);
+        let z_score = |feature: Feature| baseline.map_or(0.0, |b| b.z_score(feature, stats));
+        let burstiness = traffic.burstiness.clamp(-1.0, 1.0);
+        let total_errors = stats.crc_errors + stats.protocol_errors + stats.suspicious_flags;
+        let (data, ack) = (count(FrameType::Data), count(FrameType::Ack));
+
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            session_id: stats.session_id,
+            frame_count: frames,
+            byte_count: stats.byte_count,
+            connection_duration_seconds: stats.get_connection_duration().as_secs_f64(),
+            frames_per_second: stats.get_frames_per_second(),
+            bytes_per_second: stats.get_bytes_per_second(),
+            avg_frame_size: stats.avg_frame_size,
+            max_frame_size: stats.max_frame_size,
+            min_frame_size: if frames > 0 { stats.min_frame_size } else { 0 },
+            frame_size_std: traffic.size_std,
+            frame_size_variance: traffic.size_std * traffic.size_std,
+            hello_frames: count(FrameType::Hello),
+            data_frames: data,
+            ack_frames: ack,
+            ping_frames: count(FrameType::Ping),
+            pong_frames: count(FrameType::Pong),
+            bye_frames: count(FrameType::Bye),
+            error_frames: count(FrameType::Err),
+            data_frame_ratio: ratio(data),
+            control_frame_ratio: ratio(frames - data - ack),
+            ack_frame_ratio: ratio(ack),
+            avg_inter_arrival_time_ms: traffic.gap_mean_ms,
+            min_inter_arrival_time_ms: if min_gap.is_finite() { min_gap } else { 0.0 },
+            max_inter_arrival_time_ms: max_gap,
+            inter_arrival_std_ms: traffic.gap_std_ms,
+            inter_arrival_variance_ms: traffic.gap_std_ms * traffic.gap_std_ms,
+            timing_consistency_score: if traffic.gap_count > 0 {
+                (1.0 - traffic.gap_variation()).clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            crc_errors: stats.crc_errors,
+            protocol_errors: stats.protocol_errors,
+            suspicious_flags: stats.suspicious_flags,
+            total_errors,
+            error_rate: ratio(total_errors),
+            crc_error_rate: ratio(stats.crc_errors),
+            protocol_error_rate: ratio(stats.protocol_errors),
+            peer_ip: stats.peer_addr.ip().to_string(),
+            peer_port: stats.peer_addr.port(),
+            is_localhost: flag(stats.peer_addr.ip().is_loopback()),
+            frame_size_z_score: z_score(Feature::FrameSize),
+            fps_z_score: z_score(Feature::FramesPerSecond),
+            bps_z_score: z_score(Feature::BytesPerSecond),
+            inter_arrival_z_score: 0.0,
+            burst_score: burstiness.max(0.0),
+            steady_score: (-burstiness).max(0.0),
+            irregular_score: 1.0 - burstiness.abs(),
+            connection_established: flag(count(FrameType::Hello) > 0),
+            connection_closed_gracefully: flag(count(FrameType::Bye) > 0),
+            has_ping_pong: flag(count(FrameType::Ping) > 0 && count(FrameType::Pong) > 0),
+            uses_ack: flag(ack > 0),
+            label: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.label = Some(label);
+        self
+    }
+}
+
+const CONNECTION_COLUMNS: &[&str] = &[
+    "timestamp",
+    "session_id",
+    "frame_count",
+    "byte_count",
+    "connection_duration_seconds",
+    "frames_per_second",
+    "bytes_per_second",
+    "avg_frame_size",
+    "max_frame_size",
+    "min_frame_size",
+    "frame_size_std",
+    "frame_size_variance",
+    "hello_frames",
+    "data_frames",
+    "ack_frames",
+    "ping_frames",
+    "pong_frames",
+    "bye_frames",
+    "error_frames",
+    "data_frame_ratio",
+    "control_frame_ratio",
+    "ack_frame_ratio",
+    "avg_inter_arrival_time_ms",
+    "min_inter_arrival_time_ms",
+    "max_inter_arrival_time_ms",
+    "inter_arrival_std_ms",
+    "inter_arrival_variance_ms",
+    "timing_consistency_score",
+    "crc_errors",
+    "protocol_errors",
+    "suspicious_flags",
+    "total_errors",
+    "error_rate",
+    "crc_error_rate",
+    "protocol_error_rate",
+    "peer_ip",
+    "peer_port",
+    "is_localhost",
+    "frame_size_z_score",
+    "fps_z_score",
+    "bps_z_score",
+    "inter_arrival_z_score",
+    "burst_score",
+    "steady_score",
+    "irregular_score",
+    "connection_established",
+    "connection_closed_gracefully",
+    "has_ping_pong",
+    "uses_ack",
+];
+
+impl ExportRecord for ConnectionRecord {
+    fn columns() -> &'static [&'static str] {
+        CONNECTION_COLUMNS
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.timestamp.to_string(),
+            self.session_id.to_string(),
+            self.frame_count.to_string(),
+            self.byte_count.to_string(),
+            self.connection_duration_seconds.to_string(),
+            self.frames_per_second.to_string(),
+            self.bytes_per_second.to_string(),
+            self.avg_frame_size.to_string(),
+            self.max_frame_size.to_string(),
+            self.min_frame_size.to_string(),
+            self.frame_size_std.to_string(),
+            self.frame_size_variance.to_string(),
+            self.hello_frames.to_string(),
+            self.data_frames.to_string(),
+            self.ack_frames.to_string(),
+            self.ping_frames.to_string(),
+            self.pong_frames.to_string(),
+            self.bye_frames.to_string(),
+            self.error_frames.to_string(),
+            self.data_frame_ratio.to_string(),
+            self.control_frame_ratio.to_string(),
+            self.ack_frame_ratio.to_string(),
+            self.avg_inter_arrival_time_ms.to_string(),
+            self.min_inter_arrival_time_ms.to_string(),
+            self.max_inter_arrival_time_ms.to_string(),
+            self.inter_arrival_std_ms.to_string(),
+            self.inter_arrival_variance_ms.to_string(),
+            self.timing_consistency_score.to_string(),
+            self.crc_errors.to_string(),
+            self.protocol_errors.to_string(),
+            self.suspicious_flags.to_string(),
+            self.total_errors.to_string(),
+            self.error_rate.to_string(),
+            self.crc_error_rate.to_string(),
+            self.protocol_error_rate.to_string(),
+            self.peer_ip.clone(),
+            self.peer_port.to_string(),
+            self.is_localhost.to_string(),
+            self.frame_size_z_score.to_string(),
+            self.fps_z_score.to_string(),
+            self.bps_z_score.to_string(),
+            self.inter_arrival_z_score.to_string(),
+            self.burst_score.to_string(),
+            self.steady_score.to_string(),
+            self.irregular_score.to_string(),
+            self.connection_established.to_string(),
+            self.connection_closed_gracefully.to_string(),
+            self.has_ping_pong.to_string(),
+            self.uses_ack.to_string(),
+        ]
+    }
+
+    fn label(&self) -> Option<Label> {
+        self.label
+    }
+}
+
+/// One frame in the sequence format
+///
+/// The features are those of
+/// [`FeatureVector::sequence_row`](super::monitor::FeatureVector::sequence_row),
+/// so a model trained on them sees the same values when run on frame
+/// windows with the `onnx` feature.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameRecord {
+    pub session_id: SessionId,
+    /// Position of the frame in its connection, from 0
+    pub sequence_index: u64,
+    /// Milliseconds since the connection's first frame
+    pub timestamp_ms: u64,
+    pub frame_type: u8,
+    pub frame_size: u64,
+    pub payload_size: u64,
+    pub has_flags: u8,
+    pub header_count: u64,
+    pub time_since_last_frame_ms: f64,
+    pub time_since_session_start_ms: f64,
+    pub cumulative_frame_count: u64,
+    pub cumulative_byte_count: u64,
+    pub avg_frame_size_so_far: f64,
+    pub frames_per_second_so_far: f64,
+    pub bytes_per_second_so_far: f64,
+    pub data_frame_ratio_so_far: f64,
+    /// 1 if a CRC error was recorded since the previous frame
+    pub is_crc_error: u8,
+    /// 1 if a protocol error was recorded since the previous frame
+    pub is_protocol_error: u8,
+    pub cumulative_errors: u64,
+    pub error_rate_so_far: f64,
+    #[serde(flatten)]
+    pub label: Option<Label>,
+}
+
+impl FrameRecord {
+    /// Record for the connection's latest frame, or `None` before its first
+    pub fn new(stats: &ConnectionStats, sequence_index: u64) -> Option<Self> {
+        let row: [f64; SEQUENCE_FEATURE_COUNT] = stats.feature_vector().sequence_row()?;
+        let [frame_type, frame_size, payload_size, gap_ms, frames, bytes, avg_size, fps, bps, data_ratio, errors, error_rate, has_flags, header_count, age_ms] =
+            row;
+        Some(Self {
+            session_id: stats.session_id,
+            sequence_index,
+            timestamp_ms: age_ms as u64,
+            frame_type: frame_type as u8,
+            frame_size: frame_size as u64,
+            payload_size: payload_size as u64,
+            has_flags: has_flags as u8,
+            header_count: header_count as u64,
+            time_since_last_frame_ms: gap_ms,
+            time_since_session_start_ms: age_ms,
+            cumulative_frame_count: frames as u64,
+            cumulative_byte_count: bytes as u64,
+            avg_frame_size_so_far: avg_size,
+            frames_per_second_so_far: fps,
+            bytes_per_second_so_far: bps,
+            data_frame_ratio_so_far: data_ratio,
+            is_crc_error: 0,
+            is_protocol_error: 0,
+            cumulative_errors: errors as u64,
+            error_rate_so_far: error_rate,
+            label: None,
+        })
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.label = Some(label);
+        self
+    }
+}
+
+const FRAME_COLUMNS: &[&str] = &[
+    "session_id",
+    "sequence_index",
+    "timestamp_ms",
+    "frame_type",
+    "frame_size",
+    "payload_size",
+    "has_flags",
+    "header_count",
+    "time_since_last_frame_ms",
+    "time_since_session_start_ms",
+    "cumulative_frame_count",
+    "cumulative_byte_count",
+    "avg_frame_size_so_far",
+    "frames_per_second_so_far",
+    "bytes_per_second_so_far",
+    "data_frame_ratio_so_far",
+    "is_crc_error",
+    "is_protocol_error",
+    "cumulative_errors",
+    "error_rate_so_far",
+];
+
+impl ExportRecord for FrameRecord {
+    fn columns() -> &'static [&'static str] {
+        FRAME_COLUMNS
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.session_id.to_string(),
+            self.sequence_index.to_string(),
+            self.timestamp_ms.to_string(),
+            self.frame_type.to_string(),
+            self.frame_size.to_string(),
+            self.payload_size.to_string(),
+            self.has_flags.to_string(),
+            self.header_count.to_string(),
+            self.time_since_last_frame_ms.to_string(),
+            self.time_since_session_start_ms.to_string(),
+            self.cumulative_frame_count.to_string(),
+            self.cumulative_byte_count.to_string(),
+            self.avg_frame_size_so_far.to_string(),
+            self.frames_per_second_so_far.to_string(),
+            self.bytes_per_second_so_far.to_string(),
+            self.data_frame_ratio_so_far.to_string(),
+            self.is_crc_error.to_string(),
+            self.is_protocol_error.to_string(),
+            self.cumulative_errors.to_string(),
+            self.error_rate_so_far.to_string(),
+        ]
+    }
+
+    fn label(&self) -> Option<Label> {
+        self.label
+    }
+}
+
+/// Open `path` for appending, with an encoder that writes the CSV header
+/// only if the file is empty
+async fn open_append(
+    path: &Path,
+    format: ExportFormat,
+) -> Result<(tokio::fs::File, RecordEncoder), VstpError> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let encoder = if file.metadata().await?.len() == 0 {
+        RecordEncoder::new(format)
+    } else {
+        RecordEncoder::continuing(format)
+    };
+    Ok((file, encoder))
+}
+
+/// Write `records` to `path`, replacing the file or appending to it
+pub(crate) async fn write_records<R: ExportRecord>(
+    path: &Path,
+    records: &[R],
+    format: ExportFormat,
+    append: bool,
+) -> Result<(), VstpError> {
+    let (file, mut encoder) = if append {
+        open_append(path, format).await?
+    } else {
+        (tokio::fs::File::create(path).await?, RecordEncoder::new(format))
+    };
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        writer.write_all(&encoder.encode(record)?).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct FrameLogSession {
+    next_index: u64,
+    crc_errors: u64,
+    protocol_errors: u64,
+    /// Label from the threats the connection has raised so far
+    label: RunningLabel,
+}
+
+struct FrameLogState {
+    writer: BufWriter<tokio::fs::File>,
+    encoder: RecordEncoder,
+    sessions: HashMap<SessionId, FrameLogSession>,
+}
+
+/// Appends a [`FrameRecord`] for every frame the detector analyzes
+///
+/// Rows are labelled with the threats their connection had raised up to and
+/// including that frame, so early frames of a connection that later turns
+/// malicious are labelled normal. Writes are buffered and flushed when a
+/// session ends or on [`flush`](Self::flush).
+pub struct FrameLog {
+    path: PathBuf,
+    labels: bool,
+    state: tokio::sync::Mutex<FrameLogState>,
+}
+
+impl FrameLog {
+    /// Open or create the log at `path`
+    pub async fn open(path: impl AsRef<Path>, options: ExportOptions) -> Result<Self, VstpError> {
+        let path = path.as_ref().to_path_buf();
+        let (file, encoder) = open_append(&path, options.format).await?;
+        Ok(Self {
+            path,
+            labels: options.labels,
+            state: tokio::sync::Mutex::new(FrameLogState {
+                writer: BufWriter::new(file),
+                encoder,
+                sessions: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Log a connection's latest frame and the threats it raised
+    pub async fn record(
+        &self,
+        stats: &ConnectionStats,
+        threats: &[ThreatDetection],
+    ) -> Result<(), VstpError> {
+        let mut state = self.state.lock().await;
+        let session = state.sessions.entry(stats.session_id).or_default();
+        let Some(mut record) = FrameRecord::new(stats, session.next_index) else {
+            return Ok(());
+        };
+        session.next_index += 1;
+        record.is_crc_error = flag(stats.crc_errors > session.crc_errors);
+        record.is_protocol_error = flag(stats.protocol_errors > session.protocol_errors);
+        session.crc_errors = stats.crc_errors;
+        session.protocol_errors = stats.protocol_errors;
+        if self.labels {
+            session.label.observe(threats);
+            record = record.with_label(session.label.label());
+        }
+
+        let line = state.encoder.encode(&record)?;
+        state.writer.write_all(&line).await?;
+        Ok(())
+    }
+
+    /// Forget a closed connection and flush what was logged
+    pub async fn end_session(&self, session_id: SessionId) -> Result<(), VstpError> {
+        let mut state = self.state.lock().await;
+        state.sessions.remove(&session_id);
+        state.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Write buffered records to the file
+    pub async fn flush(&self) -> Result<(), VstpError> {
+        self.state.lock().await.writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Appends a snapshot of every connection the detector tracks to a file at
+/// a fixed interval, until dropped
+pub struct Snapshots {
+    path: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl Snapshots {
+    /// Start taking snapshots, the first one after `every`
+    pub fn start(
+        detector: Arc<AnomalyDetector>,
+        path: impl AsRef<Path>,
+        options: ExportOptions,
+        every: Duration,
+    ) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let task_path = path.clone();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+            loop {
+                interval.tick().await;
+                let records = detector.connection_records(options.labels).await;
+                if records.is_empty() {
+                    continue;
+                }
+                if let Err(e) = write_records(&task_path, &records, options.format, true).await {
+                    error!("Failed to write snapshot to {}: {}", task_path.display(), e);
+                }
+            }
+        });
+        info!("Writing connection snapshots to {} every {:?}", path.display(), every);
+        Self { path, task }
+    }
+
+    /// Path of the snapshot file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Snapshots {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Frame;
+    use crate::security::ai::patterns::AttackPattern;
+    use std::time::Instant;
+
+    fn stats() -> ConnectionStats {
+        let mut stats = ConnectionStats::new(7, "127.0.0.1:4000".parse().unwrap());
+        let start = Instant::now();
+        stats.record_frame_at(&Frame::new(FrameType::Hello), 20, start);
+        for i in 1..=4 {
+            let frame = Frame::new(FrameType::Data).with_payload(vec![0; 100]);
+            stats.record_frame_at(&frame, 120, start + Duration::from_millis(50 * i));
+        }
+        stats
+    }
+
+    fn threat(pattern: AttackPattern, confidence: f64) -> ThreatDetection {
+        ThreatDetection::new(
+            pattern,
+            ThreatLevel::from_score(confidence),
+            confidence,
+            "test".to_string(),
+        )
+    }
+
+    fn json_keys(value: &impl Serialize) -> Vec<String> {
+        match serde_json::to_value(value).unwrap() {
+            serde_json::Value::Object(map) => {
+                let mut keys: Vec<String> = map.keys().cloned().collect();
+                keys.sort();
+                keys
+            }
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn test_connection_record() {
+        let record = ConnectionRecord::new(&stats(), None);
+        assert_eq!(record.frame_count, 5);
+        assert_eq!((record.hello_frames, record.data_frames), (1, 4));
+        assert_eq!((record.min_frame_size, record.max_frame_size), (20, 120));
+        assert!((record.data_frame_ratio - 0.8).abs() < 1e-9);
+        assert!((record.control_frame_ratio - 0.2).abs() < 1e-9);
+        assert!((record.avg_inter_arrival_time_ms - 50.0).abs() < 1e-6);
+        assert!((record.timing_consistency_score - 1.0).abs() < 1e-6);
+        assert_eq!((record.is_localhost, record.connection_established), (1, 1));
+        let patterns = record.burst_score + record.steady_score + record.irregular_score;
+        assert!((patterns - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_csv_columns_match_json_fields() {
+        let record = ConnectionRecord::new(&stats(), None);
+        assert_eq!(record.values().len(), CONNECTION_COLUMNS.len());
+        let mut sorted: Vec<String> = CONNECTION_COLUMNS.iter().map(|c| c.to_string()).collect();
+        sorted.sort();
+        assert_eq!(json_keys(&record), sorted);
+
+        let frame = FrameRecord::new(&stats(), 0).unwrap();
+        assert_eq!(frame.values().len(), FRAME_COLUMNS.len());
+        let mut sorted: Vec<String> = FRAME_COLUMNS.iter().map(|c| c.to_string()).collect();
+        sorted.sort();
+        assert_eq!(json_keys(&frame), sorted);
+    }
+
+    #[test]
+    fn test_encoder_writes_header_once_and_labels() {
+        let label = Label::from_threats(&[
+            threat(AttackPattern::AnomalousTraffic, 0.6),
+            threat(AttackPattern::ConnectionFlood, 0.95),
+        ]);
+        assert_eq!(label.threat_type, "connection_flood");
+        assert_eq!(Label::from_threats(&[]), Label::normal());
+
+        // The running label of a connection matches labelling all its threats
+        let mut running = RunningLabel::default();
+        running.observe(&[threat(AttackPattern::ConnectionFlood, 0.95)]);
+        running.observe(&[threat(AttackPattern::AnomalousTraffic, 0.6)]);
+        running.observe(&[]);
+        assert_eq!(running.label(), label);
+
+        let record = ConnectionRecord::new(&stats(), None).with_label(label);
+        let mut encoder = RecordEncoder::new(ExportFormat::Csv);
+        let first = String::from_utf8(encoder.encode(&record).unwrap()).unwrap();
+        let second = String::from_utf8(encoder.encode(&record).unwrap()).unwrap();
+        let lines: Vec<&str> = first.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("timestamp,session_id,"));
+        assert!(lines[0].ends_with(",uses_ack,is_anomaly,threat_type"));
+        assert!(lines[1].ends_with(",1,connection_flood"));
+        assert_eq!(second, format!("{}\n", lines[1]));
+
+        let mut encoder = RecordEncoder::new(ExportFormat::JsonLines);
+        let line = encoder.encode(&record).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(value["threat_type"], "connection_flood");
+        assert_eq!(value["frame_count"], 5);
+    }
+
+    #[test]
+    fn test_frame_record_follows_sequence_row() {
+        let record = FrameRecord::new(&stats(), 4).unwrap();
+        assert_eq!(record.frame_type, FrameType::Data as u8);
+        assert_eq!(record.cumulative_frame_count, 5);
+        assert_eq!(record.cumulative_byte_count, 500);
+        assert_eq!(record.timestamp_ms, 200);
+        assert!((record.time_since_last_frame_ms - 50.0).abs() < 1e-6);
+        assert!(record.label.is_none());
+
+        let empty = ConnectionStats::new(8, "127.0.0.1:1".parse().unwrap());
+        assert!(FrameRecord::new(&empty, 0).is_none());
+    }
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("127.0.0.1"), "127.0.0.1");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
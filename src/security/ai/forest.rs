@@ -0,0 +1,362 @@
+//! Isolation Forest anomaly detection
+//!
+//! An Isolation Forest (Liu, Ting and Zhou, 2008) isolates points by
+//! splitting on random features at random values. Unusual points are
+//! separated from the rest in few splits, so the average depth at which a
+//! point is isolated across many random trees measures how anomalous it is.
+//!
+//! The forest here is trained on a sliding window of recent samples and
+//! retrained periodically, so what counts as normal follows the traffic.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::core::types::SessionId;
+
+use super::ensemble::DetectionModel;
+use super::models::SampleThrottle;
+use super::monitor::FeatureVector;
+use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
+
+/// Euler-Mascheroni constant
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+/// Isolation Forest settings
+#[derive(Debug, Clone)]
+pub struct IsolationForestConfig {
+    /// Number of trees
+    pub trees: usize,
+    /// Samples each tree is built from
+    pub sample_size: usize,
+    /// Recent samples the forest is trained on
+    pub window: usize,
+    /// Samples learned between retraining runs
+    pub retrain_every: usize,
+    /// Anomaly score, between 0 and 1, above which a connection is reported
+    pub threshold: f64,
+    /// Shortest time between two samples taken from the same connection
+    pub sample_interval: Duration,
+}
+
+impl Default for IsolationForestConfig {
+    fn default() -> Self {
+        Self {
+            trees: 100,
+            sample_size: 256,
+            window: 2048,
+            retrain_every: 256,
+            threshold: 0.6,
+            sample_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Split {
+        feature: usize,
+        value: f64,
+        left: usize,
+        right: usize,
+    },
+    Leaf {
+        size: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn build(samples: &mut [Vec<f64>], max_depth: usize, rng: &mut StdRng) -> Self {
+        let mut tree = Tree { nodes: Vec::new() };
+        tree.grow(samples, 0, max_depth, rng);
+        tree
+    }
+
+    /// Grow a subtree over `samples`, returning the index of its root
+    fn grow(&mut self, samples: &mut [Vec<f64>], depth: usize, max_depth: usize, rng: &mut StdRng) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node::Leaf { size: samples.len() });
+        if depth >= max_depth || samples.len() <= 1 {
+            return index;
+        }
+
+        // Only features that still vary can separate the samples
+        let dims = samples[0].len();
+        let spread: Vec<(usize, f64, f64)> = (0..dims)
+            .filter_map(|feature| {
+                let (min, max) = samples.iter().fold((f64::MAX, f64::MIN), |(lo, hi), s| {
+                    (lo.min(s[feature]), hi.max(s[feature]))
+                });
+                (max > min).then_some((feature, min, max))
+            })
+            .collect();
+        if spread.is_empty() {
+            return index;
+        }
+        let (feature, min, max) = spread[rng.gen_range(0..spread.len())];
+        let value = rng.gen_range(min..max);
+
+        let mut split = 0;
+        for i in 0..samples.len() {
+            if samples[i][feature] < value {
+                samples.swap(i, split);
+                split += 1;
+            }
+        }
+        let (below, above) = samples.split_at_mut(split);
+        let left = self.grow(below, depth + 1, max_depth, rng);
+        let right = self.grow(above, depth + 1, max_depth, rng);
+        self.nodes[index] = Node::Split {
+            feature,
+            value,
+            left,
+            right,
+        };
+        index
+    }
+
+    /// Depth at which `point` is isolated, with the expected remaining depth
+    /// added for leaves holding several samples
+    fn path_length(&self, point: &[f64]) -> f64 {
+        let mut index = 0;
+        let mut depth = 0.0;
+        loop {
+            match &self.nodes[index] {
+                Node::Split {
+                    feature,
+                    value,
+                    left,
+                    right,
+                } => {
+                    index = if point[*feature] < *value { *left } else { *right };
+                    depth += 1.0;
+                }
+                Node::Leaf { size } => return depth + average_path_length(*size),
+            }
+        }
+    }
+}
+
+/// Average path length of an unsuccessful search in a binary search tree of
+/// `n` items, which normalizes isolation depths
+fn average_path_length(n: usize) -> f64 {
+    match n {
+        0 | 1 => 0.0,
+        2 => 1.0,
+        n => {
+            let n = n as f64;
+            2.0 * ((n - 1.0).ln() + EULER_GAMMA) - 2.0 * (n - 1.0) / n
+        }
+    }
+}
+
+/// Isolation Forest over recent feature vectors
+#[derive(Debug, Clone)]
+pub struct IsolationForest {
+    config: IsolationForestConfig,
+    trees: Vec<Tree>,
+    trained_on: usize,
+    window: VecDeque<Vec<f64>>,
+    since_training: usize,
+    throttle: SampleThrottle,
+    rng: StdRng,
+}
+
+impl IsolationForest {
+    pub fn new(config: IsolationForestConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    /// A forest whose random choices are fixed by `seed`, for reproducible
+    /// experiments
+    pub fn with_seed(config: IsolationForestConfig, seed: u64) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: IsolationForestConfig, rng: StdRng) -> Self {
+        Self {
+            throttle: SampleThrottle::new(config.sample_interval),
+            config,
+            trees: Vec::new(),
+            trained_on: 0,
+            window: VecDeque::new(),
+            since_training: 0,
+            rng,
+        }
+    }
+
+    pub fn config(&self) -> &IsolationForestConfig {
+        &self.config
+    }
+
+    /// Whether the forest has been trained and can score
+    pub fn is_trained(&self) -> bool {
+        !self.trees.is_empty()
+    }
+
+    /// Build the forest from `samples`, which must all have the same length
+    pub fn fit(&mut self, samples: &[Vec<f64>]) {
+        if samples.is_empty() {
+            return;
+        }
+        let sample_size = self.config.sample_size.clamp(1, samples.len());
+        let max_depth = (sample_size as f64).log2().ceil() as usize;
+        self.trees = (0..self.config.trees.max(1))
+            .map(|_| {
+                let mut subsample: Vec<Vec<f64>> =
+                    rand::seq::index::sample(&mut self.rng, samples.len(), sample_size)
+                        .into_iter()
+                        .map(|i| samples[i].clone())
+                        .collect();
+                Tree::build(&mut subsample, max_depth, &mut self.rng)
+            })
+            .collect();
+        self.trained_on = sample_size;
+    }
+
+    /// Add a sample to the training window, retraining when due
+    pub fn add_sample(&mut self, sample: Vec<f64>) {
+        self.window.push_back(sample);
+        while self.window.len() > self.config.window.max(1) {
+            self.window.pop_front();
+        }
+        self.since_training += 1;
+
+        let enough = self.window.len() >= self.config.sample_size.min(self.config.window);
+        let due = !self.is_trained() || self.since_training >= self.config.retrain_every;
+        if enough && due {
+            let samples: Vec<Vec<f64>> = self.window.iter().cloned().collect();
+            self.fit(&samples);
+            self.since_training = 0;
+        }
+    }
+
+    /// Anomaly score of a point, from near 0 for clearly normal to near 1
+    /// for clearly anomalous, or `None` before training
+    ///
+    /// Points scoring around 0.5 are indistinguishable from the training
+    /// data.
+    pub fn anomaly_score(&self, point: &[f64]) -> Option<f64> {
+        if !self.is_trained() {
+            return None;
+        }
+        let mean_depth = self
+            .trees
+            .iter()
+            .map(|tree| tree.path_length(point))
+            .sum::<f64>()
+            / self.trees.len() as f64;
+        let normalizer = average_path_length(self.trained_on).max(1.0);
+        Some(2f64.powf(-mean_depth / normalizer))
+    }
+}
+
+impl Default for IsolationForest {
+    fn default() -> Self {
+        Self::new(IsolationForestConfig::default())
+    }
+}
+
+impl DetectionModel for IsolationForest {
+    fn name(&self) -> &str {
+        "isolation_forest"
+    }
+
+    fn learn(&mut self, features: &FeatureVector) {
+        if self.throttle.admit(features.session_id, features.observed_at) {
+            self.add_sample(features.model_inputs().to_vec());
+        }
+    }
+
+    fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        let Some(score) = self.anomaly_score(&features.model_inputs()) else {
+            return Vec::new();
+        };
+        let threshold = self.config.threshold.min(0.99);
+        if score < threshold {
+            return Vec::new();
+        }
+        // The threshold maps to 0.5 confidence and a score of 1 to full
+        let confidence = 0.5 + 0.5 * (score - threshold) / (1.0 - threshold);
+        vec![ThreatDetection::new(
+            AttackPattern::AnomalousTraffic,
+            ThreatLevel::from_score(confidence),
+            confidence,
+            format!("Traffic isolated as an outlier (anomaly score {:.2})", score),
+        )
+        .with_indicator(format!("Isolation forest score: {:.3}", score))]
+    }
+
+    fn end_session(&mut self, session_id: SessionId) {
+        self.throttle.remove_session(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points around `(x, 0)`, denser towards the centre
+    fn cluster_at(rng: &mut StdRng, x: f64, n: usize) -> Vec<Vec<f64>> {
+        let mut near = |centre: f64| centre + (0..4).map(|_| rng.gen_range(-0.5..0.5)).sum::<f64>();
+        (0..n).map(|_| vec![near(x), near(0.0)]).collect()
+    }
+
+    fn cluster(rng: &mut StdRng, n: usize) -> Vec<Vec<f64>> {
+        cluster_at(rng, 5.0, n)
+    }
+
+    #[test]
+    fn test_outliers_score_higher() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut forest = IsolationForest::with_seed(IsolationForestConfig::default(), 1);
+        assert_eq!(forest.anomaly_score(&[5.0, 0.0]), None);
+        forest.fit(&cluster(&mut rng, 500));
+
+        let inlier = forest.anomaly_score(&[5.0, 0.0]).unwrap();
+        let outlier = forest.anomaly_score(&[20.0, 8.0]).unwrap();
+        assert!(inlier < 0.5, "{}", inlier);
+        assert!(outlier > 0.6, "{}", outlier);
+    }
+
+    #[test]
+    fn test_window_trains_and_retrains() {
+        let config = IsolationForestConfig {
+            trees: 20,
+            sample_size: 64,
+            window: 128,
+            retrain_every: 32,
+            ..Default::default()
+        };
+        let mut forest = IsolationForest::with_seed(config, 2);
+        let mut rng = StdRng::seed_from_u64(3);
+        for sample in cluster(&mut rng, 63) {
+            forest.add_sample(sample);
+        }
+        assert!(!forest.is_trained());
+        forest.add_sample(vec![5.0, 0.0]);
+        assert!(forest.is_trained());
+
+        // Once the window only holds the new regime, it is normal
+        for sample in cluster_at(&mut rng, 100.0, 128) {
+            forest.add_sample(sample);
+        }
+        let new = forest.anomaly_score(&[100.0, 0.0]).unwrap();
+        let old = forest.anomaly_score(&[5.0, 0.0]).unwrap();
+        assert!(new < 0.5 && old > new + 0.1, "{} vs {}", new, old);
+    }
+
+    #[test]
+    fn test_average_path_length() {
+        assert_eq!(average_path_length(1), 0.0);
+        assert_eq!(average_path_length(2), 1.0);
+        // c(256) from the original paper
+        assert!((average_path_length(256) - 10.24).abs() < 0.01);
+    }
+}
@@ -0,0 +1,384 @@
+//! Sharing bans between nodes
+//!
+//! A [`GossipThreatStore`] wraps another store and sends every ban it
+//! records, or lifts, to its peer nodes as VSTP datagrams. A node applies
+//! the bans it hears about to its blocklist and passes them on to its own
+//! peers, so a client banned by one server is refused by the whole cluster.
+//!
+//! Messages are signed with a [`FrameKeyring`] shared by the cluster;
+//! unsigned or forged messages are dropped. Each message carries the time its
+//! origin issued it, and messages older than [`GossipConfig::max_age`] are
+//! dropped too, so a captured ban or unban cannot be replayed later to undo
+//! or repeat it. Node clocks must agree to within that window. Threat history
+//! is not shared.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::core::types::{Frame, FrameType, SessionId, VstpError};
+use crate::security::blocklist::{BanRecord, Blocklist, IpNet};
+use crate::security::mac::FrameKeyring;
+use crate::transport::udp::VstpUdpClient;
+
+use super::patterns::ThreatDetection;
+use super::store::ThreatStore;
+
+/// Header marking a frame as a gossip message
+pub const GOSSIP_HEADER: &str = "gossip";
+
+/// Header naming the key a gossip message was signed with
+pub const GOSSIP_KEY_HEADER: &str = "gossip-key";
+
+/// Header carrying the hex-encoded tag of a gossip message
+pub const GOSSIP_MAC_HEADER: &str = "gossip-mac";
+
+/// Number of recent messages remembered to stop forwarding loops
+const SEEN_CAPACITY: usize = 4096;
+
+/// Gossip node settings
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// Local address to send and receive gossip on
+    pub bind: String,
+    /// Nodes to send bans to
+    pub peers: Vec<SocketAddr>,
+    /// Cluster keys used to sign and verify messages
+    pub keyring: FrameKeyring,
+    /// How many times a message is passed on after leaving its origin
+    pub max_hops: u8,
+    /// How long after it was issued a message is still accepted
+    pub max_age: Duration,
+}
+
+impl GossipConfig {
+    /// Gossip on `bind` with the cluster keyring, with no peers yet
+    pub fn new(bind: impl Into<String>, keyring: FrameKeyring) -> Self {
+        Self {
+            bind: bind.into(),
+            peers: Vec::new(),
+            keyring,
+            max_hops: 3,
+            max_age: Duration::from_secs(60),
+        }
+    }
+
+    /// Add a node to send bans to
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peers.push(peer);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Update {
+    Ban(BanRecord),
+    Unban { net: IpNet },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Message {
+    origin: u64,
+    id: u64,
+    /// When the origin sent the message, in milliseconds since the Unix epoch
+    issued_at: u64,
+    hops: u8,
+    update: Update,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Whether a message issued at `issued_at` is within `max_age` of `now`,
+/// allowing the same skew for clocks that run ahead
+fn is_fresh(issued_at: u64, now: u64, max_age: Duration) -> bool {
+    issued_at.abs_diff(now) <= max_age.as_millis() as u64
+}
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<(u64, u64)>,
+    order: VecDeque<(u64, u64)>,
+}
+
+impl Seen {
+    /// Remember a message, returning `false` if it was already seen
+    fn insert(&mut self, key: (u64, u64)) -> bool {
+        if !self.ids.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(old) = self.order.pop_front() {
+                self.ids.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+struct Node {
+    origin: u64,
+    next_id: AtomicU64,
+    peers: RwLock<Vec<SocketAddr>>,
+    keyring: FrameKeyring,
+    max_hops: u8,
+    max_age: Duration,
+    inner: Arc<dyn ThreatStore>,
+    blocklist: Blocklist,
+    seen: Mutex<Seen>,
+}
+
+impl Node {
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn mark_seen(&self, message: &Message) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.insert((message.origin, message.id))
+    }
+
+    fn encode(&self, message: &Message) -> Result<Frame, VstpError> {
+        let payload = serde_json::to_vec(message)
+            .map_err(|e| VstpError::Protocol(format!("Failed to encode gossip: {}", e)))?;
+        let (key_id, tag) = self.keyring.sign(&payload);
+        Ok(Frame::new(FrameType::Data)
+            .with_header(GOSSIP_HEADER, "1")
+            .with_header(GOSSIP_KEY_HEADER, &key_id)
+            .with_header(GOSSIP_MAC_HEADER, &hex::encode(tag))
+            .with_payload(payload))
+    }
+
+    fn decode(&self, frame: &Frame) -> Option<Message> {
+        frame.get_header(GOSSIP_HEADER)?;
+        let verified = match (
+            frame.get_header(GOSSIP_KEY_HEADER),
+            frame.get_header(GOSSIP_MAC_HEADER).and_then(|t| hex::decode(t).ok()),
+        ) {
+            (Some(key_id), Some(tag)) => self.keyring.verify(key_id, &frame.payload, &tag),
+            _ => false,
+        };
+        if !verified {
+            return None;
+        }
+        serde_json::from_slice(&frame.payload).ok()
+    }
+
+    async fn send(&self, socket: &VstpUdpClient, message: &Message, skip: Option<SocketAddr>) {
+        let frame = match self.encode(message) {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
+        for peer in self.peers().into_iter().filter(|p| Some(*p) != skip) {
+            if let Err(e) = socket.send(frame.clone(), peer).await {
+                debug!("Failed to gossip to {}: {}", peer, e);
+            }
+        }
+    }
+
+    async fn apply(&self, update: &Update) {
+        let result = match update {
+            Update::Ban(ban) => {
+                if !self.blocklist.apply(ban) {
+                    return;
+                }
+                info!("Applied gossiped ban on {}: {}", ban.net, ban.reason);
+                self.inner.record_ban(ban).await
+            }
+            Update::Unban { net } => {
+                if !self.blocklist.unblock(*net) {
+                    return;
+                }
+                info!("Lifted ban on {} by gossip", net);
+                self.inner.lift_ban(*net).await
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to store gossiped update: {}", e);
+        }
+    }
+
+    async fn run(self: Arc<Self>, mut socket: VstpUdpClient, mut outgoing: mpsc::UnboundedReceiver<Update>) {
+        loop {
+            tokio::select! {
+                update = outgoing.recv() => {
+                    let Some(update) = update else {
+                        return;
+                    };
+                    let message = Message {
+                        origin: self.origin,
+                        id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                        issued_at: now_millis(),
+                        hops: self.max_hops,
+                        update,
+                    };
+                    self.mark_seen(&message);
+                    self.send(&socket, &message, None).await;
+                }
+                received = socket.recv() => {
+                    let (frame, from) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            debug!("Gossip receive error: {}", e);
+                            continue;
+                        }
+                    };
+                    let Some(mut message) = self.decode(&frame) else {
+                        warn!("Dropping unauthenticated gossip from {}", from);
+                        continue;
+                    };
+                    if !is_fresh(message.issued_at, now_millis(), self.max_age) {
+                        warn!("Dropping stale gossip from {}", from);
+                        continue;
+                    }
+                    if !self.mark_seen(&message) {
+                        continue;
+                    }
+                    self.apply(&message.update).await;
+                    if message.hops > 0 {
+                        message.hops -= 1;
+                        self.send(&socket, &message, Some(from)).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Store that shares bans with other nodes
+///
+/// Threats and bans are kept in the wrapped store. Bans received from peers
+/// are applied to `blocklist` and recorded in the wrapped store too, so give
+/// the detector the same blocklist with
+/// [`AnomalyDetector::with_blocklist`](super::AnomalyDetector::with_blocklist).
+pub struct GossipThreatStore {
+    node: Arc<Node>,
+    outgoing: mpsc::UnboundedSender<Update>,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl GossipThreatStore {
+    /// Bind the gossip socket and start exchanging bans
+    pub async fn start(
+        inner: Arc<dyn ThreatStore>,
+        blocklist: Blocklist,
+        config: GossipConfig,
+    ) -> Result<Self, VstpError> {
+        let socket = VstpUdpClient::bind(&config.bind).await?;
+        let local_addr = socket.local_addr()?;
+        let node = Arc::new(Node {
+            origin: rand::random(),
+            next_id: AtomicU64::new(0),
+            peers: RwLock::new(config.peers),
+            keyring: config.keyring,
+            max_hops: config.max_hops,
+            max_age: config.max_age,
+            inner,
+            blocklist,
+            seen: Mutex::new(Seen::default()),
+        });
+        let (outgoing, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(node.clone().run(socket, rx));
+        info!("Gossiping bans on {}", local_addr);
+
+        Ok(Self {
+            node,
+            outgoing,
+            local_addr,
+            task,
+        })
+    }
+
+    /// Address peers send gossip to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Start sending bans to another node
+    pub fn add_peer(&self, peer: SocketAddr) {
+        let mut peers = self.node.peers.write().unwrap_or_else(|e| e.into_inner());
+        if !peers.contains(&peer) {
+            peers.push(peer);
+        }
+    }
+
+    fn gossip(&self, update: Update) {
+        // Only fails once the gossip task has stopped
+        let _ = self.outgoing.send(update);
+    }
+}
+
+impl Drop for GossipThreatStore {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl ThreatStore for GossipThreatStore {
+    async fn record_threat(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        self.node.inner.record_threat(threat).await
+    }
+
+    async fn recent_threats(&self, limit: usize) -> Result<Vec<ThreatDetection>, VstpError> {
+        self.node.inner.recent_threats(limit).await
+    }
+
+    async fn session_threats(
+        &self,
+        session_id: SessionId,
+        limit: usize,
+    ) -> Result<Vec<ThreatDetection>, VstpError> {
+        self.node.inner.session_threats(session_id, limit).await
+    }
+
+    async fn record_ban(&self, ban: &BanRecord) -> Result<(), VstpError> {
+        self.node.inner.record_ban(ban).await?;
+        self.gossip(Update::Ban(ban.clone()));
+        Ok(())
+    }
+
+    async fn lift_ban(&self, net: IpNet) -> Result<(), VstpError> {
+        self.node.inner.lift_ban(net).await?;
+        self.gossip(Update::Unban { net });
+        Ok(())
+    }
+
+    async fn active_bans(&self) -> Result<Vec<BanRecord>, VstpError> {
+        self.node.inner.active_bans().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freshness_window() {
+        let max_age = Duration::from_secs(60);
+        let now = 1_700_000_000_000;
+        assert!(is_fresh(now, now, max_age));
+        assert!(is_fresh(now - 60_000, now, max_age));
+        assert!(is_fresh(now + 60_000, now, max_age));
+        assert!(!is_fresh(now - 60_001, now, max_age));
+        assert!(!is_fresh(now + 60_001, now, max_age));
+        assert!(!is_fresh(0, now, max_age));
+    }
+}
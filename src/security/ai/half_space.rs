@@ -0,0 +1,348 @@
+//! Streaming half-space trees
+//!
+//! Half-space trees (Tan, Ting and Liu, 2011) are built once, without
+//! looking at the data, by halving randomly chosen features of a work space
+//! at every level. Each node counts how many samples fell into it during a
+//! reference window and during the window being filled. When the latest
+//! window is full it becomes the reference, so learning costs one pass down
+//! each tree per sample and the model adapts as traffic changes.
+//!
+//! A point is scored by the reference mass of the nodes it passes through,
+//! weighted by depth: points in dense regions collect a lot of mass, points
+//! in regions no recent traffic visited collect little.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+use crate::core::types::SessionId;
+
+use super::ensemble::DetectionModel;
+use super::models::{RunningStats, SampleThrottle};
+use super::monitor::FeatureVector;
+use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
+
+/// Half-space trees settings
+#[derive(Debug, Clone)]
+pub struct HalfSpaceTreesConfig {
+    /// Number of trees
+    pub trees: usize,
+    /// Depth of every tree
+    pub depth: usize,
+    /// Samples per window
+    pub window: usize,
+    /// Nodes with less reference mass than this fraction of the window end
+    /// a point's scoring path
+    pub size_limit: f64,
+    /// Anomaly score, between 0 and 1, above which a connection is reported
+    pub threshold: f64,
+    /// Shortest time between two samples taken from the same connection
+    pub sample_interval: Duration,
+}
+
+impl Default for HalfSpaceTreesConfig {
+    fn default() -> Self {
+        Self {
+            trees: 25,
+            depth: 10,
+            window: 250,
+            size_limit: 0.1,
+            threshold: 0.8,
+            sample_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A complete binary tree stored breadth first; node `i` has children
+/// `2i + 1` and `2i + 2`
+#[derive(Debug, Clone)]
+struct Tree {
+    /// Feature and value each internal node splits on
+    splits: Vec<(usize, f64)>,
+    reference: Vec<f64>,
+    latest: Vec<f64>,
+}
+
+impl Tree {
+    fn build(depth: usize, mut low: Vec<f64>, mut high: Vec<f64>, rng: &mut StdRng) -> Self {
+        let internal = (1 << depth) - 1;
+        let nodes = (1 << (depth + 1)) - 1;
+        let mut tree = Tree {
+            splits: vec![(0, 0.0); internal],
+            reference: vec![0.0; nodes],
+            latest: vec![0.0; nodes],
+        };
+        tree.grow(0, &mut low, &mut high, rng);
+        tree
+    }
+
+    fn grow(&mut self, index: usize, low: &mut [f64], high: &mut [f64], rng: &mut StdRng) {
+        if index >= self.splits.len() {
+            return;
+        }
+        let feature = rng.gen_range(0..low.len());
+        let value = (low[feature] + high[feature]) / 2.0;
+        self.splits[index] = (feature, value);
+
+        let upper = high[feature];
+        high[feature] = value;
+        self.grow(2 * index + 1, low, high, rng);
+        high[feature] = upper;
+
+        let lower = low[feature];
+        low[feature] = value;
+        self.grow(2 * index + 2, low, high, rng);
+        low[feature] = lower;
+    }
+
+    /// Nodes from the root to the leaf `point` falls in
+    fn path<'a>(&'a self, point: &'a [f64]) -> impl Iterator<Item = usize> + 'a {
+        std::iter::successors(Some(0), move |&index| {
+            let (feature, value) = *self.splits.get(index)?;
+            Some(if point[feature] < value {
+                2 * index + 1
+            } else {
+                2 * index + 2
+            })
+        })
+    }
+}
+
+/// Streaming anomaly detector over feature vectors
+#[derive(Debug, Clone)]
+pub struct HalfSpaceTrees {
+    config: HalfSpaceTreesConfig,
+    trees: Vec<Tree>,
+    /// Samples collected to size the work space before the trees exist
+    pending: Vec<Vec<f64>>,
+    in_window: usize,
+    /// Mass scores of recently learned samples, the yardstick for normal
+    typical: RunningStats,
+    throttle: SampleThrottle,
+    rng: StdRng,
+}
+
+impl HalfSpaceTrees {
+    pub fn new(config: HalfSpaceTreesConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    /// Trees whose random choices are fixed by `seed`, for reproducible
+    /// experiments
+    pub fn with_seed(config: HalfSpaceTreesConfig, seed: u64) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: HalfSpaceTreesConfig, rng: StdRng) -> Self {
+        Self {
+            throttle: SampleThrottle::new(config.sample_interval),
+            config,
+            trees: Vec::new(),
+            pending: Vec::new(),
+            in_window: 0,
+            typical: RunningStats::new(),
+            rng,
+        }
+    }
+
+    pub fn config(&self) -> &HalfSpaceTreesConfig {
+        &self.config
+    }
+
+    /// Whether a reference window has been learned and the model can score
+    pub fn is_ready(&self) -> bool {
+        !self.trees.is_empty()
+    }
+
+    /// Learn one sample
+    ///
+    /// The first window only sizes the work space the trees divide; it then
+    /// becomes the first reference window.
+    pub fn add_sample(&mut self, sample: Vec<f64>) {
+        let window = self.config.window.max(1);
+        if !self.is_ready() {
+            self.pending.push(sample);
+            if self.pending.len() >= window {
+                self.build();
+            }
+            return;
+        }
+
+        let mass = self.mass(&sample);
+        self.typical.update_decayed(mass, 1.0 - 1.0 / window as f64);
+        for tree in &mut self.trees {
+            let path: Vec<usize> = tree.path(&sample).collect();
+            for index in path {
+                tree.latest[index] += 1.0;
+            }
+        }
+        self.in_window += 1;
+        if self.in_window >= window {
+            for tree in &mut self.trees {
+                tree.reference = std::mem::replace(&mut tree.latest, vec![0.0; tree.reference.len()]);
+            }
+            self.in_window = 0;
+        }
+    }
+
+    /// Build the trees around the pending samples and make them the first
+    /// reference window
+    fn build(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let dims = pending[0].len();
+        let (mut low, mut high) = (vec![f64::MAX; dims], vec![f64::MIN; dims]);
+        for sample in &pending {
+            for (feature, &value) in sample.iter().enumerate() {
+                low[feature] = low[feature].min(value);
+                high[feature] = high[feature].max(value);
+            }
+        }
+
+        // Each feature's work range is centred on a random point of the
+        // observed range and wide enough to cover all of it, as in the paper
+        let (mut work_low, mut work_high) = (vec![0.0; dims], vec![0.0; dims]);
+        for feature in 0..dims {
+            let (lo, hi) = (low[feature], high[feature]);
+            let centre = if hi > lo { self.rng.gen_range(lo..=hi) } else { lo };
+            let reach = (2.0 * (centre - lo).max(hi - centre)).max(1e-6);
+            work_low[feature] = centre - reach;
+            work_high[feature] = centre + reach;
+        }
+
+        let depth = self.config.depth.clamp(1, 20);
+        self.trees = (0..self.config.trees.max(1))
+            .map(|_| Tree::build(depth, work_low.clone(), work_high.clone(), &mut self.rng))
+            .collect();
+        for sample in &pending {
+            for tree in &mut self.trees {
+                let path: Vec<usize> = tree.path(sample).collect();
+                for index in path {
+                    tree.reference[index] += 1.0;
+                }
+            }
+        }
+        for sample in &pending {
+            let mass = self.mass(sample);
+            self.typical.update(mass);
+        }
+    }
+
+    /// Depth-weighted reference mass along a point's paths, averaged over
+    /// the trees
+    fn mass(&self, point: &[f64]) -> f64 {
+        let limit = self.config.size_limit * self.config.window as f64;
+        let total: f64 = self
+            .trees
+            .iter()
+            .map(|tree| {
+                let mut mass = 0.0;
+                for (depth, index) in tree.path(point).enumerate() {
+                    let reference = tree.reference[index];
+                    mass += reference * 2f64.powi(depth as i32);
+                    if reference <= limit {
+                        break;
+                    }
+                }
+                mass
+            })
+            .sum();
+        total / self.trees.len() as f64
+    }
+
+    /// Anomaly score of a point, from 0 for mass as high as typical traffic
+    /// to 1 for a region no reference traffic visited, or `None` before the
+    /// first window is complete
+    pub fn anomaly_score(&self, point: &[f64]) -> Option<f64> {
+        if !self.is_ready() || self.typical.mean() <= 0.0 {
+            return None;
+        }
+        Some((1.0 - self.mass(point) / self.typical.mean()).clamp(0.0, 1.0))
+    }
+}
+
+impl Default for HalfSpaceTrees {
+    fn default() -> Self {
+        Self::new(HalfSpaceTreesConfig::default())
+    }
+}
+
+impl DetectionModel for HalfSpaceTrees {
+    fn name(&self) -> &str {
+        "half_space_trees"
+    }
+
+    fn learn(&mut self, features: &FeatureVector) {
+        if self.throttle.admit(features.session_id, features.observed_at) {
+            self.add_sample(features.model_inputs().to_vec());
+        }
+    }
+
+    fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        let Some(score) = self.anomaly_score(&features.model_inputs()) else {
+            return Vec::new();
+        };
+        let threshold = self.config.threshold.min(0.99);
+        if score < threshold {
+            return Vec::new();
+        }
+        let confidence = 0.5 + 0.5 * (score - threshold) / (1.0 - threshold);
+        vec![ThreatDetection::new(
+            AttackPattern::AnomalousTraffic,
+            ThreatLevel::from_score(confidence),
+            confidence,
+            format!("Traffic in a region recent traffic rarely visits (anomaly score {:.2})", score),
+        )
+        .with_indicator(format!("Half-space trees score: {:.3}", score))]
+    }
+
+    fn end_session(&mut self, session_id: SessionId) {
+        self.throttle.remove_session(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HalfSpaceTreesConfig {
+        HalfSpaceTreesConfig {
+            window: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sparse_regions_score_higher() {
+        let mut model = HalfSpaceTrees::with_seed(config(), 1);
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..99 {
+            model.add_sample(vec![rng.gen_range(4.0..6.0), rng.gen_range(-1.0..1.0)]);
+        }
+        assert_eq!(model.anomaly_score(&[5.0, 0.0]), None);
+        model.add_sample(vec![5.0, 0.0]);
+        assert!(model.is_ready());
+
+        let inlier = model.anomaly_score(&[5.0, 0.0]).unwrap();
+        let outlier = model.anomaly_score(&[12.0, 5.0]).unwrap();
+        assert!(inlier < 0.5, "{}", inlier);
+        assert!(outlier > 0.8, "{}", outlier);
+    }
+
+    #[test]
+    fn test_reference_window_follows_traffic() {
+        let mut model = HalfSpaceTrees::with_seed(config(), 3);
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..100 {
+            model.add_sample(vec![rng.gen_range(0.0..2.0), rng.gen_range(0.0..2.0)]);
+        }
+        let before = model.anomaly_score(&[7.0, 7.0]).unwrap();
+
+        // Two full windows at the new spot make it the reference
+        for _ in 0..200 {
+            model.add_sample(vec![rng.gen_range(6.5..7.5), rng.gen_range(6.5..7.5)]);
+        }
+        let after = model.anomaly_score(&[7.0, 7.0]).unwrap();
+        assert!(after < before, "{} then {}", before, after);
+        assert!(model.anomaly_score(&[1.0, 1.0]).unwrap() > 0.8);
+    }
+}
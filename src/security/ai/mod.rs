@@ -8,10 +8,32 @@
 //! - Real-time threat scoring
 
 pub mod detector;
+pub mod ensemble;
+pub mod export;
+pub mod forest;
+pub mod gossip;
+pub mod half_space;
 pub mod models;
 pub mod monitor;
+#[cfg(feature = "onnx")]
+pub mod onnx;
 pub mod patterns;
+pub mod response;
+pub mod segments;
+pub mod sinks;
+pub mod store;
 
-pub use detector::AnomalyDetector;
-pub use monitor::TrafficMonitor;
+pub use detector::{AnomalyDetector, DetectionMode};
+pub use ensemble::{DetectionModel, Ensemble};
+pub use export::{ExportFormat, ExportOptions, FrameLog, Snapshots};
+pub use forest::IsolationForest;
+pub use gossip::{GossipConfig, GossipThreatStore};
+pub use half_space::HalfSpaceTrees;
+pub use monitor::{FeatureVector, TrafficMonitor};
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxModel;
 pub use patterns::{AttackPattern, ThreatLevel};
+pub use response::{ResponseAction, ResponsePolicy, ResponseRule};
+pub use segments::Segmentation;
+pub use sinks::{HttpSink, JsonlSink, SyslogSink, ThreatForwarder, ThreatSink};
+pub use store::{JsonlThreatStore, MemoryThreatStore, ThreatStore};
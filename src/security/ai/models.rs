@@ -1,179 +1,450 @@
 //! ML models and statistical analysis for anomaly detection
 
-use std::collections::VecDeque;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use super::monitor::ConnectionStats;
+use crate::core::types::SessionId;
+
+use super::monitor::{ConnectionStats, FeatureVector};
 use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
 
+/// Number of connections whose last sample time is remembered before old
+/// entries are pruned
+const MAX_TRACKED_SESSIONS: usize = 10_000;
+
+/// Gap variation below which arrivals are considered machine-regular
+const REGULAR_GAP_VARIATION: f64 = 0.1;
+
+/// Mean and variance of a stream of samples, updated one sample at a time
+///
+/// Uses Welford's algorithm extended to weighted samples (West, 1979), which
+/// stays accurate where the textbook sum-of-squares formula loses precision.
+/// Passing a decay factor below 1 to [`update_decayed`](Self::update_decayed)
+/// scales down the weight of everything seen so far, turning the statistics
+/// into an exponentially weighted moving mean and variance.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunningStats {
+    count: u64,
+    weight: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample with every earlier sample keeping its weight
+    pub fn update(&mut self, value: f64) {
+        self.update_decayed(value, 1.0);
+    }
+
+    /// Add a sample after scaling the weight of earlier samples by `decay`
+    pub fn update_decayed(&mut self, value: f64, decay: f64) {
+        let decay = decay.clamp(0.0, 1.0);
+        self.count += 1;
+        self.weight = self.weight * decay + 1.0;
+        self.m2 *= decay;
+        let delta = value - self.mean;
+        self.mean += delta / self.weight;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Number of samples added
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Total weight of the samples still counted
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population variance of the weighted samples
+    pub fn variance(&self) -> f64 {
+        if self.weight > 0.0 {
+            (self.m2 / self.weight).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Standard score of `value`, treating the standard deviation as at
+    /// least `min_std`
+    pub fn z_score(&self, value: f64, min_std: f64) -> f64 {
+        let std = self.std_dev().max(min_std);
+        if self.count == 0 || std <= 0.0 {
+            return 0.0;
+        }
+        (value - self.mean) / std
+    }
+}
+
+/// Weight left on a sample after `elapsed`, for a given half-life
+pub fn decay_factor(elapsed: Duration, half_life: Duration) -> f64 {
+    if half_life.is_zero() {
+        return 0.0;
+    }
+    0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+}
+
+/// Traffic features the baseline tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Average frame size over the rate window
+    FrameSize,
+    /// Frame rate over the rate window
+    FramesPerSecond,
+    /// Byte rate over the rate window
+    BytesPerSecond,
+}
+
+impl Feature {
+    /// Value of this feature for a connection, measured over its recent
+    /// traffic rather than its whole lifetime
+    pub fn measure(&self, stats: &ConnectionStats) -> f64 {
+        match self {
+            Feature::FrameSize => stats.windowed_avg_frame_size(),
+            Feature::FramesPerSecond => stats.windowed_frames_per_second(),
+            Feature::BytesPerSecond => stats.windowed_bytes_per_second(),
+        }
+    }
+
+    /// Value of this feature in a feature vector
+    pub fn value(&self, features: &FeatureVector) -> f64 {
+        match self {
+            Feature::FrameSize => features.frame_size,
+            Feature::FramesPerSecond => features.frames_per_second,
+            Feature::BytesPerSecond => features.bytes_per_second,
+        }
+    }
+}
+
+/// Limits how often one connection contributes a training sample, so busy
+/// connections do not dominate what a model learns
+#[derive(Debug, Clone)]
+pub struct SampleThrottle {
+    interval: Duration,
+    last_sample: HashMap<SessionId, Instant>,
+}
+
+impl SampleThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sample: HashMap::new(),
+        }
+    }
+
+    /// Whether `session_id` may contribute a sample at `now`, recording it
+    /// if so
+    pub fn admit(&mut self, session_id: SessionId, now: Instant) -> bool {
+        if let Some(last) = self.last_sample.get(&session_id) {
+            if now.saturating_duration_since(*last) < self.interval {
+                return false;
+            }
+        }
+        if self.last_sample.len() >= MAX_TRACKED_SESSIONS {
+            let interval = self.interval;
+            self.last_sample
+                .retain(|_, last| now.saturating_duration_since(*last) < interval);
+        }
+        self.last_sample.insert(session_id, now);
+        true
+    }
+
+    /// Forget a closed connection
+    pub fn remove_session(&mut self, session_id: SessionId) {
+        self.last_sample.remove(&session_id);
+    }
+}
+
+/// Baseline learning and scoring settings
+#[derive(Debug, Clone)]
+pub struct BaselineConfig {
+    /// Time for a sample's weight in the baseline to halve; `None` weighs
+    /// all samples equally
+    pub half_life: Option<Duration>,
+    /// Shortest time between two samples taken from the same connection, so
+    /// busy connections do not dominate the baseline
+    pub sample_interval: Duration,
+    /// Samples needed before anomalies are scored
+    pub min_samples: u64,
+    /// Smallest standard deviation used for scoring, as a fraction of the
+    /// feature's mean; stops near-constant baselines flagging tiny changes
+    pub min_relative_std: f64,
+    /// Absolute z-score above which a frame size is anomalous
+    pub frame_size_threshold: f64,
+    /// Z-score above which a frame rate is a flood
+    pub flood_threshold: f64,
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        Self {
+            half_life: Some(Duration::from_secs(600)),
+            sample_interval: Duration::from_secs(1),
+            min_samples: 30,
+            min_relative_std: 0.1,
+            frame_size_threshold: 3.0,
+            flood_threshold: 4.0,
+        }
+    }
+}
+
+/// What a [`BaselineModel`] has learned, for saving and restoring it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaselineState {
+    pub sample_count: u64,
+    pub frame_size: RunningStats,
+    pub frames_per_second: RunningStats,
+    pub bytes_per_second: RunningStats,
+}
+
 /// Statistical model for baseline behavior
+///
+/// Every connection contributes windowed samples of each [`Feature`], at
+/// most one per [`BaselineConfig::sample_interval`]. Each feature keeps an
+/// exponentially weighted mean and variance, and connections are scored by
+/// how many standard deviations their recent traffic is from the mean.
 #[derive(Debug, Clone)]
 pub struct BaselineModel {
-    // Normal traffic characteristics
-    avg_frame_size: f64,
-    avg_frames_per_second: f64,
-    avg_bytes_per_second: f64,
-    avg_inter_arrival_time: Duration,
+    config: BaselineConfig,
 
-    // Standard deviations for anomaly detection
-    frame_size_std: f64,
-    frames_per_second_std: f64,
-    bytes_per_second_std: f64,
+    // Normal traffic characteristics
+    frame_size: RunningStats,
+    frames_per_second: RunningStats,
+    bytes_per_second: RunningStats,
 
     // Sample count for learning
     sample_count: u64,
-
-    // Historical data for pattern recognition
-    historical_patterns: VecDeque<ConnectionStats>,
-    max_history: usize,
+    last_update: Option<Instant>,
+    throttle: SampleThrottle,
 }
 
 impl BaselineModel {
     pub fn new() -> Self {
+        Self::with_config(BaselineConfig::default())
+    }
+
+    pub fn with_config(config: BaselineConfig) -> Self {
         Self {
-            avg_frame_size: 0.0,
-            avg_frames_per_second: 0.0,
-            avg_bytes_per_second: 0.0,
-            avg_inter_arrival_time: Duration::from_millis(100),
-            frame_size_std: 0.0,
-            frames_per_second_std: 0.0,
-            bytes_per_second_std: 0.0,
+            throttle: SampleThrottle::new(config.sample_interval),
+            config,
+            frame_size: RunningStats::new(),
+            frames_per_second: RunningStats::new(),
+            bytes_per_second: RunningStats::new(),
             sample_count: 0,
-            historical_patterns: VecDeque::with_capacity(1000),
-            max_history: 1000,
+            last_update: None,
         }
     }
 
-    /// Update the baseline with new connection statistics
-    pub fn update_baseline(&mut self, stats: &ConnectionStats) {
-        self.sample_count += 1;
+    pub fn config(&self) -> &BaselineConfig {
+        &self.config
+    }
+
+    /// Running statistics for one feature
+    pub fn stats(&self, feature: Feature) -> &RunningStats {
+        match feature {
+            Feature::FrameSize => &self.frame_size,
+            Feature::FramesPerSecond => &self.frames_per_second,
+            Feature::BytesPerSecond => &self.bytes_per_second,
+        }
+    }
 
-        // Update historical patterns
-        if self.historical_patterns.len() >= self.max_history {
-            self.historical_patterns.pop_front();
+    /// Number of samples folded into the baseline
+    pub fn sample_count(&self) -> u64 {
+        self.sample_count
+    }
+
+    /// Whether the baseline has the [`BaselineConfig::min_samples`] it
+    /// needs to score connections
+    pub fn is_ready(&self) -> bool {
+        self.sample_count >= self.config.min_samples
+    }
+
+    /// What the baseline has learned
+    pub fn state(&self) -> BaselineState {
+        BaselineState {
+            sample_count: self.sample_count,
+            frame_size: self.frame_size.clone(),
+            frames_per_second: self.frames_per_second.clone(),
+            bytes_per_second: self.bytes_per_second.clone(),
         }
-        self.historical_patterns.push_back(stats.clone());
+    }
 
-        // Calculate running averages
-        let n = self.sample_count as f64;
+    /// Replace what the baseline has learned with a saved state
+    ///
+    /// Decay restarts from the next sample; per-connection sampling state is
+    /// kept.
+    pub fn restore(&mut self, state: BaselineState) {
+        self.sample_count = state.sample_count;
+        self.frame_size = state.frame_size;
+        self.frames_per_second = state.frames_per_second;
+        self.bytes_per_second = state.bytes_per_second;
+        self.last_update = None;
+    }
 
-        // Frame size
-        let old_avg = self.avg_frame_size;
-        self.avg_frame_size = (old_avg * (n - 1.0) + stats.avg_frame_size) / n;
+    /// When the baseline last took a sample
+    pub fn last_update(&self) -> Option<Instant> {
+        self.last_update
+    }
 
-        // Frames per second
-        let fps = stats.get_frames_per_second();
-        let old_fps_avg = self.avg_frames_per_second;
-        self.avg_frames_per_second = (old_fps_avg * (n - 1.0) + fps) / n;
+    /// Update the baseline with new connection statistics
+    ///
+    /// Returns whether a sample was taken; connections sampled less than
+    /// [`BaselineConfig::sample_interval`] ago are skipped.
+    pub fn update_baseline(&mut self, stats: &ConnectionStats) -> bool {
+        self.update_baseline_at(stats, Instant::now())
+    }
 
-        // Bytes per second
-        let bps = stats.get_bytes_per_second();
-        let old_bps_avg = self.avg_bytes_per_second;
-        self.avg_bytes_per_second = (old_bps_avg * (n - 1.0) + bps) / n;
+    /// [`update_baseline`](Self::update_baseline) at a given time
+    pub fn update_baseline_at(&mut self, stats: &ConnectionStats, now: Instant) -> bool {
+        let mut features = stats.feature_vector();
+        features.observed_at = now;
+        self.learn(&features)
+    }
 
-        // Calculate standard deviations (simplified)
-        if n > 1.0 {
-            let frame_size_diff = (stats.avg_frame_size - self.avg_frame_size).abs();
-            self.frame_size_std = (self.frame_size_std * (n - 1.0) + frame_size_diff) / n;
+    /// Fold a feature vector into the baseline, at the time it was taken
+    pub fn learn(&mut self, features: &FeatureVector) -> bool {
+        let now = features.observed_at;
+        if !self.throttle.admit(features.session_id, now) {
+            return false;
+        }
 
-            let fps_diff = (fps - self.avg_frames_per_second).abs();
-            self.frames_per_second_std = (self.frames_per_second_std * (n - 1.0) + fps_diff) / n;
+        let decay = match (self.config.half_life, self.last_update) {
+            (Some(half_life), Some(last)) => {
+                decay_factor(now.saturating_duration_since(last), half_life)
+            }
+            _ => 1.0,
+        };
+        self.last_update = Some(now);
+        self.sample_count += 1;
 
-            let bps_diff = (bps - self.avg_bytes_per_second).abs();
-            self.bytes_per_second_std = (self.bytes_per_second_std * (n - 1.0) + bps_diff) / n;
+        for feature in [
+            Feature::FrameSize,
+            Feature::FramesPerSecond,
+            Feature::BytesPerSecond,
+        ] {
+            let value = feature.value(features);
+            self.stats_mut(feature).update_decayed(value, decay);
         }
+        true
+    }
+
+    fn stats_mut(&mut self, feature: Feature) -> &mut RunningStats {
+        match feature {
+            Feature::FrameSize => &mut self.frame_size,
+            Feature::FramesPerSecond => &mut self.frames_per_second,
+            Feature::BytesPerSecond => &mut self.bytes_per_second,
+        }
+    }
+
+    /// Forget a closed connection's sampling state
+    pub fn remove_session(&mut self, session_id: SessionId) {
+        self.throttle.remove_session(session_id);
+    }
+
+    /// Standard score of a connection's current value for `feature`
+    pub fn z_score(&self, feature: Feature, stats: &ConnectionStats) -> f64 {
+        self.value_z_score(feature, feature.measure(stats))
+    }
+
+    fn value_z_score(&self, feature: Feature, value: f64) -> f64 {
+        let baseline = self.stats(feature);
+        let min_std = baseline.mean().abs() * self.config.min_relative_std;
+        baseline.z_score(value, min_std)
     }
 
     /// Detect anomalies in connection statistics
     pub fn detect_anomalies(&self, stats: &ConnectionStats) -> Vec<ThreatDetection> {
+        self.detect(&stats.feature_vector())
+    }
+
+    /// Detect anomalies in a feature vector
+    ///
+    /// Nothing is reported until the baseline [`is_ready`](Self::is_ready):
+    /// even the fixed-threshold checks fire too easily on traffic nobody
+    /// has vetted yet.
+    pub fn detect(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
         let mut threats = Vec::new();
+        if !self.is_ready() {
+            return threats;
+        }
 
         // Check for anomalous frame sizes
-        if self.sample_count > 10 {
-            let frame_size_z_score = if self.frame_size_std > 0.0 {
-                (stats.avg_frame_size - self.avg_frame_size).abs() / self.frame_size_std
-            } else {
-                0.0
-            };
-
-            if frame_size_z_score > 3.0 {
-                threats.push(ThreatDetection::new(
-                    AttackPattern::AnomalousTraffic,
-                    ThreatLevel::Medium,
-                    0.6,
-                    format!(
-                        "Unusual frame size detected: {:.2} (baseline: {:.2}, z-score: {:.2})",
-                        stats.avg_frame_size, self.avg_frame_size, frame_size_z_score
-                    ),
-                ));
-            }
+        let frame_size = features.frame_size;
+        let frame_size_z_score = self.value_z_score(Feature::FrameSize, frame_size);
+
+        if frame_size_z_score.abs() > self.config.frame_size_threshold {
+            threats.push(ThreatDetection::new(
+                AttackPattern::AnomalousTraffic,
+                ThreatLevel::Medium,
+                0.6,
+                format!(
+                    "Unusual frame size detected: {:.2} (baseline: {:.2}, z-score: {:.2})",
+                    frame_size,
+                    self.frame_size.mean(),
+                    frame_size_z_score
+                ),
+            ));
         }
 
         // Check for traffic flooding
-        let fps = stats.get_frames_per_second();
-        if self.sample_count > 10 && self.frames_per_second_std > 0.0 {
-            let fps_z_score = (fps - self.avg_frames_per_second).abs() / self.frames_per_second_std;
-
-            if fps_z_score > 4.0 && fps > self.avg_frames_per_second {
-                threats.push(
-                    ThreatDetection::new(
-                        AttackPattern::ConnectionFlood,
-                        ThreatLevel::High,
-                        0.8,
-                        format!(
-                            "Traffic flood detected: {:.2} fps (baseline: {:.2}, z-score: {:.2})",
-                            fps, self.avg_frames_per_second, fps_z_score
-                        ),
-                    )
-                    .with_indicator(format!("Frames per second: {:.2}", fps)),
-                );
-            }
-        }
-
-        // Check for packet theft indicators (unusual inter-arrival patterns)
-        if stats.inter_arrival_times.len() > 10 {
-            let mut suspicious_timing = false;
-            let mut consistent_intervals = true;
-            let first_interval = stats.inter_arrival_times[0];
-
-            for interval in stats.inter_arrival_times.iter().skip(1) {
-                let diff = if first_interval > *interval {
-                    first_interval.as_millis() as f64 - interval.as_millis() as f64
-                } else {
-                    interval.as_millis() as f64 - first_interval.as_millis() as f64
-                };
+        let fps = features.frames_per_second;
+        let fps_z_score = self.value_z_score(Feature::FramesPerSecond, fps);
 
-                if diff > first_interval.as_millis() as f64 * 0.5 {
-                    consistent_intervals = false;
-                }
-
-                // Very consistent intervals might indicate packet capture/replay
-                if diff < first_interval.as_millis() as f64 * 0.1 {
-                    suspicious_timing = true;
-                }
-            }
+        if fps_z_score > self.config.flood_threshold {
+            threats.push(
+                ThreatDetection::new(
+                    AttackPattern::ConnectionFlood,
+                    ThreatLevel::High,
+                    0.8,
+                    format!(
+                        "Traffic flood detected: {:.2} fps (baseline: {:.2}, z-score: {:.2})",
+                        fps,
+                        self.frames_per_second.mean(),
+                        fps_z_score
+                    ),
+                )
+                .with_indicator(format!("Frames per second: {:.2}", fps)),
+            );
+        }
 
-            if suspicious_timing && consistent_intervals {
-                threats.push(
-                    ThreatDetection::new(
-                        AttackPattern::PacketTheft,
-                        ThreatLevel::High,
-                        0.75,
-                        "Suspicious timing pattern detected - possible packet capture/replay"
-                            .to_string(),
-                    )
-                    .with_indicator("Highly consistent inter-arrival times".to_string()),
-                );
-            }
+        // Check for packet theft indicators (unusual inter-arrival patterns).
+        // Replayed captures tend to arrive with machine-regular gaps
+        let timing = &features.traffic;
+        if timing.gap_count > 10 && timing.gap_variation() < REGULAR_GAP_VARIATION {
+            threats.push(
+                ThreatDetection::new(
+                    AttackPattern::PacketTheft,
+                    ThreatLevel::High,
+                    0.75,
+                    "Suspicious timing pattern detected - possible packet capture/replay"
+                        .to_string(),
+                )
+                .with_indicator("Highly consistent inter-arrival times".to_string())
+                .with_indicator(format!(
+                    "Gap {:.2} ms, jitter {:.3} ms, variation {:.3}",
+                    timing.gap_mean_ms,
+                    timing.jitter_ms,
+                    timing.gap_variation()
+                )),
+            );
         }
 
         // Check for high error rates (possible MITM or protocol manipulation)
-        let error_rate = if stats.frame_count > 0 {
-            (stats.crc_errors + stats.protocol_errors) as f64 / stats.frame_count as f64
-        } else {
-            0.0
-        };
-
+        let error_rate = features.error_rate;
         if error_rate > 0.1 {
             threats.push(
                 ThreatDetection::new(
@@ -189,31 +460,19 @@ impl BaselineModel {
             );
         }
 
-        // Check for unusual frame type distributions
-        if stats.frame_types.len() > 0 {
-            let data_frames = stats
-                .frame_types
-                .get(&crate::core::types::FrameType::Data)
-                .copied()
-                .unwrap_or(0);
-            let data_ratio = if stats.frame_count > 0 {
-                data_frames as f64 / stats.frame_count as f64
-            } else {
-                0.0
-            };
-
-            // Very high data frame ratio might indicate data exfiltration
-            if data_ratio > 0.95 && stats.frame_count > 100 {
-                threats.push(
-                    ThreatDetection::new(
-                        AttackPattern::DataExfiltration,
-                        ThreatLevel::Medium,
-                        0.65,
-                        format!("Unusual data frame ratio: {:.2}%", data_ratio * 100.0),
-                    )
-                    .with_indicator("High data frame percentage".to_string()),
-                );
-            }
+        // Check for unusual frame type distributions. Very high data frame
+        // ratio might indicate data exfiltration
+        let data_ratio = features.data_ratio;
+        if data_ratio > 0.95 && features.frame_count > 100 {
+            threats.push(
+                ThreatDetection::new(
+                    AttackPattern::DataExfiltration,
+                    ThreatLevel::Medium,
+                    0.65,
+                    format!("Unusual data frame ratio: {:.2}%", data_ratio * 100.0),
+                )
+                .with_indicator("High data frame percentage".to_string()),
+            );
         }
 
         threats
@@ -222,9 +481,9 @@ impl BaselineModel {
     /// Get baseline statistics
     pub fn get_baseline(&self) -> (f64, f64, f64) {
         (
-            self.avg_frame_size,
-            self.avg_frames_per_second,
-            self.avg_bytes_per_second,
+            self.frame_size.mean(),
+            self.frames_per_second.mean(),
+            self.bytes_per_second.mean(),
         )
     }
 }
@@ -234,3 +493,132 @@ impl Default for BaselineModel {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{Frame, FrameType};
+
+    /// A connection that sent `fps` frames of `size` bytes per second over
+    /// the last ten seconds
+    fn connection(session_id: SessionId, fps: usize, size: usize, now: Instant) -> ConnectionStats {
+        let mut stats = ConnectionStats::new(session_id, "127.0.0.1:1".parse().unwrap());
+        stats.first_seen = now - Duration::from_secs(60);
+        stats.last_seen = now;
+        let frames = fps * 10;
+        for i in (0..frames).rev() {
+            stats
+                .recent_frames
+                .record(now - Duration::from_millis((i * 10_000 / frames) as u64), size);
+        }
+        stats.frame_count = 1_000_000;
+        stats
+    }
+
+    #[test]
+    fn test_running_stats_matches_two_pass() {
+        let samples = [1e9 + 4.0, 1e9 + 7.0, 1e9 + 13.0, 1e9 + 16.0];
+        let mut stats = RunningStats::new();
+        for x in samples {
+            stats.update(x);
+        }
+        let mean = samples.iter().sum::<f64>() / 4.0;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 4.0;
+        assert_eq!(stats.count(), 4);
+        assert!((stats.mean() - mean).abs() < 1e-6);
+        assert!((stats.variance() - variance).abs() < 1e-6);
+        assert!((stats.z_score(mean + 2.0 * variance.sqrt(), 0.0) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_decay_follows_half_life() {
+        let half_life = Duration::from_secs(10);
+        assert!((decay_factor(half_life, half_life) - 0.5).abs() < 1e-12);
+        assert!((decay_factor(Duration::from_secs(20), half_life) - 0.25).abs() < 1e-12);
+
+        // Old samples at 100 lose weight to newer samples at 200
+        let mut stats = RunningStats::new();
+        for _ in 0..100 {
+            stats.update(100.0);
+        }
+        stats.update_decayed(200.0, decay_factor(Duration::from_secs(100), half_life));
+        assert!(stats.mean() > 190.0, "{}", stats.mean());
+    }
+
+    #[test]
+    fn test_samples_are_rate_limited_per_connection() {
+        let mut model = BaselineModel::new();
+        let now = Instant::now();
+        let stats = connection(1, 10, 100, now);
+        assert!(model.update_baseline_at(&stats, now));
+        assert!(!model.update_baseline_at(&stats, now + Duration::from_millis(500)));
+        assert!(model.update_baseline_at(&connection(2, 10, 100, now), now));
+        assert!(model.update_baseline_at(&stats, now + Duration::from_secs(1)));
+        assert_eq!(model.sample_count(), 3);
+    }
+
+    #[test]
+    fn test_windowed_scores() {
+        let mut model = BaselineModel::with_config(BaselineConfig {
+            sample_interval: Duration::ZERO,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for i in 0..100u64 {
+            let now = start + Duration::from_secs(i);
+            let stats = connection(i as SessionId, 8 + (i % 5) as usize, 100 + (i % 7) as usize * 10, now);
+            model.update_baseline_at(&stats, now);
+        }
+
+        // Ordinary traffic, even on a connection that has sent a million
+        // frames over its lifetime, raises nothing
+        let now = start + Duration::from_secs(100);
+        assert!(model.detect_anomalies(&connection(500, 10, 130, now)).is_empty());
+
+        let flood = model.detect_anomalies(&connection(501, 200, 130, now));
+        assert!(flood.iter().any(|t| t.pattern == AttackPattern::ConnectionFlood));
+
+        let jumbo = model.detect_anomalies(&connection(502, 10, 5_000, now));
+        assert!(jumbo.iter().any(|t| t.pattern == AttackPattern::AnomalousTraffic));
+    }
+
+    #[test]
+    fn test_regular_gaps_flag_packet_theft() {
+        // The timing check needs no learned statistics
+        let model = BaselineModel::with_config(BaselineConfig {
+            min_samples: 0,
+            ..Default::default()
+        });
+        let frame = Frame::new(FrameType::Ping);
+        let start = Instant::now();
+
+        let mut replayed = ConnectionStats::new(1, "127.0.0.1:1".parse().unwrap());
+        let mut organic = ConnectionStats::new(2, "127.0.0.1:2".parse().unwrap());
+        for i in 0..20u64 {
+            replayed.record_frame_at(&frame, 32, start + Duration::from_millis(i * 250));
+            let jittered = i * 250 + (i * 7919 % 200);
+            organic.record_frame_at(&frame, 32, start + Duration::from_millis(jittered));
+        }
+
+        let theft = |stats: &ConnectionStats| {
+            model
+                .detect_anomalies(stats)
+                .iter()
+                .any(|t| t.pattern == AttackPattern::PacketTheft)
+        };
+        assert!(theft(&replayed));
+        assert!(!theft(&organic));
+    }
+
+    #[test]
+    fn test_no_scores_before_min_samples() {
+        let mut model = BaselineModel::new();
+        let now = Instant::now();
+        let mut stats = ConnectionStats::new(1, "127.0.0.1:1".parse().unwrap());
+        stats.record_frame(&Frame::new(FrameType::Data), 100);
+        model.update_baseline_at(&stats, now);
+        assert!(model
+            .detect_anomalies(&connection(2, 1_000, 60_000, now))
+            .is_empty());
+    }
+}
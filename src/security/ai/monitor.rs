@@ -1,13 +1,30 @@
 //! Traffic monitoring and pattern collection for anomaly detection
 
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, warn};
 
-use crate::core::types::{Frame, FrameType, SessionId};
+use crate::core::types::{Frame, FrameType, SessionId, VstpError};
+
+/// Span of recent traffic that windowed rates are measured over
+pub const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Shortest span windowed rates are averaged over, so a connection's first
+/// few frames do not read as a burst
+const MIN_RATE_SPAN: Duration = Duration::from_secs(1);
+
+/// Width of the buckets recent traffic is counted in
+const RATE_BUCKET: Duration = Duration::from_secs(1);
+
+/// Most peers without a session whose errors are tracked at once
+pub const MAX_SESSIONLESS_PEERS: usize = 1024;
+
+/// Number of recent inter-arrival gaps and frame sizes kept per connection
+pub const TIMING_HISTORY: usize = 100;
 
 /// Traffic statistics for a single connection/session
 #[derive(Debug, Clone)]
@@ -22,10 +39,88 @@ pub struct ConnectionStats {
     pub avg_frame_size: f64,
     pub max_frame_size: usize,
     pub min_frame_size: usize,
+    /// Gaps between consecutive frames, oldest first
     pub inter_arrival_times: VecDeque<Duration>,
+    /// Sizes of the most recent frames, oldest first
+    pub recent_sizes: VecDeque<usize>,
     pub crc_errors: u64,
     pub protocol_errors: u64,
     pub suspicious_flags: u64,
+    /// Frames and bytes within the last [`RATE_WINDOW`]
+    pub recent_frames: RateWindow,
+    /// The most recent frame
+    pub last_frame: Option<FrameEvent>,
+}
+
+/// One frame as it arrived, for models that look at frame sequences
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameEvent {
+    pub frame_type: FrameType,
+    /// Size of the frame on the wire
+    pub size: usize,
+    pub payload_size: usize,
+    pub header_count: usize,
+    pub has_flags: bool,
+    /// Time since the connection's previous frame; zero for its first
+    pub gap: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RateBucket {
+    start: Instant,
+    frames: u64,
+    bytes: u64,
+}
+
+/// Frame and byte counts over the last [`RATE_WINDOW`], kept in one-second
+/// buckets with running totals so recording and reading are both cheap
+#[derive(Debug, Clone, Default)]
+pub struct RateWindow {
+    buckets: VecDeque<RateBucket>,
+    frames: u64,
+    bytes: u64,
+}
+
+impl RateWindow {
+    /// Count a frame that arrived at `now`; arrivals must not go backwards
+    pub fn record(&mut self, now: Instant, size: usize) {
+        match self.buckets.back_mut() {
+            Some(bucket) if now.saturating_duration_since(bucket.start) < RATE_BUCKET => {
+                bucket.frames += 1;
+                bucket.bytes += size as u64;
+            }
+            _ => self.buckets.push_back(RateBucket {
+                start: now,
+                frames: 1,
+                bytes: size as u64,
+            }),
+        }
+        self.frames += 1;
+        self.bytes += size as u64;
+
+        while let Some(bucket) = self.buckets.front() {
+            if now.saturating_duration_since(bucket.start) <= RATE_WINDOW {
+                break;
+            }
+            self.frames -= bucket.frames;
+            self.bytes -= bucket.bytes;
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Frames in the window
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Bytes in the window
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
 }
 
 impl ConnectionStats {
@@ -42,17 +137,53 @@ impl ConnectionStats {
             avg_frame_size: 0.0,
             max_frame_size: 0,
             min_frame_size: usize::MAX,
-            inter_arrival_times: VecDeque::with_capacity(100),
+            inter_arrival_times: VecDeque::with_capacity(TIMING_HISTORY),
+            recent_sizes: VecDeque::with_capacity(TIMING_HISTORY),
             crc_errors: 0,
             protocol_errors: 0,
             suspicious_flags: 0,
+            recent_frames: RateWindow::default(),
+            last_frame: None,
         }
     }
 
     pub fn record_frame(&mut self, frame: &Frame, frame_size: usize) {
+        self.record_frame_at(frame, frame_size, Instant::now());
+    }
+
+    /// [`record_frame`](Self::record_frame) for a frame that arrived at `now`
+    pub fn record_frame_at(&mut self, frame: &Frame, frame_size: usize, now: Instant) {
+        let gap = if self.frame_count > 0 {
+            now.saturating_duration_since(self.last_seen)
+        } else {
+            Duration::ZERO
+        };
+        self.last_frame = Some(FrameEvent {
+            frame_type: frame.typ,
+            size: frame_size,
+            payload_size: frame.payload.len(),
+            header_count: frame.headers.len(),
+            has_flags: !frame.flags.is_empty(),
+            gap,
+        });
+
+        // Gap since the previous frame
+        if self.frame_count > 0 {
+            self.inter_arrival_times.push_back(gap);
+            if self.inter_arrival_times.len() > TIMING_HISTORY {
+                self.inter_arrival_times.pop_front();
+            }
+        }
+        self.recent_sizes.push_back(frame_size);
+        if self.recent_sizes.len() > TIMING_HISTORY {
+            self.recent_sizes.pop_front();
+        }
+
         self.frame_count += 1;
         self.byte_count += frame_size as u64;
-        self.last_seen = Instant::now();
+        self.last_seen = now;
+
+        self.recent_frames.record(now, frame_size);
 
         // Track frame types
         *self.frame_types.entry(frame.typ).or_insert(0) += 1;
@@ -69,18 +200,86 @@ impl ConnectionStats {
         self.avg_frame_size = (self.avg_frame_size * (self.frame_count - 1) as f64
             + frame_size as f64)
             / self.frame_count as f64;
+    }
 
-        // Record inter-arrival time (if we have a previous timestamp)
-        if self.frame_count > 1 {
-            let elapsed = self.last_seen.duration_since(self.first_seen);
-            if self.frame_count > 1 {
-                let avg_time = elapsed.as_millis() as f64 / self.frame_count as f64;
-                self.inter_arrival_times
-                    .push_back(Duration::from_millis(avg_time as u64));
-                if self.inter_arrival_times.len() > 100 {
-                    self.inter_arrival_times.pop_front();
-                }
-            }
+    /// Timing and size features over the last [`TIMING_HISTORY`] frames
+    pub fn features(&self) -> TrafficFeatures {
+        let gaps: Vec<f64> = self
+            .inter_arrival_times
+            .iter()
+            .map(|gap| gap.as_secs_f64() * 1000.0)
+            .collect();
+        let sizes: Vec<f64> = self.recent_sizes.iter().map(|&size| size as f64).collect();
+
+        let (gap_mean_ms, gap_std_ms) = mean_and_std(&gaps);
+        let gap_percentiles = percentiles(&gaps);
+        let (size_mean, size_std) = mean_and_std(&sizes);
+        let size_percentiles = percentiles(&sizes);
+
+        // Mean change between consecutive gaps, as in RFC 3550
+        let jitter_ms = if gaps.len() > 1 {
+            gaps.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (gaps.len() - 1) as f64
+        } else {
+            0.0
+        };
+        // Goh-Barabasi burstiness: -1 for a perfectly regular stream, 0 for
+        // Poisson arrivals, approaching 1 for bursts separated by long pauses
+        let burstiness = if gap_mean_ms + gap_std_ms > 0.0 {
+            (gap_std_ms - gap_mean_ms) / (gap_std_ms + gap_mean_ms)
+        } else {
+            0.0
+        };
+
+        TrafficFeatures {
+            gap_count: gaps.len(),
+            gap_mean_ms,
+            gap_std_ms,
+            gap_p50_ms: gap_percentiles[0],
+            gap_p90_ms: gap_percentiles[1],
+            gap_p99_ms: gap_percentiles[2],
+            jitter_ms,
+            burstiness,
+            gap_entropy: log_bucket_entropy(gaps.iter().map(|ms| ms * 1000.0)),
+            size_mean,
+            size_std,
+            size_p50: size_percentiles[0],
+            size_p90: size_percentiles[1],
+            size_p99: size_percentiles[2],
+            size_entropy: log_bucket_entropy(sizes.iter().copied()),
+        }
+    }
+
+    /// Snapshot of everything the detection models score the connection on
+    pub fn feature_vector(&self) -> FeatureVector {
+        let data_frames = self.frame_types.get(&FrameType::Data).copied().unwrap_or(0);
+        let errors = self.crc_errors + self.protocol_errors;
+        let data_ratio = if self.frame_count > 0 {
+            data_frames as f64 / self.frame_count as f64
+        } else {
+            0.0
+        };
+        // Frames that failed to decode were never counted as frames
+        let error_rate = if errors > 0 {
+            errors as f64 / (self.frame_count + errors) as f64
+        } else {
+            0.0
+        };
+
+        FeatureVector {
+            session_id: self.session_id,
+            segment: None,
+            observed_at: self.last_seen,
+            age: self.get_connection_duration(),
+            frame_count: self.frame_count,
+            byte_count: self.byte_count,
+            error_count: errors,
+            last_frame: self.last_frame,
+            frame_size: self.windowed_avg_frame_size(),
+            frames_per_second: self.windowed_frames_per_second(),
+            bytes_per_second: self.windowed_bytes_per_second(),
+            error_rate,
+            data_ratio,
+            traffic: self.features(),
         }
     }
 
@@ -105,6 +304,32 @@ impl ConnectionStats {
         }
     }
 
+    /// Seconds covered by the rate window: the connection's age up to
+    /// [`RATE_WINDOW`], but at least [`MIN_RATE_SPAN`]
+    fn window_span(&self) -> f64 {
+        self.get_connection_duration()
+            .clamp(MIN_RATE_SPAN, RATE_WINDOW)
+            .as_secs_f64()
+    }
+
+    /// Frame rate over the last [`RATE_WINDOW`]
+    pub fn windowed_frames_per_second(&self) -> f64 {
+        self.recent_frames.frames() as f64 / self.window_span()
+    }
+
+    /// Byte rate over the last [`RATE_WINDOW`]
+    pub fn windowed_bytes_per_second(&self) -> f64 {
+        self.recent_frames.bytes() as f64 / self.window_span()
+    }
+
+    /// Average size of frames within the last [`RATE_WINDOW`]
+    pub fn windowed_avg_frame_size(&self) -> f64 {
+        if self.recent_frames.is_empty() {
+            return 0.0;
+        }
+        self.recent_frames.bytes() as f64 / self.recent_frames.frames() as f64
+    }
+
     pub fn get_bytes_per_second(&self) -> f64 {
         let duration = self.get_connection_duration();
         if duration.as_secs() > 0 {
@@ -115,16 +340,252 @@ impl ConnectionStats {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Timing and size features derived from a connection's recent frames
+///
+/// Gap features describe the time between consecutive frames. Entropies are
+/// in bits, over power-of-two buckets (of bytes for sizes, microseconds for
+/// gaps): near zero when every value falls in one bucket, higher when values
+/// are spread out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficFeatures {
+    /// Number of gaps the gap features are computed from
+    pub gap_count: usize,
+    pub gap_mean_ms: f64,
+    pub gap_std_ms: f64,
+    pub gap_p50_ms: f64,
+    pub gap_p90_ms: f64,
+    pub gap_p99_ms: f64,
+    /// Mean absolute difference between consecutive gaps
+    pub jitter_ms: f64,
+    /// From -1 for perfectly regular arrivals to near 1 for bursty ones
+    pub burstiness: f64,
+    pub gap_entropy: f64,
+    pub size_mean: f64,
+    pub size_std: f64,
+    pub size_p50: f64,
+    pub size_p90: f64,
+    pub size_p99: f64,
+    pub size_entropy: f64,
+}
+
+impl TrafficFeatures {
+    /// Standard deviation of the gaps relative to their mean; near zero for
+    /// machine-regular traffic
+    pub fn gap_variation(&self) -> f64 {
+        if self.gap_mean_ms > 0.0 {
+            self.gap_std_ms / self.gap_mean_ms
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Number of values in [`FeatureVector::model_inputs`]
+pub const MODEL_FEATURE_COUNT: usize = 13;
+
+/// Names of the values in [`FeatureVector::model_inputs`], in order
+pub const MODEL_FEATURE_NAMES: [&str; MODEL_FEATURE_COUNT] = [
+    "frame_size",
+    "frames_per_second",
+    "bytes_per_second",
+    "error_rate",
+    "data_ratio",
+    "gap_mean_ms",
+    "gap_std_ms",
+    "gap_p90_ms",
+    "jitter_ms",
+    "burstiness",
+    "gap_entropy",
+    "size_std",
+    "size_entropy",
+];
+
+/// Number of values in [`FeatureVector::sequence_row`]
+pub const SEQUENCE_FEATURE_COUNT: usize = 15;
+
+/// Names of the values in [`FeatureVector::sequence_row`], in order; they
+/// are columns of the sequence-based schema in `ML_TRAINING_DATA_SCHEMA.md`
+pub const SEQUENCE_FEATURE_NAMES: [&str; SEQUENCE_FEATURE_COUNT] = [
+    "frame_type",
+    "frame_size",
+    "payload_size",
+    "time_since_last_frame_ms",
+    "cumulative_frame_count",
+    "cumulative_byte_count",
+    "avg_frame_size_so_far",
+    "frames_per_second_so_far",
+    "bytes_per_second_so_far",
+    "data_frame_ratio_so_far",
+    "cumulative_errors",
+    "error_rate_so_far",
+    "has_flags",
+    "header_count",
+    "time_since_session_start_ms",
+];
+
+/// A connection's features at one point in time, as scored by
+/// [`DetectionModel`](super::ensemble::DetectionModel)s
+#[derive(Debug, Clone)]
+pub struct FeatureVector {
+    pub session_id: SessionId,
+    /// Baseline segment the connection belongs to, if any
+    pub segment: Option<String>,
+    /// When the features were taken
+    pub observed_at: Instant,
+    /// Time from the connection's first frame to its latest
+    pub age: Duration,
+    /// Frames seen over the connection's lifetime
+    pub frame_count: u64,
+    /// Bytes seen over the connection's lifetime
+    pub byte_count: u64,
+    /// CRC and protocol errors over the connection's lifetime
+    pub error_count: u64,
+    /// The frame the features were taken after
+    pub last_frame: Option<FrameEvent>,
+    /// Average frame size over the rate window
+    pub frame_size: f64,
+    /// Frame rate over the rate window
+    pub frames_per_second: f64,
+    /// Byte rate over the rate window
+    pub bytes_per_second: f64,
+    /// Share of received frames that failed with a CRC or protocol error
+    pub error_rate: f64,
+    /// Share of frames that are data frames
+    pub data_ratio: f64,
+    pub traffic: TrafficFeatures,
+}
+
+impl FeatureVector {
+    pub fn with_segment(mut self, segment: Option<String>) -> Self {
+        self.segment = segment;
+        self
+    }
+
+    /// One time step for sequence models, named by
+    /// [`SEQUENCE_FEATURE_NAMES`], or `None` before the first frame
+    ///
+    /// Values are raw, as in the training data schema.
+    pub fn sequence_row(&self) -> Option<[f64; SEQUENCE_FEATURE_COUNT]> {
+        let frame = self.last_frame?;
+        let frames = self.frame_count as f64;
+        let age = self.age.as_secs_f64();
+        let per_second = |count: f64| if age > 0.0 { count / age } else { 0.0 };
+        Some([
+            frame.frame_type as u8 as f64,
+            frame.size as f64,
+            frame.payload_size as f64,
+            frame.gap.as_secs_f64() * 1000.0,
+            frames,
+            self.byte_count as f64,
+            if frames > 0.0 { self.byte_count as f64 / frames } else { 0.0 },
+            per_second(frames),
+            per_second(self.byte_count as f64),
+            self.data_ratio,
+            self.error_count as f64,
+            self.error_rate,
+            if frame.has_flags { 1.0 } else { 0.0 },
+            frame.header_count as f64,
+            age * 1000.0,
+        ])
+    }
+
+    /// Numeric inputs for generic models, named by [`MODEL_FEATURE_NAMES`]
+    ///
+    /// Sizes, rates and gaps span orders of magnitude, so they are passed
+    /// through `ln(1 + x)`; ratios, burstiness and entropies are left as is.
+    pub fn model_inputs(&self) -> [f64; MODEL_FEATURE_COUNT] {
+        let log = |x: f64| x.max(0.0).ln_1p();
+        let t = &self.traffic;
+        [
+            log(self.frame_size),
+            log(self.frames_per_second),
+            log(self.bytes_per_second),
+            self.error_rate,
+            self.data_ratio,
+            log(t.gap_mean_ms),
+            log(t.gap_std_ms),
+            log(t.gap_p90_ms),
+            log(t.jitter_ms),
+            t.burstiness,
+            t.gap_entropy,
+            log(t.size_std),
+            t.size_entropy,
+        ]
+    }
+}
+
+fn mean_and_std(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+/// 50th, 90th and 99th percentiles by nearest rank
+fn percentiles(values: &[f64]) -> [f64; 3] {
+    if values.is_empty() {
+        return [0.0; 3];
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    [0.5, 0.9, 0.99].map(|p| {
+        let rank = (p * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    })
+}
+
+/// Shannon entropy, in bits, of values grouped into power-of-two buckets
+fn log_bucket_entropy(values: impl Iterator<Item = f64>) -> f64 {
+    let mut buckets: HashMap<u32, usize> = HashMap::new();
+    let mut total = 0;
+    for value in values {
+        let bucket = (value.max(0.0) + 1.0).log2().floor() as u32;
+        *buckets.entry(bucket).or_insert(0) += 1;
+        total += 1;
+    }
+    buckets
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorType {
     Crc,
     Protocol,
     SuspiciousFlag,
 }
 
+impl ErrorType {
+    /// Kind of error a failure to receive a frame counts as, or `None` for
+    /// failures that say nothing about the peer, such as I/O errors
+    pub fn from_error(error: &VstpError) -> Option<Self> {
+        match error {
+            VstpError::CrcMismatch { .. } => Some(ErrorType::Crc),
+            VstpError::Protocol(_)
+            | VstpError::InvalidVersion { .. }
+            | VstpError::InvalidFrameType(_)
+            | VstpError::InvalidMagic(_)
+            | VstpError::ForgedFrame(_)
+            | VstpError::FrameTooLarge { .. }
+            | VstpError::DeserializationError
+            | VstpError::UnexpectedFrameType => Some(ErrorType::Protocol),
+            _ => None,
+        }
+    }
+}
+
 /// Global traffic monitor that tracks all connections
 pub struct TrafficMonitor {
     connections: Arc<RwLock<HashMap<SessionId, ConnectionStats>>>,
+    /// Errors from peers without a session, by IP address
+    sessionless: Arc<RwLock<HashMap<IpAddr, ConnectionStats>>>,
     global_stats: Arc<RwLock<GlobalStats>>,
     window_size: Duration,
 }
@@ -146,6 +607,7 @@ impl TrafficMonitor {
     pub fn new(window_size: Duration) -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            sessionless: Arc::new(RwLock::new(HashMap::new())),
             global_stats: Arc::new(RwLock::new(GlobalStats {
                 total_frames: 0,
                 total_bytes: 0,
@@ -221,6 +683,62 @@ impl TrafficMonitor {
         }
     }
 
+    /// Record an error for a connection, tracking it from `peer_addr` if no
+    /// frame has been recorded for it yet
+    pub async fn record_peer_error(
+        &self,
+        session_id: SessionId,
+        peer_addr: SocketAddr,
+        error_type: ErrorType,
+    ) {
+        let mut connections = self.connections.write().await;
+        connections
+            .entry(session_id)
+            .or_insert_with(|| ConnectionStats::new(session_id, peer_addr))
+            .record_error(error_type);
+        debug!(
+            "Error recorded for session {} from {}: {:?}",
+            session_id, peer_addr, error_type
+        );
+    }
+
+    /// Record an error from a peer that has no session, returning the
+    /// peer's record
+    ///
+    /// Records are kept by IP address, apart from sessions, so ports a
+    /// fuzzer cycles through share one. Once [`MAX_SESSIONLESS_PEERS`] peers
+    /// are tracked, the one quiet for longest is forgotten to make room.
+    /// The records' session ID is 0.
+    pub async fn record_sessionless_error(
+        &self,
+        peer_addr: SocketAddr,
+        error_type: ErrorType,
+    ) -> ConnectionStats {
+        let ip = peer_addr.ip().to_canonical();
+        let mut peers = self.sessionless.write().await;
+        if !peers.contains_key(&ip) && peers.len() >= MAX_SESSIONLESS_PEERS {
+            let quietest = peers
+                .iter()
+                .min_by_key(|(_, stats)| stats.last_seen)
+                .map(|(ip, _)| *ip);
+            if let Some(quietest) = quietest {
+                peers.remove(&quietest);
+            }
+        }
+        let stats = peers
+            .entry(ip)
+            .or_insert_with(|| ConnectionStats::new(0, peer_addr));
+        stats.last_seen = Instant::now();
+        stats.record_error(error_type);
+        debug!("Error recorded for {} without a session: {:?}", peer_addr, error_type);
+        stats.clone()
+    }
+
+    /// Error records of peers without a session
+    pub async fn get_sessionless_stats(&self) -> Vec<ConnectionStats> {
+        self.sessionless.read().await.values().cloned().collect()
+    }
+
     /// Get statistics for a specific connection
     pub async fn get_connection_stats(&self, session_id: SessionId) -> Option<ConnectionStats> {
         let connections = self.connections.read().await;
@@ -234,6 +752,7 @@ impl TrafficMonitor {
     }
 
     /// Get global statistics
+    #[allow(private_interfaces)]
     pub async fn get_global_stats(&self) -> GlobalStats {
         self.global_stats.read().await.clone()
     }
@@ -266,6 +785,10 @@ impl TrafficMonitor {
             connections.remove(&session_id);
             debug!("Cleaned up old connection: session {}", session_id);
         }
+        self.sessionless
+            .write()
+            .await
+            .retain(|_, stats| now.duration_since(stats.last_seen) <= self.window_size);
 
         let mut global = self.global_stats.write().await;
         global.active_connections = connections.len();
@@ -277,3 +800,92 @@ impl Default for TrafficMonitor {
         Self::new(Duration::from_secs(300)) // 5 minute window
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_with_gaps(gaps_ms: &[u64], sizes: &[usize]) -> ConnectionStats {
+        let mut stats = ConnectionStats::new(1, "127.0.0.1:1".parse().unwrap());
+        let frame = Frame::new(FrameType::Data);
+        let mut at = stats.first_seen;
+        stats.record_frame_at(&frame, sizes[0], at);
+        for (i, gap) in gaps_ms.iter().enumerate() {
+            at += Duration::from_millis(*gap);
+            stats.record_frame_at(&frame, sizes[(i + 1) % sizes.len()], at);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_gaps_are_between_consecutive_frames() {
+        let stats = stats_with_gaps(&[10, 30, 20], &[100]);
+        let gaps: Vec<_> = stats.inter_arrival_times.iter().map(|g| g.as_millis()).collect();
+        assert_eq!(gaps, vec![10, 30, 20]);
+
+        let features = stats.features();
+        assert_eq!(features.gap_count, 3);
+        assert!((features.gap_mean_ms - 20.0).abs() < 1e-9);
+        assert!((features.gap_p50_ms - 20.0).abs() < 1e-9);
+        assert!((features.gap_p99_ms - 30.0).abs() < 1e-9);
+        assert!((features.jitter_ms - 15.0).abs() < 1e-9);
+        assert_eq!(features.size_entropy, 0.0);
+    }
+
+    #[test]
+    fn test_regular_and_bursty_traffic() {
+        let regular = stats_with_gaps(&[50; 40], &[100]).features();
+        assert!((regular.burstiness + 1.0).abs() < 1e-9);
+        assert_eq!(regular.gap_variation(), 0.0);
+        assert_eq!(regular.gap_entropy, 0.0);
+
+        // Bursts of quick frames separated by long pauses
+        let gaps: Vec<u64> = (0..40).map(|i| if i % 10 == 9 { 5_000 } else { 1 }).collect();
+        let bursty = stats_with_gaps(&gaps, &[64, 1500, 64, 64, 9000]).features();
+        assert!(bursty.burstiness > 0.3, "{}", bursty.burstiness);
+        assert!(bursty.gap_entropy > 0.0);
+        assert!(bursty.size_entropy > 1.0);
+        assert!(bursty.gap_p90_ms < bursty.gap_p99_ms);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let stats = stats_with_gaps(&[1; 500], &[10]);
+        assert_eq!(stats.inter_arrival_times.len(), TIMING_HISTORY);
+        assert_eq!(stats.recent_sizes.len(), TIMING_HISTORY);
+        assert_eq!(stats.frame_count, 501);
+    }
+
+    #[tokio::test]
+    async fn test_sessionless_errors_are_bounded() {
+        let monitor = TrafficMonitor::default();
+        for i in 0..MAX_SESSIONLESS_PEERS + 10 {
+            let ip = std::net::Ipv6Addr::from(0x8000_0000_0000_0000_0000_0000_0000_0000u128 | i as u128);
+            let addr = SocketAddr::new(IpAddr::V6(ip), 4000);
+            monitor.record_sessionless_error(addr, ErrorType::Crc).await;
+        }
+        let peers = monitor.get_sessionless_stats().await;
+        assert_eq!(peers.len(), MAX_SESSIONLESS_PEERS);
+        assert!(monitor.get_all_connections().await.is_empty());
+
+        // Ports of one address share a record
+        let peer: SocketAddr = "192.0.2.1:1".parse().unwrap();
+        monitor.record_sessionless_error(peer, ErrorType::Crc).await;
+        let stats = monitor
+            .record_sessionless_error(SocketAddr::new(peer.ip(), 2), ErrorType::Protocol)
+            .await;
+        assert_eq!((stats.crc_errors, stats.protocol_errors), (1, 1));
+    }
+
+    #[test]
+    fn test_rate_window_drops_old_buckets() {
+        // 200 frames 100 ms apart span 20 s, only the last 10 s of which count
+        let stats = stats_with_gaps(&[100; 199], &[10]);
+        let window = &stats.recent_frames;
+        assert!(window.buckets.len() <= 11, "{}", window.buckets.len());
+        assert!((95..=101).contains(&window.frames()), "{}", window.frames());
+        assert_eq!(window.bytes(), window.frames() * 10);
+        assert!((stats.windowed_frames_per_second() - 10.0).abs() < 0.6);
+        assert_eq!(stats.windowed_avg_frame_size(), 10.0);
+    }
+}
@@ -0,0 +1,529 @@
+//! Externally trained models in ONNX format
+//!
+//! Enabled by the `onnx` cargo feature. An [`OnnxModel`] runs a model
+//! trained offline, such as the LSTM autoencoder proposed in
+//! `ML_MODEL_RECOMMENDATIONS.md`, on the CPU with tract and turns its output
+//! into threats. Add it to a detector with
+//! [`AnomalyDetector::with_model`](super::AnomalyDetector::with_model).
+//!
+//! The model's first input is fed either a connection's feature vector,
+//! shaped `[1, MODEL_FEATURE_COUNT]`, or its last frames as rows of the
+//! sequence schema in `ML_TRAINING_DATA_SCHEMA.md`, shaped
+//! `[1, length, SEQUENCE_FEATURE_COUNT]`. Its first output is read either as
+//! a reconstruction of the input, whose error is the anomaly score, or as
+//! one score per class.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+use tract_onnx::prelude::*;
+
+use crate::core::types::{SessionId, VstpError};
+
+use super::ensemble::DetectionModel;
+use super::monitor::{FeatureVector, MODEL_FEATURE_COUNT, SEQUENCE_FEATURE_COUNT};
+use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
+
+/// Limit on the number of connections whose recent frames are kept
+const MAX_WINDOWS: usize = 10_000;
+
+/// What the model is fed
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnnxInput {
+    /// [`FeatureVector::model_inputs`], shaped `[1, MODEL_FEATURE_COUNT]`
+    Features,
+    /// The connection's last `length` [`FeatureVector::sequence_row`]s,
+    /// oldest first and zero-padded at the front, shaped
+    /// `[1, length, SEQUENCE_FEATURE_COUNT]`
+    FrameWindow { length: usize },
+}
+
+impl OnnxInput {
+    fn shape(&self) -> Vec<usize> {
+        match self {
+            OnnxInput::Features => vec![1, MODEL_FEATURE_COUNT],
+            OnnxInput::FrameWindow { length } => vec![1, (*length).max(1), SEQUENCE_FEATURE_COUNT],
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            OnnxInput::Features => MODEL_FEATURE_COUNT,
+            OnnxInput::FrameWindow { .. } => SEQUENCE_FEATURE_COUNT,
+        }
+    }
+}
+
+/// How the model's output is read
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnnxOutput {
+    /// The model reconstructs its input, as an autoencoder does; a mean
+    /// squared error above `threshold` is reported as `pattern`
+    ReconstructionError {
+        threshold: f64,
+        pattern: AttackPattern,
+    },
+    /// The model scores each class; `classes[i]` is the pattern for output
+    /// `i`, `None` for normal traffic. The top class is reported when it is
+    /// a pattern scoring at least `min_score`
+    ClassScores {
+        classes: Vec<Option<AttackPattern>>,
+        /// Apply softmax to the outputs first, for models that emit logits
+        softmax: bool,
+        min_score: f64,
+    },
+}
+
+/// Per-feature standardization the model was trained with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scaler {
+    pub mean: Vec<f64>,
+    pub std: Vec<f64>,
+}
+
+impl Scaler {
+    fn apply(&self, row: &mut [f64]) {
+        for (i, value) in row.iter_mut().enumerate() {
+            let mean = self.mean.get(i).copied().unwrap_or(0.0);
+            let std = self.std.get(i).copied().filter(|s| *s > 0.0).unwrap_or(1.0);
+            *value = (*value - mean) / std;
+        }
+    }
+}
+
+/// ONNX model settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnnxModelConfig {
+    /// Name shown in threat indicators and used to change the model's weight
+    pub name: String,
+    pub input: OnnxInput,
+    pub output: OnnxOutput,
+    /// Standardization applied to every input row before inference
+    pub scaler: Option<Scaler>,
+}
+
+impl OnnxModelConfig {
+    pub fn new(input: OnnxInput, output: OnnxOutput) -> Self {
+        Self {
+            name: "onnx".to_string(),
+            input,
+            output,
+            scaler: None,
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Standardize inputs with the mean and standard deviation of each
+    /// feature in the training data
+    pub fn with_scaler(mut self, mean: Vec<f64>, std: Vec<f64>) -> Self {
+        self.scaler = Some(Scaler { mean, std });
+        self
+    }
+}
+
+/// A trained ONNX model run as a [`DetectionModel`]
+///
+/// The model itself does not learn; [`learn`](DetectionModel::learn) only
+/// records each connection's frames for [`OnnxInput::FrameWindow`], so it
+/// must be called once per frame, as the detector does.
+pub struct OnnxModel {
+    config: OnnxModelConfig,
+    plan: Arc<TypedRunnableModel>,
+    windows: HashMap<SessionId, VecDeque<[f64; SEQUENCE_FEATURE_COUNT]>>,
+}
+
+impl std::fmt::Debug for OnnxModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnnxModel")
+            .field("config", &self.config)
+            .field("sessions", &self.windows.len())
+            .finish()
+    }
+}
+
+fn onnx_error(e: impl std::fmt::Display) -> VstpError {
+    VstpError::Protocol(format!("ONNX model error: {}", e))
+}
+
+impl OnnxModel {
+    /// Load an ONNX model file
+    pub fn load(path: impl AsRef<Path>, config: OnnxModelConfig) -> Result<Self, VstpError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes, config)
+    }
+
+    /// Load an ONNX model from memory
+    pub fn from_bytes(bytes: &[u8], config: OnnxModelConfig) -> Result<Self, VstpError> {
+        if let OnnxOutput::ClassScores { classes, .. } = &config.output {
+            if classes.is_empty() {
+                return Err(VstpError::Protocol(
+                    "ONNX model error: no classes given for class scores".to_string(),
+                ));
+            }
+        }
+        let plan = tract_onnx::onnx()
+            .model_for_read(&mut &bytes[..])
+            .and_then(|model| model.with_input_fact(0, f32::fact(config.input.shape()).into()))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(onnx_error)?;
+        Ok(Self {
+            config,
+            plan,
+            windows: HashMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &OnnxModelConfig {
+        &self.config
+    }
+
+    /// The model input for a connection, or `None` if it has no frames yet,
+    /// along with the number of leading values that are padding
+    fn input(&self, features: &FeatureVector) -> Option<(Vec<f64>, usize)> {
+        let width = self.config.input.width();
+        let (mut values, padding) = match &self.config.input {
+            OnnxInput::Features => (features.model_inputs().to_vec(), 0),
+            OnnxInput::FrameWindow { length } => {
+                let window = self.windows.get(&features.session_id)?;
+                let length = (*length).max(1);
+                let rows = window.len().min(length);
+                let padding = (length - rows) * width;
+                let mut values = vec![0.0; padding];
+                for row in window.iter().skip(window.len() - rows) {
+                    values.extend_from_slice(row);
+                }
+                (values, padding)
+            }
+        };
+        if let Some(scaler) = &self.config.scaler {
+            for row in values[padding..].chunks_mut(width) {
+                scaler.apply(row);
+            }
+        }
+        Some((values, padding))
+    }
+
+    /// Run the model on an input
+    fn infer(&self, values: &[f64]) -> Result<Vec<f64>, VstpError> {
+        let values: Vec<f32> = values.iter().map(|v| *v as f32).collect();
+        let input = Tensor::from_shape(&self.config.input.shape(), &values).map_err(onnx_error)?;
+        let outputs = self.plan.run(tvec!(input.into())).map_err(onnx_error)?;
+        let output = outputs
+            .first()
+            .ok_or_else(|| onnx_error("model has no outputs"))?
+            .cast_to::<f32>()
+            .map_err(onnx_error)?;
+        let output = output.to_plain_array_view::<f32>().map_err(onnx_error)?;
+        Ok(output.iter().map(|v| *v as f64).collect())
+    }
+
+    fn reconstruction_threat(
+        &self,
+        input: &[f64],
+        padding: usize,
+        output: &[f64],
+        threshold: f64,
+        pattern: AttackPattern,
+    ) -> Option<ThreatDetection> {
+        if output.len() != input.len() {
+            warn!(
+                "ONNX model {} returned {} values for {} inputs; expected a reconstruction",
+                self.config.name,
+                output.len(),
+                input.len()
+            );
+            return None;
+        }
+        // Padding is not traffic, so it does not count towards the error
+        let real = &input[padding..];
+        let error = real
+            .iter()
+            .zip(&output[padding..])
+            .map(|(x, y)| (x - y).powi(2))
+            .sum::<f64>()
+            / real.len().max(1) as f64;
+        if threshold <= 0.0 || error <= threshold {
+            return None;
+        }
+        // The threshold maps to 0.5 confidence, twice it to 0.75
+        let confidence = 1.0 - 0.5 * threshold / error;
+        Some(
+            ThreatDetection::new(
+                pattern,
+                ThreatLevel::from_score(confidence),
+                confidence,
+                format!(
+                    "Traffic the {} model cannot reconstruct (error {:.4})",
+                    self.config.name, error
+                ),
+            )
+            .with_indicator(format!(
+                "Reconstruction error: {:.4} (threshold {:.4})",
+                error, threshold
+            )),
+        )
+    }
+
+    fn class_threat(
+        &self,
+        output: &[f64],
+        classes: &[Option<AttackPattern>],
+        softmax: bool,
+        min_score: f64,
+    ) -> Option<ThreatDetection> {
+        if output.len() != classes.len() {
+            warn!(
+                "ONNX model {} returned {} scores for {} classes",
+                self.config.name,
+                output.len(),
+                classes.len()
+            );
+            return None;
+        }
+        let scores = if softmax {
+            let max = output.iter().copied().fold(f64::MIN, f64::max);
+            let exp: Vec<f64> = output.iter().map(|v| (v - max).exp()).collect();
+            let total: f64 = exp.iter().sum();
+            exp.iter().map(|v| v / total).collect()
+        } else {
+            output.to_vec()
+        };
+        let (class, score) = scores
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let pattern = classes[class]?;
+        if score < min_score {
+            return None;
+        }
+        Some(
+            ThreatDetection::new(
+                pattern,
+                ThreatLevel::from_score(score),
+                score,
+                format!("{} classified by the {} model", pattern.description(), self.config.name),
+            )
+            .with_indicator(format!("Class {} score: {:.3}", class, score)),
+        )
+    }
+}
+
+impl DetectionModel for OnnxModel {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn learn(&mut self, features: &FeatureVector) {
+        let OnnxInput::FrameWindow { length } = self.config.input else {
+            return;
+        };
+        let Some(row) = features.sequence_row() else {
+            return;
+        };
+        if !self.windows.contains_key(&features.session_id) && self.windows.len() >= MAX_WINDOWS {
+            // Connections that ended without end_session; start over
+            self.windows.clear();
+        }
+        let window = self.windows.entry(features.session_id).or_default();
+        window.push_back(row);
+        while window.len() > length.max(1) {
+            window.pop_front();
+        }
+    }
+
+    fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        let Some((input, padding)) = self.input(features) else {
+            return Vec::new();
+        };
+        let output = match self.infer(&input) {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("{} ({})", e, self.config.name);
+                return Vec::new();
+            }
+        };
+        let threat = match &self.config.output {
+            OnnxOutput::ReconstructionError { threshold, pattern } => {
+                self.reconstruction_threat(&input, padding, &output, *threshold, *pattern)
+            }
+            OnnxOutput::ClassScores {
+                classes,
+                softmax,
+                min_score,
+            } => self.class_threat(&output, classes, *softmax, *min_score),
+        };
+        threat.into_iter().collect()
+    }
+
+    fn end_session(&mut self, session_id: SessionId) {
+        self.windows.remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{Frame, FrameType};
+    use crate::security::ai::monitor::ConnectionStats;
+    use prost::Message;
+    use tract_onnx::pb;
+
+    fn tensor_type(shape: &[usize]) -> pb::TypeProto {
+        let dim = shape
+            .iter()
+            .map(|&d| pb::tensor_shape_proto::Dimension {
+                value: Some(pb::tensor_shape_proto::dimension::Value::DimValue(d as i64)),
+                ..Default::default()
+            })
+            .collect();
+        pb::TypeProto {
+            value: Some(pb::type_proto::Value::TensorType(pb::type_proto::Tensor {
+                elem_type: pb::tensor_proto::DataType::Float as i32,
+                shape: Some(pb::TensorShapeProto { dim }),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// A one-node model computing `y = op(x, w)`
+    fn model(op: &str, input: &[usize], weight: (&[usize], Vec<f32>), output: &[usize]) -> Vec<u8> {
+        let value = |name: &str, shape: &[usize]| pb::ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(tensor_type(shape)),
+            ..Default::default()
+        };
+        let graph = pb::GraphProto {
+            name: "test".to_string(),
+            node: vec![pb::NodeProto {
+                input: vec!["x".to_string(), "w".to_string()],
+                output: vec!["y".to_string()],
+                op_type: op.to_string(),
+                ..Default::default()
+            }],
+            initializer: vec![pb::TensorProto {
+                name: "w".to_string(),
+                dims: weight.0.iter().map(|&d| d as i64).collect(),
+                data_type: pb::tensor_proto::DataType::Float as i32,
+                float_data: weight.1,
+                ..Default::default()
+            }],
+            input: vec![value("x", input)],
+            output: vec![value("y", output)],
+            ..Default::default()
+        };
+        pb::ModelProto {
+            ir_version: 7,
+            opset_import: vec![pb::OperatorSetIdProto {
+                domain: String::new(),
+                version: 13,
+            }],
+            graph: Some(graph),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    fn connection(sizes: &[usize]) -> FeatureVector {
+        let mut stats = ConnectionStats::new(1, "127.0.0.1:1".parse().unwrap());
+        for &size in sizes {
+            stats.record_frame(&Frame::new(FrameType::Data), size);
+        }
+        stats.feature_vector()
+    }
+
+    #[test]
+    fn test_reconstruction_error() {
+        // An "autoencoder" that only reproduces half of its input
+        let halve = model("Mul", &[1, MODEL_FEATURE_COUNT], (&[], vec![0.5]), &[1, MODEL_FEATURE_COUNT]);
+        let normal = connection(&[100, 100]);
+        let config = OnnxModelConfig::new(
+            OnnxInput::Features,
+            OnnxOutput::ReconstructionError {
+                threshold: 0.5,
+                pattern: AttackPattern::AnomalousTraffic,
+            },
+        )
+        .with_scaler(normal.model_inputs().to_vec(), vec![1.0; MODEL_FEATURE_COUNT]);
+        let model = OnnxModel::from_bytes(&halve, config).unwrap();
+
+        // Standardized normal traffic is all zeros, which halving keeps
+        assert!(model.score(&normal).is_empty());
+        let threats = model.score(&connection(&[60_000, 60_000]));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].pattern, AttackPattern::AnomalousTraffic);
+        assert!(threats[0].confidence > 0.5);
+    }
+
+    #[test]
+    fn test_class_scores() {
+        // Logit of class 1 is ln(1 + frame size); the others are 0
+        let mut weights = vec![0.0; MODEL_FEATURE_COUNT * 3];
+        weights[1] = 1.0;
+        let classify = model(
+            "MatMul",
+            &[1, MODEL_FEATURE_COUNT],
+            (&[MODEL_FEATURE_COUNT, 3], weights),
+            &[1, 3],
+        );
+        let config = OnnxModelConfig::new(
+            OnnxInput::Features,
+            OnnxOutput::ClassScores {
+                classes: vec![None, Some(AttackPattern::DataExfiltration), Some(AttackPattern::ConnectionFlood)],
+                softmax: true,
+                min_score: 0.9,
+            },
+        )
+        .with_name("classifier");
+        let model = OnnxModel::from_bytes(&classify, config).unwrap();
+        assert_eq!(model.name(), "classifier");
+
+        assert!(model.score(&connection(&[])).is_empty());
+        let threats = model.score(&connection(&[60_000]));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].pattern, AttackPattern::DataExfiltration);
+        assert!(threats[0].confidence > 0.99);
+    }
+
+    #[test]
+    fn test_frame_window() {
+        let shape = [1, 4, SEQUENCE_FEATURE_COUNT];
+        let zero = model("Mul", &shape, (&[], vec![0.0]), &shape);
+        let config = OnnxModelConfig::new(
+            OnnxInput::FrameWindow { length: 4 },
+            OnnxOutput::ReconstructionError {
+                threshold: 1e12,
+                pattern: AttackPattern::AnomalousTraffic,
+            },
+        );
+        let mut model = OnnxModel::from_bytes(&zero, config).unwrap();
+
+        let mut stats = ConnectionStats::new(7, "127.0.0.1:1".parse().unwrap());
+        assert!(model.score(&stats.feature_vector()).is_empty());
+        for size in [10, 20, 30, 40, 50, 60] {
+            stats.record_frame(&Frame::new(FrameType::Data), size);
+            model.learn(&stats.feature_vector());
+        }
+
+        let (input, padding) = model.input(&stats.feature_vector()).unwrap();
+        assert_eq!(padding, 0);
+        assert_eq!(input.len(), 4 * SEQUENCE_FEATURE_COUNT);
+        // Oldest kept frame first
+        assert_eq!(input[1], 30.0);
+        assert_eq!(input[3 * SEQUENCE_FEATURE_COUNT + 1], 60.0);
+
+        model.end_session(7);
+        assert!(model.input(&stats.feature_vector()).is_none());
+        assert!(OnnxModel::from_bytes(b"not a model", OnnxModelConfig::new(
+            OnnxInput::Features,
+            OnnxOutput::ClassScores { classes: vec![None], softmax: false, min_score: 0.5 },
+        ))
+        .is_err());
+    }
+}
@@ -3,7 +3,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Types of attack patterns that can be detected
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AttackPattern {
     /// Packet theft / Sniffing attempt
     PacketTheft,
@@ -42,6 +42,22 @@ impl AttackPattern {
             AttackPattern::SuspiciousActivity => "Suspicious activity detected",
         }
     }
+
+    /// Name of the pattern in the `threat_type` column of training data
+    pub fn label(&self) -> &'static str {
+        match self {
+            AttackPattern::PacketTheft => "packet_theft",
+            AttackPattern::ManInTheMiddle => "mitm",
+            AttackPattern::ReplayAttack => "replay_attack",
+            AttackPattern::ProtocolViolation => "protocol_violation",
+            AttackPattern::AnomalousTraffic => "anomalous_traffic",
+            AttackPattern::ConnectionFlood => "connection_flood",
+            AttackPattern::UnauthorizedAccess => "unauthorized_access",
+            AttackPattern::DataExfiltration => "data_exfiltration",
+            AttackPattern::TimingAttack => "timing_attack",
+            AttackPattern::SuspiciousActivity => "suspicious_activity",
+        }
+    }
 }
 
 /// Threat level classification
@@ -103,7 +119,7 @@ impl ThreatDetection {
         Self {
             pattern,
             threat_level,
-            confidence: confidence.min(1.0).max(0.0),
+            confidence: confidence.clamp(0.0, 1.0),
             description,
             session_id: None,
             timestamp: std::time::SystemTime::now()
@@ -0,0 +1,431 @@
+//! Graduated automated responses to detected threats
+//!
+//! A [`ResponsePolicy`] maps a threat's pattern, level and confidence to a
+//! [`ResponseAction`], from logging it through throttling the session to
+//! banning the peer. The detector decides the action as it records a threat
+//! and the server owning the session carries out the parts only it can,
+//! such as sending a warning or closing the connection.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::core::types::SessionId;
+
+use super::patterns::{AttackPattern, ThreatDetection, ThreatLevel};
+
+/// What to do about a threat, from mildest to harshest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseAction {
+    /// Log the threat and do nothing else
+    Log,
+    /// Send the peer an ERR frame warning it and keep the session open
+    Warn,
+    /// Space the session's frames at least `delay` apart for `duration`
+    Throttle { delay: Duration, duration: Duration },
+    /// Close the session so the peer has to authenticate again
+    ///
+    /// There is no re-authentication round within a session: servers carry
+    /// this out like [`Disconnect`](Self::Disconnect), telling the peer why
+    /// first, and the client reconnects and authenticates from scratch.
+    Reauthenticate,
+    /// Close the session
+    Disconnect,
+    /// Close the session and ban the peer's address for the given time, or
+    /// for the blocklist's escalating ban if `None`
+    Ban(Option<Duration>),
+}
+
+impl ResponseAction {
+    fn severity(&self) -> u8 {
+        match self {
+            ResponseAction::Log => 0,
+            ResponseAction::Warn => 1,
+            ResponseAction::Throttle { .. } => 2,
+            ResponseAction::Reauthenticate => 3,
+            ResponseAction::Disconnect => 4,
+            ResponseAction::Ban(_) => 5,
+        }
+    }
+
+    /// Whether the server should close the session
+    pub fn ends_session(&self) -> bool {
+        matches!(
+            self,
+            ResponseAction::Reauthenticate | ResponseAction::Disconnect | ResponseAction::Ban(_)
+        )
+    }
+
+    /// Message to send the peer in an ERR frame, if the action tells it
+    pub fn notice(&self) -> Option<&'static str> {
+        match self {
+            ResponseAction::Log | ResponseAction::Throttle { .. } => None,
+            ResponseAction::Warn => Some("warning: suspicious traffic detected"),
+            ResponseAction::Reauthenticate => Some("re-authentication required"),
+            ResponseAction::Disconnect => Some("disconnected due to suspicious traffic"),
+            ResponseAction::Ban(_) => Some("banned due to security threat"),
+        }
+    }
+}
+
+/// Respond with `action` to threats at or above a level and confidence
+#[derive(Debug, Clone)]
+pub struct ResponseRule {
+    /// Lowest threat level the rule applies to
+    pub min_level: ThreatLevel,
+    /// Lowest confidence the rule applies to
+    pub min_confidence: f64,
+    /// What to do
+    pub action: ResponseAction,
+    /// How long after firing for a session the rule stays quiet for that
+    /// session and pattern
+    pub cooldown: Duration,
+}
+
+impl ResponseRule {
+    /// Apply `action` to threats of `min_level` or above, at any confidence
+    pub fn new(min_level: ThreatLevel, action: ResponseAction) -> Self {
+        Self {
+            min_level,
+            min_confidence: 0.0,
+            action,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_min_confidence(mut self, min_confidence: f64) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    fn matches(&self, threat: &ThreatDetection) -> bool {
+        threat.threat_level >= self.min_level && threat.confidence >= self.min_confidence
+    }
+}
+
+/// Rules deciding how to respond to each attack pattern
+///
+/// Patterns with rules of their own use only those; every other pattern
+/// uses the default rules. When several rules match a threat the harshest
+/// action wins.
+///
+/// A policy starts with no rules, so threats are only logged;
+/// [`ResponsePolicy::graduated`] is a reasonable starting point.
+#[derive(Debug, Clone, Default)]
+pub struct ResponsePolicy {
+    rules: HashMap<AttackPattern, Vec<ResponseRule>>,
+    default_rules: Vec<ResponseRule>,
+}
+
+impl ResponsePolicy {
+    /// Create a policy without rules
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warn on medium threats, throttle on high ones and disconnect on
+    /// critical ones, banning the peer instead for critical replay and
+    /// man-in-the-middle attacks
+    pub fn graduated() -> Self {
+        let ban = ResponseRule::new(ThreatLevel::Critical, ResponseAction::Ban(None));
+        Self::new()
+            .with_default_rule(ResponseRule::new(ThreatLevel::Medium, ResponseAction::Warn))
+            .with_default_rule(ResponseRule::new(
+                ThreatLevel::High,
+                ResponseAction::Throttle {
+                    delay: Duration::from_millis(50),
+                    duration: Duration::from_secs(60),
+                },
+            ))
+            .with_default_rule(ResponseRule::new(
+                ThreatLevel::Critical,
+                ResponseAction::Disconnect,
+            ))
+            .with_rule(AttackPattern::ReplayAttack, ban.clone())
+            .with_rule(AttackPattern::ManInTheMiddle, ban)
+    }
+
+    /// Add a rule for `pattern`, which then stops using the default rules
+    pub fn with_rule(mut self, pattern: AttackPattern, rule: ResponseRule) -> Self {
+        self.rules.entry(pattern).or_default().push(rule);
+        self
+    }
+
+    /// Add a rule for patterns without rules of their own
+    pub fn with_default_rule(mut self, rule: ResponseRule) -> Self {
+        self.default_rules.push(rule);
+        self
+    }
+
+    /// Rules that apply to `pattern`
+    pub fn rules_for(&self, pattern: AttackPattern) -> &[ResponseRule] {
+        self.rules
+            .get(&pattern)
+            .map(Vec::as_slice)
+            .unwrap_or(&self.default_rules)
+    }
+
+    /// The harshest rule matching `threat`
+    pub fn decide(&self, threat: &ThreatDetection) -> Option<&ResponseRule> {
+        self.rules_for(threat.pattern)
+            .iter()
+            .filter(|rule| rule.matches(threat))
+            .max_by_key(|rule| rule.action.severity())
+    }
+}
+
+/// Session whose frames are being spaced out
+struct Throttle {
+    delay: Duration,
+    until: Instant,
+    next: Instant,
+}
+
+#[derive(Default)]
+struct ResponderState {
+    /// When each (session, pattern, action) may fire again
+    cooldowns: HashMap<(SessionId, AttackPattern, u8), Instant>,
+    throttles: HashMap<SessionId, Throttle>,
+    /// Actions the session's server has yet to carry out
+    pending: HashMap<SessionId, Vec<ResponseAction>>,
+}
+
+/// Applies a [`ResponsePolicy`] and keeps the state its actions need
+pub(crate) struct Responder {
+    policy: ResponsePolicy,
+    state: Mutex<ResponderState>,
+}
+
+impl Responder {
+    pub(crate) fn new(policy: ResponsePolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ResponderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Decide how to respond to a threat on a session, or `None` if no rule
+    /// matches or the matching one is cooling down
+    pub(crate) fn decide(
+        &self,
+        session_id: SessionId,
+        threat: &ThreatDetection,
+    ) -> Option<ResponseAction> {
+        let rule = self.policy.decide(threat)?;
+        let now = Instant::now();
+        let key = (session_id, threat.pattern, rule.action.severity());
+        let mut state = self.state();
+        if state.cooldowns.get(&key).is_some_and(|until| *until > now) {
+            return None;
+        }
+        state.cooldowns.insert(key, now + rule.cooldown);
+        Some(rule.action)
+    }
+
+    /// Start a throttle or queue an action for the session's server
+    pub(crate) fn apply(&self, session_id: SessionId, action: ResponseAction) {
+        let mut state = self.state();
+        match action {
+            ResponseAction::Log => {}
+            ResponseAction::Throttle { delay, duration } => {
+                let now = Instant::now();
+                state.throttles.insert(
+                    session_id,
+                    Throttle {
+                        delay,
+                        until: now + duration,
+                        next: now,
+                    },
+                );
+            }
+            _ => {
+                let pending = state.pending.entry(session_id).or_default();
+                if !pending.contains(&action) {
+                    pending.push(action);
+                }
+            }
+        }
+    }
+
+    /// Take the actions queued for a session
+    pub(crate) fn take(&self, session_id: SessionId) -> Vec<ResponseAction> {
+        self.state().pending.remove(&session_id).unwrap_or_default()
+    }
+
+    /// How long to hold a session's next frame back, reserving its slot
+    pub(crate) fn throttle_delay(&self, session_id: SessionId) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let throttle = state.throttles.get_mut(&session_id)?;
+        if throttle.until <= now {
+            state.throttles.remove(&session_id);
+            return None;
+        }
+        let slot = throttle.next.max(now);
+        throttle.next = slot + throttle.delay;
+        Some(slot - now).filter(|wait| !wait.is_zero())
+    }
+
+    /// Whether a throttled session's frame arriving now is on time, taking
+    /// the next slot if so; early frames are refused without taking one
+    pub(crate) fn throttle_admit(&self, session_id: SessionId) -> bool {
+        let now = Instant::now();
+        let mut state = self.state();
+        let Some(throttle) = state.throttles.get_mut(&session_id) else {
+            return true;
+        };
+        if throttle.until <= now {
+            state.throttles.remove(&session_id);
+            return true;
+        }
+        if throttle.next > now {
+            return false;
+        }
+        throttle.next = now + throttle.delay;
+        true
+    }
+
+    /// Forget a session that has closed
+    pub(crate) fn end_session(&self, session_id: SessionId) {
+        let mut state = self.state();
+        state.cooldowns.retain(|(id, _, _), _| *id != session_id);
+        state.throttles.remove(&session_id);
+        state.pending.remove(&session_id);
+    }
+
+    /// Drop expired cooldowns and throttles, and actions queued for sessions
+    /// nothing has fired on recently, whose server has likely gone
+    pub(crate) fn purge_expired(&self) {
+        let now = Instant::now();
+        let mut state = self.state();
+        state.cooldowns.retain(|_, until| *until > now);
+        state.throttles.retain(|_, throttle| throttle.until > now);
+        let ResponderState {
+            cooldowns, pending, ..
+        } = &mut *state;
+        pending.retain(|session_id, _| cooldowns.keys().any(|(id, _, _)| id == session_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threat(pattern: AttackPattern, level: ThreatLevel, confidence: f64) -> ThreatDetection {
+        ThreatDetection::new(pattern, level, confidence, "test".to_string())
+    }
+
+    #[test]
+    fn test_harshest_matching_rule_wins() {
+        let policy = ResponsePolicy::graduated();
+
+        let low = threat(AttackPattern::AnomalousTraffic, ThreatLevel::Low, 0.9);
+        assert!(policy.decide(&low).is_none());
+        let medium = threat(AttackPattern::AnomalousTraffic, ThreatLevel::Medium, 0.9);
+        assert_eq!(policy.decide(&medium).unwrap().action, ResponseAction::Warn);
+        let critical = threat(AttackPattern::AnomalousTraffic, ThreatLevel::Critical, 0.9);
+        assert_eq!(
+            policy.decide(&critical).unwrap().action,
+            ResponseAction::Disconnect
+        );
+
+        // Patterns with their own rules ignore the default ones
+        let replay = threat(AttackPattern::ReplayAttack, ThreatLevel::High, 1.0);
+        assert!(policy.decide(&replay).is_none());
+        let replay = threat(AttackPattern::ReplayAttack, ThreatLevel::Critical, 1.0);
+        assert_eq!(
+            policy.decide(&replay).unwrap().action,
+            ResponseAction::Ban(None)
+        );
+    }
+
+    #[test]
+    fn test_min_confidence() {
+        let policy = ResponsePolicy::new().with_rule(
+            AttackPattern::PacketTheft,
+            ResponseRule::new(ThreatLevel::Low, ResponseAction::Disconnect)
+                .with_min_confidence(0.8),
+        );
+        let unsure = threat(AttackPattern::PacketTheft, ThreatLevel::High, 0.6);
+        assert!(policy.decide(&unsure).is_none());
+        let sure = threat(AttackPattern::PacketTheft, ThreatLevel::High, 0.9);
+        assert!(policy.decide(&sure).is_some());
+        assert!(ResponsePolicy::new().decide(&sure).is_none());
+    }
+
+    #[test]
+    fn test_cooldown_suppresses_repeats_but_not_escalation() {
+        let responder = Responder::new(ResponsePolicy::graduated());
+        let medium = threat(AttackPattern::AnomalousTraffic, ThreatLevel::Medium, 0.9);
+        let critical = threat(AttackPattern::AnomalousTraffic, ThreatLevel::Critical, 0.9);
+
+        assert_eq!(responder.decide(1, &medium), Some(ResponseAction::Warn));
+        assert_eq!(responder.decide(1, &medium), None);
+        assert_eq!(responder.decide(2, &medium), Some(ResponseAction::Warn));
+        assert_eq!(responder.decide(1, &critical), Some(ResponseAction::Disconnect));
+
+        responder.end_session(1);
+        assert_eq!(responder.decide(1, &medium), Some(ResponseAction::Warn));
+    }
+
+    #[test]
+    fn test_pending_actions_and_throttle() {
+        let responder = Responder::new(ResponsePolicy::new());
+        responder.apply(1, ResponseAction::Log);
+        responder.apply(1, ResponseAction::Warn);
+        responder.apply(1, ResponseAction::Warn);
+        responder.apply(1, ResponseAction::Disconnect);
+        assert_eq!(
+            responder.take(1),
+            vec![ResponseAction::Warn, ResponseAction::Disconnect]
+        );
+        assert!(responder.take(1).is_empty());
+
+        assert!(responder.throttle_delay(1).is_none());
+        let delay = Duration::from_secs(1);
+        responder.apply(
+            1,
+            ResponseAction::Throttle {
+                delay,
+                duration: Duration::from_secs(60),
+            },
+        );
+        assert!(responder.throttle_delay(1).is_none());
+        let wait = responder.throttle_delay(1).unwrap();
+        assert!(wait > delay / 2 && wait <= delay);
+        let wait = responder.throttle_delay(1).unwrap();
+        assert!(wait > delay && wait <= delay * 2);
+        assert!(responder.throttle_delay(2).is_none());
+    }
+
+    #[test]
+    fn test_throttle_admit_refuses_early_frames() {
+        let responder = Responder::new(ResponsePolicy::new());
+        assert!(responder.throttle_admit(1));
+        responder.apply(
+            1,
+            ResponseAction::Throttle {
+                delay: Duration::from_millis(50),
+                duration: Duration::from_secs(60),
+            },
+        );
+        assert!(responder.throttle_admit(1));
+        // Refused frames do not push the next slot back
+        for _ in 0..10 {
+            assert!(!responder.throttle_admit(1));
+        }
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(responder.throttle_admit(1));
+        assert!(!responder.throttle_admit(1));
+        assert!(responder.throttle_admit(2));
+    }
+}
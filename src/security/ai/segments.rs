@@ -0,0 +1,400 @@
+//! Baselines segmented by peer, principal or route
+//!
+//! One global baseline compares a bulk-upload client and a chat client
+//! against the same averages. A [`SegmentedBaseline`] keeps a separate
+//! [`BaselineModel`] per segment key, chosen by a [`Segmentation`], next to
+//! the global one. Until a segment has seen
+//! [`BaselineConfig::min_samples`] samples its connections are scored against
+//! the global baseline instead.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::core::types::{Frame, SessionId};
+
+use super::ensemble::DetectionModel;
+use super::models::{BaselineConfig, BaselineModel, BaselineState};
+use super::monitor::{ConnectionStats, FeatureVector};
+use super::patterns::ThreatDetection;
+
+/// Default limit on the number of segment baselines kept
+pub const DEFAULT_MAX_SEGMENTS: usize = 10_000;
+
+/// What a [`Segmentation`] can look at to pick a segment
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentInput<'a> {
+    pub session_id: SessionId,
+    pub peer_addr: SocketAddr,
+    /// ID of the session's authenticated principal, if known
+    pub principal: Option<&'a str>,
+    pub frame: &'a Frame,
+}
+
+/// Custom segment classifier; `None` leaves the frame to the global baseline
+pub type Classifier = Arc<dyn Fn(&SegmentInput<'_>) -> Option<String> + Send + Sync>;
+
+/// How connections are grouped into baselines
+#[derive(Clone, Default)]
+pub enum Segmentation {
+    /// A single global baseline
+    #[default]
+    Global,
+    /// One baseline per peer IP address
+    PeerIp,
+    /// One baseline per authenticated principal
+    Principal,
+    /// One baseline per value of a frame header, such as a route
+    Header(String),
+    /// Segment key chosen by a function
+    Custom(Classifier),
+}
+
+impl std::fmt::Debug for Segmentation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Segmentation::Global => write!(f, "Global"),
+            Segmentation::PeerIp => write!(f, "PeerIp"),
+            Segmentation::Principal => write!(f, "Principal"),
+            Segmentation::Header(name) => f.debug_tuple("Header").field(name).finish(),
+            Segmentation::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl Segmentation {
+    /// Segment key for a frame, or `None` for the global baseline only
+    pub fn key(&self, input: &SegmentInput<'_>) -> Option<String> {
+        match self {
+            Segmentation::Global => None,
+            Segmentation::PeerIp => Some(input.peer_addr.ip().to_string()),
+            Segmentation::Principal => input.principal.map(str::to_string),
+            Segmentation::Header(name) => input.frame.get_header(name).map(str::to_string),
+            Segmentation::Custom(classify) => classify(input),
+        }
+    }
+}
+
+/// What a [`SegmentedBaseline`] has learned, as saved to disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaselineSnapshot {
+    pub global: BaselineState,
+    pub segments: HashMap<String, BaselineState>,
+}
+
+/// A global baseline plus one per segment
+#[derive(Debug, Clone)]
+pub struct SegmentedBaseline {
+    config: BaselineConfig,
+    segmentation: Segmentation,
+    global: BaselineModel,
+    segments: HashMap<String, BaselineModel>,
+    max_segments: usize,
+}
+
+impl SegmentedBaseline {
+    pub fn new(config: BaselineConfig, segmentation: Segmentation) -> Self {
+        Self {
+            global: BaselineModel::with_config(config.clone()),
+            config,
+            segmentation,
+            segments: HashMap::new(),
+            max_segments: DEFAULT_MAX_SEGMENTS,
+        }
+    }
+
+    /// Keep at most `max` segment baselines, dropping the least recently
+    /// updated one to make room
+    pub fn with_max_segments(mut self, max: usize) -> Self {
+        self.max_segments = max.max(1);
+        self
+    }
+
+    pub fn segmentation(&self) -> &Segmentation {
+        &self.segmentation
+    }
+
+    /// Segment key for a frame
+    pub fn key(&self, input: &SegmentInput<'_>) -> Option<String> {
+        self.segmentation.key(input)
+    }
+
+    pub fn global(&self) -> &BaselineModel {
+        &self.global
+    }
+
+    pub fn segment(&self, key: &str) -> Option<&BaselineModel> {
+        self.segments.get(key)
+    }
+
+    /// Keys of the segments with a baseline of their own
+    pub fn segment_keys(&self) -> Vec<String> {
+        self.segments.keys().cloned().collect()
+    }
+
+    /// Fold a connection's statistics into the global baseline and its
+    /// segment's
+    pub fn update_baseline(&mut self, key: Option<&str>, stats: &ConnectionStats) {
+        self.update_baseline_at(key, stats, Instant::now());
+    }
+
+    /// [`update_baseline`](Self::update_baseline) at a given time
+    pub fn update_baseline_at(&mut self, key: Option<&str>, stats: &ConnectionStats, now: Instant) {
+        let mut features = stats.feature_vector().with_segment(key.map(str::to_string));
+        features.observed_at = now;
+        self.learn(&features);
+    }
+
+    /// Fold a feature vector into the global baseline and the baseline of
+    /// its [`segment`](FeatureVector::segment)
+    pub fn learn(&mut self, features: &FeatureVector) {
+        self.global.learn(features);
+        let Some(key) = features.segment.as_deref() else {
+            return;
+        };
+        if !self.segments.contains_key(key) && self.segments.len() >= self.max_segments {
+            self.evict_oldest();
+        }
+        self.segments
+            .entry(key.to_string())
+            .or_insert_with(|| BaselineModel::with_config(self.config.clone()))
+            .learn(features);
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .segments
+            .iter()
+            .min_by_key(|(_, model)| model.last_update())
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.segments.remove(&key);
+        }
+    }
+
+    /// The baseline a connection in segment `key` is scored against: its
+    /// segment's once it has enough samples, the global one before that
+    pub fn model_for(&self, key: Option<&str>) -> (&BaselineModel, Option<&str>) {
+        match key.and_then(|key| self.segments.get_key_value(key)) {
+            Some((key, model)) if model.is_ready() => {
+                (model, Some(key.as_str()))
+            }
+            _ => (&self.global, None),
+        }
+    }
+
+    /// Detect anomalies against the connection's segment baseline, falling
+    /// back to the global baseline during cold start
+    pub fn detect_anomalies(&self, key: Option<&str>, stats: &ConnectionStats) -> Vec<ThreatDetection> {
+        self.detect(&stats.feature_vector().with_segment(key.map(str::to_string)))
+    }
+
+    /// [`detect_anomalies`](Self::detect_anomalies) for a feature vector
+    pub fn detect(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        let (model, segment) = self.model_for(features.segment.as_deref());
+        let baseline = match segment {
+            Some(segment) => format!("Baseline: segment {}", segment),
+            None => "Baseline: global".to_string(),
+        };
+        model
+            .detect(features)
+            .into_iter()
+            .map(|threat| threat.with_indicator(baseline.clone()))
+            .collect()
+    }
+
+    /// What the global and segment baselines have learned
+    pub fn snapshot(&self) -> BaselineSnapshot {
+        BaselineSnapshot {
+            global: self.global.state(),
+            segments: self
+                .segments
+                .iter()
+                .map(|(key, model)| (key.clone(), model.state()))
+                .collect(),
+        }
+    }
+
+    /// Replace the baselines with a snapshot
+    ///
+    /// Segment keys are only meaningful under the segmentation the snapshot
+    /// was taken with. Past the segment limit, the segments with the fewest
+    /// samples are left out.
+    pub fn restore(&mut self, snapshot: BaselineSnapshot) {
+        self.global.restore(snapshot.global);
+        let mut segments: Vec<(String, BaselineState)> = snapshot.segments.into_iter().collect();
+        segments.sort_by_key(|(_, state)| std::cmp::Reverse(state.sample_count));
+        segments.truncate(self.max_segments);
+        self.segments = segments
+            .into_iter()
+            .map(|(key, state)| {
+                let mut model = BaselineModel::with_config(self.config.clone());
+                model.restore(state);
+                (key, model)
+            })
+            .collect();
+    }
+
+    /// Forget a closed connection's sampling state
+    pub fn remove_session(&mut self, session_id: SessionId) {
+        self.global.remove_session(session_id);
+        for model in self.segments.values_mut() {
+            model.remove_session(session_id);
+        }
+    }
+}
+
+/// The hand-written rules, scored against the connection's baseline
+impl DetectionModel for SegmentedBaseline {
+    fn name(&self) -> &str {
+        "rules"
+    }
+
+    fn learn(&mut self, features: &FeatureVector) {
+        SegmentedBaseline::learn(self, features);
+    }
+
+    fn score(&self, features: &FeatureVector) -> Vec<ThreatDetection> {
+        self.detect(features)
+    }
+
+    fn end_session(&mut self, session_id: SessionId) {
+        self.remove_session(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::FrameType;
+    use crate::security::ai::patterns::AttackPattern;
+    use std::time::Duration;
+
+    fn connection(session_id: SessionId, size: usize, now: Instant) -> ConnectionStats {
+        let mut stats = ConnectionStats::new(session_id, "127.0.0.1:1".parse().unwrap());
+        stats.first_seen = now - Duration::from_secs(60);
+        stats.last_seen = now;
+        for i in (0..100).rev() {
+            stats
+                .recent_frames
+                .record(now - Duration::from_millis(i * 100), size);
+        }
+        stats
+    }
+
+    fn unusual_size(baseline: &SegmentedBaseline, key: Option<&str>, size: usize, now: Instant) -> bool {
+        baseline
+            .detect_anomalies(key, &connection(999, size, now))
+            .iter()
+            .any(|t| t.pattern == AttackPattern::AnomalousTraffic)
+    }
+
+    #[test]
+    fn test_segments_keep_separate_baselines() {
+        let config = BaselineConfig {
+            sample_interval: Duration::ZERO,
+            min_samples: 20,
+            ..Default::default()
+        };
+        let mut baseline = SegmentedBaseline::new(config, Segmentation::Header("route".into()));
+        let start = Instant::now();
+        for i in 0..40u64 {
+            let now = start + Duration::from_secs(i);
+            let jitter = (i % 5) as usize;
+            baseline.update_baseline_at(Some("chat"), &connection(i as SessionId, 100 + jitter * 5, now), now);
+            baseline.update_baseline_at(Some("upload"), &connection(i as SessionId + 100, 60_000 + jitter * 1_000, now), now);
+        }
+        let now = start + Duration::from_secs(40);
+
+        assert!(unusual_size(&baseline, Some("chat"), 60_000, now));
+        assert!(!unusual_size(&baseline, Some("upload"), 60_000, now));
+        assert!(unusual_size(&baseline, Some("upload"), 100, now));
+        // The global baseline mixes both, so neither size stands out
+        assert!(!unusual_size(&baseline, None, 60_000, now));
+
+        let threats = baseline.detect_anomalies(Some("chat"), &connection(999, 60_000, now));
+        assert!(threats[0].indicators.contains(&"Baseline: segment chat".to_string()));
+    }
+
+    #[test]
+    fn test_cold_segments_fall_back_to_global() {
+        let config = BaselineConfig {
+            sample_interval: Duration::ZERO,
+            min_samples: 20,
+            ..Default::default()
+        };
+        let mut baseline = SegmentedBaseline::new(config, Segmentation::PeerIp);
+        let start = Instant::now();
+        for i in 0..30u64 {
+            let now = start + Duration::from_secs(i);
+            baseline.update_baseline_at(Some("192.0.2.1"), &connection(1, 100 + (i % 3) as usize, now), now);
+        }
+        let now = start + Duration::from_secs(30);
+        baseline.update_baseline_at(Some("192.0.2.2"), &connection(2, 5_000, now), now);
+
+        let (model, segment) = baseline.model_for(Some("192.0.2.2"));
+        assert_eq!(segment, None);
+        assert_eq!(model.sample_count(), 31);
+        assert_eq!(baseline.model_for(Some("192.0.2.1")).1, Some("192.0.2.1"));
+        assert_eq!(baseline.model_for(Some("unknown")).1, None);
+    }
+
+    #[test]
+    fn test_keys_and_eviction() {
+        let frame = Frame::new(FrameType::Data).with_header("route", "/upload");
+        let input = SegmentInput {
+            session_id: 1,
+            peer_addr: "192.0.2.1:4000".parse().unwrap(),
+            principal: Some("alice"),
+            frame: &frame,
+        };
+        assert_eq!(Segmentation::Global.key(&input), None);
+        assert_eq!(Segmentation::PeerIp.key(&input).as_deref(), Some("192.0.2.1"));
+        assert_eq!(Segmentation::Principal.key(&input).as_deref(), Some("alice"));
+        assert_eq!(Segmentation::Header("route".into()).key(&input).as_deref(), Some("/upload"));
+        let custom = Segmentation::Custom(Arc::new(|input: &SegmentInput<'_>| {
+            Some(format!("{:?}", input.frame.typ))
+        }));
+        assert_eq!(custom.key(&input).as_deref(), Some("Data"));
+
+        let mut baseline =
+            SegmentedBaseline::new(BaselineConfig::default(), Segmentation::PeerIp).with_max_segments(2);
+        let start = Instant::now();
+        for (i, key) in ["a", "b", "a", "c"].iter().enumerate() {
+            let now = start + Duration::from_secs(i as u64 * 2);
+            baseline.update_baseline_at(Some(key), &connection(i as SessionId, 100, now), now);
+        }
+        let mut keys = baseline.segment_keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let config = BaselineConfig {
+            sample_interval: Duration::ZERO,
+            min_samples: 5,
+            ..Default::default()
+        };
+        let mut baseline = SegmentedBaseline::new(config.clone(), Segmentation::PeerIp);
+        let start = Instant::now();
+        for i in 0..11u64 {
+            let now = start + Duration::from_secs(i);
+            let key = if i % 2 == 0 { "a" } else { "b" };
+            baseline.update_baseline_at(Some(key), &connection(i as SessionId, 100, now), now);
+        }
+
+        let json = serde_json::to_string(&baseline.snapshot()).unwrap();
+        let mut restored = SegmentedBaseline::new(config, Segmentation::PeerIp).with_max_segments(1);
+        assert!(!restored.global().is_ready());
+        restored.restore(serde_json::from_str(&json).unwrap());
+        assert!(restored.global().is_ready());
+        let (before, after) = (baseline.global().state(), restored.global().state());
+        assert_eq!(after.sample_count, 11);
+        assert!((after.frame_size.mean() - before.frame_size.mean()).abs() < 1e-9);
+        assert_eq!(restored.segment_keys().len(), 1);
+        assert_eq!(restored.model_for(Some("a")).1, Some("a"));
+    }
+}
@@ -0,0 +1,432 @@
+//! Forwarding threats to external systems
+//!
+//! [`AnomalyDetector::subscribe`](super::AnomalyDetector::subscribe) hands
+//! out a receiver for every threat the detector records. A [`ThreatSink`]
+//! delivers threats elsewhere, such as to a SIEM, and a [`ThreatForwarder`]
+//! feeds one from a subscription in the background. Three sinks are
+//! provided: a JSON lines file, syslog over UDP and an HTTP POST webhook.
+
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::core::types::VstpError;
+
+use super::patterns::{ThreatDetection, ThreatLevel};
+
+/// Default time an [`HttpSink`] waits for the endpoint to respond
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Somewhere threats are delivered to
+#[async_trait]
+pub trait ThreatSink: Send + Sync {
+    /// Deliver one threat
+    async fn send(&self, threat: &ThreatDetection) -> Result<(), VstpError>;
+}
+
+fn encode(threat: &ThreatDetection) -> Result<Vec<u8>, VstpError> {
+    serde_json::to_vec(threat)
+        .map_err(|e| VstpError::Protocol(format!("Failed to encode threat: {}", e)))
+}
+
+/// Feeds threats from a detector subscription to a sink until dropped
+///
+/// Threats the sink fails to take are logged and dropped, as are threats
+/// missed because the sink fell too far behind.
+pub struct ThreatForwarder {
+    task: JoinHandle<()>,
+}
+
+impl ThreatForwarder {
+    /// Start forwarding the threats `receiver` gets to `sink`
+    pub fn start(mut receiver: broadcast::Receiver<ThreatDetection>, sink: Arc<dyn ThreatSink>) -> Self {
+        let task = tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(threat) => {
+                        if let Err(e) = sink.send(&threat).await {
+                            warn!("Failed to forward threat {:?}: {}", threat.pattern, e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Threat sink fell behind and missed {} threat(s)", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Self { task }
+    }
+}
+
+impl Drop for ThreatForwarder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Sink appending each threat to a JSON lines file
+pub struct JsonlSink {
+    path: PathBuf,
+    file: tokio::sync::Mutex<tokio::fs::File>,
+}
+
+impl JsonlSink {
+    /// Open or create the file at `path`
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, VstpError> {
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        Ok(Self {
+            path,
+            file: tokio::sync::Mutex::new(file),
+        })
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl ThreatSink for JsonlSink {
+    async fn send(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        let mut line = encode(threat)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Sink sending each threat to a syslog collector as an RFC 5424 message
+/// over UDP
+///
+/// The message ID is the pattern's training data label and the message
+/// body is the threat as JSON.
+pub struct SyslogSink {
+    socket: UdpSocket,
+    dest: SocketAddr,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+impl SyslogSink {
+    /// Send to the collector at `dest`, with facility 4 (security) and the
+    /// `HOSTNAME` environment variable as host name
+    pub async fn connect(dest: SocketAddr) -> Result<Self, VstpError> {
+        let bind = if dest.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind).await?;
+        Ok(Self {
+            socket,
+            dest,
+            facility: 4,
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+            app_name: "vstp".to_string(),
+        })
+    }
+
+    /// Use a syslog facility code other than 4 (security), such as 16 for
+    /// local0
+    pub fn with_facility(mut self, facility: u8) -> Self {
+        self.facility = facility.min(23);
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    pub fn with_app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = app_name.into();
+        self
+    }
+
+    /// Format a threat as an RFC 5424 message
+    pub fn format(&self, threat: &ThreatDetection) -> Result<Vec<u8>, VstpError> {
+        let severity = match threat.threat_level {
+            ThreatLevel::Critical => 2,
+            ThreatLevel::High => 3,
+            ThreatLevel::Medium => 4,
+            ThreatLevel::Low => 5,
+            ThreatLevel::None => 6,
+        };
+        let header = format!(
+            "<{}>1 {} {} {} {} {} - ",
+            self.facility * 8 + severity,
+            rfc3339(threat.timestamp),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            threat.pattern.label(),
+        );
+        let mut message = header.into_bytes();
+        message.extend(encode(threat)?);
+        Ok(message)
+    }
+}
+
+#[async_trait]
+impl ThreatSink for SyslogSink {
+    async fn send(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        let message = self.format(threat)?;
+        self.socket.send_to(&message, self.dest).await?;
+        Ok(())
+    }
+}
+
+/// UTC time of a Unix timestamp in RFC 3339 form
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil date from days since the epoch, after Howard Hinnant's
+    // `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Sink POSTing each threat as JSON to an HTTP endpoint
+///
+/// Only plain `http://` URLs are supported; put a TLS-terminating proxy in
+/// front of endpoints that need HTTPS. Any response other than 2xx is an
+/// error.
+pub struct HttpSink {
+    host: String,
+    port: u16,
+    path: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl HttpSink {
+    /// POST to `url`, such as `http://siem.local:8080/vstp/threats`
+    pub fn new(url: &str) -> Result<Self, VstpError> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            VstpError::Protocol(format!("Unsupported webhook URL {}: only http:// is supported", url))
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port.parse().map_err(|_| {
+                    VstpError::Protocol(format!("Invalid port in webhook URL {}", url))
+                })?;
+                (host, port)
+            }
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(VstpError::Protocol(format!("Missing host in webhook URL {}", url)));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            headers: Vec::new(),
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+        })
+    }
+
+    /// Send an extra header with every request, such as `Authorization`
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<u16, VstpError> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let mut stream = TcpStream::connect((host, self.port)).await?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).await?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                VstpError::Protocol(format!("Invalid webhook response: {:?}", status_line.trim_end()))
+            })
+    }
+}
+
+#[async_trait]
+impl ThreatSink for HttpSink {
+    async fn send(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        let body = encode(threat)?;
+        let status = timeout(self.timeout, self.post(&body))
+            .await
+            .map_err(|_| VstpError::Timeout)??;
+        if !(200..300).contains(&status) {
+            return Err(VstpError::Protocol(format!(
+                "Webhook {}:{}{} returned HTTP {}",
+                self.host, self.port, self.path, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::ai::patterns::AttackPattern;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn threat() -> ThreatDetection {
+        ThreatDetection::new(
+            AttackPattern::ReplayAttack,
+            ThreatLevel::Critical,
+            1.0,
+            "Replayed frame rejected".to_string(),
+        )
+        .with_session_id(7)
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_webhook_url_parsing() {
+        let sink = HttpSink::new("http://siem.local:8080/vstp/threats").unwrap();
+        assert_eq!((sink.host.as_str(), sink.port, sink.path.as_str()), ("siem.local", 8080, "/vstp/threats"));
+        let sink = HttpSink::new("http://[::1]").unwrap();
+        assert_eq!((sink.host.as_str(), sink.port, sink.path.as_str()), ("[::1]", 80, "/"));
+        assert!(HttpSink::new("https://siem.local/").is_err());
+        assert!(HttpSink::new("http://siem.local:http/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_syslog_sink() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink = SyslogSink::connect(collector.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_hostname("node-1");
+        sink.send(&threat()).await.unwrap();
+
+        let mut buf = [0u8; 2048];
+        let len = collector.recv(&mut buf).await.unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        // Facility 4 (security), severity 2 (critical)
+        assert!(message.starts_with("<34>1 "));
+        let fields: Vec<_> = message.splitn(8, ' ').collect();
+        assert_eq!(fields[2], "node-1");
+        assert_eq!(fields[3], "vstp");
+        assert_eq!(fields[5], "replay_attack");
+        assert_eq!(fields[6], "-");
+        let body: ThreatDetection = serde_json::from_str(fields[7]).unwrap();
+        assert_eq!(body.session_id, Some(7));
+    }
+
+    /// Accept one request, reply with `status` and return the request
+    async fn stub_server(status: &'static str) -> (SocketAddr, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (addr, task)
+    }
+
+    #[tokio::test]
+    async fn test_http_sink() {
+        let (addr, server) = stub_server("200 OK").await;
+        let sink = HttpSink::new(&format!("http://{}/threats", addr))
+            .unwrap()
+            .with_header("Authorization", "Bearer t0ken");
+        sink.send(&threat()).await.unwrap();
+
+        let request = server.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /threats HTTP/1.1\r\n"));
+        assert!(head.contains("\r\nAuthorization: Bearer t0ken"));
+        assert!(head.contains("\r\nContent-Type: application/json"));
+        let posted: ThreatDetection = serde_json::from_str(body).unwrap();
+        assert_eq!(posted.pattern, AttackPattern::ReplayAttack);
+
+        let (addr, _server) = stub_server("500 Internal Server Error").await;
+        let sink = HttpSink::new(&format!("http://{}/threats", addr)).unwrap();
+        assert!(sink.send(&threat()).await.is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! Storage for threat history and bans
+//!
+//! The detector keeps its threat history and the bans it issues in a
+//! [`ThreatStore`]. [`MemoryThreatStore`] is a bounded ring buffer and the
+//! default; [`JsonlThreatStore`] also appends every record to a JSON lines
+//! file so history and bans survive a restart. To share bans across a
+//! cluster, wrap either in a
+//! [`GossipThreatStore`](super::gossip::GossipThreatStore).
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::core::types::{SessionId, VstpError};
+use crate::security::blocklist::{BanRecord, IpNet};
+
+use super::patterns::ThreatDetection;
+
+/// Default number of threats kept in memory
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1000;
+
+/// Where the detector keeps threats and bans
+#[async_trait]
+pub trait ThreatStore: Send + Sync {
+    /// Store a detected threat
+    async fn record_threat(&self, threat: &ThreatDetection) -> Result<(), VstpError>;
+
+    /// The most recent threats, newest first
+    async fn recent_threats(&self, limit: usize) -> Result<Vec<ThreatDetection>, VstpError>;
+
+    /// The most recent threats for one session, newest first
+    async fn session_threats(
+        &self,
+        session_id: SessionId,
+        limit: usize,
+    ) -> Result<Vec<ThreatDetection>, VstpError> {
+        let threats = self.recent_threats(usize::MAX).await?;
+        Ok(threats
+            .into_iter()
+            .filter(|t| t.session_id == Some(session_id))
+            .take(limit)
+            .collect())
+    }
+
+    /// Store a ban
+    async fn record_ban(&self, ban: &BanRecord) -> Result<(), VstpError>;
+
+    /// Remove the ban on exactly `net`
+    async fn lift_ban(&self, net: IpNet) -> Result<(), VstpError>;
+
+    /// Bans that have not expired
+    async fn active_bans(&self) -> Result<Vec<BanRecord>, VstpError>;
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    threats: VecDeque<ThreatDetection>,
+    bans: HashMap<IpNet, BanRecord>,
+}
+
+/// In-memory store keeping the most recent threats in a ring buffer
+#[derive(Debug)]
+pub struct MemoryThreatStore {
+    capacity: usize,
+    records: Mutex<Records>,
+}
+
+impl MemoryThreatStore {
+    /// Create a store holding up to `capacity` threats
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            records: Mutex::new(Records {
+                threats: VecDeque::with_capacity(capacity.clamp(1, DEFAULT_HISTORY_CAPACITY)),
+                bans: HashMap::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Records> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push_threat(&self, threat: ThreatDetection) {
+        let mut records = self.lock();
+        if records.threats.len() == self.capacity {
+            records.threats.pop_front();
+        }
+        records.threats.push_back(threat);
+    }
+
+    fn insert_ban(&self, ban: BanRecord) {
+        self.lock().bans.insert(ban.net, ban);
+    }
+
+    fn remove_ban(&self, net: &IpNet) {
+        self.lock().bans.remove(net);
+    }
+
+    fn bans(&self) -> Vec<BanRecord> {
+        let mut records = self.lock();
+        records.bans.retain(|_, ban| ban.is_active());
+        records.bans.values().cloned().collect()
+    }
+}
+
+impl Default for MemoryThreatStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl ThreatStore for MemoryThreatStore {
+    async fn record_threat(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        self.push_threat(threat.clone());
+        Ok(())
+    }
+
+    async fn recent_threats(&self, limit: usize) -> Result<Vec<ThreatDetection>, VstpError> {
+        let records = self.lock();
+        Ok(records.threats.iter().rev().take(limit).cloned().collect())
+    }
+
+    async fn session_threats(
+        &self,
+        session_id: SessionId,
+        limit: usize,
+    ) -> Result<Vec<ThreatDetection>, VstpError> {
+        let records = self.lock();
+        Ok(records
+            .threats
+            .iter()
+            .rev()
+            .filter(|t| t.session_id == Some(session_id))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn record_ban(&self, ban: &BanRecord) -> Result<(), VstpError> {
+        self.insert_ban(ban.clone());
+        Ok(())
+    }
+
+    async fn lift_ban(&self, net: IpNet) -> Result<(), VstpError> {
+        self.remove_ban(&net);
+        Ok(())
+    }
+
+    async fn active_bans(&self) -> Result<Vec<BanRecord>, VstpError> {
+        Ok(self.bans())
+    }
+}
+
+/// One line of a [`JsonlThreatStore`] file
+///
+/// Externally tagged: serde cannot buffer the `u128` session IDs that an
+/// internally tagged enum would need to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Threat(ThreatDetection),
+    Ban(BanRecord),
+    Unban { net: IpNet },
+}
+
+/// Store that appends every record to a JSON lines file
+///
+/// Opening the file replays it, so the most recent threats and the bans
+/// still in force are available again after a restart. Queries are served
+/// from memory. The file only grows; call [`compact`](Self::compact) to
+/// rewrite it with just the records still held.
+pub struct JsonlThreatStore {
+    path: PathBuf,
+    memory: MemoryThreatStore,
+    file: tokio::sync::Mutex<tokio::fs::File>,
+}
+
+impl JsonlThreatStore {
+    /// Open or create the file at `path`, keeping up to `capacity` threats
+    /// in memory
+    pub async fn open(path: impl AsRef<Path>, capacity: usize) -> Result<Self, VstpError> {
+        let path = path.as_ref().to_path_buf();
+        let memory = MemoryThreatStore::new(capacity);
+        let mut torn = false;
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => {
+                torn = !contents.is_empty() && !contents.ends_with('\n');
+                for (number, line) in contents.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(line) {
+                        Ok(Entry::Threat(threat)) => memory.push_threat(threat),
+                        Ok(Entry::Ban(ban)) => memory.insert_ban(ban),
+                        Ok(Entry::Unban { net }) => memory.remove_ban(&net),
+                        // A crash can leave a partial last line
+                        Err(e) => warn!(
+                            "Skipping line {} of {}: {}",
+                            number + 1,
+                            path.display(),
+                            e
+                        ),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        if torn {
+            // Start the next record on a fresh line
+            file.write_all(b"\n").await?;
+        }
+        Ok(Self {
+            path,
+            memory,
+            file: tokio::sync::Mutex::new(file),
+        })
+    }
+
+    /// Path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn append(&self, entry: &Entry) -> Result<(), VstpError> {
+        let mut line = serde_json::to_vec(entry)
+            .map_err(|e| VstpError::Protocol(format!("Failed to encode record: {}", e)))?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Rewrite the file with only the threats held in memory and the bans
+    /// still in force
+    pub async fn compact(&self) -> Result<(), VstpError> {
+        let mut file = self.file.lock().await;
+
+        let mut contents = Vec::new();
+        let threats = self.memory.recent_threats(usize::MAX).await?;
+        let entries = threats
+            .into_iter()
+            .rev()
+            .map(Entry::Threat)
+            .chain(self.memory.bans().into_iter().map(Entry::Ban));
+        for entry in entries {
+            serde_json::to_writer(&mut contents, &entry)
+                .map_err(|e| VstpError::Protocol(format!("Failed to encode record: {}", e)))?;
+            contents.push(b'\n');
+        }
+
+        let tmp = self.path.with_extension("compact");
+        tokio::fs::write(&tmp, &contents).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        *file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ThreatStore for JsonlThreatStore {
+    async fn record_threat(&self, threat: &ThreatDetection) -> Result<(), VstpError> {
+        self.memory.push_threat(threat.clone());
+        self.append(&Entry::Threat(threat.clone())).await
+    }
+
+    async fn recent_threats(&self, limit: usize) -> Result<Vec<ThreatDetection>, VstpError> {
+        self.memory.recent_threats(limit).await
+    }
+
+    async fn session_threats(
+        &self,
+        session_id: SessionId,
+        limit: usize,
+    ) -> Result<Vec<ThreatDetection>, VstpError> {
+        self.memory.session_threats(session_id, limit).await
+    }
+
+    async fn record_ban(&self, ban: &BanRecord) -> Result<(), VstpError> {
+        self.memory.insert_ban(ban.clone());
+        self.append(&Entry::Ban(ban.clone())).await
+    }
+
+    async fn lift_ban(&self, net: IpNet) -> Result<(), VstpError> {
+        self.memory.remove_ban(&net);
+        self.append(&Entry::Unban { net }).await
+    }
+
+    async fn active_bans(&self) -> Result<Vec<BanRecord>, VstpError> {
+        Ok(self.memory.bans())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::ai::patterns::{AttackPattern, ThreatLevel};
+
+    fn threat(session_id: SessionId) -> ThreatDetection {
+        ThreatDetection::new(
+            AttackPattern::SuspiciousActivity,
+            ThreatLevel::Medium,
+            0.6,
+            format!("threat {}", session_id),
+        )
+        .with_session_id(session_id)
+    }
+
+    fn ban(net: &str) -> BanRecord {
+        BanRecord {
+            net: net.parse().unwrap(),
+            expires_at: None,
+            reason: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_ring_buffer() {
+        let store = MemoryThreatStore::new(3);
+        for session_id in 1..=5 {
+            store.record_threat(&threat(session_id)).await.unwrap();
+        }
+        let recent = store.recent_threats(10).await.unwrap();
+        let sessions: Vec<_> = recent.iter().map(|t| t.session_id.unwrap()).collect();
+        assert_eq!(sessions, vec![5, 4, 3]);
+        assert!(store.session_threats(1, 10).await.unwrap().is_empty());
+        assert_eq!(store.session_threats(4, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("vstp-threats-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let store = JsonlThreatStore::open(&path, 2).await.unwrap();
+            for session_id in 1..=3 {
+                store.record_threat(&threat(session_id)).await.unwrap();
+            }
+            store.record_ban(&ban("192.0.2.1")).await.unwrap();
+            store.record_ban(&ban("198.51.100.0/24")).await.unwrap();
+            store.lift_ban("192.0.2.1".parse().unwrap()).await.unwrap();
+        }
+        // A torn write at the end of the file is skipped
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, b"{\"threat\":{\"pat"))
+            .unwrap();
+
+        let store = JsonlThreatStore::open(&path, 2).await.unwrap();
+        let sessions: Vec<_> = store
+            .recent_threats(10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|t| t.session_id.unwrap())
+            .collect();
+        assert_eq!(sessions, vec![3, 2]);
+        assert_eq!(store.active_bans().await.unwrap(), vec![ban("198.51.100.0/24")]);
+
+        store.compact().await.unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 3);
+        let store = JsonlThreatStore::open(&path, 2).await.unwrap();
+        assert_eq!(store.recent_threats(10).await.unwrap().len(), 2);
+        assert_eq!(store.active_bans().await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use serde_json::Value;
+use std::path::Path;
+
+use super::{AuthDecision, Authenticator, Principal, AUTH_SCHEME_HEADER, BEARER_SCHEME};
+use crate::core::types::{Frame, VstpError};
+
+/// Verifies JWT bearer tokens against a local JWKS
+///
+/// The token is read from the payload of a HELLO whose `auth-scheme` header
+/// is `bearer`. Tokens must name their signing key with a `kid` header. The
+/// `sub` claim becomes the principal ID, and roles come from a `roles` array
+/// claim or a space-separated `scope` claim. Other string claims are kept as
+/// principal attributes.
+#[derive(Debug, Clone)]
+pub struct JwtAuthenticator {
+    jwks: JwkSet,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtAuthenticator {
+    pub fn new(jwks: JwkSet) -> Self {
+        Self {
+            jwks,
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Load the key set from a JWKS JSON file
+    pub fn from_jwks_file(path: impl AsRef<Path>) -> Result<Self, VstpError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)?;
+        let jwks = serde_json::from_str(&json).map_err(|e| {
+            VstpError::Protocol(format!("Invalid JWKS file {}: {}", path.display(), e))
+        })?;
+        Ok(Self::new(jwks))
+    }
+
+    /// Require the `iss` claim to match
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Require the `aud` claim to contain this audience
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, String> {
+        let header = decode_header(token).map_err(|e| format!("malformed token: {}", e))?;
+        let kid = header.kid.as_deref().ok_or("token has no key id")?;
+        let jwk = self
+            .jwks
+            .find(kid)
+            .ok_or_else(|| format!("unknown key id {}", kid))?;
+
+        // Never let the token pick a different algorithm than its key declares
+        if let Some(key_alg) = jwk.common.key_algorithm {
+            if key_alg.to_string() != format!("{:?}", header.alg) {
+                return Err(format!("algorithm {:?} does not match key", header.alg));
+            }
+        }
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable key: {}", e))?;
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        let claims = decode::<Value>(token, &key, &validation)
+            .map_err(|e| format!("invalid token: {}", e))?
+            .claims;
+        Ok(principal_from_claims(&claims))
+    }
+}
+
+fn principal_from_claims(claims: &Value) -> Principal {
+    let mut principal = Principal::new(claims["sub"].as_str().unwrap_or_default());
+
+    if let Some(roles) = claims["roles"].as_array() {
+        for role in roles.iter().filter_map(Value::as_str) {
+            principal = principal.with_role(role);
+        }
+    } else if let Some(scope) = claims["scope"].as_str() {
+        for role in scope.split_whitespace() {
+            principal = principal.with_role(role);
+        }
+    }
+
+    if let Some(map) = claims.as_object() {
+        for (key, value) in map {
+            if key != "sub" {
+                if let Some(value) = value.as_str() {
+                    principal = principal.with_attribute(key, value);
+                }
+            }
+        }
+    }
+    principal
+}
+
+#[async_trait]
+impl Authenticator for JwtAuthenticator {
+    async fn authenticate(&self, hello: &Frame) -> AuthDecision {
+        if hello.get_header(AUTH_SCHEME_HEADER) != Some(BEARER_SCHEME) {
+            return AuthDecision::Reject("missing bearer token".to_string());
+        }
+        let Ok(token) = std::str::from_utf8(&hello.payload) else {
+            return AuthDecision::Reject("malformed bearer token".to_string());
+        };
+        match self.verify(token) {
+            Ok(principal) => AuthDecision::Accept(principal),
+            Err(reason) => AuthDecision::Reject(reason),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Pluggable authentication for the session handshake
+//!
+//! A server configured with an [`Authenticator`] expects the client's first
+//! frame to be a HELLO carrying credentials in its headers, or in its payload
+//! for JWT bearer tokens, which outgrow the 255-byte header value limit once
+//! signed with RSA or ECDSA keys. The authenticator
+//! either accepts it, rejects it, or asks for a challenge-response round: the
+//! server then sends a PING with an `auth-challenge` header and the client
+//! answers with a PONG carrying `auth-response`. On success the server sends
+//! WELCOME with an `auth-principal` header; on failure it sends ERR and closes
+//! the connection.
+//!
+//! The authenticated [`Principal`] stays attached to the session and is
+//! handed to handlers alongside every frame.
+
+mod challenge;
+mod jwt;
+mod token;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::core::types::{Frame, FrameType};
+
+pub use challenge::{hmac_response, HmacChallengeAuthenticator};
+pub use jwt::JwtAuthenticator;
+pub use token::SharedTokenAuthenticator;
+
+/// HELLO header carrying a shared token
+pub const AUTH_TOKEN_HEADER: &str = "auth-token";
+
+/// HELLO header naming the key for HMAC challenge-response
+pub const AUTH_KEY_ID_HEADER: &str = "auth-key-id";
+
+/// HELLO header naming the kind of credential carried in the payload
+pub const AUTH_SCHEME_HEADER: &str = "auth-scheme";
+
+/// `auth-scheme` value for a JWT bearer token in the HELLO payload
+pub const BEARER_SCHEME: &str = "bearer";
+
+/// PING header carrying a hex-encoded challenge
+pub const AUTH_CHALLENGE_HEADER: &str = "auth-challenge";
+
+/// PONG header carrying the hex-encoded answer to a challenge
+pub const AUTH_RESPONSE_HEADER: &str = "auth-response";
+
+/// WELCOME header naming the authenticated principal
+pub const AUTH_PRINCIPAL_HEADER: &str = "auth-principal";
+
+/// An authenticated identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    /// Stable identifier, e.g. a user name or JWT subject
+    pub id: String,
+    /// Roles granted to this principal
+    pub roles: Vec<String>,
+    /// Extra attributes from the credential, e.g. JWT claims
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            roles: Vec::new(),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Grant a role
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    /// Attach an attribute
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Whether the principal has a role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+/// Outcome of an authentication step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// The client is authenticated
+    Accept(Principal),
+    /// Send this challenge to the client and wait for its response
+    Challenge(Vec<u8>),
+    /// The client is refused, with a reason sent back in the ERR frame
+    Reject(String),
+}
+
+/// Verifies client credentials during the HELLO handshake
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Inspect the client's HELLO
+    async fn authenticate(&self, hello: &Frame) -> AuthDecision;
+
+    /// Check the client's PONG answering `challenge`
+    async fn verify_response(
+        &self,
+        _hello: &Frame,
+        _challenge: &[u8],
+        _response: &Frame,
+    ) -> AuthDecision {
+        AuthDecision::Reject("unexpected challenge response".to_string())
+    }
+}
+
+/// Credentials a client presents in its HELLO
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// A shared token
+    Token(String),
+    /// A key ID and secret for HMAC challenge-response
+    Hmac { key_id: String, secret: Vec<u8> },
+    /// A JWT bearer token
+    Bearer(String),
+}
+
+impl Credentials {
+    /// HELLO frame presenting these credentials
+    pub fn hello_frame(&self) -> Frame {
+        let hello = Frame::new(FrameType::Hello);
+        match self {
+            Credentials::Token(token) => hello.with_header(AUTH_TOKEN_HEADER, token),
+            Credentials::Hmac { key_id, .. } => hello.with_header(AUTH_KEY_ID_HEADER, key_id),
+            Credentials::Bearer(token) => hello
+                .with_header(AUTH_SCHEME_HEADER, BEARER_SCHEME)
+                .with_payload(token.as_bytes().to_vec()),
+        }
+    }
+
+    /// PONG answering a server challenge, if these credentials can answer it
+    pub fn answer_challenge(&self, challenge: &Frame) -> Option<Frame> {
+        let Credentials::Hmac { key_id, secret } = self else {
+            return None;
+        };
+        let challenge = hex::decode(challenge.get_header(AUTH_CHALLENGE_HEADER)?).ok()?;
+        let response = hmac_response(secret, key_id, &challenge);
+        Some(Frame::new(FrameType::Pong).with_header(AUTH_RESPONSE_HEADER, &hex::encode(response)))
+    }
+}
+
+/// Compare two byte strings in time independent of where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    const JWT_SECRET: &[u8] = b"vstp-test-signing-secret";
+
+    fn jwks_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("vstp-{}-{}.json", name, std::process::id()));
+        let jwks = serde_json::json!({
+            "keys": [{
+                "kty": "oct",
+                "kid": "test-key",
+                "alg": "HS256",
+                "k": "dnN0cC10ZXN0LXNpZ25pbmctc2VjcmV0",
+            }]
+        });
+        std::fs::write(&path, jwks.to_string()).unwrap();
+        path
+    }
+
+    fn jwt(kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(jsonwebtoken::Algorithm::HS256);
+        header.kid = Some(kid.to_string());
+        encode(&header, &claims, &EncodingKey::from_secret(JWT_SECRET)).unwrap()
+    }
+
+    fn expiry() -> u64 {
+        jsonwebtoken::get_current_timestamp() + 600
+    }
+
+    #[tokio::test]
+    async fn test_shared_token() {
+        let auth = SharedTokenAuthenticator::new()
+            .with_token("s3cret", Principal::new("sensor-1").with_role("publisher"));
+
+        let hello = Credentials::Token("s3cret".to_string()).hello_frame();
+        match auth.authenticate(&hello).await {
+            AuthDecision::Accept(principal) => {
+                assert_eq!(principal.id, "sensor-1");
+                assert!(principal.has_role("publisher"));
+            }
+            other => panic!("expected accept, got {:?}", other),
+        }
+
+        let wrong = Credentials::Token("guess".to_string()).hello_frame();
+        assert!(matches!(auth.authenticate(&wrong).await, AuthDecision::Reject(_)));
+        let missing = Frame::new(FrameType::Hello);
+        assert!(matches!(auth.authenticate(&missing).await, AuthDecision::Reject(_)));
+    }
+
+    #[tokio::test]
+    async fn test_hmac_challenge_response() {
+        let auth = HmacChallengeAuthenticator::new().with_key(
+            "device-7",
+            b"device-secret".to_vec(),
+            Principal::new("device-7"),
+        );
+        let credentials = Credentials::Hmac {
+            key_id: "device-7".to_string(),
+            secret: b"device-secret".to_vec(),
+        };
+        let hello = credentials.hello_frame();
+
+        let AuthDecision::Challenge(challenge) = auth.authenticate(&hello).await else {
+            panic!("expected a challenge");
+        };
+        let ping = Frame::new(FrameType::Ping)
+            .with_header(AUTH_CHALLENGE_HEADER, &hex::encode(&challenge));
+        let pong = credentials.answer_challenge(&ping).unwrap();
+        assert_eq!(
+            auth.verify_response(&hello, &challenge, &pong).await,
+            AuthDecision::Accept(Principal::new("device-7"))
+        );
+
+        // An answer to a different challenge does not verify
+        let other = auth.authenticate(&hello).await;
+        let AuthDecision::Challenge(other) = other else {
+            panic!("expected a challenge");
+        };
+        assert!(matches!(
+            auth.verify_response(&hello, &other, &pong).await,
+            AuthDecision::Reject(_)
+        ));
+
+        // Nor does one made with the wrong secret
+        let impostor = Credentials::Hmac {
+            key_id: "device-7".to_string(),
+            secret: b"guessed".to_vec(),
+        };
+        let forged = impostor.answer_challenge(&ping).unwrap();
+        assert!(matches!(
+            auth.verify_response(&hello, &challenge, &forged).await,
+            AuthDecision::Reject(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_bearer() {
+        let path = jwks_file("jwt-bearer");
+        let auth = JwtAuthenticator::from_jwks_file(&path)
+            .unwrap()
+            .with_issuer("vstp-test");
+        std::fs::remove_file(&path).ok();
+
+        let token = jwt(
+            "test-key",
+            serde_json::json!({
+                "sub": "alice",
+                "iss": "vstp-test",
+                "exp": expiry(),
+                "scope": "read write",
+            }),
+        );
+        match auth
+            .authenticate(&Credentials::Bearer(token).hello_frame())
+            .await
+        {
+            AuthDecision::Accept(principal) => {
+                assert_eq!(principal.id, "alice");
+                assert_eq!(principal.roles, vec!["read", "write"]);
+                assert_eq!(principal.attributes.get("iss").map(String::as_str), Some("vstp-test"));
+            }
+            other => panic!("expected accept, got {:?}", other),
+        }
+
+        let rejected = [
+            // Wrong issuer
+            jwt("test-key", serde_json::json!({"sub": "bob", "iss": "other", "exp": expiry()})),
+            // Expired
+            jwt("test-key", serde_json::json!({"sub": "bob", "iss": "vstp-test", "exp": 1})),
+            // Unknown key
+            jwt("missing", serde_json::json!({"sub": "bob", "iss": "vstp-test", "exp": expiry()})),
+            // Not a JWT
+            "not-a-token".to_string(),
+        ];
+        for token in rejected {
+            let hello = Credentials::Bearer(token).hello_frame();
+            assert!(matches!(auth.authenticate(&hello).await, AuthDecision::Reject(_)));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use async_trait::async_trait;
+
+use super::{constant_time_eq, AuthDecision, Authenticator, Principal, AUTH_TOKEN_HEADER};
+use crate::core::types::Frame;
+
+/// Accepts clients presenting one of a set of shared tokens
+#[derive(Debug, Clone, Default)]
+pub struct SharedTokenAuthenticator {
+    tokens: Vec<(String, Principal)>,
+}
+
+impl SharedTokenAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `token` as `principal`
+    pub fn with_token(mut self, token: impl Into<String>, principal: Principal) -> Self {
+        self.tokens.push((token.into(), principal));
+        self
+    }
+}
+
+#[async_trait]
+impl Authenticator for SharedTokenAuthenticator {
+    async fn authenticate(&self, hello: &Frame) -> AuthDecision {
+        let Some(presented) = hello.get_header(AUTH_TOKEN_HEADER) else {
+            return AuthDecision::Reject("missing token".to_string());
+        };
+
+        // Compare against every token so timing does not reveal which matched
+        let mut matched = None;
+        for (token, principal) in &self.tokens {
+            if constant_time_eq(token.as_bytes(), presented.as_bytes()) && matched.is_none() {
+                matched = Some(principal);
+            }
+        }
+
+        match matched {
+            Some(principal) => AuthDecision::Accept(principal.clone()),
+            None => AuthDecision::Reject("invalid token".to_string()),
+        }
+    }
+}
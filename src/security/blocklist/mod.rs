@@ -0,0 +1,629 @@
+//! Address blocklist
+//!
+//! Blocking a session only stops that session: a client that reconnects gets
+//! a fresh session ID. A [`Blocklist`] bans peer addresses instead, either
+//! single IPs or CIDR ranges, each with an optional expiry, so servers can
+//! turn a client away before a session exists.
+//!
+//! Bans issued with [`Blocklist::ban`] escalate: each repeat offense from the
+//! same address within [`BlocklistConfig::offense_memory`] multiplies the
+//! previous ban length, up to [`BlocklistConfig::max_ban`].
+//!
+//! Bans are exported and restored as [`BanRecord`]s, which use wall-clock
+//! expiry times so they survive restarts and can be shared between nodes.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::core::types::VstpError;
+
+/// An IP network in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Create a network, clearing any host bits in `addr`
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, VstpError> {
+        let addr = addr.to_canonical();
+        let max = max_prefix(addr);
+        if prefix > max {
+            return Err(VstpError::Protocol(format!(
+                "Invalid prefix length /{} for {}",
+                prefix, addr
+            )));
+        }
+        Ok(Self {
+            addr: mask(addr, prefix),
+            prefix,
+        })
+    }
+
+    /// The network address
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The prefix length
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether the network is a single address
+    pub fn is_host(&self) -> bool {
+        self.prefix == max_prefix(self.addr)
+    }
+
+    /// Whether `ip` is inside this network
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && mask(ip, self.prefix) == self.addr
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        Self {
+            addr,
+            prefix: max_prefix(addr),
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = VstpError;
+
+    /// Parse `addr/prefix`, or a bare address as a single-host network
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || VstpError::Protocol(format!("Invalid network: {}", s));
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr = addr.parse().map_err(|_| invalid())?;
+                let prefix = prefix.parse().map_err(|_| invalid())?;
+                Self::new(addr, prefix)
+            }
+            None => s.parse::<IpAddr>().map(Self::from).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for IpNet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for IpNet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4((u32::from(v4) & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6((u128::from(v6) & mask).into())
+        }
+    }
+}
+
+/// Ban lengths for [`Blocklist::ban`]
+#[derive(Debug, Clone)]
+pub struct BlocklistConfig {
+    /// Length of a first offender's ban
+    pub base_ban: Duration,
+    /// Factor each repeat offense multiplies the ban by
+    pub multiplier: u32,
+    /// Longest ban an offender can reach
+    pub max_ban: Duration,
+    /// How long an offense counts towards escalation
+    pub offense_memory: Duration,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            base_ban: Duration::from_secs(60),
+            multiplier: 2,
+            max_ban: Duration::from_secs(24 * 60 * 60),
+            offense_memory: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// An active ban
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    /// Banned address or range
+    pub net: IpNet,
+    /// When the ban lifts; `None` for a permanent ban
+    pub expires: Option<Instant>,
+    /// Why the address was banned
+    pub reason: String,
+}
+
+impl Ban {
+    fn is_active(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A ban in a form that can be stored or sent to another node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    /// Banned address or range
+    pub net: IpNet,
+    /// When the ban lifts, in milliseconds since the Unix epoch; `None` for
+    /// a permanent ban
+    pub expires_at: Option<u64>,
+    /// Why the address was banned
+    pub reason: String,
+}
+
+impl BanRecord {
+    /// Time left on the ban, `None` if it has expired, and `Some(None)` if it
+    /// is permanent
+    fn remaining(&self) -> Option<Option<Duration>> {
+        match self.expires_at {
+            None => Some(None),
+            Some(at) => at
+                .checked_sub(now_millis())
+                .filter(|ms| *ms > 0)
+                .map(|ms| Some(Duration::from_millis(ms))),
+        }
+    }
+
+    /// Whether the ban is still in force
+    pub fn is_active(&self) -> bool {
+        self.remaining().is_some()
+    }
+}
+
+impl From<&Ban> for BanRecord {
+    fn from(ban: &Ban) -> Self {
+        let now = Instant::now();
+        Self {
+            net: ban.net,
+            // Too far off to count in milliseconds is as good as permanent
+            expires_at: ban.expires.and_then(|at| {
+                u64::try_from(at.saturating_duration_since(now).as_millis())
+                    .ok()
+                    .and_then(|ms| now_millis().checked_add(ms))
+            }),
+            reason: ban.reason.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Offenses {
+    count: u32,
+    last: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Single-address bans, looked up directly
+    hosts: HashMap<IpAddr, Ban>,
+    /// Range bans, scanned in order
+    nets: Vec<Ban>,
+    offenses: HashMap<IpAddr, Offenses>,
+}
+
+/// Shared set of banned addresses
+///
+/// Clones share the same bans, so a detector and the servers it protects can
+/// each hold one. Expired bans stop matching immediately and are dropped by
+/// [`purge_expired`](Self::purge_expired).
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    config: Arc<BlocklistConfig>,
+    state: Arc<RwLock<State>>,
+}
+
+impl Blocklist {
+    /// Create an empty blocklist
+    pub fn new(config: BlocklistConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            state: Arc::default(),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Ban an address or range for `ttl`, or until unblocked if `None`
+    ///
+    /// Replaces any existing ban on exactly the same network. A `ttl` too
+    /// long to represent is treated as permanent.
+    pub fn block(&self, net: IpNet, ttl: Option<Duration>, reason: impl Into<String>) {
+        let ban = Ban {
+            net,
+            expires: ttl.and_then(|ttl| Instant::now().checked_add(ttl)),
+            reason: reason.into(),
+        };
+        let mut state = self.write();
+        if net.is_host() {
+            state.hosts.insert(net.addr(), ban);
+        } else {
+            state.nets.retain(|b| b.net != net);
+            state.nets.push(ban);
+        }
+    }
+
+    /// Ban an offending address, escalating the ban for repeat offenders
+    ///
+    /// Returns the length of the ban. An existing longer ban on the address
+    /// is kept.
+    pub fn ban(&self, ip: IpAddr, reason: impl Into<String>) -> Duration {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+        let mut state = self.write();
+
+        let offenses = state.offenses.entry(ip).or_insert(Offenses {
+            count: 0,
+            last: now,
+        });
+        if now.duration_since(offenses.last) > self.config.offense_memory {
+            offenses.count = 0;
+        }
+        offenses.count = offenses.count.saturating_add(1);
+        offenses.last = now;
+
+        let factor = self
+            .config
+            .multiplier
+            .checked_pow(offenses.count - 1)
+            .unwrap_or(u32::MAX);
+        let ttl = self
+            .config
+            .base_ban
+            .saturating_mul(factor)
+            .min(self.config.max_ban);
+
+        // A ban too long to represent as an `Instant` is as good as permanent
+        let expires = now.checked_add(ttl);
+        let outlasted = state.hosts.get(&ip).is_some_and(|ban| match (ban.expires, expires) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(current), Some(new)) => current > new,
+        });
+        if !outlasted {
+            state.hosts.insert(
+                ip,
+                Ban {
+                    net: IpNet::from(ip),
+                    expires,
+                    reason: reason.into(),
+                },
+            );
+        }
+        ttl
+    }
+
+    /// Apply a stored or shared ban, returning whether it changed anything
+    ///
+    /// Expired records are ignored, and an existing ban on the same network
+    /// that lasts longer is kept.
+    pub fn apply(&self, record: &BanRecord) -> bool {
+        let Some(ttl) = record.remaining() else {
+            return false;
+        };
+        let now = Instant::now();
+        // A ban too long to represent as an `Instant` is as good as permanent
+        let ttl = ttl.filter(|ttl| now.checked_add(*ttl).is_some());
+        let expires = ttl.map(|ttl| now + ttl);
+        let current = {
+            let state = self.read();
+            let ban = if record.net.is_host() {
+                state.hosts.get(&record.net.addr())
+            } else {
+                state.nets.iter().find(|b| b.net == record.net)
+            };
+            ban.filter(|b| b.is_active(now)).map(|b| b.expires)
+        };
+        let longer = match (current, expires) {
+            (None, _) => true,
+            (Some(None), _) => false,
+            (Some(Some(_)), None) => true,
+            (Some(Some(current)), Some(new)) => new > current,
+        };
+        if longer {
+            self.block(record.net, ttl, record.reason.clone());
+        }
+        longer
+    }
+
+    /// Active bans, for storing or sharing
+    pub fn records(&self) -> Vec<BanRecord> {
+        let now = Instant::now();
+        let state = self.read();
+        state
+            .hosts
+            .values()
+            .chain(state.nets.iter())
+            .filter(|b| b.is_active(now))
+            .map(BanRecord::from)
+            .collect()
+    }
+
+    /// Lift the ban on exactly `net`, returning whether there was one
+    ///
+    /// Offense history is kept, so a lifted offender who offends again still
+    /// gets a longer ban.
+    pub fn unblock(&self, net: IpNet) -> bool {
+        let mut state = self.write();
+        if net.is_host() {
+            state.hosts.remove(&net.addr()).is_some()
+        } else {
+            let before = state.nets.len();
+            state.nets.retain(|b| b.net != net);
+            state.nets.len() != before
+        }
+    }
+
+    /// Whether `ip` is covered by an active ban
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+        let state = self.read();
+        state.hosts.get(&ip).is_some_and(|b| b.is_active(now))
+            || state
+                .nets
+                .iter()
+                .any(|b| b.is_active(now) && b.net.contains(ip))
+    }
+
+    /// The active ban covering `ip`, preferring a single-address ban
+    pub fn lookup(&self, ip: IpAddr) -> Option<Ban> {
+        let ip = ip.to_canonical();
+        let now = Instant::now();
+        let state = self.read();
+        state
+            .hosts
+            .get(&ip)
+            .filter(|b| b.is_active(now))
+            .or_else(|| {
+                state
+                    .nets
+                    .iter()
+                    .find(|b| b.is_active(now) && b.net.contains(ip))
+            })
+            .cloned()
+    }
+
+    /// Number of offenses recorded against `ip` that still count towards
+    /// escalation
+    pub fn offenses(&self, ip: IpAddr) -> u32 {
+        let ip = ip.to_canonical();
+        let state = self.read();
+        state
+            .offenses
+            .get(&ip)
+            .filter(|o| o.last.elapsed() <= self.config.offense_memory)
+            .map_or(0, |o| o.count)
+    }
+
+    /// Number of bans held, including expired ones not yet purged
+    pub fn len(&self) -> usize {
+        let state = self.read();
+        state.hosts.len() + state.nets.len()
+    }
+
+    /// Whether no bans are held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop expired bans and offenses too old to count
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        let memory = self.config.offense_memory;
+        let mut state = self.write();
+        state.hosts.retain(|_, b| b.is_active(now));
+        state.nets.retain(|b| b.is_active(now));
+        state
+            .offenses
+            .retain(|_, o| now.duration_since(o.last) <= memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_net() {
+        let net: IpNet = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(net.to_string(), "10.1.0.0/16");
+        assert!(net.contains(ip("10.1.200.7")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.9")));
+        assert!(!net.contains(ip("::1")));
+
+        let v6: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:ffff::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("192.0.2.1")));
+
+        let host: IpNet = "192.0.2.1".parse().unwrap();
+        assert!(host.is_host());
+        assert_eq!(host.prefix(), 32);
+
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("10.0.0/8".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn test_block_and_unblock() {
+        let blocklist = Blocklist::default();
+        let net: IpNet = "198.51.100.0/24".parse().unwrap();
+        blocklist.block(net, None, "abuse");
+        blocklist.block(IpNet::from(ip("192.0.2.1")), None, "scanner");
+
+        assert!(blocklist.is_blocked(ip("198.51.100.77")));
+        assert!(blocklist.is_blocked(ip("::ffff:192.0.2.1")));
+        assert!(!blocklist.is_blocked(ip("192.0.2.2")));
+        assert_eq!(blocklist.lookup(ip("198.51.100.1")).unwrap().reason, "abuse");
+
+        assert!(blocklist.unblock(net));
+        assert!(!blocklist.unblock(net));
+        assert!(!blocklist.is_blocked(ip("198.51.100.77")));
+        assert_eq!(blocklist.len(), 1);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let blocklist = Blocklist::default();
+        blocklist.block(IpNet::from(ip("192.0.2.1")), Some(Duration::ZERO), "expired");
+        blocklist.block("192.0.2.0/24".parse().unwrap(), Some(Duration::ZERO), "expired");
+        blocklist.block(IpNet::from(ip("192.0.2.9")), Some(Duration::from_secs(60)), "live");
+
+        assert!(!blocklist.is_blocked(ip("192.0.2.1")));
+        assert!(blocklist.is_blocked(ip("192.0.2.9")));
+        assert_eq!(blocklist.len(), 3);
+        blocklist.purge_expired();
+        assert_eq!(blocklist.len(), 1);
+    }
+
+    #[test]
+    fn test_escalation() {
+        let blocklist = Blocklist::new(BlocklistConfig {
+            base_ban: Duration::from_secs(10),
+            multiplier: 3,
+            max_ban: Duration::from_secs(200),
+            ..Default::default()
+        });
+        let offender = ip("203.0.113.5");
+
+        assert_eq!(blocklist.ban(offender, "first"), Duration::from_secs(10));
+        assert_eq!(blocklist.ban(offender, "second"), Duration::from_secs(30));
+        assert_eq!(blocklist.ban(offender, "third"), Duration::from_secs(90));
+        assert_eq!(blocklist.ban(offender, "fourth"), Duration::from_secs(200));
+        assert_eq!(blocklist.offenses(offender), 4);
+        assert_eq!(blocklist.lookup(offender).unwrap().reason, "fourth");
+
+        // Unblocking keeps the history
+        blocklist.unblock(IpNet::from(offender));
+        assert!(!blocklist.is_blocked(offender));
+        assert_eq!(blocklist.ban(offender, "fifth"), Duration::from_secs(200));
+
+        // A permanent ban is not shortened
+        let other = ip("203.0.113.6");
+        blocklist.block(IpNet::from(other), None, "manual");
+        blocklist.ban(other, "offense");
+        assert_eq!(blocklist.lookup(other).unwrap().expires, None);
+
+        // Bans too long to represent are permanent rather than a panic
+        let endless = Blocklist::new(BlocklistConfig {
+            base_ban: Duration::MAX,
+            max_ban: Duration::MAX,
+            ..Default::default()
+        });
+        assert_eq!(endless.ban(offender, "first"), Duration::MAX);
+        assert_eq!(endless.lookup(offender).unwrap().expires, None);
+        endless.ban(offender, "second");
+        assert_eq!(endless.lookup(offender).unwrap().expires, None);
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let blocklist = Blocklist::default();
+        blocklist.block("10.0.0.0/8".parse().unwrap(), None, "range");
+        blocklist.block(IpNet::from(ip("192.0.2.1")), Some(Duration::from_secs(60)), "host");
+        blocklist.block(IpNet::from(ip("192.0.2.2")), Some(Duration::ZERO), "expired");
+
+        let records = blocklist.records();
+        assert_eq!(records.len(), 2);
+        let json = serde_json::to_string(&records).unwrap();
+        assert!(json.contains("\"10.0.0.0/8\""), "{}", json);
+
+        let restored = Blocklist::default();
+        for record in serde_json::from_str::<Vec<BanRecord>>(&json).unwrap() {
+            assert!(restored.apply(&record));
+        }
+        assert!(restored.is_blocked(ip("10.9.9.9")));
+        assert!(restored.is_blocked(ip("192.0.2.1")));
+        assert!(!restored.is_blocked(ip("192.0.2.2")));
+
+        // Shorter and expired bans do not replace longer ones
+        let shorter = BanRecord {
+            net: IpNet::from(ip("192.0.2.1")),
+            expires_at: Some(now_millis() + 1_000),
+            reason: "short".to_string(),
+        };
+        assert!(!restored.apply(&shorter));
+        let expired = BanRecord {
+            net: IpNet::from(ip("192.0.2.3")),
+            expires_at: Some(now_millis() - 1_000),
+            reason: "old".to_string(),
+        };
+        assert!(!restored.apply(&expired));
+        assert!(!restored.is_blocked(ip("192.0.2.3")));
+
+        // Bans too far in the future to represent become permanent
+        let distant = BanRecord {
+            net: IpNet::from(ip("192.0.2.4")),
+            expires_at: Some(u64::MAX),
+            reason: "distant".to_string(),
+        };
+        assert!(restored.apply(&distant));
+        assert!(restored.is_blocked(ip("192.0.2.4")));
+        restored.block(IpNet::from(ip("192.0.2.5")), Some(Duration::MAX), "forever");
+        assert!(restored
+            .records()
+            .iter()
+            .any(|r| r.net == IpNet::from(ip("192.0.2.5")) && r.expires_at.is_none()));
+        // Representable as an `Instant` but not in milliseconds since the epoch
+        restored.block(IpNet::from(ip("192.0.2.6")), Some(Duration::from_secs(1 << 62)), "aeons");
+        assert!(restored
+            .records()
+            .iter()
+            .any(|r| r.net == IpNet::from(ip("192.0.2.6")) && r.expires_at.is_none()));
+    }
+}
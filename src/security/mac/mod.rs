@@ -0,0 +1,226 @@
+//! Per-frame message authentication
+//!
+//! CRC32 catches corruption but anyone can recompute it. In integrity mode
+//! every frame carries a truncated HMAC-SHA256 tag over its type, flags,
+//! headers and payload, keyed with a secret shared by the two ends of the
+//! session. Frames are marked with [`Flags::MAC`](crate::core::types::Flags::MAC),
+//! name their key in a `mac-key` header and carry the tag in a final binary
+//! `mac` header.
+//!
+//! A [`FrameKeyring`] holds the key used for sending plus any older keys still
+//! accepted for verification, so peers can rotate keys without dropping
+//! frames that are in flight.
+//!
+//! A server that requires MACs on every session gives each session its own
+//! key: once the session is set up it sends a WELCOME with a random
+//! `mac-nonce` header, and both ends derive the key from a shared master
+//! secret and that nonce.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header naming the key a frame was signed with
+pub const MAC_KEY_HEADER: &str = "mac-key";
+
+/// Header carrying the frame's authentication tag; always the last header
+pub const MAC_HEADER: &str = "mac";
+
+/// Length of the truncated HMAC-SHA256 tag
+pub const MAC_LEN: usize = 16;
+
+/// WELCOME header carrying the hex-encoded nonce a session key is derived from
+pub const MAC_NONCE_HEADER: &str = "mac-nonce";
+
+/// Length of the per-session key derivation nonce
+pub const MAC_NONCE_LEN: usize = 16;
+
+#[derive(Debug)]
+struct Keys {
+    active: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+/// Shared set of frame authentication keys
+///
+/// Clones share the same keys, so a codec and the code that rotates its keys
+/// can each hold one.
+#[derive(Clone)]
+pub struct FrameKeyring {
+    inner: Arc<RwLock<Keys>>,
+}
+
+impl std::fmt::Debug for FrameKeyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameKeyring")
+            .field("active", &self.active_key_id())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FrameKeyring {
+    /// Create a keyring that signs with `key`
+    pub fn new(key_id: impl Into<String>, key: impl Into<Vec<u8>>) -> Self {
+        let key_id = key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(key_id.clone(), key.into());
+        Self {
+            inner: Arc::new(RwLock::new(Keys {
+                active: key_id,
+                keys,
+            })),
+        }
+    }
+
+    /// Create a keyring for one session, deriving its key from a master
+    /// secret and the nonce the server sent in `mac-nonce`
+    pub fn for_session(key_id: impl Into<String>, master: &[u8], nonce: &[u8]) -> Self {
+        Self::new(key_id, derive_session_key(master, nonce))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Keys> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Keys> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// ID of the key new frames are signed with
+    pub fn active_key_id(&self) -> String {
+        self.read().active.clone()
+    }
+
+    /// Start signing with a new key; older keys are still accepted until retired
+    pub fn rotate(&self, key_id: impl Into<String>, key: impl Into<Vec<u8>>) {
+        let key_id = key_id.into();
+        let mut keys = self.write();
+        keys.keys.insert(key_id.clone(), key.into());
+        keys.active = key_id;
+    }
+
+    /// Accept frames signed with `key` without signing with it
+    pub fn add_key(&self, key_id: impl Into<String>, key: impl Into<Vec<u8>>) {
+        self.write().keys.insert(key_id.into(), key.into());
+    }
+
+    /// Stop accepting a key; the active key cannot be retired
+    pub fn retire(&self, key_id: &str) -> bool {
+        let mut keys = self.write();
+        if keys.active == key_id {
+            return false;
+        }
+        keys.keys.remove(key_id).is_some()
+    }
+
+    /// Sign `data` with the active key, returning its ID and the tag
+    pub fn sign(&self, data: &[u8]) -> (String, [u8; MAC_LEN]) {
+        let keys = self.read();
+        let key = &keys.keys[&keys.active];
+        (keys.active.clone(), tag(key, data))
+    }
+
+    /// Check a tag made with `key_id`; unknown keys never verify
+    pub fn verify(&self, key_id: &str, data: &[u8], tag: &[u8]) -> bool {
+        let keys = self.read();
+        let Some(key) = keys.keys.get(key_id) else {
+            return false;
+        };
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        tag.len() == MAC_LEN && mac.verify_truncated_left(tag).is_ok()
+    }
+}
+
+fn tag(key: &[u8], data: &[u8]) -> [u8; MAC_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    let mut tag = [0u8; MAC_LEN];
+    tag.copy_from_slice(&mac.finalize().into_bytes()[..MAC_LEN]);
+    tag
+}
+
+/// Derive a per-session key from a master secret and a session nonce
+pub fn derive_session_key(master: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(master).expect("HMAC accepts any key length");
+    mac.update(b"vstp frame mac");
+    mac.update(nonce);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Master secret a server derives every session's frame MAC key from
+#[derive(Clone)]
+pub struct SessionMacConfig {
+    /// Key ID the derived keys are named with
+    pub key_id: String,
+    master: Arc<[u8]>,
+}
+
+impl std::fmt::Debug for SessionMacConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionMacConfig")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionMacConfig {
+    pub fn new(key_id: impl Into<String>, master: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key_id: key_id.into(),
+            master: master.into().into(),
+        }
+    }
+
+    /// Pick a fresh nonce and the keyring derived from it
+    pub fn new_session(&self) -> ([u8; MAC_NONCE_LEN], FrameKeyring) {
+        let nonce = rand::random::<[u8; MAC_NONCE_LEN]>();
+        (nonce, FrameKeyring::for_session(self.key_id.clone(), &self.master, &nonce))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keyring = FrameKeyring::new("k1", b"first key".to_vec());
+        let (key_id, tag) = keyring.sign(b"frame bytes");
+        assert_eq!(key_id, "k1");
+        assert!(keyring.verify("k1", b"frame bytes", &tag));
+        assert!(!keyring.verify("k1", b"frame bytez", &tag));
+        assert!(!keyring.verify("k2", b"frame bytes", &tag));
+        assert!(!keyring.verify("k1", b"frame bytes", &tag[..8]));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let keyring = FrameKeyring::new("k1", b"first key".to_vec());
+        let shared = keyring.clone();
+        let (_, old_tag) = keyring.sign(b"in flight");
+
+        shared.rotate("k2", b"second key".to_vec());
+        assert_eq!(keyring.active_key_id(), "k2");
+        assert!(keyring.verify("k1", b"in flight", &old_tag));
+        assert!(!keyring.retire("k2"));
+        assert!(keyring.retire("k1"));
+        assert!(!keyring.verify("k1", b"in flight", &old_tag));
+    }
+
+    #[test]
+    fn test_session_keys_differ() {
+        assert_ne!(derive_session_key(b"master", b"n1"), derive_session_key(b"master", b"n2"));
+        assert_eq!(derive_session_key(b"master", b"n1"), derive_session_key(b"master", b"n1"));
+
+        let config = SessionMacConfig::new("s1", b"master".to_vec());
+        let (nonce, server) = config.new_session();
+        let client = FrameKeyring::for_session("s1", b"master", &nonce);
+        let (key_id, tag) = server.sign(b"frame");
+        assert!(client.verify(&key_id, b"frame", &tag));
+        assert_ne!(config.new_session().0, nonce);
+    }
+}
@@ -1,8 +1,22 @@
 pub mod crc;
 pub mod tls;
+pub mod noise;
+pub mod policy;
+pub mod blocklist;
+pub mod auth;
+pub mod mac;
+pub mod replay;
+pub mod sealed;
 pub mod ai;
 
 // Re-export commonly used types
 pub use crc::CrcValidator;
-pub use tls::TlsConfig;
+pub use tls::{CertResolver, TlsConfig};
+pub use noise::{NoiseConfig, NoiseKeypair, NoisePattern};
+pub use policy::Policy;
+pub use blocklist::{Blocklist, IpNet};
+pub use auth::{Authenticator, AuthDecision, Credentials, Principal};
+pub use mac::{FrameKeyring, SessionMacConfig};
+pub use replay::{ReplayConfig, ReplayProtection};
+pub use sealed::{SealIdentity, SealPublicKeys};
 pub use ai::{AnomalyDetector, TrafficMonitor, AttackPattern, ThreatLevel};
@@ -0,0 +1,441 @@
+//! Noise protocol secure channel for TCP
+//!
+//! An alternative to TLS for deployments without X.509 PKI. Both sides hold a
+//! static Curve25519 keypair and authenticate each other with the Noise XX or
+//! IK handshake. Peer public keys can be pinned.
+//!
+//! The handshake rides in the usual session opening: the client's HELLO and
+//! the server's WELCOME carry Noise messages in their payloads, marked with a
+//! `noise` header naming the protocol. XX needs a third message, which the
+//! client sends in a second HELLO. Afterwards every frame is encoded, sealed
+//! with ChaCha20-Poly1305 and sent as the payload of a DATA frame with the
+//! [`Flags::ENC`] flag. Frames larger than one Noise message are split over
+//! several such frames; all but the last also carry [`Flags::FRAG`].
+
+use std::fmt;
+use std::time::Duration;
+use snow::{HandshakeState, TransportState};
+
+use crate::core::frame::{encode_frame, try_decode_frame};
+use crate::core::types::{Flags, Frame, FrameType, VstpError};
+
+/// Header naming the Noise protocol of a handshake frame
+pub const NOISE_HEADER: &str = "noise";
+
+/// Largest Noise message, including the authentication tag
+const MAX_NOISE_MESSAGE: usize = 65535;
+
+/// Size of the Poly1305 authentication tag
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext sealed into a single frame
+const MAX_CHUNK: usize = MAX_NOISE_MESSAGE - TAG_LEN;
+
+/// Largest frame accepted inside the encrypted channel (the codec's default)
+const MAX_INNER_FRAME: usize = 8 * 1024 * 1024;
+
+/// A Curve25519 public key
+pub type PublicKey = [u8; 32];
+
+/// Noise handshake pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Both static keys are exchanged during the handshake (three messages)
+    XX,
+    /// The client already knows the server's static key (two messages)
+    IK,
+}
+
+impl NoisePattern {
+    /// Full Noise protocol name
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            NoisePattern::XX => "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            NoisePattern::IK => "Noise_IK_25519_ChaChaPoly_BLAKE2s",
+        }
+    }
+
+    /// Parse a full Noise protocol name
+    pub fn from_protocol_name(name: &str) -> Option<Self> {
+        [NoisePattern::XX, NoisePattern::IK]
+            .into_iter()
+            .find(|p| p.protocol_name() == name)
+    }
+}
+
+/// A static Curve25519 keypair
+#[derive(Clone)]
+pub struct NoiseKeypair {
+    pub public: PublicKey,
+    pub private: [u8; 32],
+}
+
+impl NoiseKeypair {
+    /// Generate a new random keypair
+    pub fn generate() -> Result<Self, VstpError> {
+        let params = NoisePattern::XX
+            .protocol_name()
+            .parse()
+            .map_err(noise_error)?;
+        let keypair = snow::Builder::new(params)
+            .generate_keypair()
+            .map_err(noise_error)?;
+        Ok(Self {
+            public: to_key(&keypair.public)?,
+            private: to_key(&keypair.private)?,
+        })
+    }
+}
+
+impl fmt::Debug for NoiseKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseKeypair")
+            .field("public", &hex(&self.public))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Noise configuration for a TCP client or server
+#[derive(Debug, Clone)]
+pub struct NoiseConfig {
+    /// Handshake pattern used when connecting; servers accept both
+    pub pattern: NoisePattern,
+    /// Our static keypair
+    pub keypair: NoiseKeypair,
+    /// The server's static key, required by clients using IK
+    pub remote_public_key: Option<PublicKey>,
+    /// Peer static keys that are accepted; empty accepts any peer
+    pub pinned_keys: Vec<PublicKey>,
+    /// Handshake timeout
+    pub handshake_timeout: Duration,
+}
+
+impl NoiseConfig {
+    /// Create a configuration using XX with the given static keypair
+    pub fn new(keypair: NoiseKeypair) -> Self {
+        Self {
+            pattern: NoisePattern::XX,
+            keypair,
+            remote_public_key: None,
+            pinned_keys: Vec::new(),
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Set the handshake pattern
+    pub fn with_pattern(mut self, pattern: NoisePattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Set the server's static key (needed for IK) and pin it
+    pub fn with_remote_key(mut self, key: PublicKey) -> Self {
+        self.remote_public_key = Some(key);
+        self.pin_peer_key(key)
+    }
+
+    /// Only accept peers presenting this static key (may be called repeatedly)
+    pub fn pin_peer_key(mut self, key: PublicKey) -> Self {
+        if !self.pinned_keys.contains(&key) {
+            self.pinned_keys.push(key);
+        }
+        self
+    }
+
+    /// Set the handshake timeout
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+}
+
+fn noise_error(e: snow::Error) -> VstpError {
+    VstpError::Protocol(format!("Noise handshake failed: {}", e))
+}
+
+fn to_key(bytes: &[u8]) -> Result<PublicKey, VstpError> {
+    bytes
+        .try_into()
+        .map_err(|_| VstpError::Protocol("Invalid Noise key length".to_string()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether a HELLO starts a Noise handshake
+pub fn is_noise_hello(frame: &Frame) -> bool {
+    frame.typ == FrameType::Hello && frame.get_header(NOISE_HEADER).is_some()
+}
+
+/// Handshake in progress, driven by the frames each side receives
+pub struct NoiseHandshake {
+    state: HandshakeState,
+    pattern: NoisePattern,
+    pinned_keys: Vec<PublicKey>,
+}
+
+impl NoiseHandshake {
+    /// Start a handshake as the client, returning the first HELLO to send
+    pub fn initiate(config: &NoiseConfig) -> Result<(Self, Frame), VstpError> {
+        let params = config
+            .pattern
+            .protocol_name()
+            .parse()
+            .map_err(noise_error)?;
+        let mut builder = snow::Builder::new(params).local_private_key(&config.keypair.private);
+        if config.pattern == NoisePattern::IK {
+            let remote = config.remote_public_key.as_ref().ok_or_else(|| {
+                VstpError::Protocol("Noise IK requires the server's public key".to_string())
+            })?;
+            builder = builder.remote_public_key(remote);
+        }
+
+        let mut handshake = Self {
+            state: builder.build_initiator().map_err(noise_error)?,
+            pattern: config.pattern,
+            pinned_keys: config.pinned_keys.clone(),
+        };
+        let hello = handshake.write()?;
+        Ok((handshake, hello))
+    }
+
+    /// Answer a client's first HELLO as the server, returning the handshake
+    /// and the WELCOME to send
+    pub fn respond(config: &NoiseConfig, hello: &Frame) -> Result<(Self, Frame), VstpError> {
+        let pattern = hello
+            .get_header(NOISE_HEADER)
+            .and_then(NoisePattern::from_protocol_name)
+            .ok_or_else(|| VstpError::Protocol("Unsupported Noise protocol".to_string()))?;
+        let params = pattern.protocol_name().parse().map_err(noise_error)?;
+        let state = snow::Builder::new(params)
+            .local_private_key(&config.keypair.private)
+            .build_responder()
+            .map_err(noise_error)?;
+
+        let mut handshake = Self {
+            state,
+            pattern,
+            pinned_keys: config.pinned_keys.clone(),
+        };
+        let welcome = handshake
+            .read(hello)?
+            .ok_or_else(|| VstpError::Protocol("Noise handshake stalled".to_string()))?;
+        Ok((handshake, welcome))
+    }
+
+    /// Process the peer's next handshake frame, returning our reply if the
+    /// pattern calls for one
+    pub fn read(&mut self, frame: &Frame) -> Result<Option<Frame>, VstpError> {
+        let expected = if self.state.is_initiator() {
+            FrameType::Welcome
+        } else {
+            FrameType::Hello
+        };
+        if frame.typ != expected || frame.get_header(NOISE_HEADER).is_none() {
+            return Err(VstpError::UnexpectedFrameType);
+        }
+
+        let mut scratch = vec![0u8; MAX_NOISE_MESSAGE];
+        self.state
+            .read_message(&frame.payload, &mut scratch)
+            .map_err(noise_error)?;
+
+        if !self.state.is_handshake_finished() && self.state.is_my_turn() {
+            return Ok(Some(self.write()?));
+        }
+        Ok(None)
+    }
+
+    fn write(&mut self) -> Result<Frame, VstpError> {
+        let mut message = vec![0u8; MAX_NOISE_MESSAGE];
+        let len = self
+            .state
+            .write_message(&[], &mut message)
+            .map_err(noise_error)?;
+        message.truncate(len);
+
+        let typ = if self.state.is_initiator() {
+            FrameType::Hello
+        } else {
+            FrameType::Welcome
+        };
+        Ok(Frame::new(typ)
+            .with_header(NOISE_HEADER, self.pattern.protocol_name())
+            .with_payload(message))
+    }
+
+    /// Whether both sides have sent all handshake messages
+    pub fn is_finished(&self) -> bool {
+        self.state.is_handshake_finished()
+    }
+
+    /// Switch to the encrypted channel, checking the peer's key against the pins
+    pub fn into_transport(self) -> Result<NoiseTransport, VstpError> {
+        let remote_static = self
+            .state
+            .get_remote_static()
+            .map(to_key)
+            .transpose()?
+            .ok_or_else(|| VstpError::Protocol("Peer sent no static key".to_string()))?;
+
+        if !self.pinned_keys.is_empty() && !self.pinned_keys.contains(&remote_static) {
+            return Err(VstpError::Protocol(format!(
+                "Peer key {} is not pinned",
+                hex(&remote_static)
+            )));
+        }
+
+        Ok(NoiseTransport {
+            state: self.state.into_transport_mode().map_err(noise_error)?,
+            remote_static,
+            pending: Vec::new(),
+        })
+    }
+}
+
+/// Encrypted channel established by a [`NoiseHandshake`]
+pub struct NoiseTransport {
+    state: TransportState,
+    remote_static: PublicKey,
+    /// Plaintext of a frame split over several sealed frames
+    pending: Vec<u8>,
+}
+
+impl fmt::Debug for NoiseTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseTransport")
+            .field("remote_static", &hex(&self.remote_static))
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseTransport {
+    /// The peer's authenticated static key
+    pub fn remote_static(&self) -> PublicKey {
+        self.remote_static
+    }
+
+    /// Encrypt a frame into one or more sealed frames
+    pub fn seal(&mut self, frame: &Frame) -> Result<Vec<Frame>, VstpError> {
+        let plaintext = encode_frame(frame)?;
+        let chunks: Vec<&[u8]> = plaintext.chunks(MAX_CHUNK).collect();
+        let last = chunks.len() - 1;
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut ciphertext = vec![0u8; chunk.len() + TAG_LEN];
+                let len = self
+                    .state
+                    .write_message(chunk, &mut ciphertext)
+                    .map_err(|e| VstpError::Protocol(format!("Encryption failed: {}", e)))?;
+                ciphertext.truncate(len);
+
+                let mut sealed = Frame::new(FrameType::Data).with_payload(ciphertext);
+                sealed.flags.insert(Flags::ENC);
+                if i < last {
+                    sealed.flags.insert(Flags::FRAG);
+                }
+                Ok(sealed)
+            })
+            .collect()
+    }
+
+    /// Decrypt a sealed frame, returning the inner frame once it is complete
+    pub fn open(&mut self, sealed: &Frame) -> Result<Option<Frame>, VstpError> {
+        if !sealed.flags.contains(Flags::ENC) {
+            return Err(VstpError::Protocol(
+                "Cleartext frame on encrypted channel".to_string(),
+            ));
+        }
+
+        let mut plaintext = vec![0u8; sealed.payload.len()];
+        let len = self
+            .state
+            .read_message(&sealed.payload, &mut plaintext)
+            .map_err(|_| VstpError::Protocol("Frame authentication failed".to_string()))?;
+        self.pending.extend_from_slice(&plaintext[..len]);
+
+        if self.pending.len() > MAX_INNER_FRAME {
+            return Err(VstpError::FrameTooLarge {
+                size: self.pending.len(),
+                limit: MAX_INNER_FRAME,
+            });
+        }
+        if sealed.flags.contains(Flags::FRAG) {
+            return Ok(None);
+        }
+
+        let mut buf = bytes::BytesMut::from(&self.pending[..]);
+        self.pending.clear();
+        try_decode_frame(&mut buf, MAX_INNER_FRAME)?
+            .map(Some)
+            .ok_or_else(|| VstpError::Protocol("Truncated encrypted frame".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_handshake(
+        client: &NoiseConfig,
+        server: &NoiseConfig,
+    ) -> Result<(NoiseTransport, NoiseTransport), VstpError> {
+        let (mut initiator, hello) = NoiseHandshake::initiate(client)?;
+        let (mut responder, welcome) = NoiseHandshake::respond(server, &hello)?;
+        if let Some(hello) = initiator.read(&welcome)? {
+            assert!(responder.read(&hello)?.is_none());
+        }
+        assert!(initiator.is_finished() && responder.is_finished());
+        Ok((initiator.into_transport()?, responder.into_transport()?))
+    }
+
+    #[test]
+    fn test_xx_and_ik_handshakes() {
+        let client_keys = NoiseKeypair::generate().unwrap();
+        let server_keys = NoiseKeypair::generate().unwrap();
+        let server = NoiseConfig::new(server_keys.clone());
+
+        for pattern in [NoisePattern::XX, NoisePattern::IK] {
+            let client = NoiseConfig::new(client_keys.clone())
+                .with_pattern(pattern)
+                .with_remote_key(server_keys.public);
+            let (mut client_tx, mut server_tx) = run_handshake(&client, &server).unwrap();
+            assert_eq!(client_tx.remote_static(), server_keys.public);
+            assert_eq!(server_tx.remote_static(), client_keys.public);
+
+            let frame = Frame::new(FrameType::Data).with_payload(vec![9u8; 200_000]);
+            let sealed = client_tx.seal(&frame).unwrap();
+            assert_eq!(sealed.len(), 4);
+
+            let mut opened = None;
+            for part in &sealed {
+                opened = server_tx.open(part).unwrap();
+            }
+            assert_eq!(opened.unwrap().payload, frame.payload);
+        }
+    }
+
+    #[test]
+    fn test_pinning_rejects_unknown_peer() {
+        let server = NoiseConfig::new(NoiseKeypair::generate().unwrap())
+            .pin_peer_key(NoiseKeypair::generate().unwrap().public);
+        let client = NoiseConfig::new(NoiseKeypair::generate().unwrap());
+
+        assert!(run_handshake(&client, &server).is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampered_frame() {
+        let server = NoiseConfig::new(NoiseKeypair::generate().unwrap());
+        let client = NoiseConfig::new(NoiseKeypair::generate().unwrap());
+        let (mut client_tx, mut server_tx) = run_handshake(&client, &server).unwrap();
+
+        let mut sealed = client_tx.seal(&Frame::new(FrameType::Ping)).unwrap();
+        sealed[0].payload[3] ^= 0x80;
+        assert!(server_tx.open(&sealed[0]).is_err());
+    }
+}
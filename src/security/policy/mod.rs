@@ -0,0 +1,267 @@
+//! Role-based authorization for frames
+//!
+//! A [`Policy`] maps role names to what a holder of that role may send:
+//! frame types, allowed values for headers such as an RPC `method`, and a
+//! payload size limit. A frame is allowed when any role of the sending
+//! principal allows it; sessions without a principal get the policy's
+//! anonymous roles. Policies load from JSON or TOML:
+//!
+//! ```toml
+//! anonymous = ["guest"]
+//!
+//! [roles.guest]
+//! frame_types = ["hello", "ping", "bye"]
+//!
+//! [roles.publisher]
+//! frame_types = ["data", "ping", "bye"]
+//! max_payload = 65536
+//! headers = { method = ["publish", "status.*"] }
+//! ```
+//!
+//! A header rule only applies to frames that carry the header, and then
+//! every copy of the header must be UTF-8 and match; values ending in `*`
+//! match by prefix.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::core::types::{Frame, FrameType, VstpError};
+use crate::security::auth::Principal;
+
+/// What one role may send
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolePolicy {
+    /// Frame types the role may send, by name; empty allows every type
+    pub frame_types: Vec<String>,
+    /// Largest payload the role may send, in bytes
+    pub max_payload: Option<usize>,
+    /// Allowed values for headers the role sends
+    pub headers: HashMap<String, Vec<String>>,
+}
+
+/// Authorization policy evaluated for every frame after authentication
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Permissions by role name
+    pub roles: HashMap<String, RolePolicy>,
+    /// Roles for sessions without an authenticated principal
+    pub anonymous: Vec<String>,
+}
+
+fn parse_frame_type(name: &str) -> Option<FrameType> {
+    match name.to_ascii_lowercase().as_str() {
+        "hello" => Some(FrameType::Hello),
+        "welcome" => Some(FrameType::Welcome),
+        "data" => Some(FrameType::Data),
+        "ping" => Some(FrameType::Ping),
+        "pong" => Some(FrameType::Pong),
+        "bye" => Some(FrameType::Bye),
+        "ack" => Some(FrameType::Ack),
+        "err" => Some(FrameType::Err),
+        _ => None,
+    }
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    }
+}
+
+impl RolePolicy {
+    /// Why this role does not allow the frame, if it does not
+    fn check(&self, frame: &Frame) -> Result<(), String> {
+        if !self.frame_types.is_empty()
+            && !self
+                .frame_types
+                .iter()
+                .any(|name| parse_frame_type(name) == Some(frame.typ))
+        {
+            return Err(format!("frame type {:?} not allowed", frame.typ));
+        }
+        if let Some(max) = self.max_payload {
+            if frame.payload.len() > max {
+                return Err(format!(
+                    "payload of {} bytes exceeds limit of {}",
+                    frame.payload.len(),
+                    max
+                ));
+            }
+        }
+        // Every copy of a header must pass, or a second one could slip by
+        for (header, allowed) in &self.headers {
+            for h in frame.headers.iter().filter(|h| h.key == header.as_bytes()) {
+                let Ok(value) = std::str::from_utf8(&h.value) else {
+                    return Err(format!("{} is not valid UTF-8", header));
+                };
+                if !allowed.iter().any(|pattern| matches(pattern, value)) {
+                    return Err(format!("{} {:?} not allowed", header, value));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Policy {
+    /// Parse a JSON policy
+    pub fn from_json(json: &str) -> Result<Self, VstpError> {
+        let policy: Self = serde_json::from_str(json)
+            .map_err(|e| VstpError::Protocol(format!("Invalid policy: {}", e)))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Parse a TOML policy
+    pub fn from_toml(toml: &str) -> Result<Self, VstpError> {
+        let policy: Self = toml::from_str(toml)
+            .map_err(|e| VstpError::Protocol(format!("Invalid policy: {}", e)))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Load a policy file, as TOML if it has a `.toml` extension and as JSON
+    /// otherwise
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, VstpError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            _ => Self::from_json(&contents),
+        }
+    }
+
+    /// Reject frame type names and role references that would never match
+    fn validate(&self) -> Result<(), VstpError> {
+        for (role, rules) in &self.roles {
+            if let Some(name) = rules
+                .frame_types
+                .iter()
+                .find(|name| parse_frame_type(name).is_none())
+            {
+                return Err(VstpError::Protocol(format!(
+                    "Invalid policy: role {} names unknown frame type {}",
+                    role, name
+                )));
+            }
+        }
+        if let Some(role) = self.anonymous.iter().find(|r| !self.roles.contains_key(*r)) {
+            return Err(VstpError::Protocol(format!(
+                "Invalid policy: anonymous role {} is not defined",
+                role
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decide whether `principal` may send `frame`, returning the reason
+    /// for a denial
+    pub fn authorize(&self, principal: Option<&Principal>, frame: &Frame) -> Result<(), String> {
+        let roles = match principal {
+            Some(principal) => &principal.roles,
+            None => &self.anonymous,
+        };
+
+        let mut reason = None;
+        for role in roles {
+            let Some(rules) = self.roles.get(role) else {
+                continue;
+            };
+            match rules.check(frame) {
+                Ok(()) => return Ok(()),
+                Err(denied) => reason = reason.or(Some(denied)),
+            }
+        }
+
+        let who = principal.map_or("anonymous session", |p| p.id.as_str());
+        Err(match reason {
+            Some(reason) => format!("{}: {}", who, reason),
+            None => format!("{}: no role grants access", who),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::Header;
+
+    const POLICY_TOML: &str = r#"
+        anonymous = ["guest"]
+
+        [roles.guest]
+        frame_types = ["hello", "ping", "bye"]
+
+        [roles.publisher]
+        frame_types = ["data", "ping"]
+        max_payload = 16
+        headers = { method = ["publish", "status.*"] }
+
+        [roles.admin]
+    "#;
+
+    #[test]
+    fn test_role_rules() {
+        let policy = Policy::from_toml(POLICY_TOML).unwrap();
+        let publisher = Principal::new("sensor").with_role("publisher");
+        let admin = Principal::new("root").with_role("admin");
+        let nobody = Principal::new("nobody").with_role("undefined");
+
+        let data = |method: &str, len: usize| {
+            Frame::new(FrameType::Data)
+                .with_header("method", method)
+                .with_payload(vec![0; len])
+        };
+
+        assert!(policy.authorize(Some(&publisher), &data("publish", 16)).is_ok());
+        assert!(policy.authorize(Some(&publisher), &data("status.get", 1)).is_ok());
+        assert!(policy.authorize(Some(&publisher), &data("publish", 17)).is_err());
+        assert!(policy.authorize(Some(&publisher), &data("delete", 1)).is_err());
+        let plain = Frame::new(FrameType::Data);
+        assert!(policy.authorize(Some(&publisher), &plain).is_ok());
+        assert!(policy
+            .authorize(Some(&publisher), &Frame::new(FrameType::Bye))
+            .is_err());
+
+        // A role with no rules allows everything
+        assert!(policy.authorize(Some(&admin), &data("delete", 1000)).is_ok());
+        assert!(policy.authorize(Some(&nobody), &Frame::new(FrameType::Ping)).is_err());
+
+        assert!(policy.authorize(None, &Frame::new(FrameType::Ping)).is_ok());
+        let denied = policy.authorize(None, &plain).unwrap_err();
+        assert!(denied.contains("anonymous"), "{}", denied);
+
+        // A disallowed value behind an allowed one, or one that is not
+        // UTF-8, is still checked
+        let smuggled = data("publish", 1).with_header("method", "delete");
+        assert!(policy.authorize(Some(&publisher), &smuggled).is_err());
+        let mut binary = Frame::new(FrameType::Data);
+        binary.headers.push(Header {
+            key: b"method".to_vec(),
+            value: vec![0xff, 0xfe],
+        });
+        assert!(policy.authorize(Some(&publisher), &binary).is_err());
+    }
+
+    #[test]
+    fn test_json_and_validation() {
+        let json = r#"{
+            "roles": { "reader": { "frame_types": ["Data"], "max_payload": 4 } }
+        }"#;
+        let policy = Policy::from_json(json).unwrap();
+        let reader = Principal::new("r").with_role("reader");
+        assert!(policy
+            .authorize(Some(&reader), &Frame::new(FrameType::Data))
+            .is_ok());
+        // No anonymous roles: unauthenticated sessions get nothing
+        assert!(policy.authorize(None, &Frame::new(FrameType::Data)).is_err());
+
+        assert!(Policy::from_json(r#"{"roles": {"x": {"frame_types": ["bogus"]}}}"#).is_err());
+        assert!(Policy::from_json(r#"{"anonymous": ["missing"]}"#).is_err());
+        assert!(Policy::from_json(r#"{"roles": {"x": {"max_size": 1}}}"#).is_err());
+    }
+}
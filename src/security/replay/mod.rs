@@ -0,0 +1,317 @@
+//! Replay protection for sessions
+//!
+//! The sender stamps every frame with a monotonically increasing `seq`
+//! header and, optionally, a `ts` header holding the send time in
+//! milliseconds since the Unix epoch. The receiver tracks accepted sequence
+//! numbers in a sliding bitmap window and rejects duplicates, numbers that
+//! have fallen behind the window, and frames whose timestamp is outside the
+//! allowed age.
+//!
+//! Sequence numbers only prove anything when an attacker cannot rewrite
+//! them, so pair this with Noise or per-frame MACs.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::core::types::Frame;
+
+/// Header carrying the sender's frame sequence number
+pub const SEQ_HEADER: &str = "seq";
+
+/// Header carrying the send time in milliseconds since the Unix epoch
+pub const TIMESTAMP_HEADER: &str = "ts";
+
+/// Sliding window of accepted sequence numbers
+#[derive(Debug)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `i` is set when `highest - i` has been accepted
+    bitmap: Vec<u64>,
+    size: u64,
+}
+
+impl ReplayWindow {
+    pub fn new(size: u64) -> Self {
+        let size = size.max(1);
+        Self {
+            highest: None,
+            bitmap: vec![0; size.div_ceil(64) as usize],
+            size,
+        }
+    }
+
+    fn bit(&self, offset: u64) -> bool {
+        self.bitmap[(offset / 64) as usize] & (1 << (offset % 64)) != 0
+    }
+
+    /// Whether `seq` would be accepted
+    pub fn check(&self, seq: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if seq > highest => true,
+            Some(highest) => {
+                let offset = highest - seq;
+                offset < self.size && !self.bit(offset)
+            }
+        }
+    }
+
+    /// Why `seq` would be rejected, if it would be
+    pub fn rejection(&self, seq: u64) -> Option<ReplayRejection> {
+        if self.check(seq) {
+            return None;
+        }
+        let stale = self.highest.is_some_and(|highest| highest - seq >= self.size);
+        Some(if stale {
+            ReplayRejection::Stale(seq)
+        } else {
+            ReplayRejection::Duplicate(seq)
+        })
+    }
+
+    /// Record `seq` as accepted, returning `false` if it is a replay or too old
+    pub fn accept(&mut self, seq: u64) -> bool {
+        if !self.check(seq) {
+            return false;
+        }
+        let highest = self.highest.unwrap_or(seq);
+        if seq > highest || self.highest.is_none() {
+            self.shift(seq - highest);
+            self.highest = Some(seq);
+        }
+        let offset = self.highest.unwrap_or(seq) - seq;
+        self.bitmap[(offset / 64) as usize] |= 1 << (offset % 64);
+        true
+    }
+
+    /// Highest sequence number accepted so far
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    /// Move the window forward by `by` sequence numbers
+    fn shift(&mut self, by: u64) {
+        if by == 0 {
+            return;
+        }
+        if by >= self.size {
+            self.bitmap.iter_mut().for_each(|w| *w = 0);
+            return;
+        }
+        let words = (by / 64) as usize;
+        let bits = by % 64;
+        let len = self.bitmap.len();
+        for i in (0..len).rev() {
+            let src = i.checked_sub(words);
+            let mut word = src.map_or(0, |s| self.bitmap[s] << bits);
+            if bits > 0 {
+                if let Some(prev) = src.and_then(|s| s.checked_sub(1)) {
+                    word |= self.bitmap[prev] >> (64 - bits);
+                }
+            }
+            self.bitmap[i] = word;
+        }
+    }
+}
+
+/// Replay protection settings
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Number of sequence numbers tracked behind the highest one received
+    pub window: u64,
+    /// Stamp outgoing frames with the send time and reject incoming frames
+    /// older than this, or this far in the future; `None` disables timestamps
+    pub max_age: Option<Duration>,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            window: 1024,
+            max_age: None,
+        }
+    }
+}
+
+/// Why a frame was rejected as a replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayRejection {
+    /// The frame has no usable sequence number
+    MissingSequence,
+    /// The sequence number was already accepted
+    Duplicate(u64),
+    /// The sequence number is too far behind the newest one to be tracked
+    Stale(u64),
+    /// The frame has no usable timestamp
+    MissingTimestamp,
+    /// The timestamp is outside the allowed age
+    Expired { seq: u64, age_ms: i64 },
+}
+
+impl std::fmt::Display for ReplayRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayRejection::MissingSequence => write!(f, "missing sequence number"),
+            ReplayRejection::Duplicate(seq) => write!(f, "duplicate sequence number {}", seq),
+            ReplayRejection::Stale(seq) => write!(f, "stale sequence number {}", seq),
+            ReplayRejection::MissingTimestamp => write!(f, "missing timestamp"),
+            ReplayRejection::Expired { seq, age_ms } => {
+                write!(f, "frame {} has timestamp {} ms off", seq, age_ms)
+            }
+        }
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// Per-session replay state: a sequence counter for frames sent and a
+/// window over frames received
+#[derive(Debug)]
+pub struct ReplayProtection {
+    config: ReplayConfig,
+    next_seq: u64,
+    window: ReplayWindow,
+}
+
+impl ReplayProtection {
+    pub fn new(config: ReplayConfig) -> Self {
+        Self {
+            window: ReplayWindow::new(config.window),
+            next_seq: 1,
+            config,
+        }
+    }
+
+    /// Add sequence and timestamp headers to an outgoing frame
+    pub fn stamp(&mut self, mut frame: Frame) -> Frame {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        frame
+            .headers
+            .retain(|h| h.key != SEQ_HEADER.as_bytes() && h.key != TIMESTAMP_HEADER.as_bytes());
+        frame = frame.with_header(SEQ_HEADER, &seq.to_string());
+        if self.config.max_age.is_some() {
+            frame = frame.with_header(TIMESTAMP_HEADER, &now_millis().to_string());
+        }
+        frame
+    }
+
+    /// Check an incoming frame, recording its sequence number if accepted
+    pub fn check(&mut self, frame: &Frame) -> Result<(), ReplayRejection> {
+        let seq = frame
+            .get_header(SEQ_HEADER)
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or(ReplayRejection::MissingSequence)?;
+
+        if let Some(rejection) = self.window.rejection(seq) {
+            return Err(rejection);
+        }
+
+        if let Some(max_age) = self.config.max_age {
+            let sent = frame
+                .get_header(TIMESTAMP_HEADER)
+                .and_then(|v| v.parse::<i64>().ok())
+                .ok_or(ReplayRejection::MissingTimestamp)?;
+            // The timestamp comes from the peer, so it can be anything
+            let age_ms = now_millis().saturating_sub(sent);
+            if age_ms.unsigned_abs() > max_age.as_millis() as u64 {
+                return Err(ReplayRejection::Expired { seq, age_ms });
+            }
+        }
+
+        self.window.accept(seq);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::FrameType;
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::new(128);
+        assert!(window.accept(5));
+        assert!(window.accept(3));
+        assert!(!window.accept(3));
+        assert!(window.accept(200));
+        assert!(!window.accept(5));
+        assert!(window.accept(100));
+        assert!(!window.accept(100));
+        assert!(!window.accept(72));
+        assert!(window.accept(73));
+    }
+
+    #[test]
+    fn test_rejects_duplicates_and_stale_frames() {
+        let config = ReplayConfig {
+            window: 64,
+            ..Default::default()
+        };
+        let mut sender = ReplayProtection::new(config.clone());
+        let mut receiver = ReplayProtection::new(config);
+
+        let frames: Vec<Frame> = (0..100)
+            .map(|_| sender.stamp(Frame::new(FrameType::Data)))
+            .collect();
+        assert_eq!(frames[0].get_header(SEQ_HEADER), Some("1"));
+
+        assert_eq!(receiver.check(&frames[1]), Ok(()));
+        assert_eq!(receiver.check(&frames[0]), Ok(()));
+        assert_eq!(receiver.check(&frames[1]), Err(ReplayRejection::Duplicate(2)));
+        assert_eq!(receiver.check(&frames[99]), Ok(()));
+        assert_eq!(receiver.check(&frames[2]), Err(ReplayRejection::Stale(3)));
+        assert_eq!(receiver.check(&frames[50]), Ok(()));
+
+        assert_eq!(
+            receiver.check(&Frame::new(FrameType::Data)),
+            Err(ReplayRejection::MissingSequence)
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let config = ReplayConfig {
+            max_age: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let mut sender = ReplayProtection::new(config.clone());
+        let mut receiver = ReplayProtection::new(config);
+
+        let fresh = sender.stamp(Frame::new(FrameType::Data));
+        assert!(fresh.get_header(TIMESTAMP_HEADER).is_some());
+        assert_eq!(receiver.check(&fresh), Ok(()));
+
+        // A captured frame replayed later with a new sequence number
+        let old = Frame::new(FrameType::Data)
+            .with_header(SEQ_HEADER, "1000")
+            .with_header(TIMESTAMP_HEADER, &(now_millis() - 60_000).to_string());
+        assert!(matches!(
+            receiver.check(&old),
+            Err(ReplayRejection::Expired { seq: 1000, .. })
+        ));
+        // Rejected frames do not move the window
+        let retry = sender.stamp(Frame::new(FrameType::Data));
+        assert_eq!(receiver.check(&retry), Ok(()));
+
+        let unstamped = Frame::new(FrameType::Data).with_header(SEQ_HEADER, "5");
+        assert_eq!(receiver.check(&unstamped), Err(ReplayRejection::MissingTimestamp));
+
+        // Extreme timestamps are expired rather than overflowing
+        for (seq, ts) in [(6, i64::MIN), (7, i64::MAX)] {
+            let extreme = Frame::new(FrameType::Data)
+                .with_header(SEQ_HEADER, &seq.to_string())
+                .with_header(TIMESTAMP_HEADER, &ts.to_string());
+            assert!(matches!(
+                receiver.check(&extreme),
+                Err(ReplayRejection::Expired { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! End-to-end sealed payloads
+//!
+//! A sealed DATA frame can pass through relays and brokers that never see
+//! its contents. The sender encrypts the payload to the recipient's X25519
+//! key with a fresh ephemeral key per message (ECDH, HKDF-SHA256,
+//! ChaCha20-Poly1305) and signs the result with its Ed25519 key. Headers
+//! name the algorithm, the recipient, the sender and the ephemeral key so
+//! any hop can route or verify the frame, but only the holder of the
+//! recipient's private key can open it.
+//!
+//! The sender's signing key is bound into the AEAD associated data, so
+//! swapping in another signature and sender header makes the payload fail
+//! to decrypt.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519Public, StaticSecret};
+
+use crate::core::types::{Frame, FrameType, VstpError};
+
+/// Header naming the sealing algorithm
+pub const SEAL_ALG_HEADER: &str = "seal-alg";
+
+/// Header carrying the recipient's X25519 public key (hex)
+pub const SEAL_RECIPIENT_HEADER: &str = "seal-to";
+
+/// Header carrying the sender's Ed25519 public key (hex)
+pub const SEAL_SENDER_HEADER: &str = "seal-from";
+
+/// Header carrying the per-message ephemeral X25519 public key (hex)
+pub const SEAL_EPHEMERAL_HEADER: &str = "seal-epk";
+
+/// Header carrying the sender's Ed25519 signature (hex)
+pub const SEAL_SIGNATURE_HEADER: &str = "seal-sig";
+
+/// The only sealing algorithm currently defined
+pub const SEAL_ALGORITHM: &str = "x25519-chacha20poly1305-ed25519";
+
+const KDF_INFO: &[u8] = b"vstp sealed payload v1";
+
+/// A 32-byte public key
+pub type SealKey = [u8; 32];
+
+/// Public half of a [`SealIdentity`], shared with peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SealPublicKeys {
+    /// X25519 key others seal payloads to
+    pub encryption: SealKey,
+    /// Ed25519 key others verify this identity's signatures with
+    pub signing: SealKey,
+}
+
+/// Long-term keys for sealing and opening payloads
+#[derive(Clone)]
+pub struct SealIdentity {
+    encryption: StaticSecret,
+    signing: SigningKey,
+}
+
+impl std::fmt::Debug for SealIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SealIdentity")
+            .field("public", &self.public_keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SealIdentity {
+    /// Generate a fresh identity
+    pub fn generate() -> Self {
+        Self {
+            encryption: StaticSecret::random_from_rng(OsRng),
+            signing: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Rebuild an identity from stored private keys
+    pub fn from_secrets(encryption: [u8; 32], signing: [u8; 32]) -> Self {
+        Self {
+            encryption: StaticSecret::from(encryption),
+            signing: SigningKey::from_bytes(&signing),
+        }
+    }
+
+    /// Keys to hand out to peers
+    pub fn public_keys(&self) -> SealPublicKeys {
+        SealPublicKeys {
+            encryption: X25519Public::from(&self.encryption).to_bytes(),
+            signing: self.signing.verifying_key().to_bytes(),
+        }
+    }
+}
+
+/// A payload opened by its recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenedPayload {
+    /// The decrypted payload
+    pub payload: Vec<u8>,
+    /// Ed25519 key of the sender, verified against the signature
+    pub sender: SealKey,
+}
+
+fn seal_error(reason: &str) -> VstpError {
+    VstpError::Protocol(format!("Sealed payload: {}", reason))
+}
+
+fn header_key(frame: &Frame, name: &str) -> Result<SealKey, VstpError> {
+    let value = frame
+        .get_header(name)
+        .ok_or_else(|| seal_error(&format!("missing {} header", name)))?;
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| seal_error(&format!("malformed {} header", name)))
+}
+
+/// Associated data and signed message prefix: binds every key to the ciphertext
+fn binding(ephemeral: &SealKey, recipient: &SealKey, sender: &SealKey) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SEAL_ALGORITHM.len() + 96);
+    data.extend_from_slice(SEAL_ALGORITHM.as_bytes());
+    data.extend_from_slice(ephemeral);
+    data.extend_from_slice(recipient);
+    data.extend_from_slice(sender);
+    data
+}
+
+fn cipher(shared: &[u8; 32], ephemeral: &SealKey, recipient: &SealKey) -> ChaCha20Poly1305 {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(recipient);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(KDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+impl Frame {
+    /// Build a DATA frame whose payload only `recipient` can open
+    ///
+    /// `recipient` is the recipient's X25519 key, i.e.
+    /// [`SealPublicKeys::encryption`].
+    pub fn sealed(
+        payload: &[u8],
+        sender: &SealIdentity,
+        recipient: &SealKey,
+    ) -> Result<Frame, VstpError> {
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral = X25519Public::from(&ephemeral_secret).to_bytes();
+        let shared = ephemeral_secret.diffie_hellman(&X25519Public::from(*recipient));
+        if !shared.was_contributory() {
+            return Err(seal_error("invalid recipient key"));
+        }
+        let sender_key = sender.signing.verifying_key().to_bytes();
+
+        // Each message has its own ephemeral key, hence its own AEAD key,
+        // so a fixed nonce is never reused
+        let aad = binding(&ephemeral, recipient, &sender_key);
+        let ciphertext = cipher(shared.as_bytes(), &ephemeral, recipient)
+            .encrypt(
+                Nonce::from_slice(&[0u8; 12]),
+                Payload {
+                    msg: payload,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| seal_error("encryption failed"))?;
+
+        let mut signed = aad;
+        signed.extend_from_slice(&ciphertext);
+        let signature = sender.signing.sign(&signed);
+
+        Ok(Frame::new(FrameType::Data)
+            .with_header(SEAL_ALG_HEADER, SEAL_ALGORITHM)
+            .with_header(SEAL_RECIPIENT_HEADER, &hex::encode(recipient))
+            .with_header(SEAL_SENDER_HEADER, &hex::encode(sender_key))
+            .with_header(SEAL_EPHEMERAL_HEADER, &hex::encode(ephemeral))
+            .with_header(SEAL_SIGNATURE_HEADER, &hex::encode(signature.to_bytes()))
+            .with_payload(ciphertext))
+    }
+
+    /// Whether this frame carries a sealed payload
+    pub fn is_sealed(&self) -> bool {
+        self.get_header(SEAL_ALG_HEADER).is_some()
+    }
+
+    /// Check the sender's signature without decrypting
+    ///
+    /// Relays can use this to drop forged frames; it does not prove the
+    /// payload is addressed to anyone in particular.
+    pub fn verify_sealed(&self) -> Result<SealKey, VstpError> {
+        if self.get_header(SEAL_ALG_HEADER) != Some(SEAL_ALGORITHM) {
+            return Err(seal_error("unsupported or missing algorithm"));
+        }
+        let recipient = header_key(self, SEAL_RECIPIENT_HEADER)?;
+        let sender = header_key(self, SEAL_SENDER_HEADER)?;
+        let ephemeral = header_key(self, SEAL_EPHEMERAL_HEADER)?;
+        let signature: [u8; 64] = self
+            .get_header(SEAL_SIGNATURE_HEADER)
+            .and_then(|v| hex::decode(v).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| seal_error("missing or malformed signature"))?;
+
+        let verifying_key =
+            VerifyingKey::from_bytes(&sender).map_err(|_| seal_error("invalid sender key"))?;
+        let mut signed = binding(&ephemeral, &recipient, &sender);
+        signed.extend_from_slice(&self.payload);
+        verifying_key
+            .verify(&signed, &Signature::from_bytes(&signature))
+            .map_err(|_| VstpError::ForgedFrame("sealed payload signature does not verify".to_string()))?;
+        Ok(sender)
+    }
+
+    /// Verify and decrypt a sealed payload addressed to `recipient`
+    pub fn open_sealed(&self, recipient: &SealIdentity) -> Result<OpenedPayload, VstpError> {
+        let sender = self.verify_sealed()?;
+        let addressed_to = header_key(self, SEAL_RECIPIENT_HEADER)?;
+        if addressed_to != recipient.public_keys().encryption {
+            return Err(seal_error("addressed to a different key"));
+        }
+        let ephemeral = header_key(self, SEAL_EPHEMERAL_HEADER)?;
+
+        let shared = recipient
+            .encryption
+            .diffie_hellman(&X25519Public::from(ephemeral));
+        let aad = binding(&ephemeral, &addressed_to, &sender);
+        let payload = cipher(shared.as_bytes(), &ephemeral, &addressed_to)
+            .decrypt(
+                Nonce::from_slice(&[0u8; 12]),
+                Payload {
+                    msg: &self.payload,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| seal_error("decryption failed"))?;
+
+        Ok(OpenedPayload { payload, sender })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let alice = SealIdentity::generate();
+        let bob = SealIdentity::generate();
+
+        let frame = Frame::sealed(b"for bob only", &alice, &bob.public_keys().encryption).unwrap();
+        assert!(frame.is_sealed());
+        assert!(!frame.payload.windows(12).any(|w| w == b"for bob only"));
+        assert_eq!(frame.verify_sealed().unwrap(), alice.public_keys().signing);
+
+        let opened = frame.open_sealed(&bob).unwrap();
+        assert_eq!(opened.payload, b"for bob only");
+        assert_eq!(opened.sender, alice.public_keys().signing);
+
+        // Nobody else can open it, including the sender
+        assert!(frame.open_sealed(&alice).is_err());
+        assert!(frame.open_sealed(&SealIdentity::generate()).is_err());
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let alice = SealIdentity::generate();
+        let bob = SealIdentity::generate();
+        let mallory = SealIdentity::generate();
+        let frame = Frame::sealed(b"pay 10", &alice, &bob.public_keys().encryption).unwrap();
+
+        let mut tampered = frame.clone();
+        tampered.payload[0] ^= 1;
+        assert!(matches!(tampered.open_sealed(&bob), Err(VstpError::ForgedFrame(_))));
+
+        // Re-signing someone else's ciphertext breaks decryption
+        let mut resigned = frame.clone();
+        let mallory_key = mallory.public_keys().signing;
+        let ephemeral = header_key(&frame, SEAL_EPHEMERAL_HEADER).unwrap();
+        let recipient = bob.public_keys().encryption;
+        let mut signed = binding(&ephemeral, &recipient, &mallory_key);
+        signed.extend_from_slice(&frame.payload);
+        let signature = mallory.signing.sign(&signed);
+        resigned.headers.retain(|h| {
+            h.key != SEAL_SENDER_HEADER.as_bytes() && h.key != SEAL_SIGNATURE_HEADER.as_bytes()
+        });
+        let resigned = resigned
+            .with_header(SEAL_SENDER_HEADER, &hex::encode(mallory_key))
+            .with_header(SEAL_SIGNATURE_HEADER, &hex::encode(signature.to_bytes()));
+        assert!(resigned.verify_sealed().is_ok());
+        assert!(resigned.open_sealed(&bob).is_err());
+    }
+
+    #[test]
+    fn test_identity_roundtrip() {
+        let identity = SealIdentity::generate();
+        let restored = SealIdentity::from_secrets(
+            identity.encryption.to_bytes(),
+            identity.signing.to_bytes(),
+        );
+        assert_eq!(identity.public_keys(), restored.public_keys());
+    }
+}
@@ -1,5 +1,23 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::core::types::VstpError;
+
+mod resolver;
+
+pub use resolver::CertResolver;
+
+/// A certificate served to clients that ask for one host name via SNI
+#[derive(Debug, Clone)]
+pub struct SniCertificate {
+    /// Host name, or a wildcard such as `*.example.com`
+    pub server_name: String,
+    /// Path to certificate chain file (PEM)
+    pub cert_path: String,
+    /// Path to private key file (PEM)
+    pub key_path: String,
+}
+
 /// TLS configuration for secure connections
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
@@ -7,6 +25,12 @@ pub struct TlsConfig {
     pub cert_path: Option<String>,
     /// Path to private key file
     pub key_path: Option<String>,
+    /// Per-host certificates selected by SNI; the default pair above serves
+    /// everyone else
+    pub sni_certs: Vec<SniCertificate>,
+    /// How often to check certificate files for changes, or `None` to load
+    /// them once
+    pub reload_interval: Option<Duration>,
     /// Whether to verify client certificates
     pub verify_client: bool,
     /// TLS handshake timeout
@@ -18,6 +42,8 @@ impl Default for TlsConfig {
         Self {
             cert_path: None,
             key_path: None,
+            sni_certs: Vec::new(),
+            reload_interval: Some(Duration::from_secs(30)),
             verify_client: false,
             handshake_timeout: Duration::from_secs(30),
         }
@@ -42,6 +68,27 @@ impl TlsConfig {
         self
     }
 
+    /// Serve a separate certificate to clients asking for `server_name`
+    pub fn with_sni_cert(
+        mut self,
+        server_name: impl Into<String>,
+        cert_path: impl Into<String>,
+        key_path: impl Into<String>,
+    ) -> Self {
+        self.sni_certs.push(SniCertificate {
+            server_name: server_name.into(),
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        });
+        self
+    }
+
+    /// Set how often certificate files are checked for changes
+    pub fn reload_interval(mut self, interval: Option<Duration>) -> Self {
+        self.reload_interval = interval;
+        self
+    }
+
     /// Enable or disable client certificate verification
     pub fn verify_client(mut self, verify: bool) -> Self {
         self.verify_client = verify;
@@ -53,4 +100,37 @@ impl TlsConfig {
         self.handshake_timeout = timeout;
         self
     }
+
+    /// Load the configured certificates and start watching them for changes
+    /// if a reload interval is set
+    ///
+    /// Must be called within a Tokio runtime when reloading is enabled. The
+    /// watcher stops when the last reference to the resolver is dropped.
+    pub fn cert_resolver(&self) -> Result<Arc<CertResolver>, VstpError> {
+        let resolver = Arc::new(CertResolver::from_config(self)?);
+        if let Some(interval) = self.reload_interval {
+            resolver.watch(interval);
+        }
+        Ok(resolver)
+    }
+
+    /// Build a rustls server configuration that selects certificates with
+    /// `resolver`
+    pub fn server_config(
+        &self,
+        resolver: Arc<CertResolver>,
+    ) -> Result<Arc<rustls::ServerConfig>, VstpError> {
+        if self.verify_client {
+            return Err(VstpError::Protocol(
+                "Client certificate verification is not supported yet".to_string(),
+            ));
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| VstpError::Protocol(format!("TLS configuration error: {}", e)))?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        Ok(Arc::new(config))
+    }
 }
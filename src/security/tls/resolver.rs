@@ -0,0 +1,419 @@
+//! SNI certificate selection with hot reload
+
+use rustls::crypto::CryptoProvider;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::TlsConfig;
+use crate::core::types::VstpError;
+
+/// A certificate chain and key loaded from a pair of PEM files
+struct LoadedCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// Modification times of the files the current key was loaded from
+    modified: (SystemTime, SystemTime),
+    key: Arc<CertifiedKey>,
+}
+
+impl LoadedCert {
+    fn load(
+        cert_path: &Path,
+        key_path: &Path,
+        provider: &CryptoProvider,
+    ) -> Result<Self, VstpError> {
+        let modified = (modified(cert_path)?, modified(key_path)?);
+        let key = load_certified_key(cert_path, key_path, provider)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            modified,
+            key: Arc::new(key),
+        })
+    }
+
+    /// Reload if either file changed, returning whether the key was replaced
+    ///
+    /// A pair that fails to load, e.g. because only one of the files has been
+    /// replaced so far, keeps the old key and is retried on the next call.
+    fn refresh(&mut self, provider: &CryptoProvider) -> bool {
+        let current = match (modified(&self.cert_path), modified(&self.key_path)) {
+            (Ok(cert), Ok(key)) => (cert, key),
+            _ => return false,
+        };
+        if current == self.modified {
+            return false;
+        }
+        match load_certified_key(&self.cert_path, &self.key_path, provider) {
+            Ok(key) => {
+                self.key = Arc::new(key);
+                self.modified = current;
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "Keeping previous certificate for {}: {}",
+                    self.cert_path.display(),
+                    e
+                );
+                false
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Result<SystemTime, VstpError> {
+    Ok(std::fs::metadata(path)?.modified()?)
+}
+
+fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, VstpError> {
+    let pem_error = |path: &Path, e: &dyn std::fmt::Display| {
+        VstpError::Protocol(format!("Invalid PEM in {}: {}", path.display(), e))
+    };
+
+    let cert_pem = std::fs::read(cert_path)?;
+    let certs = rustls_pemfile::certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(cert_path, &e))?;
+    if certs.is_empty() {
+        return Err(pem_error(cert_path, &"no certificates"));
+    }
+
+    let key_pem = std::fs::read(key_path)?;
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])
+        .map_err(|e| pem_error(key_path, &e))?
+        .ok_or_else(|| pem_error(key_path, &"no private key"))?;
+
+    CertifiedKey::from_der(certs, key, provider).map_err(|e| {
+        VstpError::Protocol(format!(
+            "Certificate {} does not match key {}: {}",
+            cert_path.display(),
+            key_path.display(),
+            e
+        ))
+    })
+}
+
+#[derive(Default)]
+struct Certs {
+    default: Option<LoadedCert>,
+    by_name: HashMap<String, LoadedCert>,
+}
+
+/// Picks the server certificate by SNI name and reloads certificate files
+/// when they change on disk
+///
+/// Names may be exact (`api.example.com`) or a wildcard for one label
+/// (`*.example.com`). Clients without SNI, or with a name that matches no
+/// entry, get the default certificate if one is configured and are refused
+/// otherwise. Reloading only affects new handshakes; established sessions
+/// keep the certificate they were set up with.
+pub struct CertResolver {
+    provider: Arc<CryptoProvider>,
+    certs: RwLock<Certs>,
+}
+
+impl std::fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("server_names", &self.server_names())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertResolver {
+    /// Load every certificate named in the configuration
+    pub fn from_config(config: &TlsConfig) -> Result<Self, VstpError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut certs = Certs::default();
+
+        match (&config.cert_path, &config.key_path) {
+            (Some(cert), Some(key)) => {
+                certs.default = Some(LoadedCert::load(cert.as_ref(), key.as_ref(), &provider)?);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(VstpError::Protocol(
+                    "Default certificate needs both a cert and a key path".to_string(),
+                ))
+            }
+        }
+        for sni in &config.sni_certs {
+            let loaded = LoadedCert::load(sni.cert_path.as_ref(), sni.key_path.as_ref(), &provider)?;
+            certs.by_name.insert(sni.server_name.to_ascii_lowercase(), loaded);
+        }
+        if certs.default.is_none() && certs.by_name.is_empty() {
+            return Err(VstpError::Protocol("No certificates configured".to_string()));
+        }
+
+        Ok(Self {
+            provider,
+            certs: RwLock::new(certs),
+        })
+    }
+
+    /// Host names with a dedicated certificate
+    pub fn server_names(&self) -> Vec<String> {
+        let certs = self.certs.read().unwrap_or_else(|e| e.into_inner());
+        let mut names: Vec<String> = certs.by_name.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Certificate for a client that asked for `server_name`
+    pub fn resolve_name(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let certs = self.certs.read().unwrap_or_else(|e| e.into_inner());
+        let by_name = server_name.map(str::to_ascii_lowercase).and_then(|name| {
+            certs.by_name.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                certs.by_name.get(&format!("*.{}", parent))
+            })
+        });
+        by_name
+            .or(certs.default.as_ref())
+            .map(|loaded| loaded.key.clone())
+    }
+
+    /// Reload certificates whose files changed, returning how many were replaced
+    pub fn reload(&self) -> usize {
+        let mut certs = self.certs.write().unwrap_or_else(|e| e.into_inner());
+        let Certs { default, by_name } = &mut *certs;
+        let mut reloaded = 0;
+        for loaded in default.iter_mut().chain(by_name.values_mut()) {
+            if loaded.refresh(&self.provider) {
+                info!("Reloaded certificate {}", loaded.cert_path.display());
+                reloaded += 1;
+            }
+        }
+        reloaded
+    }
+
+    /// Check the certificate files for changes every `interval`
+    ///
+    /// The task stops once the resolver is dropped.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let resolver: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match resolver.upgrade() {
+                    Some(resolver) => {
+                        resolver.reload();
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.resolve_name(client_hello.server_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConnection};
+
+    struct Ca {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Ca {
+        fn new() -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Self {
+                cert: params.self_signed(&key).unwrap(),
+                key,
+            }
+        }
+
+        /// Issue a certificate for `name` and write it to `dir`, returning
+        /// the file paths and the certificate
+        fn issue(&self, dir: &Path, name: &str, file: &str) -> (String, String, CertificateDer<'static>) {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .signed_by(&key, &self.cert, &self.key)
+                .unwrap();
+            let cert_path = dir.join(format!("{}.crt", file));
+            let key_path = dir.join(format!("{}.key", file));
+            std::fs::write(&cert_path, cert.pem()).unwrap();
+            std::fs::write(&key_path, key.serialize_pem()).unwrap();
+            (
+                cert_path.to_string_lossy().into_owned(),
+                key_path.to_string_lossy().into_owned(),
+                cert.der().clone(),
+            )
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vstp-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Mark a file as changed even on filesystems with coarse timestamps
+    fn touch(path: &str, offset: Duration) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + offset)
+            .unwrap();
+    }
+
+    /// Run a TLS handshake in memory, returning the certificate the server presented
+    fn handshake(
+        server_config: Arc<rustls::ServerConfig>,
+        ca: &Ca,
+        name: &str,
+    ) -> Result<CertificateDer<'static>, rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(name.to_string()).unwrap();
+        let mut client: Connection =
+            ClientConnection::new(Arc::new(client_config), server_name)?.into();
+        let mut server: Connection = ServerConnection::new(server_config)?.into();
+
+        fn pump(from: &mut Connection, to: &mut Connection) -> Result<(), rustls::Error> {
+            let mut buf = Vec::new();
+            while from.wants_write() {
+                from.write_tls(&mut buf).unwrap();
+            }
+            let mut rd = &buf[..];
+            while !rd.is_empty() {
+                to.read_tls(&mut rd).unwrap();
+            }
+            to.process_new_packets().map(|_| ())
+        }
+
+        while client.is_handshaking() || server.is_handshaking() {
+            pump(&mut client, &mut server)?;
+            pump(&mut server, &mut client)?;
+        }
+        Ok(client.peer_certificates().unwrap()[0].clone())
+    }
+
+    #[test]
+    fn test_sni_selection() {
+        let dir = temp_dir("sni");
+        let ca = Ca::new();
+        let (default_cert, default_key, default_der) = ca.issue(&dir, "fallback.example", "default");
+        let (a_cert, a_key, a_der) = ca.issue(&dir, "a.example", "a");
+        let (b_cert, b_key, b_der) = ca.issue(&dir, "b.tenants.example", "b");
+
+        let config = TlsConfig::new()
+            .with_cert(default_cert)
+            .with_key(default_key)
+            .with_sni_cert("A.example", a_cert, a_key)
+            .with_sni_cert("*.tenants.example", b_cert, b_key)
+            .reload_interval(None);
+        let resolver = Arc::new(CertResolver::from_config(&config).unwrap());
+        assert_eq!(resolver.server_names(), vec!["*.tenants.example", "a.example"]);
+
+        let served = |name: Option<&str>| resolver.resolve_name(name).unwrap().cert[0].clone();
+        assert_eq!(served(Some("a.example")), a_der);
+        assert_eq!(served(Some("b.tenants.example")), b_der);
+        assert_eq!(served(Some("deep.b.tenants.example")), default_der);
+        assert_eq!(served(None), default_der);
+
+        // The same selection happens in a real handshake
+        let server_config = config.server_config(resolver.clone()).unwrap();
+        assert_eq!(handshake(server_config.clone(), &ca, "a.example").unwrap(), a_der);
+        assert_eq!(
+            handshake(server_config, &ca, "b.tenants.example").unwrap(),
+            b_der
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_hot_reload() {
+        let dir = temp_dir("reload");
+        let ca = Ca::new();
+        let (cert, key, old_der) = ca.issue(&dir, "a.example", "a");
+        let config = TlsConfig::new()
+            .with_sni_cert("a.example", cert.clone(), key.clone())
+            .reload_interval(None);
+        let resolver = Arc::new(CertResolver::from_config(&config).unwrap());
+        let server_config = config.server_config(resolver.clone()).unwrap();
+
+        // No SNI match and no default certificate: the handshake is refused
+        assert!(handshake(server_config.clone(), &ca, "other.example").is_err());
+
+        let in_use = resolver.resolve_name(Some("a.example")).unwrap();
+        assert_eq!(resolver.reload(), 0);
+
+        // A new certificate with the old key is a mismatched pair and is not
+        // picked up until the key is replaced as well
+        let new_key = KeyPair::generate().unwrap();
+        let new_cert = CertificateParams::new(vec!["a.example".to_string()])
+            .unwrap()
+            .signed_by(&new_key, &ca.cert, &ca.key)
+            .unwrap();
+        std::fs::write(&cert, new_cert.pem()).unwrap();
+        touch(&cert, Duration::from_secs(10));
+        assert_eq!(resolver.reload(), 0);
+        assert_eq!(resolver.resolve_name(Some("a.example")).unwrap().cert[0], old_der);
+
+        std::fs::write(&key, new_key.serialize_pem()).unwrap();
+        touch(&key, Duration::from_secs(10));
+        assert_eq!(resolver.reload(), 1);
+        assert_eq!(
+            handshake(server_config, &ca, "a.example").unwrap(),
+            *new_cert.der()
+        );
+
+        // Sessions set up before the reload still hold the old certificate
+        assert_eq!(in_use.cert[0], old_der);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_watcher_stops_with_resolver() {
+        let dir = temp_dir("watch");
+        let ca = Ca::new();
+        let (cert, key, _) = ca.issue(&dir, "a.example", "a");
+        let config = TlsConfig::new().with_cert(cert).with_key(key);
+
+        let resolver = Arc::new(CertResolver::from_config(&config).unwrap());
+        let watcher = resolver.watch(Duration::from_millis(10));
+        drop(resolver);
+        tokio::time::timeout(Duration::from_secs(1), watcher)
+            .await
+            .unwrap()
+            .unwrap();
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
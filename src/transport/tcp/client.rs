@@ -1,16 +1,23 @@
 use futures::SinkExt;
 use tokio::net::TcpStream;
+use tokio::time::timeout;
 use tokio_stream::StreamExt;
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{debug, info};
 
-use crate::core::types::{Frame, FrameType, VstpError};
+use crate::core::types::{Flags, Frame, FrameType, VstpError};
 use crate::codec::VstpFrameCodec as Codec;
+use crate::security::auth::{Credentials, AUTH_PRINCIPAL_HEADER};
+use crate::security::mac::{FrameKeyring, MAC_NONCE_HEADER};
+use crate::security::replay::{ReplayConfig, ReplayProtection};
+use crate::security::noise::{NoiseConfig, NoiseHandshake, NoiseTransport, PublicKey};
 
 /// TCP client for VSTP protocol
 pub struct VstpTcpClient {
     framed_write: FramedWrite<tokio::net::tcp::OwnedWriteHalf, Codec>,
     framed_read: FramedRead<tokio::net::tcp::OwnedReadHalf, Codec>,
+    noise: Option<NoiseTransport>,
+    replay: Option<ReplayProtection>,
 }
 
 impl VstpTcpClient {
@@ -26,23 +33,179 @@ impl VstpTcpClient {
         Ok(Self {
             framed_write,
             framed_read,
+            noise: None,
+            replay: None,
         })
     }
 
+    /// Connect to a VSTP server over a Noise secure channel
+    ///
+    /// The handshake replaces the plain HELLO/WELCOME exchange; every frame
+    /// sent or received afterwards is encrypted.
+    pub async fn connect_with_noise(addr: &str, config: NoiseConfig) -> Result<Self, VstpError> {
+        let mut client = Self::connect(addr).await?;
+        timeout(config.handshake_timeout, client.noise_handshake(&config))
+            .await
+            .map_err(|_| VstpError::Timeout)??;
+        info!("Noise handshake with {} complete", addr);
+        Ok(client)
+    }
+
+    async fn noise_handshake(&mut self, config: &NoiseConfig) -> Result<(), VstpError> {
+        let (mut handshake, hello) = NoiseHandshake::initiate(config)?;
+        self.framed_write.send(hello).await?;
+
+        while !handshake.is_finished() {
+            let frame = self
+                .framed_read
+                .try_next()
+                .await?
+                .ok_or(VstpError::ConnectionClosed)?;
+            if frame.typ == FrameType::Err {
+                return Err(VstpError::Protocol(format!(
+                    "Handshake refused: {}",
+                    String::from_utf8_lossy(&frame.payload)
+                )));
+            }
+            if let Some(reply) = handshake.read(&frame)? {
+                self.framed_write.send(reply).await?;
+            }
+        }
+
+        self.noise = Some(handshake.into_transport()?);
+        Ok(())
+    }
+
+    /// Authenticate with a server that requires it, returning the principal
+    /// ID the server assigned
+    ///
+    /// This replaces [`send_hello`](Self::send_hello): the credentials travel
+    /// in the HELLO, and a challenge from the server is answered when the
+    /// credentials support it.
+    pub async fn authenticate(&mut self, credentials: &Credentials) -> Result<String, VstpError> {
+        self.send(credentials.hello_frame()).await?;
+
+        loop {
+            let frame = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+            match frame.typ {
+                FrameType::Welcome => {
+                    let principal = frame.get_header(AUTH_PRINCIPAL_HEADER).unwrap_or_default();
+                    info!("Authenticated as {}", principal);
+                    return Ok(principal.to_string());
+                }
+                FrameType::Err => {
+                    return Err(VstpError::Protocol(format!(
+                        "Authentication refused: {}",
+                        String::from_utf8_lossy(&frame.payload)
+                    )));
+                }
+                FrameType::Ping => match credentials.answer_challenge(&frame) {
+                    Some(pong) => self.send(pong).await?,
+                    None => {
+                        return Err(VstpError::Protocol(
+                            "Server sent a challenge these credentials cannot answer".to_string(),
+                        ))
+                    }
+                },
+                _ => return Err(VstpError::UnexpectedFrameType),
+            }
+        }
+    }
+
+    /// Sign every frame from now on and reject unsigned or forged frames
+    /// from the server
+    ///
+    /// Both ends must switch at the same point in the conversation, e.g.
+    /// right after authentication.
+    pub fn enable_frame_mac(&mut self, keyring: FrameKeyring) {
+        self.framed_write.encoder_mut().set_mac(Some(keyring.clone()));
+        self.framed_read.decoder_mut().set_mac(Some(keyring));
+    }
+
+    /// Set up per-frame MACs with a server that requires them, deriving the
+    /// session key from `master` and the nonce in the server's WELCOME
+    ///
+    /// Call this right after connecting, or after authenticating if the
+    /// server also authenticates.
+    pub async fn enable_session_frame_mac(
+        &mut self,
+        key_id: impl Into<String>,
+        master: &[u8],
+    ) -> Result<(), VstpError> {
+        let welcome = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+        if welcome.typ == FrameType::Err {
+            return Err(VstpError::Protocol(format!(
+                "Server refused the session: {}",
+                String::from_utf8_lossy(&welcome.payload)
+            )));
+        }
+        let nonce = welcome
+            .get_header(MAC_NONCE_HEADER)
+            .filter(|_| welcome.typ == FrameType::Welcome)
+            .and_then(|v| hex::decode(v).ok())
+            .ok_or_else(|| VstpError::Protocol("Server did not send a MAC nonce".to_string()))?;
+        self.enable_frame_mac(FrameKeyring::for_session(key_id, master, &nonce));
+        Ok(())
+    }
+
+    /// Stamp outgoing frames with sequence numbers and reject replayed
+    /// frames from the server from now on
+    pub fn enable_replay_protection(&mut self, config: ReplayConfig) {
+        self.replay = Some(ReplayProtection::new(config));
+    }
+
+    /// The server's static key, if connected over Noise
+    pub fn peer_public_key(&self) -> Option<PublicKey> {
+        self.noise.as_ref().map(NoiseTransport::remote_static)
+    }
+
     /// Send a frame to the server
     pub async fn send(&mut self, frame: Frame) -> Result<(), VstpError> {
         debug!("Sending frame: {:?}", frame.typ);
-        self.framed_write.send(frame).await?;
+        let frame = match &mut self.replay {
+            Some(replay) => replay.stamp(frame),
+            None => frame,
+        };
+        match &mut self.noise {
+            Some(noise) => {
+                for sealed in noise.seal(&frame)? {
+                    self.framed_write.send(sealed).await?;
+                }
+            }
+            None => self.framed_write.send(frame).await?,
+        }
         Ok(())
     }
 
     /// Receive a frame from the server
     pub async fn recv(&mut self) -> Result<Option<Frame>, VstpError> {
-        let frame = self.framed_read.try_next().await?;
-        if let Some(ref frame) = frame {
+        loop {
+            let Some(frame) = self.framed_read.try_next().await? else {
+                return Ok(None);
+            };
+            let frame = match &mut self.noise {
+                // With XX the server can only check our key after we finish
+                Some(_) if frame.typ == FrameType::Err && !frame.flags.contains(Flags::ENC) => {
+                    return Err(VstpError::Protocol(format!(
+                        "Handshake refused: {}",
+                        String::from_utf8_lossy(&frame.payload)
+                    )));
+                }
+                Some(noise) => match noise.open(&frame)? {
+                    Some(frame) => frame,
+                    // Part of a frame split over several sealed frames
+                    None => continue,
+                },
+                None => frame,
+            };
+            if let Some(replay) = &mut self.replay {
+                replay
+                    .check(&frame)
+                    .map_err(|rejection| VstpError::ReplayedFrame(rejection.to_string()))?;
+            }
             debug!("Received frame: {:?}", frame.typ);
+            return Ok(Some(frame));
         }
-        Ok(frame)
     }
 
     /// Close the connection gracefully
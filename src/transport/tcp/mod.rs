@@ -6,4 +6,4 @@ pub mod client;
 pub mod server;
 
 pub use client::VstpTcpClient;
-pub use server::VstpTcpServer;
+pub use server::{SessionContext, VstpTcpServer};
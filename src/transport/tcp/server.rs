@@ -1,63 +1,339 @@
 use futures::SinkExt;
 use std::future::Future;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::core::types::{Frame, SessionId, VstpError};
+use crate::core::types::{Frame, FrameType, SessionId, VstpError};
 use crate::codec::VstpFrameCodec as Codec;
+use crate::security::ai::monitor::ErrorType;
 use crate::security::ai::AnomalyDetector;
+use crate::security::auth::{
+    AuthDecision, Authenticator, Principal, AUTH_CHALLENGE_HEADER, AUTH_PRINCIPAL_HEADER,
+};
+use crate::security::blocklist::Blocklist;
+use crate::security::mac::{FrameKeyring, SessionMacConfig, MAC_NONCE_HEADER};
+use crate::security::policy::Policy;
+use crate::security::replay::{ReplayConfig, ReplayProtection, ReplayRejection};
+use crate::security::noise::{
+    is_noise_hello, NoiseConfig, NoiseHandshake, NoiseTransport, PublicKey,
+};
+
+/// How long a client has to complete authentication after connecting
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of set-up connections waiting for `accept()`
+const ACCEPT_QUEUE_SIZE: usize = 128;
+
+/// How often an idle session checks whether its peer has been banned
+const BAN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resolve once `ip` is banned, or never without a blocklist
+async fn banned(blocklist: Option<&Blocklist>, ip: IpAddr) {
+    let Some(blocklist) = blocklist else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(BAN_CHECK_INTERVAL);
+    while !blocklist.is_blocked(ip) {
+        interval.tick().await;
+    }
+}
+
+/// What a handler knows about the session a frame arrived on
+#[derive(Debug, Clone)]
+pub struct SessionContext {
+    /// Server-assigned session ID
+    pub session_id: SessionId,
+    /// Client address
+    pub peer_addr: std::net::SocketAddr,
+    /// Authenticated identity, if the server has an authenticator
+    pub principal: Option<Arc<Principal>>,
+}
 
 /// TCP connection handler
 pub struct VstpTcpConnection {
     framed: Framed<TcpStream, Codec>,
     session_id: SessionId,
     peer_addr: std::net::SocketAddr,
+    noise: Option<NoiseTransport>,
+    principal: Option<Arc<Principal>>,
+    replay: Option<ReplayProtection>,
 }
 
 impl VstpTcpConnection {
     /// Send a frame to the client
     pub async fn send(&mut self, frame: Frame) -> Result<(), VstpError> {
-        self.framed.send(frame).await?;
+        let frame = match &mut self.replay {
+            Some(replay) => replay.stamp(frame),
+            None => frame,
+        };
+        match &mut self.noise {
+            Some(noise) => {
+                for sealed in noise.seal(&frame)? {
+                    self.framed.send(sealed).await?;
+                }
+            }
+            None => self.framed.send(frame).await?,
+        }
         Ok(())
     }
 
     /// Receive a frame from the client
+    ///
+    /// With replay protection on, a replayed frame is consumed and reported
+    /// as [`VstpError::ReplayedFrame`]; the connection stays usable.
     pub async fn recv(&mut self) -> Result<Option<Frame>, VstpError> {
-        let frame = self.framed.next().await.transpose()?;
-        Ok(frame)
+        match self.recv_checked().await? {
+            Some(Ok(frame)) => Ok(Some(frame)),
+            Some(Err(rejection)) => Err(VstpError::ReplayedFrame(rejection.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Receive a frame, passing replay rejections back to the caller
+    async fn recv_checked(&mut self) -> Result<Option<Result<Frame, ReplayRejection>>, VstpError> {
+        let Some(frame) = self.recv_frame().await? else {
+            return Ok(None);
+        };
+        if let Some(replay) = &mut self.replay {
+            if let Err(rejection) = replay.check(&frame) {
+                warn!(
+                    "Session {} rejected replayed frame: {}",
+                    self.session_id, rejection
+                );
+                return Ok(Some(Err(rejection)));
+            }
+        }
+        Ok(Some(Ok(frame)))
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Frame>, VstpError> {
+        loop {
+            let Some(frame) = self.framed.next().await.transpose()? else {
+                return Ok(None);
+            };
+            match &mut self.noise {
+                Some(noise) => {
+                    // `None` means the frame was split over several sealed frames
+                    if let Some(frame) = noise.open(&frame)? {
+                        return Ok(Some(frame));
+                    }
+                }
+                None => return Ok(Some(frame)),
+            }
+        }
+    }
+
+    /// Carry out the detector's pending responses for this session,
+    /// returning `false` once one of them closes it
+    async fn apply_responses(&mut self, detector: &AnomalyDetector) -> bool {
+        for action in detector.take_responses(self.session_id) {
+            if let Some(notice) = action.notice() {
+                let err = Frame::new(FrameType::Err).with_payload(notice.as_bytes().to_vec());
+                if self.send(err).await.is_err() {
+                    return false;
+                }
+            }
+            if action.ends_session() {
+                warn!("Closing session {} in response to a threat: {:?}", self.session_id, action);
+                return false;
+            }
+        }
+        true
     }
 
     /// Get the peer address
     pub fn peer_addr(&self) -> std::net::SocketAddr {
         self.peer_addr
     }
-}
 
-/// TCP server for VSTP protocol
-pub struct VstpTcpServer {
-    listener: TcpListener,
-    next_session_id: Arc<Mutex<u128>>,
-}
+    /// Get the session ID
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
 
-impl VstpTcpServer {
-    /// Bind to the specified address
-    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self, VstpError> {
-        let listener = TcpListener::bind(addr).await?;
-        info!("VSTP TCP server bound to {}", listener.local_addr()?);
+    /// The authenticated identity of the client, if the server authenticates
+    pub fn principal(&self) -> Option<&Arc<Principal>> {
+        self.principal.as_ref()
+    }
 
-        Ok(Self {
-            listener,
-            next_session_id: Arc::new(Mutex::new(1)),
-        })
+    /// Context handed to handlers for frames on this connection
+    pub fn context(&self) -> SessionContext {
+        SessionContext {
+            session_id: self.session_id,
+            peer_addr: self.peer_addr,
+            principal: self.principal.clone(),
+        }
     }
 
-    /// Accept a new client connection
-    pub async fn accept(&self) -> Result<VstpTcpConnection, VstpError> {
-        let (socket, addr) = self.listener.accept().await?;
+    /// Sign every frame from now on and reject unsigned or forged frames
+    /// from the client
+    pub fn enable_frame_mac(&mut self, keyring: FrameKeyring) {
+        self.framed.codec_mut().set_mac(Some(keyring));
+    }
+
+    /// Stamp outgoing frames with sequence numbers and reject replayed
+    /// incoming frames from now on
+    pub fn enable_replay_protection(&mut self, config: ReplayConfig) {
+        self.replay = Some(ReplayProtection::new(config));
+    }
+
+    /// The client's static key, if the connection uses Noise
+    pub fn peer_public_key(&self) -> Option<PublicKey> {
+        self.noise.as_ref().map(NoiseTransport::remote_static)
+    }
+
+    /// Run the server side of the Noise handshake, if configured
+    async fn secure(&mut self, config: Option<&NoiseConfig>) -> Result<(), VstpError> {
+        let Some(config) = config else {
+            return Ok(());
+        };
+        match timeout(config.handshake_timeout, self.noise_handshake(config)).await {
+            Ok(result) => result,
+            Err(_) => Err(VstpError::Timeout),
+        }
+    }
+
+    async fn noise_handshake(&mut self, config: &NoiseConfig) -> Result<(), VstpError> {
+        let hello = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+        if !is_noise_hello(&hello) {
+            return self.refuse("noise handshake required").await;
+        }
+
+        let (mut handshake, welcome) = match NoiseHandshake::respond(config, &hello) {
+            Ok(started) => started,
+            Err(_) => return self.refuse("noise handshake failed").await,
+        };
+        self.framed.send(welcome).await?;
+
+        while !handshake.is_finished() {
+            let frame = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+            if let Some(reply) = handshake.read(&frame)? {
+                self.framed.send(reply).await?;
+            }
+        }
+
+        match handshake.into_transport() {
+            Ok(transport) => {
+                self.noise = Some(transport);
+                Ok(())
+            }
+            Err(_) => self.refuse("peer key not accepted").await,
+        }
+    }
+
+    /// Authenticate the client's HELLO, if the server has an authenticator
+    async fn authenticate(
+        &mut self,
+        authenticator: Option<&dyn Authenticator>,
+    ) -> Result<(), VstpError> {
+        let Some(authenticator) = authenticator else {
+            return Ok(());
+        };
+        match timeout(AUTH_TIMEOUT, self.auth_handshake(authenticator)).await {
+            Ok(result) => result,
+            Err(_) => self.refuse("authentication timed out").await,
+        }
+    }
+
+    async fn auth_handshake(&mut self, authenticator: &dyn Authenticator) -> Result<(), VstpError> {
+        let hello = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+        if hello.typ != FrameType::Hello {
+            return self.refuse("authentication required").await;
+        }
+
+        let mut decision = authenticator.authenticate(&hello).await;
+        if let AuthDecision::Challenge(challenge) = decision {
+            let ping = Frame::new(FrameType::Ping)
+                .with_header(AUTH_CHALLENGE_HEADER, &hex::encode(&challenge));
+            self.send(ping).await?;
+            let response = self.recv().await?.ok_or(VstpError::ConnectionClosed)?;
+            decision = authenticator
+                .verify_response(&hello, &challenge, &response)
+                .await;
+        }
+
+        match decision {
+            AuthDecision::Accept(principal) => {
+                info!(
+                    "Session {} authenticated as {}",
+                    self.session_id, principal.id
+                );
+                let welcome =
+                    Frame::new(FrameType::Welcome).with_header(AUTH_PRINCIPAL_HEADER, &principal.id);
+                self.principal = Some(Arc::new(principal));
+                self.send(welcome).await
+            }
+            AuthDecision::Reject(reason) => self.refuse(&reason).await,
+            AuthDecision::Challenge(_) => self.refuse("too many challenges").await,
+        }
+    }
+
+    /// Run every handshake the server requires, in order
+    async fn establish(&mut self, setup: &SessionSetup) -> Result<(), VstpError> {
+        self.secure(setup.noise.as_deref()).await?;
+        self.authenticate(setup.authenticator.as_deref()).await?;
+        if let Some(config) = &setup.mac {
+            let (nonce, keyring) = config.new_session();
+            let welcome = Frame::new(FrameType::Welcome).with_header(MAC_NONCE_HEADER, &hex::encode(nonce));
+            self.send(welcome).await?;
+            self.enable_frame_mac(keyring);
+        }
+        if let Some(config) = &setup.replay {
+            self.enable_replay_protection(config.clone());
+        }
+        Ok(())
+    }
+
+    /// Tell the client why the session was refused
+    async fn refuse(&mut self, reason: &str) -> Result<(), VstpError> {
+        let err = Frame::new(FrameType::Err).with_payload(reason.as_bytes().to_vec());
+        // Cleartext until the Noise handshake completes
+        let _ = self.send(err).await;
+        Err(VstpError::Protocol(format!(
+            "Session with {} refused: {}",
+            self.peer_addr, reason
+        )))
+    }
+}
+
+/// Handshakes every new connection goes through before it is handed out
+#[derive(Clone, Default)]
+struct SessionSetup {
+    noise: Option<Arc<NoiseConfig>>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    mac: Option<SessionMacConfig>,
+    replay: Option<ReplayConfig>,
+}
+
+/// Listening socket and session numbering, shared with the `accept()` driver
+struct Listener {
+    listener: TcpListener,
+    next_session_id: Mutex<u128>,
+}
+
+impl Listener {
+    /// Accept a TCP connection from an address that is not banned, without
+    /// running any handshake
+    async fn accept_stream(
+        &self,
+        blocklist: Option<&Blocklist>,
+    ) -> Result<VstpTcpConnection, VstpError> {
+        let (socket, addr) = loop {
+            let (socket, addr) = self.listener.accept().await?;
+            if blocklist.is_some_and(|b| b.is_blocked(addr.ip())) {
+                warn!("Refused connection from banned address {}", addr);
+                continue;
+            }
+            break (socket, addr);
+        };
         let session_id = {
             let mut id_guard = self.next_session_id.lock().await;
             *id_guard += 1;
@@ -70,12 +346,160 @@ impl VstpTcpServer {
             framed: Framed::new(socket, Codec::default()),
             session_id,
             peer_addr: addr,
+            noise: None,
+            principal: None,
+            replay: None,
         })
     }
 
+    /// Accept connections forever, setting each one up in its own task and
+    /// queueing the ones that succeed for `accept()`
+    async fn drive(
+        self: Arc<Self>,
+        setup: SessionSetup,
+        blocklist: Option<Blocklist>,
+        accept_tx: mpsc::Sender<VstpTcpConnection>,
+    ) {
+        loop {
+            let mut conn = match self.accept_stream(blocklist.as_ref()).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let setup = setup.clone();
+            let accept_tx = accept_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = conn.establish(&setup).await {
+                    warn!("Session {} rejected: {}", conn.session_id, e);
+                    return;
+                }
+                let _ = accept_tx.send(conn).await;
+            });
+        }
+    }
+}
+
+/// Background task feeding `accept()`
+struct Acceptor {
+    rx: mpsc::Receiver<VstpTcpConnection>,
+    driver: JoinHandle<()>,
+}
+
+/// TCP server for VSTP protocol
+pub struct VstpTcpServer {
+    listener: Arc<Listener>,
+    acceptor: Mutex<Option<Acceptor>>,
+    setup: SessionSetup,
+    policy: Option<Arc<Policy>>,
+    blocklist: Option<Blocklist>,
+}
+
+impl VstpTcpServer {
+    /// Bind to the specified address
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self, VstpError> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("VSTP TCP server bound to {}", listener.local_addr()?);
+
+        Ok(Self {
+            listener: Arc::new(Listener {
+                listener,
+                next_session_id: Mutex::new(1),
+            }),
+            acceptor: Mutex::new(None),
+            setup: SessionSetup::default(),
+            policy: None,
+            blocklist: None,
+        })
+    }
+
+    /// Require every client to open a Noise secure channel
+    pub fn with_noise(mut self, config: NoiseConfig) -> Self {
+        self.setup.noise = Some(Arc::new(config));
+        self
+    }
+
+    /// Require every client to authenticate with its HELLO
+    ///
+    /// With Noise also enabled, authentication runs inside the secure channel.
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.setup.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Require a per-frame MAC on every session, keyed per session from a
+    /// shared master secret
+    ///
+    /// After the Noise handshake and authentication, if any, the server sends
+    /// a WELCOME with a `mac-nonce` header and signs every frame from then
+    /// on. Clients call
+    /// [`VstpTcpClient::enable_session_frame_mac`](crate::transport::tcp::VstpTcpClient::enable_session_frame_mac)
+    /// at that point to derive the same key.
+    pub fn with_frame_mac(mut self, config: SessionMacConfig) -> Self {
+        self.setup.mac = Some(config);
+        self
+    }
+
+    /// Require sequence numbers on every frame once the session is set up,
+    /// rejecting replays
+    ///
+    /// Clients must call
+    /// [`VstpTcpClient::enable_replay_protection`](crate::transport::tcp::VstpTcpClient::enable_replay_protection)
+    /// right after connecting, or after authenticating and setting up frame
+    /// MACs if the server requires them.
+    pub fn with_replay_protection(mut self, config: ReplayConfig) -> Self {
+        self.setup.replay = Some(config);
+        self
+    }
+
+    /// Check every frame against an authorization policy before the handler
+    /// sees it
+    ///
+    /// Denied frames are answered with ERR and reported to the detector;
+    /// the session stays open. Only applies to [`run`](Self::run) and its
+    /// variants.
+    pub fn with_policy(mut self, policy: Arc<Policy>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Refuse connections from banned addresses
+    ///
+    /// Banned clients are disconnected as soon as they connect, and open
+    /// sessions from an address are closed within a second of it getting
+    /// banned, even if they are idle. When run with a detector and no
+    /// blocklist of its own, the server uses the detector's.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    /// Accept a new client connection, completing the Noise handshake and
+    /// authentication if the server requires them
+    ///
+    /// The first call starts a background task that owns the listener from
+    /// then on. Handshakes run concurrently in their own tasks, so a slow
+    /// client does not hold up others, and connections that fail to set up
+    /// are logged and skipped rather than returned as errors.
+    pub async fn accept(&self) -> Result<VstpTcpConnection, VstpError> {
+        let mut acceptor = self.acceptor.lock().await;
+        let acceptor = acceptor.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+            let driver = tokio::spawn(self.listener.clone().drive(
+                self.setup.clone(),
+                self.blocklist.clone(),
+                tx,
+            ));
+            Acceptor { rx, driver }
+        });
+
+        acceptor.rx.recv().await.ok_or(VstpError::ConnectionClosed)
+    }
+
     /// Get the local address this server is bound to
     pub fn local_addr(&self) -> Result<std::net::SocketAddr, VstpError> {
-        self.listener.local_addr().map_err(|e| VstpError::Io(e))
+        self.listener.listener.local_addr().map_err(VstpError::Io)
     }
 
     /// Run the server with the provided handler function
@@ -96,25 +520,114 @@ impl VstpTcpServer {
     where
         F: Fn(SessionId, Frame) -> Fut + Send + Sync + Clone + 'static,
         Fut: Future<Output = ()> + Send,
+    {
+        self.run_with_context(move |ctx, frame| handler(ctx.session_id, frame), detector)
+            .await
+    }
+
+    /// Run the server with a handler that receives the full session context,
+    /// including the authenticated principal
+    pub async fn run_with_context<F, Fut>(
+        self,
+        handler: F,
+        detector: Option<Arc<AnomalyDetector>>,
+    ) -> Result<(), VstpError>
+    where
+        F: Fn(SessionContext, Frame) -> Fut + Send + Sync + Clone + 'static,
+        Fut: Future<Output = ()> + Send,
     {
         info!("VSTP TCP server starting...");
 
+        let blocklist = self
+            .blocklist
+            .clone()
+            .or_else(|| detector.as_ref().map(|d| d.blocklist()));
+
         loop {
-            match self.accept().await {
+            match self.listener.accept_stream(blocklist.as_ref()).await {
                 Ok(mut conn) => {
                     let handler = handler.clone();
                     let detector = detector.clone();
+                    let setup = self.setup.clone();
+                    let policy = self.policy.clone();
+                    let blocklist = blocklist.clone();
                     let session_id = conn.session_id;
                     let peer_addr = conn.peer_addr;
 
                     tokio::spawn(async move {
-                        while let Ok(Some(frame)) = conn.recv().await {
+                        // Handshake in the session task so a slow client
+                        // cannot hold up the accept loop
+                        if let Err(e) = conn.establish(&setup).await {
+                            warn!("Session {} rejected: {}", session_id, e);
+                            return;
+                        }
+                        let context = conn.context();
+                        if let (Some(detector), Some(principal)) = (&detector, &context.principal) {
+                            detector.set_principal(session_id, principal.id.clone()).await;
+                        }
+
+                        loop {
+                            // Race the next frame against a ban so idle
+                            // sessions are closed too
+                            let received = tokio::select! {
+                                received = conn.recv_checked() => received,
+                                () = banned(blocklist.as_ref(), peer_addr.ip()) => {
+                                    warn!("Closing session {}: peer {} is banned", session_id, peer_addr);
+                                    break;
+                                }
+                            };
+                            let received = match received {
+                                Ok(Some(received)) => received,
+                                Ok(None) => break,
+                                Err(e) => {
+                                    // The stream cannot be resynchronized after
+                                    // a bad frame, so the session ends either way
+                                    warn!("Session {} failed to receive a frame: {}", session_id, e);
+                                    if let Some(detector) = &detector {
+                                        detector.report_decode_error(Some(session_id), peer_addr, &e).await;
+                                    }
+                                    break;
+                                }
+                            };
+                            if blocklist.as_ref().is_some_and(|b| b.is_blocked(peer_addr.ip())) {
+                                warn!("Closing session {}: peer {} is banned", session_id, peer_addr);
+                                break;
+                            }
+                            if let Some(delay) = detector.as_ref().and_then(|d| d.throttle_delay(session_id)) {
+                                tokio::time::sleep(delay).await;
+                            }
+
+                            let frame = match received {
+                                Ok(frame) => frame,
+                                Err(rejection) => {
+                                    if let Some(detector) = &detector {
+                                        detector.report_replay(session_id, &rejection).await;
+                                        if !conn.apply_responses(detector).await {
+                                            break;
+                                        }
+                                        if detector.is_blocked(session_id).await {
+                                            tracing::error!("Session {} blocked due to security threat", session_id);
+                                            break;
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+
                             // Run AI anomaly detection if enabled
                             if let Some(detector) = &detector {
                                 let frame_size = std::mem::size_of_val(&frame) + frame.payload.len();
+                                if frame.flags.unknown_bits() != 0 {
+                                    detector
+                                        .report_error(session_id, peer_addr, ErrorType::SuspiciousFlag)
+                                        .await;
+                                }
                                 
                                 match detector.analyze_frame(session_id, peer_addr, &frame, frame_size).await {
                                     Ok(threats) => {
+                                        if !conn.apply_responses(detector).await {
+                                            break;
+                                        }
                                         if !threats.is_empty() {
                                             tracing::warn!(
                                                 "Detected {} threat(s) for session {}",
@@ -137,13 +650,35 @@ impl VstpTcpServer {
                                 }
                             }
 
+                            if let Some(policy) = &policy {
+                                if let Err(reason) = policy.authorize(context.principal.as_deref(), &frame) {
+                                    warn!("Session {} denied: {}", session_id, reason);
+                                    let err = Frame::new(FrameType::Err).with_payload(reason.clone().into_bytes());
+                                    if conn.send(err).await.is_err() {
+                                        break;
+                                    }
+                                    if let Some(detector) = &detector {
+                                        detector.report_unauthorized(session_id, &reason).await;
+                                        if !conn.apply_responses(detector).await {
+                                            break;
+                                        }
+                                        if detector.is_blocked(session_id).await {
+                                            tracing::error!("Session {} blocked due to security threat", session_id);
+                                            break;
+                                        }
+                                    }
+                                    continue;
+                                }
+                            }
+
                             // Process frame with handler
-                            handler(session_id, frame).await;
+                            handler(context.clone(), frame).await;
                         }
                         info!("Session {} ended", session_id);
                         
                         // Cleanup connection from detector
                         if let Some(detector) = &detector {
+                            detector.end_session(session_id).await;
                             detector.cleanup().await;
                         }
                     });
@@ -155,3 +690,11 @@ impl VstpTcpServer {
         }
     }
 }
+
+impl Drop for VstpTcpServer {
+    fn drop(&mut self) {
+        if let Some(acceptor) = self.acceptor.get_mut() {
+            acceptor.driver.abort();
+        }
+    }
+}
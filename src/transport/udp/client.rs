@@ -1,4 +1,5 @@
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::time::timeout;
@@ -6,9 +7,16 @@ use tracing::{debug, info};
 
 use crate::core::frame::{encode_frame, try_decode_frame};
 use crate::core::types::{Flags, Frame, FrameType, Header, VstpError};
+use crate::transport::udp::crypto::{
+    self, is_encrypted, DatagramCipher, HANDSHAKE_HEADER, NOISE_PATTERN,
+};
 use crate::transport::udp::reassembly::{
-    fragment_payload, extract_fragment_info, add_fragment_headers,
-    ReassemblyManager, MAX_DATAGRAM_SIZE,
+    fragment_payload_with_fec, extract_fragment_info, add_fragment_headers,
+    strip_fragment_headers, ReassemblyManager, MAX_DATAGRAM_SIZE,
+};
+use crate::transport::udp::session::{
+    extract_connection_id, format_connection_id, path_response_frame, ConnectionId,
+    CONN_ID_HEADER,
 };
 
 /// Configuration for UDP client
@@ -26,6 +34,11 @@ pub struct UdpConfig {
     pub use_crc: bool,
     /// Whether to allow fragmentation
     pub allow_frag: bool,
+    /// Parity fragments added per data fragment of a fragmented frame, so
+    /// lost fragments can be rebuilt without a retransmission (0.0 disables FEC)
+    pub fec_redundancy: f64,
+    /// Whether sessions opened with `connect` are encrypted
+    pub encrypt: bool,
 }
 
 impl Default for UdpConfig {
@@ -37,6 +50,8 @@ impl Default for UdpConfig {
             ack_timeout: Duration::from_secs(2),
             use_crc: true,
             allow_frag: true,
+            fec_redundancy: 0.0,
+            encrypt: false,
         }
     }
 }
@@ -47,6 +62,9 @@ pub struct VstpUdpClient {
     config: UdpConfig,
     reassembly: ReassemblyManager,
     next_msg_id: u64,
+    next_frag_id: AtomicU8,
+    connection: Option<(SocketAddr, ConnectionId)>,
+    cipher: Option<Box<DatagramCipher>>,
 }
 
 impl VstpUdpClient {
@@ -60,6 +78,9 @@ impl VstpUdpClient {
             config: UdpConfig::default(),
             reassembly: ReassemblyManager::new(),
             next_msg_id: 1,
+            next_frag_id: AtomicU8::new(0),
+            connection: None,
+            cipher: None,
         })
     }
 
@@ -73,11 +94,94 @@ impl VstpUdpClient {
             config,
             reassembly: ReassemblyManager::new(),
             next_msg_id: 1,
+            next_frag_id: AtomicU8::new(0),
+            connection: None,
+            cipher: None,
         })
     }
 
+    /// Open a session with a server using the HELLO/WELCOME handshake
+    ///
+    /// Once connected, every frame sent to `dest` carries the connection ID
+    /// issued by the server. With `UdpConfig::encrypt` set, the handshake also
+    /// derives session keys and every later datagram to `dest` is encrypted
+    /// until `connect` is called again; the client never falls back to
+    /// cleartext on its own.
+    pub async fn connect(&mut self, dest: SocketAddr) -> Result<ConnectionId, VstpError> {
+        self.connection = None;
+        self.cipher = None;
+
+        for attempt in 0..=self.config.max_retries {
+            let mut hello = Frame::new(FrameType::Hello);
+            let handshake = if self.config.encrypt {
+                let (state, message) = crypto::initiate()?;
+                hello = hello
+                    .with_header(HANDSHAKE_HEADER, NOISE_PATTERN)
+                    .with_payload(message);
+                Some(state)
+            } else {
+                None
+            };
+            self.send(hello, dest).await?;
+
+            let result = match self.wait_for_welcome(dest).await {
+                Ok(welcome) => self.complete_handshake(welcome, handshake),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(conn_id) => {
+                    info!("Connected to {} (connection {:016x})", dest, conn_id);
+                    self.connection = Some((dest, conn_id));
+                    return Ok(conn_id);
+                }
+                Err(_) if attempt < self.config.max_retries => {
+                    tokio::time::sleep(self.calculate_retry_delay(attempt)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(VstpError::Timeout)
+    }
+
+    /// Take the connection ID from a WELCOME and finish the key exchange
+    fn complete_handshake(
+        &mut self,
+        welcome: Frame,
+        handshake: Option<snow::HandshakeState>,
+    ) -> Result<ConnectionId, VstpError> {
+        let conn_id = extract_connection_id(&welcome)
+            .ok_or_else(|| VstpError::Protocol("WELCOME without connection ID".to_string()))?;
+
+        if let Some(handshake) = handshake {
+            if welcome.payload.is_empty() {
+                return Err(VstpError::Protocol(
+                    "Server did not accept encryption".to_string(),
+                ));
+            }
+            self.cipher = Some(Box::new(crypto::finish(handshake, &welcome.payload)?));
+        }
+        Ok(conn_id)
+    }
+
+    /// Get the connection ID issued by the server, if connected
+    pub fn connection_id(&self) -> Option<ConnectionId> {
+        self.connection.map(|(_, conn_id)| conn_id)
+    }
+
     /// Send a frame to the specified destination
-    pub async fn send(&self, frame: Frame, dest: SocketAddr) -> Result<(), VstpError> {
+    pub async fn send(&self, mut frame: Frame, dest: SocketAddr) -> Result<(), VstpError> {
+        if let Some((server, conn_id)) = self.connection {
+            if server == dest {
+                if let Some(cipher) = &self.cipher {
+                    return self.send_sealed(frame, dest, conn_id, cipher).await;
+                }
+                if extract_connection_id(&frame).is_none() {
+                    frame = frame.with_header(CONN_ID_HEADER, &format_connection_id(conn_id));
+                }
+            }
+        }
+
         let encoded = encode_frame(&frame)?;
 
         // Check if we need fragmentation
@@ -155,51 +259,150 @@ impl VstpUdpClient {
 
             // Try to decode as a complete frame first
             let mut buf = bytes::BytesMut::from(data);
-            match try_decode_frame(&mut buf, 65536) {
-                Ok(Some(frame)) => {
-                    // Check if this is a fragmented frame
-                    if let Some(fragment) = extract_fragment_info(&frame) {
-                        // Handle fragmentation
-                        if let Some(assembled_data) =
-                            self.reassembly.add_fragment(from_addr, fragment).await?
-                        {
-                            // Reassemble the complete frame
-                            let mut complete_frame = frame;
-                            complete_frame.payload = assembled_data;
-                            // Remove fragment headers
-                            complete_frame.headers.retain(|h| {
-                                h.key != b"frag-id"
-                                    && h.key != b"frag-index"
-                                    && h.key != b"frag-total"
-                            });
-                            return Ok((complete_frame, from_addr));
-                        } else {
-                            // Fragment received, continue waiting for more
-                            continue;
-                        }
-                    } else {
-                        // Complete frame received
-                        return Ok((frame, from_addr));
+            let frame = match try_decode_frame(&mut buf, 65536) {
+                Ok(Some(frame)) => frame,
+                // Incomplete or invalid frame, continue waiting
+                Ok(None) | Err(_) => continue,
+            };
+            let Some(frame) = self.decrypt(frame, from_addr) else {
+                continue;
+            };
+
+            // Check if this is a fragmented frame
+            if let Some(fragment) = extract_fragment_info(&frame) {
+                // Handle fragmentation
+                match self.reassembly.add_fragment(from_addr, fragment).await {
+                    Ok(Some(assembled_data)) => {
+                        // Reassemble the complete frame
+                        let mut complete_frame = frame;
+                        complete_frame.payload = assembled_data;
+                        strip_fragment_headers(&mut complete_frame);
+                        return Ok((complete_frame, from_addr));
+                    }
+                    // Fragment received, continue waiting for more
+                    Ok(None) => continue,
+                    // Counted in the reassembly metrics; a bad fragment from
+                    // the peer is not a receive error
+                    Err(e) => {
+                        debug!("Dropping fragment from {}: {}", from_addr, e);
+                        continue;
                     }
                 }
-                Ok(None) => {
-                    // Incomplete frame, continue waiting
-                    continue;
+            }
+
+            // Prove we own the session after our address changed
+            if let Some(response) = path_response_frame(&frame) {
+                if let Some((server, conn_id)) = self.connection.filter(|(s, _)| *s == from_addr) {
+                    debug!("Answering path challenge from {}", server);
+                    // Encrypted sessions only migrate once the response
+                    // proves we hold the session keys
+                    let response = match &self.cipher {
+                        Some(cipher) => cipher.seal(&response, Some(conn_id))?,
+                        None => response,
+                    };
+                    self.socket.send_to(&encode_frame(&response)?, server).await?;
                 }
-                Err(_) => {
-                    // Invalid frame, continue waiting
-                    continue;
+                continue;
+            }
+
+            // The server no longer knows our session. Encrypted sessions
+            // never get here with a cleartext ERR, and a sealed one leaves
+            // the keys in place: the caller decides whether to reconnect.
+            if frame.typ == FrameType::Err
+                && self.cipher.is_none()
+                && self.connection == extract_connection_id(&frame).map(|id| (from_addr, id))
+            {
+                debug!("Connection to {} was reset by the server", from_addr);
+                self.connection = None;
+                self.cipher = None;
+            }
+
+            // Complete frame received
+            return Ok((frame, from_addr));
+        }
+    }
+
+    /// Open an encrypted datagram from the server, or drop frames that an
+    /// encrypted session should never see in the clear
+    ///
+    /// Anyone who has seen the connection ID can forge a cleartext WELCOME,
+    /// ERR or PING, so once keys are set nothing unauthenticated from the
+    /// server is acted on. If the server really lost the session, sends time
+    /// out and the caller has to `connect` again.
+    fn decrypt(&self, frame: Frame, from_addr: SocketAddr) -> Option<Frame> {
+        let from_server = self.connection.map(|(server, _)| server) == Some(from_addr);
+        let cipher = self.cipher.as_ref().filter(|_| from_server);
+
+        match cipher {
+            Some(cipher) if is_encrypted(&frame) => match cipher.open(&frame) {
+                Ok(inner) => Some(inner),
+                Err(e) => {
+                    debug!("Dropping datagram from {}: {}", from_addr, e);
+                    None
                 }
+            },
+            Some(_) => {
+                debug!("Dropping cleartext frame from {}", from_addr);
+                None
+            }
+            None if is_encrypted(&frame) => {
+                debug!("Dropping encrypted datagram without session keys from {}", from_addr);
+                None
             }
+            None => Some(frame),
+        }
+    }
+
+    /// Send a frame over an encrypted session, fragmenting it first if needed
+    async fn send_sealed(
+        &self,
+        frame: Frame,
+        dest: SocketAddr,
+        conn_id: ConnectionId,
+        cipher: &DatagramCipher,
+    ) -> Result<(), VstpError> {
+        let datagrams = if encode_frame(&frame)?.len() > MAX_DATAGRAM_SIZE && self.config.allow_frag
+        {
+            self.split(frame)?
+        } else {
+            vec![frame]
+        };
+
+        for datagram in &datagrams {
+            let sealed = encode_frame(&cipher.seal(datagram, Some(conn_id))?)?;
+            self.socket.send_to(&sealed, dest).await?;
+        }
+        debug!("Sent {} encrypted datagram(s) to {}", datagrams.len(), dest);
+        Ok(())
+    }
+
+    /// Split a frame into fragment frames, or return it unchanged if its
+    /// payload fits in one datagram
+    fn split(&self, frame: Frame) -> Result<Vec<Frame>, VstpError> {
+        let frag_id = self.next_frag_id.fetch_add(1, Ordering::Relaxed);
+        let fragments =
+            fragment_payload_with_fec(&frame.payload, frag_id, self.config.fec_redundancy)?;
+
+        if fragments.is_empty() {
+            // Only the headers pushed the frame over the datagram size
+            return Ok(vec![frame]);
         }
+
+        Ok(fragments
+            .iter()
+            .map(|fragment| {
+                let mut frag_frame = frame.clone();
+                frag_frame.payload = fragment.data.clone();
+                add_fragment_headers(&mut frag_frame, fragment);
+                frag_frame.flags.insert(Flags::FRAG);
+                frag_frame
+            })
+            .collect())
     }
 
     /// Send a fragmented frame
     async fn send_fragmented(&self, frame: Frame, dest: SocketAddr) -> Result<(), VstpError> {
-        let encoded = encode_frame(&frame)?;
-        let frag_id = (self.next_msg_id % 256) as u8;
-
-        let fragments = fragment_payload(&encoded, frag_id)?;
+        let fragments = self.split(frame)?;
 
         info!(
             "Sending fragmented frame to {} ({} fragments)",
@@ -207,20 +410,12 @@ impl VstpUdpClient {
             fragments.len()
         );
 
-        for fragment in fragments {
-            let mut frag_frame = frame.clone();
-            add_fragment_headers(&mut frag_frame, &fragment);
-            frag_frame.flags.insert(Flags::FRAG);
-
-            let frag_encoded = encode_frame(&frag_frame)?;
+        let total = fragments.len();
+        for (index, frag_frame) in fragments.iter().enumerate() {
+            let frag_encoded = encode_frame(frag_frame)?;
             self.socket.send_to(&frag_encoded, dest).await?;
 
-            debug!(
-                "Sent fragment {}/{} to {}",
-                fragment.frag_index + 1,
-                fragment.frag_total,
-                dest
-            );
+            debug!("Sent fragment {}/{} to {}", index + 1, total, dest);
         }
 
         Ok(())
@@ -260,15 +455,49 @@ impl VstpUdpClient {
         Err(VstpError::Timeout)
     }
 
+    /// Wait for a WELCOME from `from_addr`
+    async fn wait_for_welcome(&mut self, from_addr: SocketAddr) -> Result<Frame, VstpError> {
+        let start_time = Instant::now();
+
+        while start_time.elapsed() < self.config.ack_timeout {
+            match timeout(Duration::from_millis(100), self.recv()).await {
+                Ok(Ok((frame, addr))) if addr == from_addr && frame.typ == FrameType::Welcome => {
+                    return Ok(frame);
+                }
+                Ok(Ok((frame, addr))) if addr == from_addr && frame.typ == FrameType::Err => {
+                    return Err(VstpError::Protocol(format!(
+                        "Connection refused: {}",
+                        String::from_utf8_lossy(&frame.payload)
+                    )));
+                }
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => continue,
+            }
+        }
+
+        Err(VstpError::Timeout)
+    }
+
     /// Calculate retry delay with exponential backoff
     fn calculate_retry_delay(&self, attempt: usize) -> Duration {
         let delay = self.config.retry_delay.as_millis() as u64 * (2_u64.pow(attempt as u32));
         Duration::from_millis(delay.min(self.config.max_retry_delay.as_millis() as u64))
     }
 
+    /// Move the client to a new local address, keeping its session
+    ///
+    /// The server validates the new path the next time it hears from us and
+    /// then migrates the session, including any partially reassembled frames.
+    pub async fn rebind(&mut self, local_addr: &str) -> Result<(), VstpError> {
+        self.socket = UdpSocket::bind(local_addr).await?;
+        info!("VSTP UDP client rebound to {}", self.socket.local_addr()?);
+        Ok(())
+    }
+
     /// Get the local address this client is bound to
     pub fn local_addr(&self) -> Result<SocketAddr, VstpError> {
-        self.socket.local_addr().map_err(|e| VstpError::Io(e))
+        self.socket.local_addr().map_err(VstpError::Io)
     }
 
     /// Get the number of active reassembly sessions
@@ -0,0 +1,248 @@
+//! Authenticated encryption for UDP sessions
+//!
+//! An encrypting client puts the first message of a Noise NN handshake in the
+//! payload of its HELLO, marked with an `enc-handshake` header, and the server
+//! answers with the second message in the WELCOME payload. Both sides then
+//! hold a pair of ChaCha20-Poly1305 keys. NN does not authenticate either
+//! side, so it protects against passive observers rather than an attacker on
+//! the path.
+//!
+//! Every datagram after the handshake, including fragments and ACKs, is sent
+//! as an outer DATA frame with the [`Flags::ENC`] flag. The outer frame only
+//! carries the `conn-id` header used for routing and an `enc-seq` header with
+//! the sender's sequence number, which doubles as the AEAD nonce. Its payload
+//! is the sealed encoding of the inner frame. Receivers reject sequence
+//! numbers they have already accepted or that fall behind the replay window.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use snow::{HandshakeState, StatelessTransportState};
+
+use crate::core::frame::{encode_frame, try_decode_frame};
+use crate::core::types::{Flags, Frame, FrameType, VstpError};
+use crate::security::replay::{ReplayRejection, ReplayWindow};
+use crate::transport::udp::session::{format_connection_id, ConnectionId, CONN_ID_HEADER};
+
+/// Noise protocol used for the UDP handshake
+pub const NOISE_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+/// HELLO/WELCOME header naming the Noise protocol of the handshake payload
+pub const HANDSHAKE_HEADER: &str = "enc-handshake";
+
+/// Header carrying the sender's datagram sequence number (the AEAD nonce)
+pub const ENC_SEQ_HEADER: &str = "enc-seq";
+
+/// Number of sequence numbers tracked behind the highest one received
+pub const REPLAY_WINDOW: u64 = 1024;
+
+/// Size of the Poly1305 authentication tag appended to every datagram
+const TAG_LEN: usize = 16;
+
+/// Largest Noise message
+const MAX_NOISE_MESSAGE: usize = 65535;
+
+fn noise_error(e: snow::Error) -> VstpError {
+    VstpError::Protocol(format!("Noise handshake failed: {}", e))
+}
+
+/// Start a handshake as the client, returning the state and the HELLO payload
+pub fn initiate() -> Result<(HandshakeState, Vec<u8>), VstpError> {
+    let params = NOISE_PATTERN.parse().map_err(noise_error)?;
+    let mut handshake = snow::Builder::new(params)
+        .build_initiator()
+        .map_err(noise_error)?;
+
+    let mut message = vec![0u8; MAX_NOISE_MESSAGE];
+    let len = handshake.write_message(&[], &mut message).map_err(noise_error)?;
+    message.truncate(len);
+    Ok((handshake, message))
+}
+
+/// Answer a client's HELLO payload, returning the session cipher and the
+/// WELCOME payload
+pub fn respond(hello: &[u8]) -> Result<(DatagramCipher, Vec<u8>), VstpError> {
+    let params = NOISE_PATTERN.parse().map_err(noise_error)?;
+    let mut handshake = snow::Builder::new(params)
+        .build_responder()
+        .map_err(noise_error)?;
+
+    let mut scratch = vec![0u8; MAX_NOISE_MESSAGE];
+    handshake.read_message(hello, &mut scratch).map_err(noise_error)?;
+    let mut message = vec![0u8; MAX_NOISE_MESSAGE];
+    let len = handshake.write_message(&[], &mut message).map_err(noise_error)?;
+    message.truncate(len);
+
+    Ok((DatagramCipher::from_handshake(handshake)?, message))
+}
+
+/// Complete the client side of the handshake with the WELCOME payload
+pub fn finish(mut handshake: HandshakeState, welcome: &[u8]) -> Result<DatagramCipher, VstpError> {
+    let mut scratch = vec![0u8; MAX_NOISE_MESSAGE];
+    handshake.read_message(welcome, &mut scratch).map_err(noise_error)?;
+    DatagramCipher::from_handshake(handshake)
+}
+
+/// Whether a frame is an encrypted datagram
+pub fn is_encrypted(frame: &Frame) -> bool {
+    frame.flags.contains(Flags::ENC)
+}
+
+/// Per-session AEAD state derived from the handshake
+pub struct DatagramCipher {
+    transport: StatelessTransportState,
+    next_seq: AtomicU64,
+    replay: Mutex<ReplayWindow>,
+}
+
+impl std::fmt::Debug for DatagramCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatagramCipher")
+            .field("next_seq", &self.next_seq)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DatagramCipher {
+    fn from_handshake(handshake: HandshakeState) -> Result<Self, VstpError> {
+        Ok(Self {
+            transport: handshake
+                .into_stateless_transport_mode()
+                .map_err(noise_error)?,
+            next_seq: AtomicU64::new(0),
+            replay: Mutex::new(ReplayWindow::new(REPLAY_WINDOW)),
+        })
+    }
+
+    /// Encrypt a frame into an outer datagram frame
+    pub fn seal(&self, frame: &Frame, conn_id: Option<ConnectionId>) -> Result<Frame, VstpError> {
+        let plaintext = encode_frame(frame)?;
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+
+        let mut ciphertext = vec![0u8; plaintext.len() + TAG_LEN];
+        let len = self
+            .transport
+            .write_message(seq, &plaintext, &mut ciphertext)
+            .map_err(|e| VstpError::Protocol(format!("Encryption failed: {}", e)))?;
+        ciphertext.truncate(len);
+
+        let mut sealed = Frame::new(FrameType::Data);
+        sealed.flags.insert(Flags::ENC);
+        if let Some(conn_id) = conn_id {
+            sealed = sealed.with_header(CONN_ID_HEADER, &format_connection_id(conn_id));
+        }
+        Ok(sealed
+            .with_header(ENC_SEQ_HEADER, &format!("{:x}", seq))
+            .with_payload(ciphertext))
+    }
+
+    /// Decrypt an outer datagram frame back into the frame it carries
+    ///
+    /// Replayed or stale datagrams fail with [`VstpError::ReplayedFrame`].
+    pub fn open(&self, sealed: &Frame) -> Result<Frame, VstpError> {
+        self.open_checked(sealed)?
+            .map_err(|rejection| VstpError::ReplayedFrame(rejection.to_string()))
+    }
+
+    /// Decrypt an outer datagram frame, passing replay rejections back to the
+    /// caller
+    ///
+    /// A datagram only counts as replayed if it authenticates, so a forged
+    /// `enc-seq` header cannot make a session look like it is under attack.
+    pub fn open_checked(&self, sealed: &Frame) -> Result<Result<Frame, ReplayRejection>, VstpError> {
+        let (seq, frame) = self.unseal(sealed)?;
+
+        // Only authenticated datagrams move the replay window
+        let mut replay = self.replay.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(rejection) = replay.rejection(seq) {
+            return Ok(Err(rejection));
+        }
+        replay.accept(seq);
+        Ok(Ok(frame))
+    }
+
+    /// Decrypt an outer datagram frame without recording its sequence number
+    ///
+    /// For datagrams that are not yet trusted to be delivered, such as those
+    /// from an address the session has not validated; otherwise a copy sent
+    /// ahead from elsewhere would get the real one rejected as a replay.
+    pub fn authenticate(&self, sealed: &Frame) -> Result<Frame, VstpError> {
+        self.unseal(sealed).map(|(_, frame)| frame)
+    }
+
+    fn unseal(&self, sealed: &Frame) -> Result<(u64, Frame), VstpError> {
+        let seq = sealed
+            .get_header(ENC_SEQ_HEADER)
+            .and_then(|v| u64::from_str_radix(v, 16).ok())
+            .ok_or_else(|| VstpError::Protocol("Missing encryption sequence".to_string()))?;
+
+        let mut plaintext = vec![0u8; sealed.payload.len()];
+        let len = self
+            .transport
+            .read_message(seq, &sealed.payload, &mut plaintext)
+            .map_err(|_| VstpError::Protocol("Datagram authentication failed".to_string()))?;
+        plaintext.truncate(len);
+
+        let mut buf = bytes::BytesMut::from(&plaintext[..]);
+        let frame = try_decode_frame(&mut buf, MAX_NOISE_MESSAGE)?
+            .ok_or_else(|| VstpError::Protocol("Truncated encrypted frame".to_string()))?;
+        Ok((seq, frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake() -> (DatagramCipher, DatagramCipher) {
+        let (client, hello) = initiate().unwrap();
+        let (server, welcome) = respond(&hello).unwrap();
+        (finish(client, &welcome).unwrap(), server)
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let (client, server) = handshake();
+        let frame = Frame::new(FrameType::Data)
+            .with_header("msg-id", "7")
+            .with_payload(b"telemetry".to_vec());
+
+        let sealed = client.seal(&frame, Some(42)).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.payload.windows(9).any(|w| w == b"telemetry"));
+
+        let opened = server.open(&sealed).unwrap();
+        assert_eq!(opened.payload, frame.payload);
+        assert_eq!(opened.get_header("msg-id"), Some("7"));
+
+        // Replies use the other direction's key
+        let reply = server.seal(&Frame::new(FrameType::Ack), None).unwrap();
+        assert_eq!(client.open(&reply).unwrap().typ, FrameType::Ack);
+    }
+
+    #[test]
+    fn test_rejects_replay_and_tampering() {
+        let (client, server) = handshake();
+        let sealed = client.seal(&Frame::new(FrameType::Data), None).unwrap();
+        server.open(&sealed).unwrap();
+        assert_eq!(
+            server.open_checked(&sealed).unwrap(),
+            Err(ReplayRejection::Duplicate(0))
+        );
+        assert!(matches!(server.open(&sealed), Err(VstpError::ReplayedFrame(_))));
+
+        // Authenticating alone does not use up a sequence number
+        let fresh = client.seal(&Frame::new(FrameType::Data), None).unwrap();
+        server.authenticate(&fresh).unwrap();
+        server.authenticate(&fresh).unwrap();
+        server.open(&fresh).unwrap();
+
+        let mut tampered = client.seal(&Frame::new(FrameType::Data), None).unwrap();
+        tampered.payload[0] ^= 1;
+        assert!(server.open(&tampered).is_err());
+
+        // Another session's key does not open it either
+        let (other_client, _) = handshake();
+        let foreign = other_client.seal(&Frame::new(FrameType::Data), None).unwrap();
+        assert!(server.open(&foreign).is_err());
+    }
+}
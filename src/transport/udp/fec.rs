@@ -0,0 +1,106 @@
+//! Reed-Solomon forward error correction for UDP fragment groups
+//!
+//! A fragment group of `k` data fragments is extended with `m` parity
+//! fragments. The receiver can rebuild the original payload from any `k` of
+//! the `k + m` fragments, so up to `m` losses are repaired without a
+//! retransmission.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::core::types::VstpError;
+
+/// FEC parameters carried by every fragment of a protected group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecInfo {
+    /// Number of data fragments in the group; the rest are parity
+    pub data_fragments: u8,
+    /// Length of the original payload before padding
+    pub payload_len: u32,
+}
+
+/// Number of parity fragments for `data_fragments` at the given redundancy
+/// ratio, capped so the whole group fits in `max_total` fragments
+pub fn parity_count(data_fragments: usize, redundancy: f64, max_total: usize) -> usize {
+    if redundancy <= 0.0 || data_fragments == 0 {
+        return 0;
+    }
+    let wanted = (data_fragments as f64 * redundancy).ceil() as usize;
+    wanted.max(1).min(max_total.saturating_sub(data_fragments))
+}
+
+fn codec(data: usize, parity: usize) -> Result<ReedSolomon, VstpError> {
+    ReedSolomon::new(data, parity)
+        .map_err(|e| VstpError::Protocol(format!("Invalid FEC parameters: {:?}", e)))
+}
+
+/// Split `payload` into `data` equally sized shards and append `parity`
+/// parity shards
+pub fn encode(payload: &[u8], data: usize, parity: usize) -> Result<Vec<Vec<u8>>, VstpError> {
+    let shard_size = payload.len().div_ceil(data).max(1);
+    let mut shards: Vec<Vec<u8>> = (0..data)
+        .map(|i| {
+            let start = (i * shard_size).min(payload.len());
+            let end = ((i + 1) * shard_size).min(payload.len());
+            let mut shard = payload[start..end].to_vec();
+            shard.resize(shard_size, 0);
+            shard
+        })
+        .collect();
+    shards.extend((0..parity).map(|_| vec![0u8; shard_size]));
+
+    codec(data, parity)?
+        .encode(&mut shards)
+        .map_err(|e| VstpError::Protocol(format!("FEC encode failed: {:?}", e)))?;
+    Ok(shards)
+}
+
+/// Rebuild the original payload from a group with at least `info.data_fragments`
+/// shards present
+pub fn decode(shards: &[Option<Vec<u8>>], info: FecInfo) -> Result<Vec<u8>, VstpError> {
+    let data = info.data_fragments as usize;
+    let mut shards = shards.to_vec();
+
+    if shards[..data].iter().any(|s| s.is_none()) {
+        codec(data, shards.len() - data)?
+            .reconstruct_data(&mut shards)
+            .map_err(|e| VstpError::Protocol(format!("FEC reconstruction failed: {:?}", e)))?;
+    }
+
+    let mut payload: Vec<u8> = shards[..data].iter().flatten().flatten().copied().collect();
+    if payload.len() < info.payload_len as usize {
+        return Err(VstpError::Protocol("FEC payload length mismatch".to_string()));
+    }
+    payload.truncate(info.payload_len as usize);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovers_from_any_k_of_n() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let shards = encode(&payload, 4, 2).unwrap();
+        let info = FecInfo {
+            data_fragments: 4,
+            payload_len: payload.len() as u32,
+        };
+
+        let mut received: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        received[0] = None;
+        received[3] = None;
+        assert_eq!(decode(&received, info).unwrap(), payload);
+
+        received[1] = None;
+        assert!(decode(&received, info).is_err());
+    }
+
+    #[test]
+    fn test_parity_count() {
+        assert_eq!(parity_count(10, 0.0, 255), 0);
+        assert_eq!(parity_count(10, 0.25, 255), 3);
+        assert_eq!(parity_count(1, 0.01, 255), 1);
+        assert_eq!(parity_count(250, 0.5, 255), 5);
+    }
+}
@@ -1,11 +1,18 @@
 //! UDP transport implementation for VSTP
 //!
 //! This module provides async UDP client and server implementations with
-//! fragmentation, CRC validation, and optional ACK reliability.
+//! fragmentation, CRC validation, optional ACK reliability, and multicast
+//! group messaging.
 
 pub mod client;
 pub mod server;
 pub mod reassembly;
+pub mod fec;
+pub mod crypto;
+pub mod multicast;
+pub mod session;
 
 pub use client::VstpUdpClient;
 pub use server::VstpUdpServer;
+pub use multicast::{MulticastConfig, VstpMulticastPublisher, VstpMulticastReceiver};
+pub use session::{ConnectionId, VstpUdpConnection};
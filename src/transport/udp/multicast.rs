@@ -0,0 +1,695 @@
+//! Multicast and broadcast group messaging over UDP
+//!
+//! A [`VstpMulticastPublisher`] sends VSTP frames to a multicast group (or a
+//! broadcast address). Every published frame carries an `mc-seq` header with
+//! a per-publisher sequence number; large frames are fragmented exactly like
+//! unicast frames, optionally with FEC parity.
+//!
+//! A [`VstpMulticastReceiver`] joins one or more groups and reassembles
+//! fragmented frames separately for every source. When it notices a gap in a
+//! source's sequence numbers it can send a NACK (an ACK frame with an
+//! `mc-nack` header listing the missing sequence numbers) back to the
+//! publisher, which resends those frames from its repair history.
+//!
+//! NACKs are unauthenticated and their source address can be forged, so
+//! repair is off by default. When enabled, only receivers listed in
+//! [`MulticastConfig::repair_receivers`] get repairs by unicast; a NACK from
+//! anywhere else is answered by resending the frame to the group it was
+//! published to. Every NACK is capped at
+//! [`MulticastConfig::max_repairs_per_nack`] frames and repairs to each
+//! destination are rate limited.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::core::frame::{encode_frame, try_decode_frame};
+use crate::core::types::{Flags, Frame, FrameType, VstpError};
+use crate::security::blocklist::IpNet;
+use crate::transport::udp::reassembly::{
+    add_fragment_headers, extract_fragment_info, fragment_payload_with_fec,
+    strip_fragment_headers, ReassemblyConfig, ReassemblyManager,
+};
+use crate::transport::udp::session::ReliabilityWindow;
+
+/// Header carrying the publisher's sequence number of a multicast frame
+pub const MULTICAST_SEQ_HEADER: &str = "mc-seq";
+
+/// Header listing the sequence numbers a receiver is missing
+pub const MULTICAST_NACK_HEADER: &str = "mc-nack";
+
+/// Largest datagram a multicast socket will accept
+const MAX_RECV_SIZE: usize = 65536;
+
+/// Configuration shared by multicast publishers and receivers
+#[derive(Debug, Clone)]
+pub struct MulticastConfig {
+    /// Time-to-live (IPv4) or hop limit (IPv6) of published datagrams
+    pub ttl: u32,
+    /// Whether published datagrams are looped back to receivers on this host
+    pub loopback: bool,
+    /// Whether publishing to broadcast addresses is allowed
+    pub broadcast: bool,
+    /// Local interface used for IPv4 group membership and publishing
+    pub interface_v4: Ipv4Addr,
+    /// Interface index used for IPv6 group membership (0 = system default)
+    pub interface_v6: u32,
+    /// Parity fragments per data fragment of a fragmented frame (0.0 disables FEC)
+    pub fec_redundancy: f64,
+    /// Whether the publisher answers NACKs from its repair history
+    pub repair: bool,
+    /// Number of recently published frames kept for NACK repair
+    pub repair_history: usize,
+    /// Receivers that get repairs sent straight to them; NACKs from other
+    /// addresses are answered by resending to the frame's group
+    pub repair_receivers: Vec<IpNet>,
+    /// Maximum number of frames resent for one NACK
+    pub max_repairs_per_nack: usize,
+    /// Maximum number of frames resent to one destination per second
+    pub repair_rate_limit: u32,
+    /// Maximum number of destinations the repair rate limit tracks
+    pub max_repair_destinations: usize,
+    /// Whether receivers ask the publisher to resend missing frames
+    pub nack: bool,
+    /// Delay before a missing frame is requested again
+    pub nack_interval: Duration,
+    /// Maximum number of requests sent for one missing frame
+    pub max_nack_retries: u32,
+    /// Maximum number of missing frames tracked per source
+    pub max_pending_nacks: usize,
+    /// Number of delivered sequence numbers remembered per source to drop duplicates
+    pub dedup_window: usize,
+    /// Maximum number of sources a receiver tracks; the least recently
+    /// heard one is forgotten to make room
+    pub max_sources: usize,
+    /// Reassembly limits for fragmented frames
+    pub reassembly: ReassemblyConfig,
+}
+
+impl Default for MulticastConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 1,
+            loopback: true,
+            broadcast: false,
+            interface_v4: Ipv4Addr::UNSPECIFIED,
+            interface_v6: 0,
+            fec_redundancy: 0.0,
+            repair: false,
+            repair_history: 1024,
+            repair_receivers: Vec::new(),
+            max_repairs_per_nack: 16,
+            repair_rate_limit: 64,
+            max_repair_destinations: 1024,
+            nack: true,
+            nack_interval: Duration::from_millis(200),
+            max_nack_retries: 3,
+            max_pending_nacks: 256,
+            dedup_window: 1024,
+            max_sources: 256,
+            reassembly: ReassemblyConfig::default(),
+        }
+    }
+}
+
+/// A published frame kept for repair
+#[derive(Debug)]
+struct PublishedFrame {
+    seq: u64,
+    /// Group (or address) the frame was published to
+    group: SocketAddr,
+    datagrams: Vec<Vec<u8>>,
+}
+
+/// Encoded datagrams of recently published frames, by sequence number
+#[derive(Debug, Default)]
+struct RepairHistory {
+    frames: VecDeque<PublishedFrame>,
+}
+
+impl RepairHistory {
+    fn push(&mut self, frame: PublishedFrame, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        while self.frames.len() >= capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    fn get(&self, seq: u64) -> Option<&PublishedFrame> {
+        // Sequence numbers are pushed in increasing order
+        let index = self.frames.binary_search_by_key(&seq, |f| f.seq).ok()?;
+        Some(&self.frames[index])
+    }
+}
+
+/// Per-destination token buckets limiting how fast repairs are resent
+#[derive(Debug)]
+struct RepairLimiter {
+    buckets: HashMap<IpAddr, (f64, Instant)>,
+    rate: f64,
+    capacity: usize,
+}
+
+impl RepairLimiter {
+    fn new(rate: u32, capacity: usize) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            rate: rate as f64,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Take one repair from `dest`'s budget, returning `false` if it is spent
+    fn allow(&mut self, dest: IpAddr) -> bool {
+        let now = Instant::now();
+        if !self.buckets.contains_key(&dest) && self.buckets.len() >= self.capacity {
+            // Buckets that have refilled carry no state worth keeping
+            let rate = self.rate;
+            self.buckets
+                .retain(|_, (tokens, at)| *tokens + at.elapsed().as_secs_f64() * rate < rate);
+            if self.buckets.len() >= self.capacity {
+                return false;
+            }
+        }
+
+        let (tokens, at) = self.buckets.entry(dest).or_insert((self.rate, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.rate).min(self.rate);
+        *at = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// Publishes VSTP frames to multicast groups or broadcast addresses
+pub struct VstpMulticastPublisher {
+    socket: Arc<UdpSocket>,
+    config: MulticastConfig,
+    next_seq: AtomicU64,
+    next_frag_id: AtomicU8,
+    history: Arc<Mutex<RepairHistory>>,
+    repairs_sent: Arc<AtomicU64>,
+    repair_task: Option<JoinHandle<()>>,
+}
+
+impl VstpMulticastPublisher {
+    /// Create a publisher bound to the specified local address
+    pub async fn bind(local_addr: &str) -> Result<Self, VstpError> {
+        Self::bind_with_config(local_addr, MulticastConfig::default()).await
+    }
+
+    /// Create a publisher with custom configuration
+    pub async fn bind_with_config(
+        local_addr: &str,
+        config: MulticastConfig,
+    ) -> Result<Self, VstpError> {
+        let socket = UdpSocket::bind(local_addr).await?;
+        if socket.local_addr()?.is_ipv4() {
+            socket.set_multicast_ttl_v4(config.ttl)?;
+            socket.set_multicast_loop_v4(config.loopback)?;
+            if !config.interface_v4.is_unspecified() {
+                SockRef::from(&socket).set_multicast_if_v4(&config.interface_v4)?;
+            }
+        } else {
+            SockRef::from(&socket).set_multicast_hops_v6(config.ttl)?;
+            socket.set_multicast_loop_v6(config.loopback)?;
+            if config.interface_v6 != 0 {
+                SockRef::from(&socket).set_multicast_if_v6(config.interface_v6)?;
+            }
+        }
+        socket.set_broadcast(config.broadcast)?;
+        info!("VSTP multicast publisher bound to {}", socket.local_addr()?);
+
+        let socket = Arc::new(socket);
+        let history = Arc::new(Mutex::new(RepairHistory::default()));
+        let repairs_sent = Arc::new(AtomicU64::new(0));
+        let repair_task = (config.repair && config.repair_history > 0).then(|| {
+            tokio::spawn(serve_repairs(
+                socket.clone(),
+                config.clone(),
+                history.clone(),
+                repairs_sent.clone(),
+            ))
+        });
+
+        Ok(Self {
+            socket,
+            config,
+            next_seq: AtomicU64::new(1),
+            next_frag_id: AtomicU8::new(0),
+            history,
+            repairs_sent,
+            repair_task,
+        })
+    }
+
+    /// Publish a frame to a group (or broadcast address), returning its
+    /// sequence number
+    pub async fn publish(&self, frame: Frame, group: SocketAddr) -> Result<u64, VstpError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let frame = frame.with_header(MULTICAST_SEQ_HEADER, &seq.to_string());
+        let datagrams = self.encode_datagrams(frame)?;
+
+        for datagram in &datagrams {
+            self.socket.send_to(datagram, group).await?;
+        }
+        debug!(
+            "Published frame {} to {} ({} datagrams)",
+            seq,
+            group,
+            datagrams.len()
+        );
+
+        if self.repair_task.is_some() {
+            let published = PublishedFrame {
+                seq,
+                group,
+                datagrams,
+            };
+            self.history
+                .lock()
+                .expect("repair history lock poisoned")
+                .push(published, self.config.repair_history);
+        }
+        Ok(seq)
+    }
+
+    fn encode_datagrams(&self, frame: Frame) -> Result<Vec<Vec<u8>>, VstpError> {
+        let frag_id = self.next_frag_id.fetch_add(1, Ordering::Relaxed);
+        let fragments =
+            fragment_payload_with_fec(&frame.payload, frag_id, self.config.fec_redundancy)?;
+        if fragments.is_empty() {
+            return Ok(vec![encode_frame(&frame)?.to_vec()]);
+        }
+
+        fragments
+            .iter()
+            .map(|fragment| {
+                let mut frag_frame = frame.clone();
+                frag_frame.payload = fragment.data.clone();
+                add_fragment_headers(&mut frag_frame, fragment);
+                frag_frame.flags.insert(Flags::FRAG);
+                Ok(encode_frame(&frag_frame)?.to_vec())
+            })
+            .collect()
+    }
+
+    /// Get the local address of the publisher; NACKs are sent here
+    pub fn local_addr(&self) -> Result<SocketAddr, VstpError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Number of frames resent in answer to NACKs
+    pub fn repairs_sent(&self) -> u64 {
+        self.repairs_sent.load(Ordering::Relaxed)
+    }
+
+    /// Get the publisher configuration
+    pub fn config(&self) -> &MulticastConfig {
+        &self.config
+    }
+}
+
+impl Drop for VstpMulticastPublisher {
+    fn drop(&mut self) {
+        if let Some(task) = self.repair_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Answer NACKs from receivers by resending frames from the repair history
+async fn serve_repairs(
+    socket: Arc<UdpSocket>,
+    config: MulticastConfig,
+    history: Arc<Mutex<RepairHistory>>,
+    repairs_sent: Arc<AtomicU64>,
+) {
+    let mut limiter = RepairLimiter::new(config.repair_rate_limit, config.max_repair_destinations);
+    let mut buf = vec![0u8; MAX_RECV_SIZE];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // ICMP errors from earlier sends surface here on some platforms
+                debug!("Multicast publisher receive error: {}", e);
+                continue;
+            }
+        };
+
+        let mut bytes = bytes::BytesMut::from(&buf[..len]);
+        let Ok(Some(frame)) = try_decode_frame(&mut bytes, MAX_RECV_SIZE) else {
+            continue;
+        };
+        if frame.typ != FrameType::Ack {
+            continue;
+        }
+        let Some(missing) = frame.get_header(MULTICAST_NACK_HEADER) else {
+            continue;
+        };
+
+        // The source of a NACK can be forged, so only known receivers get
+        // repairs sent to them directly
+        let unicast = config.repair_receivers.iter().any(|net| net.contains(from.ip()));
+        let requested = missing
+            .split(',')
+            .filter_map(|s| s.parse::<u64>().ok())
+            .take(config.max_repairs_per_nack);
+        for seq in requested {
+            let repair = history
+                .lock()
+                .expect("repair history lock poisoned")
+                .get(seq)
+                .map(|frame| (frame.group, frame.datagrams.clone()));
+            let Some((group, datagrams)) = repair else {
+                debug!("NACK from {} for frame {} outside repair history", from, seq);
+                continue;
+            };
+            let dest = if unicast { from } else { group };
+            if !limiter.allow(dest.ip()) {
+                debug!("Repair rate limit reached for {}, ignoring NACK from {}", dest, from);
+                break;
+            }
+            for datagram in &datagrams {
+                if let Err(e) = socket.send_to(datagram, dest).await {
+                    warn!("Failed to resend frame {} to {}: {}", seq, dest, e);
+                }
+            }
+            repairs_sent.fetch_add(1, Ordering::Relaxed);
+            debug!("Resent frame {} to {}", seq, dest);
+        }
+    }
+}
+
+/// A missing frame the receiver has asked for
+#[derive(Debug)]
+struct PendingNack {
+    /// When the frame was last requested, `None` if it has not been yet
+    last_sent: Option<Instant>,
+    attempts: u32,
+}
+
+/// Per-source receive state
+#[derive(Debug)]
+struct SourceState {
+    highest_seq: u64,
+    delivered: ReliabilityWindow,
+    pending: BTreeMap<u64, PendingNack>,
+    last_seen: Instant,
+}
+
+impl SourceState {
+    fn new(seq: u64, dedup_window: usize) -> Self {
+        Self {
+            highest_seq: seq,
+            delivered: ReliabilityWindow::new(dedup_window),
+            pending: BTreeMap::new(),
+            last_seen: Instant::now(),
+        }
+    }
+
+    /// Record a sequence number and return the ones that should be NACKed now
+    fn observe(&mut self, seq: u64, config: &MulticastConfig) -> Vec<u64> {
+        let now = Instant::now();
+        self.last_seen = now;
+        self.pending.remove(&seq);
+
+        if seq > self.highest_seq {
+            // Only the most recent gap is worth asking for
+            let first_missing =
+                (self.highest_seq + 1).max(seq.saturating_sub(config.max_pending_nacks as u64));
+            for missing in first_missing..seq {
+                self.pending.insert(
+                    missing,
+                    PendingNack {
+                        last_sent: None,
+                        attempts: 0,
+                    },
+                );
+            }
+            self.highest_seq = seq;
+        }
+
+        while self.pending.len() > config.max_pending_nacks {
+            self.pending.pop_first();
+        }
+        self.pending
+            .retain(|_, pending| pending.attempts < config.max_nack_retries);
+
+        let mut due = Vec::new();
+        for (missing, pending) in self.pending.iter_mut() {
+            let due_now = pending
+                .last_sent
+                .is_none_or(|sent| now.duration_since(sent) >= config.nack_interval);
+            if due_now {
+                pending.last_sent = Some(now);
+                pending.attempts += 1;
+                due.push(*missing);
+            }
+        }
+        due
+    }
+}
+
+/// Receives VSTP frames published to multicast groups
+pub struct VstpMulticastReceiver {
+    socket: UdpSocket,
+    config: MulticastConfig,
+    reassembly: ReassemblyManager<SocketAddr>,
+    sources: HashMap<SocketAddr, SourceState>,
+    groups: HashSet<IpAddr>,
+    nacks_sent: u64,
+}
+
+impl VstpMulticastReceiver {
+    /// Create a receiver bound to the specified address, usually the
+    /// unspecified address and the group port (e.g. `0.0.0.0:5000`)
+    ///
+    /// The socket allows address reuse so several receivers on one host can
+    /// listen to the same group.
+    pub async fn bind(local_addr: &str) -> Result<Self, VstpError> {
+        Self::bind_with_config(local_addr, MulticastConfig::default()).await
+    }
+
+    /// Create a receiver with custom configuration
+    pub async fn bind_with_config(
+        local_addr: &str,
+        config: MulticastConfig,
+    ) -> Result<Self, VstpError> {
+        let addr: SocketAddr = local_addr.parse().map_err(|_| VstpError::InvalidAddress)?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        info!("VSTP multicast receiver bound to {}", socket.local_addr()?);
+
+        Ok(Self {
+            socket,
+            reassembly: ReassemblyManager::with_config(config.reassembly.clone()),
+            config,
+            sources: HashMap::new(),
+            groups: HashSet::new(),
+            nacks_sent: 0,
+        })
+    }
+
+    /// Join a multicast group on the configured interface
+    pub fn join(&mut self, group: IpAddr) -> Result<(), VstpError> {
+        match group {
+            IpAddr::V4(group) => self
+                .socket
+                .join_multicast_v4(group, self.config.interface_v4)?,
+            IpAddr::V6(group) => self
+                .socket
+                .join_multicast_v6(&group, self.config.interface_v6)?,
+        }
+        self.groups.insert(group);
+        info!("Joined multicast group {}", group);
+        Ok(())
+    }
+
+    /// Leave a multicast group joined with [`join`](Self::join)
+    pub fn leave(&mut self, group: IpAddr) -> Result<(), VstpError> {
+        match group {
+            IpAddr::V4(group) => self
+                .socket
+                .leave_multicast_v4(group, self.config.interface_v4)?,
+            IpAddr::V6(group) => self
+                .socket
+                .leave_multicast_v6(&group, self.config.interface_v6)?,
+        }
+        self.groups.remove(&group);
+        info!("Left multicast group {}", group);
+        Ok(())
+    }
+
+    /// Groups currently joined
+    pub fn groups(&self) -> impl Iterator<Item = &IpAddr> {
+        self.groups.iter()
+    }
+
+    /// Receive the next complete frame and the publisher it came from
+    ///
+    /// Fragmented frames are reassembled per source, duplicates are dropped,
+    /// and gaps in a source's sequence numbers are NACKed if enabled.
+    pub async fn recv(&mut self) -> Result<(Frame, SocketAddr), VstpError> {
+        let mut buf = vec![0u8; MAX_RECV_SIZE];
+
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            let mut bytes = bytes::BytesMut::from(&buf[..len]);
+            let mut frame = match try_decode_frame(&mut bytes, MAX_RECV_SIZE) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Dropping malformed multicast datagram from {}: {}", from, e);
+                    continue;
+                }
+            };
+
+            let seq = frame
+                .get_header(MULTICAST_SEQ_HEADER)
+                .and_then(|v| v.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                if !self.track_sequence(seq, from).await? {
+                    continue;
+                }
+            }
+
+            if let Some(fragment) = extract_fragment_info(&frame) {
+                match self.reassembly.add_fragment(from, fragment).await {
+                    Ok(Some(payload)) => {
+                        frame.payload = payload;
+                        strip_fragment_headers(&mut frame);
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        debug!("Dropping multicast fragment from {}: {}", from, e);
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(seq) = seq {
+                if let Some(state) = self.sources.get_mut(&from) {
+                    if !state.delivered.insert(seq) {
+                        continue;
+                    }
+                }
+            }
+            return Ok((frame, from));
+        }
+    }
+
+    /// Update the per-source sequence state, sending NACKs for gaps.
+    /// Returns `false` if the frame was already delivered.
+    async fn track_sequence(&mut self, seq: u64, from: SocketAddr) -> Result<bool, VstpError> {
+        let dedup_window = self.config.dedup_window;
+        if !self.sources.contains_key(&from) && self.sources.len() >= self.config.max_sources {
+            self.forget_oldest_source();
+        }
+        let state = self
+            .sources
+            .entry(from)
+            // Receivers joining mid-stream start at whatever arrives first
+            .or_insert_with(|| SourceState::new(seq, dedup_window));
+        if state.delivered.contains(seq) {
+            return Ok(false);
+        }
+
+        let missing = state.observe(seq, &self.config);
+        if self.config.nack && !missing.is_empty() {
+            let list: Vec<String> = missing.iter().map(u64::to_string).collect();
+            let nack =
+                Frame::new(FrameType::Ack).with_header(MULTICAST_NACK_HEADER, &list.join(","));
+            self.socket.send_to(&encode_frame(&nack)?, from).await?;
+            self.nacks_sent += 1;
+            debug!("NACKed frames {:?} from {}", missing, from);
+        }
+        Ok(true)
+    }
+
+    /// Drop the state of the source heard from least recently
+    fn forget_oldest_source(&mut self) {
+        let oldest = self
+            .sources
+            .iter()
+            .min_by_key(|(_, state)| state.last_seen)
+            .map(|(addr, _)| *addr);
+        if let Some(addr) = oldest {
+            self.sources.remove(&addr);
+            debug!("Forgetting multicast source {}", addr);
+        }
+    }
+
+    /// Get the local address of the receiver
+    pub fn local_addr(&self) -> Result<SocketAddr, VstpError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Number of NACK datagrams sent to publishers
+    pub fn nacks_sent(&self) -> u64 {
+        self.nacks_sent
+    }
+
+    /// Get the number of active reassembly sessions
+    pub async fn reassembly_session_count(&self) -> usize {
+        self.reassembly.session_count().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_is_nacked_until_retries_run_out() {
+        let config = MulticastConfig {
+            nack_interval: Duration::ZERO,
+            max_nack_retries: 2,
+            ..Default::default()
+        };
+        let mut state = SourceState::new(1, 16);
+
+        assert_eq!(state.observe(4, &config), vec![2, 3]);
+        // A repair for 2 arrives, 3 is requested once more then given up
+        assert_eq!(state.observe(2, &config), vec![3]);
+        assert!(state.observe(5, &config).is_empty());
+    }
+
+    #[test]
+    fn test_repair_history_is_bounded() {
+        let group: SocketAddr = "239.1.2.3:5000".parse().unwrap();
+        let mut history = RepairHistory::default();
+        for seq in 1..=5 {
+            let datagrams = vec![vec![seq as u8]];
+            history.push(PublishedFrame { seq, group, datagrams }, 3);
+        }
+        assert!(history.get(2).is_none());
+        assert_eq!(history.get(4).unwrap().datagrams, vec![vec![4]]);
+    }
+
+    #[test]
+    fn test_repair_limiter_caps_each_destination() {
+        let mut limiter = RepairLimiter::new(2, 1);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(limiter.allow(a));
+        assert!(limiter.allow(a));
+        assert!(!limiter.allow(a));
+        // The only slot is taken by a bucket that has not refilled
+        assert!(!limiter.allow(b));
+    }
+}
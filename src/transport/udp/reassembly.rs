@@ -1,12 +1,16 @@
 //! Fragmentation and reassembly for UDP frames
 
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tracing::{debug, warn};
 
+use super::fec::{self, FecInfo};
 use crate::core::types::{Frame, Header, VstpError};
 
 /// Maximum size for a single UDP datagram (recommended MTU)
@@ -21,6 +25,100 @@ pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
 /// Maximum number of concurrent reassembly sessions
 pub const MAX_REASSEMBLY_SESSIONS: usize = 1000;
 
+/// Maximum number of concurrent reassembly sessions for a single peer
+pub const MAX_REASSEMBLY_SESSIONS_PER_PEER: usize = 16;
+
+/// Maximum number of payload bytes buffered across all reassembly sessions
+pub const MAX_REASSEMBLY_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long a completed FEC group is remembered so its late parity fragments
+/// are dropped instead of opening a new session
+pub const FEC_GROUP_LINGER: Duration = Duration::from_secs(2);
+
+/// Which sessions to drop first when a reassembly limit is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the session that was started first
+    Oldest,
+    /// Evict the session holding the most buffered bytes
+    Largest,
+}
+
+/// Limits applied by a [`ReassemblyManager`]
+#[derive(Debug, Clone)]
+pub struct ReassemblyConfig {
+    /// Maximum number of concurrent sessions across all peers
+    pub max_sessions: usize,
+    /// Maximum number of concurrent sessions for a single peer
+    pub max_sessions_per_peer: usize,
+    /// Maximum number of fragment bytes buffered across all sessions
+    pub max_buffered_bytes: usize,
+    /// Sessions not completed within this time are discarded
+    pub timeout: Duration,
+    /// Which sessions to evict when the global limits are reached
+    pub eviction_policy: EvictionPolicy,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: MAX_REASSEMBLY_SESSIONS,
+            max_sessions_per_peer: MAX_REASSEMBLY_SESSIONS_PER_PEER,
+            max_buffered_bytes: MAX_REASSEMBLY_BYTES,
+            timeout: REASSEMBLY_TIMEOUT,
+            eviction_policy: EvictionPolicy::Oldest,
+        }
+    }
+}
+
+/// Counters describing reassembly activity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReassemblyMetrics {
+    /// Frames successfully reassembled
+    pub completed: u64,
+    /// Sessions discarded because they timed out
+    pub expired: u64,
+    /// Sessions evicted because their peer hit the per-peer session cap
+    pub evicted_peer_limit: u64,
+    /// Sessions evicted because the global session cap was reached
+    pub evicted_session_limit: u64,
+    /// Sessions evicted to stay within the global byte budget
+    pub evicted_memory_limit: u64,
+    /// Fragments rejected as malformed, duplicate or oversized
+    pub rejected_fragments: u64,
+    /// Frames rebuilt from parity because data fragments were missing
+    pub recovered: u64,
+    /// Fragments that arrived after their FEC group was already rebuilt
+    pub late_fragments: u64,
+}
+
+#[derive(Debug, Default)]
+struct MetricsCounters {
+    completed: AtomicU64,
+    expired: AtomicU64,
+    evicted_peer_limit: AtomicU64,
+    evicted_session_limit: AtomicU64,
+    evicted_memory_limit: AtomicU64,
+    rejected_fragments: AtomicU64,
+    recovered: AtomicU64,
+    late_fragments: AtomicU64,
+}
+
+impl MetricsCounters {
+    fn snapshot(&self) -> ReassemblyMetrics {
+        ReassemblyMetrics {
+            completed: self.completed.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            evicted_peer_limit: self.evicted_peer_limit.load(Ordering::Relaxed),
+            evicted_session_limit: self.evicted_session_limit.load(Ordering::Relaxed),
+            evicted_memory_limit: self.evicted_memory_limit.load(Ordering::Relaxed),
+            rejected_fragments: self.rejected_fragments.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            late_fragments: self.late_fragments.load(Ordering::Relaxed),
+        }
+    }
+}
+
 /// A fragment of a larger frame
 #[derive(Debug, Clone)]
 pub struct Fragment {
@@ -28,24 +126,37 @@ pub struct Fragment {
     pub frag_index: u8,
     pub frag_total: u8,
     pub data: Vec<u8>,
+    /// Present when the group carries Reed-Solomon parity fragments
+    pub fec: Option<FecInfo>,
 }
 
+/// Identifies where fragments come from, e.g. a peer address or a session
+pub trait ReassemblySource: Copy + Eq + Hash + Debug + Send + 'static {}
+
+impl<T: Copy + Eq + Hash + Debug + Send + 'static> ReassemblySource for T {}
+
 /// A reassembly session for a fragmented frame
 #[derive(Debug)]
-struct ReassemblySession {
+struct ReassemblySession<K> {
     frag_id: u8,
     total_fragments: u8,
+    fec: Option<FecInfo>,
     received_fragments: Vec<Option<Vec<u8>>>,
+    received_count: usize,
+    buffered_bytes: usize,
     created_at: Instant,
-    from_addr: SocketAddr,
+    from_addr: K,
 }
 
-impl ReassemblySession {
-    fn new(frag_id: u8, total_fragments: u8, from_addr: SocketAddr) -> Self {
+impl<K> ReassemblySession<K> {
+    fn new(frag_id: u8, total_fragments: u8, fec: Option<FecInfo>, from_addr: K) -> Self {
         Self {
             frag_id,
             total_fragments,
+            fec,
             received_fragments: vec![None; total_fragments as usize],
+            received_count: 0,
+            buffered_bytes: 0,
             created_at: Instant::now(),
             from_addr,
         }
@@ -60,12 +171,26 @@ impl ReassemblySession {
             return Err(VstpError::Protocol("Duplicate fragment".to_string()));
         }
 
+        self.buffered_bytes += data.len();
         self.received_fragments[frag_index as usize] = Some(data);
+        self.received_count += 1;
         Ok(())
     }
 
     fn is_complete(&self) -> bool {
-        self.received_fragments.iter().all(|f| f.is_some())
+        match self.fec {
+            Some(info) => self.received_count >= info.data_fragments as usize,
+            None => self.received_count == self.total_fragments as usize,
+        }
+    }
+
+    /// Whether assembling needs parity because some data fragments are missing
+    fn needs_recovery(&self) -> bool {
+        self.fec.is_some_and(|info| {
+            self.received_fragments[..info.data_fragments as usize]
+                .iter()
+                .any(|f| f.is_none())
+        })
     }
 
     fn assemble(&self) -> Result<Vec<u8>, VstpError> {
@@ -73,70 +198,192 @@ impl ReassemblySession {
             return Err(VstpError::Protocol("Frame not complete".to_string()));
         }
 
-        let mut result = Vec::new();
-        for fragment in &self.received_fragments {
-            if let Some(data) = fragment {
-                result.extend_from_slice(data);
-            }
+        if let Some(info) = self.fec {
+            return fec::decode(&self.received_fragments, info);
+        }
+
+        let mut result = Vec::with_capacity(self.buffered_bytes);
+        for data in self.received_fragments.iter().flatten() {
+            result.extend_from_slice(data);
         }
         Ok(result)
     }
 
-    fn is_expired(&self) -> bool {
-        self.created_at.elapsed() > REASSEMBLY_TIMEOUT
+    fn is_expired(&self, timeout: Duration) -> bool {
+        self.created_at.elapsed() > timeout
     }
 }
 
-/// Manages reassembly of fragmented UDP frames
+type SessionKey<K> = (K, u8);
+
+/// Session table plus the bookkeeping needed to enforce limits cheaply
 #[derive(Debug)]
-pub struct ReassemblyManager {
-    sessions: Arc<Mutex<HashMap<(SocketAddr, u8), ReassemblySession>>>,
+struct SessionTable<K> {
+    sessions: HashMap<SessionKey<K>, ReassemblySession<K>>,
+    per_peer: HashMap<K, usize>,
+    buffered_bytes: usize,
+    /// FEC groups completed before all of their fragments arrived
+    finished_fec: HashMap<SessionKey<K>, (u8, Instant)>,
+}
+
+impl<K> Default for SessionTable<K> {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            per_peer: HashMap::new(),
+            buffered_bytes: 0,
+            finished_fec: HashMap::new(),
+        }
+    }
 }
 
-impl ReassemblyManager {
+impl<K: ReassemblySource> SessionTable<K> {
+    fn insert(&mut self, key: SessionKey<K>, session: ReassemblySession<K>) {
+        *self.per_peer.entry(key.0).or_insert(0) += 1;
+        self.buffered_bytes += session.buffered_bytes;
+        self.sessions.insert(key, session);
+    }
+
+    fn remove(&mut self, key: &SessionKey<K>) -> Option<ReassemblySession<K>> {
+        let session = self.sessions.remove(key)?;
+        self.buffered_bytes -= session.buffered_bytes;
+        if let Some(count) = self.per_peer.get_mut(&key.0) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_peer.remove(&key.0);
+            }
+        }
+        Some(session)
+    }
+
+    fn peer_sessions(&self, addr: &K) -> usize {
+        self.per_peer.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Pick a victim according to the policy, optionally restricted to one peer
+    /// and never choosing `protect`.
+    fn select_victim(
+        &self,
+        policy: EvictionPolicy,
+        peer: Option<K>,
+        protect: Option<SessionKey<K>>,
+    ) -> Option<SessionKey<K>> {
+        let candidates = self
+            .sessions
+            .iter()
+            .filter(|(key, _)| peer.is_none_or(|p| key.0 == p))
+            .filter(|(key, _)| Some(**key) != protect);
+
+        match policy {
+            EvictionPolicy::Oldest => candidates
+                .min_by_key(|(_, session)| session.created_at)
+                .map(|(key, _)| *key),
+            EvictionPolicy::Largest => candidates
+                .max_by_key(|(_, session)| session.buffered_bytes)
+                .map(|(key, _)| *key),
+        }
+    }
+}
+
+/// Manages reassembly of fragmented UDP frames, keyed by fragment source
+#[derive(Debug)]
+pub struct ReassemblyManager<K = SocketAddr> {
+    sessions: Arc<Mutex<SessionTable<K>>>,
+    config: ReassemblyConfig,
+    metrics: Arc<MetricsCounters>,
+}
+
+impl<K: ReassemblySource> ReassemblyManager<K> {
     pub fn new() -> Self {
+        Self::with_config(ReassemblyConfig::default())
+    }
+
+    /// Create a reassembly manager with custom limits
+    pub fn with_config(config: ReassemblyConfig) -> Self {
         Self {
-            sessions: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(SessionTable::default())),
+            config,
+            metrics: Arc::new(MetricsCounters::default()),
         }
     }
 
     /// Add a fragment to the reassembly manager
     pub async fn add_fragment(
         &self,
-        from_addr: SocketAddr,
+        from_addr: K,
         fragment: Fragment,
     ) -> Result<Option<Vec<u8>>, VstpError> {
         let key = (from_addr, fragment.frag_id);
-        let mut sessions = self.sessions.lock().await;
+        let mut table = self.sessions.lock().await;
 
         // Clean up expired sessions first
-        self.cleanup_expired(&mut sessions).await;
+        self.cleanup_expired(&mut table);
 
-        // Check if we have too many sessions
-        if sessions.len() >= MAX_REASSEMBLY_SESSIONS {
-            return Err(VstpError::Protocol(
-                "Too many reassembly sessions".to_string(),
-            ));
+        if self.is_late_fragment(&mut table, key, &fragment) {
+            self.metrics.late_fragments.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
         }
 
-        let session = sessions.entry(key).or_insert_with(|| {
-            ReassemblySession::new(fragment.frag_id, fragment.frag_total, from_addr)
-        });
+        if let Err(e) = self.validate_fragment(&table, key, &fragment) {
+            self.metrics.rejected_fragments.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
 
-        session.add_fragment(fragment.frag_index, fragment.data)?;
+        if !table.sessions.contains_key(&key) {
+            self.make_room_for_session(&mut table, from_addr);
+            table.insert(
+                key,
+                ReassemblySession::new(
+                    fragment.frag_id,
+                    fragment.frag_total,
+                    fragment.fec,
+                    from_addr,
+                ),
+            );
+        }
 
-        if session.is_complete() {
+        if !self.make_room_for_bytes(&mut table, key, fragment.data.len()) {
+            // Only this session is left and it still does not fit
+            self.evict(&mut table, key, &self.metrics.evicted_memory_limit, "memory limit");
+            return Err(VstpError::Protocol(
+                "Reassembly memory budget exhausted".to_string(),
+            ));
+        }
+
+        let frag_index = fragment.frag_index;
+        let frag_len = fragment.data.len();
+        let session = table
+            .sessions
+            .get_mut(&key)
+            .expect("reassembly session was just inserted");
+        if let Err(e) = session.add_fragment(frag_index, fragment.data) {
+            self.metrics.rejected_fragments.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        let complete = session.is_complete();
+        table.buffered_bytes += frag_len;
+
+        if complete {
+            let session = table.remove(&key).expect("reassembly session exists");
+            if session.received_count < session.total_fragments as usize {
+                table
+                    .finished_fec
+                    .insert(key, (session.total_fragments, Instant::now()));
+            }
             let assembled_data = session.assemble()?;
-            sessions.remove(&key);
+            if session.needs_recovery() {
+                self.metrics.recovered.fetch_add(1, Ordering::Relaxed);
+            }
+            self.metrics.completed.fetch_add(1, Ordering::Relaxed);
             debug!(
-                "Successfully reassembled fragmented frame from {}",
+                "Successfully reassembled fragmented frame from {:?}",
                 from_addr
             );
             Ok(Some(assembled_data))
         } else {
             debug!(
-                "Fragment {}/{} received from {}",
-                fragment.frag_index + 1,
+                "Fragment {}/{} received from {:?}",
+                frag_index + 1,
                 fragment.frag_total,
                 from_addr
             );
@@ -144,32 +391,177 @@ impl ReassemblyManager {
         }
     }
 
+    /// Reject fragments that could never be reassembled or would never fit
+    fn validate_fragment(
+        &self,
+        table: &SessionTable<K>,
+        key: SessionKey<K>,
+        fragment: &Fragment,
+    ) -> Result<(), VstpError> {
+        if fragment.frag_total == 0 || fragment.frag_index >= fragment.frag_total {
+            return Err(VstpError::Protocol("Invalid fragment index".to_string()));
+        }
+
+        if let Some(info) = fragment.fec {
+            if info.data_fragments == 0 || info.data_fragments > fragment.frag_total {
+                return Err(VstpError::Protocol("Invalid FEC parameters".to_string()));
+            }
+        }
+
+        if fragment.data.len() > self.config.max_buffered_bytes {
+            return Err(VstpError::Protocol(
+                "Fragment exceeds reassembly memory budget".to_string(),
+            ));
+        }
+
+        if let Some(session) = table.sessions.get(&key) {
+            if session.total_fragments != fragment.frag_total || session.fec != fragment.fec {
+                return Err(VstpError::Protocol(
+                    "Fragment total does not match reassembly session".to_string(),
+                ));
+            }
+            let shard_len = session.received_fragments.iter().flatten().next().map(Vec::len);
+            if session.fec.is_some() && shard_len.is_some_and(|len| len != fragment.data.len()) {
+                return Err(VstpError::Protocol(
+                    "FEC fragment size does not match reassembly session".to_string(),
+                ));
+            }
+            if session.received_fragments[fragment.frag_index as usize].is_some() {
+                return Err(VstpError::Protocol("Duplicate fragment".to_string()));
+            }
+        } else if self.config.max_sessions == 0 || self.config.max_sessions_per_peer == 0 {
+            return Err(VstpError::Protocol(
+                "Too many reassembly sessions".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether `fragment` belongs to an FEC group that was already rebuilt.
+    /// A fragment with a different shape means the frag_id has been reused,
+    /// so the stale record is dropped.
+    fn is_late_fragment(
+        &self,
+        table: &mut SessionTable<K>,
+        key: SessionKey<K>,
+        fragment: &Fragment,
+    ) -> bool {
+        let Some(&(total, _)) = table.finished_fec.get(&key) else {
+            return false;
+        };
+        if fragment.fec.is_some() && fragment.frag_total == total {
+            return true;
+        }
+        table.finished_fec.remove(&key);
+        false
+    }
+
+    /// Evict sessions until a new session from `from_addr` fits within the
+    /// per-peer and global session caps
+    fn make_room_for_session(&self, table: &mut SessionTable<K>, from_addr: K) {
+        // A peer over its own cap only ever loses its own sessions, so a single
+        // noisy sender cannot push everyone else out.
+        while table.peer_sessions(&from_addr) >= self.config.max_sessions_per_peer {
+            let Some(victim) =
+                table.select_victim(EvictionPolicy::Oldest, Some(from_addr), None)
+            else {
+                break;
+            };
+            self.evict(table, victim, &self.metrics.evicted_peer_limit, "per-peer limit");
+        }
+
+        while table.sessions.len() >= self.config.max_sessions {
+            let Some(victim) = table.select_victim(self.config.eviction_policy, None, None)
+            else {
+                break;
+            };
+            self.evict(table, victim, &self.metrics.evicted_session_limit, "session limit");
+        }
+    }
+
+    /// Evict other sessions until `incoming` more bytes fit in the byte budget.
+    /// Returns `false` if the budget cannot be met without evicting `key` itself.
+    fn make_room_for_bytes(
+        &self,
+        table: &mut SessionTable<K>,
+        key: SessionKey<K>,
+        incoming: usize,
+    ) -> bool {
+        while table.buffered_bytes + incoming > self.config.max_buffered_bytes {
+            let Some(victim) = table.select_victim(self.config.eviction_policy, None, Some(key))
+            else {
+                return false;
+            };
+            self.evict(table, victim, &self.metrics.evicted_memory_limit, "memory limit");
+        }
+        true
+    }
+
+    fn evict(
+        &self,
+        table: &mut SessionTable<K>,
+        key: SessionKey<K>,
+        counter: &AtomicU64,
+        reason: &str,
+    ) {
+        if let Some(session) = table.remove(&key) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Evicted reassembly session for frag_id {} from {:?} ({}, {} bytes buffered)",
+                session.frag_id, session.from_addr, reason, session.buffered_bytes
+            );
+        }
+    }
+
     /// Clean up expired reassembly sessions
-    async fn cleanup_expired(&self, sessions: &mut HashMap<(SocketAddr, u8), ReassemblySession>) {
-        let expired_keys: Vec<_> = sessions
+    fn cleanup_expired(&self, table: &mut SessionTable<K>) {
+        let expired_keys: Vec<_> = table
+            .sessions
             .iter()
-            .filter(|(_, session)| session.is_expired())
+            .filter(|(_, session)| session.is_expired(self.config.timeout))
             .map(|(key, _)| *key)
             .collect();
 
         for key in expired_keys {
-            if let Some(session) = sessions.remove(&key) {
+            if let Some(session) = table.remove(&key) {
+                self.metrics.expired.fetch_add(1, Ordering::Relaxed);
                 warn!(
-                    "Expired reassembly session for frag_id {} from {}",
+                    "Expired reassembly session for frag_id {} from {:?}",
                     session.frag_id, session.from_addr
                 );
             }
         }
+
+        table
+            .finished_fec
+            .retain(|_, (_, finished_at)| finished_at.elapsed() < FEC_GROUP_LINGER);
     }
 
     /// Get the number of active reassembly sessions
     pub async fn session_count(&self) -> usize {
-        let sessions = self.sessions.lock().await;
-        sessions.len()
+        let table = self.sessions.lock().await;
+        table.sessions.len()
+    }
+
+    /// Get the number of fragment bytes currently buffered
+    pub async fn buffered_bytes(&self) -> usize {
+        let table = self.sessions.lock().await;
+        table.buffered_bytes
+    }
+
+    /// Get a snapshot of the reassembly counters
+    pub fn metrics(&self) -> ReassemblyMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Get the limits this manager enforces
+    pub fn config(&self) -> &ReassemblyConfig {
+        &self.config
     }
 }
 
-impl Default for ReassemblyManager {
+impl<K: ReassemblySource> Default for ReassemblyManager<K> {
     fn default() -> Self {
         Self::new()
     }
@@ -181,8 +573,8 @@ pub fn fragment_payload(payload: &[u8], frag_id: u8) -> Result<Vec<Fragment>, Vs
         return Ok(vec![]); // No fragmentation needed
     }
 
-    let total_fragments = ((payload.len() + MAX_DATAGRAM_SIZE - 1) / MAX_DATAGRAM_SIZE) as u8;
-    if total_fragments > MAX_FRAGMENTS as u8 {
+    let total_fragments = payload.len().div_ceil(MAX_DATAGRAM_SIZE);
+    if total_fragments > MAX_FRAGMENTS {
         return Err(VstpError::Protocol(format!(
             "Payload too large: {} fragments needed (max {})",
             total_fragments, MAX_FRAGMENTS
@@ -194,14 +586,57 @@ pub fn fragment_payload(payload: &[u8], frag_id: u8) -> Result<Vec<Fragment>, Vs
         fragments.push(Fragment {
             frag_id,
             frag_index: i as u8,
-            frag_total: total_fragments,
+            frag_total: total_fragments as u8,
             data: chunk.to_vec(),
+            fec: None,
         });
     }
 
     Ok(fragments)
 }
 
+/// Split a large payload into fragments and append Reed-Solomon parity
+/// fragments, `redundancy` parity fragments per data fragment (rounded up).
+///
+/// A redundancy of `0.0` behaves exactly like [`fragment_payload`].
+pub fn fragment_payload_with_fec(
+    payload: &[u8],
+    frag_id: u8,
+    redundancy: f64,
+) -> Result<Vec<Fragment>, VstpError> {
+    if payload.len() <= MAX_DATAGRAM_SIZE || redundancy <= 0.0 {
+        return fragment_payload(payload, frag_id);
+    }
+
+    let data_fragments = payload.len().div_ceil(MAX_DATAGRAM_SIZE);
+    if data_fragments >= MAX_FRAGMENTS {
+        return Err(VstpError::Protocol(format!(
+            "Payload too large: {} fragments needed (max {} with FEC)",
+            data_fragments,
+            MAX_FRAGMENTS - 1
+        )));
+    }
+    let parity_fragments = fec::parity_count(data_fragments, redundancy, MAX_FRAGMENTS);
+    let frag_total = (data_fragments + parity_fragments) as u8;
+    let info = FecInfo {
+        data_fragments: data_fragments as u8,
+        payload_len: payload.len() as u32,
+    };
+
+    let shards = fec::encode(payload, data_fragments, parity_fragments)?;
+    Ok(shards
+        .into_iter()
+        .enumerate()
+        .map(|(i, data)| Fragment {
+            frag_id,
+            frag_index: i as u8,
+            frag_total,
+            data,
+            fec: Some(info),
+        })
+        .collect())
+}
+
 /// Extract fragment information from frame headers
 pub fn extract_fragment_info(frame: &Frame) -> Option<Fragment> {
     // Look for fragment headers
@@ -227,6 +662,7 @@ pub fn extract_fragment_info(frame: &Frame) -> Option<Fragment> {
                             frag_index,
                             frag_total,
                             data: frame.payload.clone(),
+                            fec: extract_fec_info(frame),
                         });
                     }
                 }
@@ -236,6 +672,31 @@ pub fn extract_fragment_info(frame: &Frame) -> Option<Fragment> {
     None
 }
 
+/// Parse the FEC headers of a fragment frame, if present
+fn extract_fec_info(frame: &Frame) -> Option<FecInfo> {
+    let header = |key: &[u8]| {
+        frame
+            .headers
+            .iter()
+            .find(|h| h.key == key)
+            .and_then(|h| std::str::from_utf8(&h.value).ok())
+    };
+    Some(FecInfo {
+        data_fragments: header(b"frag-data")?.parse().ok()?,
+        payload_len: header(b"frag-len")?.parse().ok()?,
+    })
+}
+
+/// Remove fragment and FEC headers from a reassembled frame
+pub fn strip_fragment_headers(frame: &mut Frame) {
+    frame.headers.retain(|h| {
+        !matches!(
+            h.key.as_slice(),
+            b"frag-id" | b"frag-index" | b"frag-total" | b"frag-data" | b"frag-len"
+        )
+    });
+}
+
 /// Add fragment headers to a frame
 pub fn add_fragment_headers(frame: &mut Frame, fragment: &Fragment) {
     frame.headers.push(Header {
@@ -250,4 +711,145 @@ pub fn add_fragment_headers(frame: &mut Frame, fragment: &Fragment) {
         key: b"frag-total".to_vec(),
         value: fragment.frag_total.to_string().into_bytes(),
     });
+    if let Some(info) = fragment.fec {
+        frame.headers.push(Header {
+            key: b"frag-data".to_vec(),
+            value: info.data_fragments.to_string().into_bytes(),
+        });
+        frame.headers.push(Header {
+            key: b"frag-len".to_vec(),
+            value: info.payload_len.to_string().into_bytes(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frag(frag_id: u8, frag_index: u8, frag_total: u8, len: usize) -> Fragment {
+        Fragment {
+            frag_id,
+            frag_index,
+            frag_total,
+            data: vec![frag_index; len],
+            fec: None,
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[tokio::test]
+    async fn test_reassembles_out_of_order() {
+        let manager = ReassemblyManager::new();
+        assert!(manager.add_fragment(addr(1), frag(7, 1, 2, 3)).await.unwrap().is_none());
+        let data = manager.add_fragment(addr(1), frag(7, 0, 2, 3)).await.unwrap().unwrap();
+
+        assert_eq!(data, vec![0, 0, 0, 1, 1, 1]);
+        assert_eq!(manager.session_count().await, 0);
+        assert_eq!(manager.buffered_bytes().await, 0);
+        assert_eq!(manager.metrics().completed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_per_peer_cap_only_evicts_that_peer() {
+        let manager = ReassemblyManager::with_config(ReassemblyConfig {
+            max_sessions: 4,
+            max_sessions_per_peer: 2,
+            ..Default::default()
+        });
+
+        manager.add_fragment(addr(2), frag(0, 0, 2, 10)).await.unwrap();
+        for id in 0..5 {
+            manager.add_fragment(addr(1), frag(id, 0, 2, 10)).await.unwrap();
+        }
+
+        // The noisy peer is held to two sessions and the other peer keeps its own
+        assert_eq!(manager.session_count().await, 3);
+        assert_eq!(manager.metrics().evicted_peer_limit, 3);
+        assert!(manager.add_fragment(addr(2), frag(0, 1, 2, 10)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_byte_budget_evicts_largest() {
+        let manager = ReassemblyManager::with_config(ReassemblyConfig {
+            max_buffered_bytes: 100,
+            eviction_policy: EvictionPolicy::Largest,
+            ..Default::default()
+        });
+
+        manager.add_fragment(addr(1), frag(1, 0, 3, 60)).await.unwrap();
+        manager.add_fragment(addr(2), frag(1, 0, 3, 20)).await.unwrap();
+        manager.add_fragment(addr(3), frag(1, 0, 3, 30)).await.unwrap();
+
+        assert_eq!(manager.session_count().await, 2);
+        assert_eq!(manager.buffered_bytes().await, 50);
+        assert_eq!(manager.metrics().evicted_memory_limit, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_malformed_fragments() {
+        let manager = ReassemblyManager::new();
+
+        assert!(manager.add_fragment(addr(1), frag(1, 0, 0, 1)).await.is_err());
+        assert!(manager.add_fragment(addr(1), frag(1, 3, 2, 1)).await.is_err());
+        manager.add_fragment(addr(1), frag(1, 0, 2, 1)).await.unwrap();
+        assert!(manager.add_fragment(addr(1), frag(1, 0, 2, 1)).await.is_err());
+        assert!(manager.add_fragment(addr(1), frag(1, 1, 3, 1)).await.is_err());
+
+        assert_eq!(manager.metrics().rejected_fragments, 4);
+        assert_eq!(manager.session_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_dropped() {
+        let manager = ReassemblyManager::with_config(ReassemblyConfig {
+            timeout: Duration::from_millis(10),
+            ..Default::default()
+        });
+
+        manager.add_fragment(addr(1), frag(1, 0, 2, 1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        manager.add_fragment(addr(1), frag(2, 0, 2, 1)).await.unwrap();
+
+        assert_eq!(manager.session_count().await, 1);
+        assert_eq!(manager.metrics().expired, 1);
+    }
+
+    #[tokio::test]
+    async fn test_fec_rebuilds_from_any_k_fragments() {
+        let payload: Vec<u8> = (0..5000u32).map(|i| (i % 253) as u8).collect();
+        let fragments = fragment_payload_with_fec(&payload, 9, 0.5).unwrap();
+        assert_eq!(fragments.len(), 5 + 3);
+
+        let manager = ReassemblyManager::new();
+        let mut result = None;
+        // Lose three fragments, including two data fragments
+        for fragment in fragments.iter().filter(|f| ![0, 2, 6].contains(&f.frag_index)) {
+            result = manager.add_fragment(addr(1), fragment.clone()).await.unwrap();
+        }
+
+        assert_eq!(result.unwrap(), payload);
+        assert_eq!(manager.metrics().recovered, 1);
+
+        // A late fragment of the rebuilt group must not start a new session
+        assert!(manager.add_fragment(addr(1), fragments[6].clone()).await.unwrap().is_none());
+        assert_eq!(manager.session_count().await, 0);
+        assert_eq!(manager.metrics().late_fragments, 1);
+    }
+
+    #[test]
+    fn test_fec_headers_roundtrip() {
+        let payload = vec![7u8; 3000];
+        let fragments = fragment_payload_with_fec(&payload, 1, 0.34).unwrap();
+        let mut frame = Frame::new(crate::core::types::FrameType::Data)
+            .with_payload(fragments[4].data.clone());
+        add_fragment_headers(&mut frame, &fragments[4]);
+
+        let parsed = extract_fragment_info(&frame).unwrap();
+        assert_eq!(parsed.frag_total, 5);
+        assert_eq!(parsed.fec, fragments[4].fec);
+    }
 }
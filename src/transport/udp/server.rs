@@ -1,16 +1,27 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
 
 use crate::core::frame::{encode_frame, try_decode_frame};
-use crate::core::types::{Flags, Frame, FrameType, Header, VstpError, VSTP_VERSION};
+use crate::core::types::{Flags, Frame, FrameType, Header, SessionId, VstpError, VSTP_VERSION};
+use crate::security::ai::monitor::ErrorType;
 use crate::security::ai::AnomalyDetector;
+use crate::security::blocklist::Blocklist;
+use crate::security::replay::ReplayRejection;
+use crate::transport::udp::crypto::{self, is_encrypted, DatagramCipher, HANDSHAKE_HEADER};
 use crate::transport::udp::reassembly::{
-    extract_fragment_info, ReassemblyManager, MAX_DATAGRAM_SIZE,
+    extract_fragment_info, strip_fragment_headers, EvictionPolicy, ReassemblyConfig,
+    ReassemblyManager, ReassemblyMetrics, MAX_DATAGRAM_SIZE, MAX_REASSEMBLY_BYTES, MAX_REASSEMBLY_SESSIONS,
+    MAX_REASSEMBLY_SESSIONS_PER_PEER, REASSEMBLY_TIMEOUT,
+};
+use crate::transport::udp::session::{
+    extract_connection_id, format_connection_id, ConnectionId, PathEvent, UdpSession,
+    UdpSessionTable, VstpUdpConnection, CONNECTION_QUEUE_SIZE, CONN_ID_HEADER,
 };
 
 /// Configuration for UDP server
@@ -22,6 +33,24 @@ pub struct UdpServerConfig {
     pub allow_frag: bool,
     /// Maximum number of concurrent reassembly sessions
     pub max_reassembly_sessions: usize,
+    /// Maximum number of concurrent reassembly sessions per peer
+    pub max_reassembly_sessions_per_peer: usize,
+    /// Maximum number of fragment bytes buffered across all reassembly sessions
+    pub max_reassembly_bytes: usize,
+    /// Time allowed for all fragments of a frame to arrive
+    pub reassembly_timeout: Duration,
+    /// Which reassembly sessions to evict first when a limit is reached
+    pub reassembly_eviction: EvictionPolicy,
+    /// Maximum number of concurrent UDP sessions
+    pub max_sessions: usize,
+    /// Sessions with no traffic for this long are expired
+    pub session_idle_timeout: Duration,
+    /// Number of recent message IDs remembered per session to drop retransmissions
+    pub reliability_window: usize,
+    /// How long a path challenge to a migrating peer stays valid
+    pub path_validation_timeout: Duration,
+    /// Refuse sessions that do not negotiate encryption in their HELLO
+    pub require_encryption: bool,
 }
 
 impl Default for UdpServerConfig {
@@ -29,17 +58,708 @@ impl Default for UdpServerConfig {
         Self {
             use_crc: true,
             allow_frag: true,
-            max_reassembly_sessions: 1000,
+            max_reassembly_sessions: MAX_REASSEMBLY_SESSIONS,
+            max_reassembly_sessions_per_peer: MAX_REASSEMBLY_SESSIONS_PER_PEER,
+            max_reassembly_bytes: MAX_REASSEMBLY_BYTES,
+            reassembly_timeout: REASSEMBLY_TIMEOUT,
+            reassembly_eviction: EvictionPolicy::Oldest,
+            max_sessions: 10_000,
+            session_idle_timeout: Duration::from_secs(60),
+            reliability_window: 256,
+            path_validation_timeout: Duration::from_secs(5),
+            require_encryption: false,
         }
     }
 }
 
-/// VSTP UDP Server
-pub struct VstpUdpServer {
+impl UdpServerConfig {
+    /// Reassembly limits derived from this configuration
+    pub fn reassembly_config(&self) -> ReassemblyConfig {
+        ReassemblyConfig {
+            max_sessions: self.max_reassembly_sessions,
+            max_sessions_per_peer: self.max_reassembly_sessions_per_peer,
+            max_buffered_bytes: self.max_reassembly_bytes,
+            timeout: self.reassembly_timeout,
+            eviction_policy: self.reassembly_eviction,
+        }
+    }
+
+    /// How often idle sessions are swept
+    fn sweep_interval(&self) -> Duration {
+        (self.session_idle_timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(1))
+    }
+}
+
+/// A frame that has been decoded, reassembled and matched to a session
+pub(crate) struct Routed {
+    frame: Frame,
+    addr: SocketAddr,
+    session_id: SessionId,
+    conn_id: ConnectionId,
+    /// This frame completed a HELLO/WELCOME handshake
+    handshake: bool,
+    /// Queue of the connection handed out by `accept()`, if any
+    tx: Option<mpsc::Sender<Frame>>,
+}
+
+/// Result of matching a datagram to a session
+struct Route {
+    session_id: SessionId,
+    conn_id: ConnectionId,
+    handshake: bool,
+    tx: Option<mpsc::Sender<Frame>>,
+    /// Frame to send back to the peer before anything else
+    reply: Option<Frame>,
+    /// Whether the datagram should be processed further
+    deliver: bool,
+    /// Session keys if the session is encrypted
+    cipher: Option<Arc<DatagramCipher>>,
+}
+
+impl Route {
+    /// Answer a datagram with `reply` without delivering it
+    fn reject(conn_id: ConnectionId, reply: Frame) -> Self {
+        Self {
+            session_id: 0,
+            conn_id,
+            handshake: false,
+            tx: None,
+            reply: Some(reply),
+            deliver: false,
+            cipher: None,
+        }
+    }
+}
+
+/// State shared between the server handle, its connections and the
+/// `accept()` driver task
+pub(crate) struct Shared {
     socket: UdpSocket,
     config: UdpServerConfig,
-    reassembly: ReassemblyManager,
-    next_session_id: Arc<Mutex<u128>>,
+    reassembly: ReassemblyManager<SessionId>,
+    sessions: Mutex<UdpSessionTable>,
+    next_session_id: Mutex<u128>,
+    last_sweep: Mutex<Instant>,
+    blocklist: std::sync::RwLock<Option<Blocklist>>,
+    /// Detector that undecodable datagrams are reported to
+    detector: std::sync::RwLock<Option<Arc<AnomalyDetector>>>,
+}
+
+impl Shared {
+    fn new(socket: UdpSocket, config: UdpServerConfig) -> Self {
+        Self {
+            socket,
+            reassembly: ReassemblyManager::with_config(config.reassembly_config()),
+            config,
+            sessions: Mutex::new(UdpSessionTable::default()),
+            next_session_id: Mutex::new(1),
+            last_sweep: Mutex::new(Instant::now()),
+            blocklist: std::sync::RwLock::new(None),
+            detector: std::sync::RwLock::new(None),
+        }
+    }
+
+    pub(crate) async fn send_to(&self, frame: &Frame, dest: SocketAddr) -> Result<(), VstpError> {
+        let encoded = encode_frame(frame)?;
+        self.socket.send_to(&encoded, dest).await?;
+        Ok(())
+    }
+
+    /// Send a frame to a session's peer, sealing it if the session is encrypted
+    pub(crate) async fn send_session(
+        &self,
+        frame: &Frame,
+        dest: SocketAddr,
+        cipher: Option<&DatagramCipher>,
+    ) -> Result<(), VstpError> {
+        match cipher {
+            Some(cipher) => self.send_to(&cipher.seal(frame, None)?, dest).await,
+            None => self.send_to(frame, dest).await,
+        }
+    }
+
+    fn detector(&self) -> Option<Arc<AnomalyDetector>> {
+        self.detector.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Report a datagram that could not be decoded to the detector, against
+    /// its sender's session if it has one
+    async fn report_decode_error(&self, addr: SocketAddr, error: &VstpError) {
+        let Some(detector) = self.detector() else {
+            return;
+        };
+        let session_id = {
+            let sessions = self.sessions.lock().await;
+            sessions
+                .lookup_addr(&addr)
+                .and_then(|conn_id| sessions.get(conn_id))
+                .map(|session| session.session_id)
+        };
+        detector.report_decode_error(session_id, addr, error).await;
+    }
+
+    /// Tell the detector a session is gone so it can drop its state
+    async fn end_session(&self, session_id: SessionId) {
+        if let Some(detector) = self.detector() {
+            detector.end_session(session_id).await;
+        }
+    }
+
+    /// Carry out the detector's pending responses for a session, returning
+    /// `false` once one of them closes it
+    ///
+    /// A closed session is forgotten, so the peer has to send HELLO again.
+    async fn apply_responses(
+        &self,
+        detector: &AnomalyDetector,
+        conn_id: ConnectionId,
+        session_id: SessionId,
+    ) -> bool {
+        for action in detector.take_responses(session_id) {
+            let peer = {
+                let sessions = self.sessions.lock().await;
+                sessions
+                    .get(conn_id)
+                    .map(|session| (session.peer_addr(), session.cipher()))
+            };
+            let Some((addr, cipher)) = peer else {
+                return false;
+            };
+            if let Some(notice) = action.notice() {
+                let err = error_frame(conn_id, notice);
+                if let Err(e) = self.send_session(&err, addr, cipher.as_deref()).await {
+                    debug!("Failed to send response to {}: {}", addr, e);
+                }
+            }
+            if action.ends_session() {
+                self.sessions.lock().await.remove(conn_id);
+                self.end_session(session_id).await;
+                warn!("Closing UDP session {} in response to a threat: {:?}", session_id, action);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn is_banned(&self, addr: &SocketAddr) -> bool {
+        let blocklist = self.blocklist.read().unwrap_or_else(|e| e.into_inner());
+        blocklist.as_ref().is_some_and(|b| b.is_blocked(addr.ip()))
+    }
+
+    /// Receive the next complete frame and the session it belongs to
+    async fn recv_routed(&self) -> Result<Routed, VstpError> {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE * 2]; // Extra space for headers
+
+        loop {
+            self.maybe_expire_idle().await;
+
+            // Wake up periodically so idle sessions are expired even without traffic
+            let (len, from_addr) =
+                match timeout(self.config.sweep_interval(), self.socket.recv_from(&mut buf)).await
+                {
+                    Ok(received) => received?,
+                    Err(_) => continue,
+                };
+            if self.is_banned(&from_addr) {
+                debug!("Dropping datagram from banned address {}", from_addr);
+                continue;
+            }
+            let data = &buf[..len];
+            debug!("Received {} bytes from {}", len, from_addr);
+
+            // Try to decode the frame
+            let mut data = bytes::BytesMut::from(data);
+            let mut frame = match try_decode_frame(&mut data, 65536) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue, // Incomplete frame
+                Err(e) => {
+                    debug!("Dropping invalid datagram from {}: {}", from_addr, e);
+                    self.report_decode_error(from_addr, &e).await;
+                    continue;
+                }
+            };
+
+            let Some(route) = self.route(&frame, from_addr).await else {
+                continue;
+            };
+            if let Some(reply) = &route.reply {
+                let _ = self.send_to(reply, from_addr).await;
+            }
+            if !route.deliver {
+                continue;
+            }
+            if frame.flags.unknown_bits() != 0 {
+                if let Some(detector) = self.detector() {
+                    detector
+                        .report_error(route.session_id, from_addr, ErrorType::SuspiciousFlag)
+                        .await;
+                }
+            }
+
+            // Encrypted sessions only accept sealed datagrams after the handshake
+            frame = match &route.cipher {
+                Some(cipher) if is_encrypted(&frame) => match cipher.open_checked(&frame) {
+                    Ok(Ok(inner)) => inner,
+                    Ok(Err(rejection)) => {
+                        // Networks duplicate datagrams, so a few repeats are not an attack
+                        if matches!(rejection, ReplayRejection::Duplicate(_))
+                            && !self
+                                .sessions
+                                .lock()
+                                .await
+                                .get_mut(route.conn_id)
+                                .is_some_and(UdpSession::note_duplicate)
+                        {
+                            debug!(
+                                "UDP session {} dropped duplicate datagram from {}: {}",
+                                route.session_id, from_addr, rejection
+                            );
+                            continue;
+                        }
+                        warn!(
+                            "UDP session {} rejected replayed datagram from {}: {}",
+                            route.session_id, from_addr, rejection
+                        );
+                        if let Some(detector) = self.detector() {
+                            detector.report_replay(route.session_id, &rejection).await;
+                            self.apply_responses(&detector, route.conn_id, route.session_id)
+                                .await;
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("Dropping datagram from {}: {}", from_addr, e);
+                        continue;
+                    }
+                },
+                Some(_) if frame.typ == FrameType::Hello => {
+                    frame.headers.retain(|h| h.key != HANDSHAKE_HEADER.as_bytes());
+                    frame.payload.clear();
+                    frame
+                }
+                None if !is_encrypted(&frame) && !self.config.require_encryption => frame,
+                _ => {
+                    debug!(
+                        "Dropping datagram from {} that does not match the session's encryption",
+                        from_addr
+                    );
+                    continue;
+                }
+            };
+
+            // Check if this is a fragmented frame
+            if let Some(fragment) = extract_fragment_info(&frame) {
+                match self.reassembly.add_fragment(route.session_id, fragment).await {
+                    Ok(Some(assembled_data)) => {
+                        frame.payload = assembled_data;
+                        strip_fragment_headers(&mut frame);
+                    }
+                    // Fragment received, continue waiting for more
+                    Ok(None) => continue,
+                    // Counted in the reassembly metrics; the peer caused it,
+                    // so it must not end the receive loop
+                    Err(e) => {
+                        debug!("Dropping fragment from {}: {}", from_addr, e);
+                        continue;
+                    }
+                }
+            }
+            frame.headers.retain(|h| h.key != CONN_ID_HEADER.as_bytes());
+
+            // Send ACK if requested, delivering retransmissions only once
+            if frame.flags.contains(Flags::REQ_ACK) {
+                if let Some(msg_id) = extract_msg_id(&frame) {
+                    let _ = self.send_ack(msg_id, from_addr, route.cipher.as_deref()).await;
+                    if !self.record_delivery(route.conn_id, msg_id).await {
+                        debug!("Dropping retransmitted message {} from {}", msg_id, from_addr);
+                        continue;
+                    }
+                }
+            }
+
+            let mut tx = route.tx;
+            if frame.typ == FrameType::Bye {
+                if let Some(session) = self.sessions.lock().await.remove(route.conn_id) {
+                    debug!("Session {} closed by {}", session.session_id, from_addr);
+                    tx = session.tx;
+                }
+            }
+
+            return Ok(Routed {
+                frame,
+                addr: from_addr,
+                session_id: route.session_id,
+                conn_id: route.conn_id,
+                handshake: route.handshake,
+                tx,
+            });
+        }
+    }
+
+    /// Match a datagram to a session, creating one if needed
+    async fn route(&self, frame: &Frame, addr: SocketAddr) -> Option<Route> {
+        let mut sessions = self.sessions.lock().await;
+
+        let known_conn_id = extract_connection_id(frame).filter(|id| sessions.contains(*id));
+        if let Some(conn_id) = extract_connection_id(frame) {
+            // A HELLO carrying a stale connection ID simply opens a new session
+            if known_conn_id.is_none() && frame.typ != FrameType::Hello {
+                debug!("Unknown connection {:016x} from {}", conn_id, addr);
+                return Some(Route::reject(conn_id, unknown_connection_frame(conn_id)));
+            }
+        }
+        // HELLOs are cleartext, so anyone able to spoof the client's address
+        // could send one. A key exchange therefore never replaces an
+        // encrypted session's keys; it opens a new session instead.
+        let rekey = |session: &UdpSession| {
+            frame.typ == FrameType::Hello
+                && frame.get_header(HANDSHAKE_HEADER).is_some()
+                && session.cipher().is_some()
+        };
+
+        if let Some(session) = known_conn_id
+            .and_then(|id| sessions.get_mut(id))
+            .filter(|session| !rekey(session))
+        {
+            let conn_id = session.conn_id;
+            if session.peer_addr() != addr {
+                let session_id = session.session_id;
+                // Anyone who has seen the connection ID can send from a new
+                // address, so encrypted sessions only validate a path that
+                // proves it holds the session keys. Sequence numbers are only
+                // recorded once the path is validated, so a captured datagram
+                // sent ahead from elsewhere cannot get the real one rejected.
+                let cipher = session.cipher();
+                let sealed = frame;
+                let opened;
+                let frame = match &cipher {
+                    Some(cipher) => match is_encrypted(frame).then(|| cipher.authenticate(frame)) {
+                        Some(Ok(inner)) => {
+                            opened = inner;
+                            &opened
+                        }
+                        _ => {
+                            debug!(
+                                "Ignoring unauthenticated datagram for session {} from {}",
+                                session_id, addr
+                            );
+                            return None;
+                        }
+                    },
+                    None => frame,
+                };
+                let reply = match session.on_new_path(
+                    frame,
+                    addr,
+                    self.config.path_validation_timeout,
+                ) {
+                    PathEvent::Challenge(challenge) => {
+                        debug!(
+                            "Validating new path {} for session {}",
+                            addr, session_id
+                        );
+                        match &cipher {
+                            Some(cipher) => cipher.seal(&challenge, None).ok(),
+                            None => Some(challenge),
+                        }
+                    }
+                    PathEvent::Pending => None,
+                    PathEvent::Validated => {
+                        if let Some(cipher) = &cipher {
+                            if !matches!(cipher.open_checked(sealed), Ok(Ok(_))) {
+                                debug!(
+                                    "Ignoring replayed path response for session {} from {}",
+                                    session_id, addr
+                                );
+                                return None;
+                            }
+                        }
+                        session.last_seen = Instant::now();
+                        if let Some(old_addr) = sessions.migrate(conn_id, addr) {
+                            info!(
+                                "UDP session {} migrated from {} to {}",
+                                session_id, old_addr, addr
+                            );
+                        }
+                        None
+                    }
+                };
+                return Some(Route {
+                    session_id,
+                    conn_id,
+                    handshake: false,
+                    tx: None,
+                    reply,
+                    deliver: false,
+                    cipher: None,
+                });
+            }
+            session.last_seen = Instant::now();
+            let (reply, deliver) = self.answer_hello(session, frame);
+            return Some(Route {
+                session_id: session.session_id,
+                conn_id,
+                handshake: false,
+                tx: session.tx.clone(),
+                reply,
+                deliver,
+                cipher: session.cipher(),
+            });
+        }
+
+        if let Some(conn_id) = sessions.lookup_addr(&addr) {
+            if let Some(session) = sessions.get_mut(conn_id).filter(|session| !rekey(session)) {
+                session.last_seen = Instant::now();
+                let (reply, deliver) = self.answer_hello(session, frame);
+                let handshake = frame.typ == FrameType::Hello && deliver && !session.established;
+                if handshake {
+                    session.established = true;
+                }
+                return Some(Route {
+                    session_id: session.session_id,
+                    conn_id,
+                    handshake,
+                    tx: session.tx.clone(),
+                    reply,
+                    deliver,
+                    cipher: session.cipher(),
+                });
+            }
+        }
+
+        if self.config.require_encryption && frame.typ != FrameType::Hello {
+            debug!("Dropping datagram from {} without an encrypted session", addr);
+            return None;
+        }
+
+        if sessions.len() >= self.config.max_sessions {
+            let expired = sessions.expire_idle(self.config.session_idle_timeout);
+            if !expired.is_empty() {
+                debug!("Expired {} idle UDP session(s)", expired.len());
+            }
+            if sessions.len() >= self.config.max_sessions {
+                warn!("Too many UDP sessions, dropping datagram from {}", addr);
+                return None;
+            }
+        }
+
+        let session_id = {
+            let mut id_guard = self.next_session_id.lock().await;
+            *id_guard += 1;
+            *id_guard
+        };
+        let conn_id = loop {
+            let candidate = rand::random::<ConnectionId>();
+            if candidate != 0 && !sessions.contains(candidate) {
+                break candidate;
+            }
+        };
+
+        let mut session = UdpSession::new(session_id, conn_id, addr, self.config.reliability_window);
+        let (reply, deliver) = self.answer_hello(&session, frame);
+        if !deliver {
+            return Some(Route::reject(conn_id, reply?));
+        }
+        let handshake = frame.typ == FrameType::Hello;
+        session.established = handshake;
+        let cipher = session.cipher();
+        sessions.insert(session);
+        info!(
+            "New UDP session {} from {} (connection {:016x})",
+            session_id, addr, conn_id
+        );
+
+        Some(Route {
+            session_id,
+            conn_id,
+            handshake,
+            tx: None,
+            reply,
+            deliver: true,
+            cipher,
+        })
+    }
+
+    /// Build the reply to a HELLO, running the key exchange if the client
+    /// started one. Returns the reply and whether the frame is accepted.
+    fn answer_hello(&self, session: &UdpSession, frame: &Frame) -> (Option<Frame>, bool) {
+        if frame.typ != FrameType::Hello {
+            return (None, true);
+        }
+        let conn_id = session.conn_id;
+
+        if frame.get_header(HANDSHAKE_HEADER).is_none() {
+            // A session that negotiated keys is never downgraded to cleartext
+            if self.config.require_encryption || session.cipher().is_some() {
+                return (Some(error_frame(conn_id, "encryption required")), false);
+            }
+            return (Some(welcome_frame(conn_id)), true);
+        }
+
+        match crypto::respond(&frame.payload) {
+            Ok((cipher, message)) => {
+                session.set_cipher(Some(cipher));
+                let welcome = welcome_frame(conn_id)
+                    .with_header(HANDSHAKE_HEADER, crypto::NOISE_PATTERN)
+                    .with_payload(message);
+                (Some(welcome), true)
+            }
+            Err(e) => {
+                debug!("Rejecting HELLO for connection {:016x}: {}", conn_id, e);
+                (Some(error_frame(conn_id, "handshake failed")), false)
+            }
+        }
+    }
+
+    /// Record a delivered message ID, returning `false` for duplicates
+    async fn record_delivery(&self, conn_id: ConnectionId, msg_id: u64) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        sessions
+            .get_mut(conn_id)
+            .map(|session| session.window.insert(msg_id))
+            .unwrap_or(true)
+    }
+
+    /// Expire idle sessions if the sweep interval has elapsed
+    async fn maybe_expire_idle(&self) {
+        {
+            let mut last_sweep = self.last_sweep.lock().await;
+            if last_sweep.elapsed() < self.config.sweep_interval() {
+                return;
+            }
+            *last_sweep = Instant::now();
+        }
+
+        let expired = self
+            .sessions
+            .lock()
+            .await
+            .expire_idle(self.config.session_idle_timeout);
+        for session in expired {
+            info!(
+                "UDP session {} from {} expired after {:?}",
+                session.session_id,
+                session.peer_addr(),
+                session.created_at.elapsed()
+            );
+            self.end_session(session.session_id).await;
+        }
+        if let Some(detector) = self.detector() {
+            detector.cleanup().await;
+        }
+    }
+
+    /// Hand a freshly established session to `accept()`
+    async fn attach(
+        self: &Arc<Self>,
+        conn_id: ConnectionId,
+    ) -> Option<VstpUdpConnection> {
+        let (tx, rx) = mpsc::channel(CONNECTION_QUEUE_SIZE);
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(conn_id)?;
+        session.tx = Some(tx);
+        Some(VstpUdpConnection::new(self.clone(), rx, session))
+    }
+
+    /// Read datagrams forever, feeding sessions and the accept queue
+    async fn drive(self: Arc<Self>, accept_tx: mpsc::Sender<VstpUdpConnection>) {
+        loop {
+            let routed = match self.recv_routed().await {
+                Ok(routed) => routed,
+                Err(e) => {
+                    debug!("Error receiving frame: {}", e);
+                    continue;
+                }
+            };
+
+            if routed.handshake {
+                let Some(conn) = self.attach(routed.conn_id).await else {
+                    continue;
+                };
+                if accept_tx.try_send(conn).is_err() {
+                    warn!(
+                        "Accept queue full, dropping UDP session {} from {}",
+                        routed.session_id, routed.addr
+                    );
+                    self.sessions.lock().await.remove(routed.conn_id);
+                    self.end_session(routed.session_id).await;
+                }
+            } else if let Some(tx) = routed.tx {
+                if tx.try_send(routed.frame).is_err() {
+                    warn!(
+                        "Connection queue full for session {}, dropping frame",
+                        routed.session_id
+                    );
+                }
+            } else {
+                debug!(
+                    "Dropping frame from {} without an accepted session",
+                    routed.addr
+                );
+            }
+        }
+    }
+
+    /// Send an ACK for a received message
+    async fn send_ack(
+        &self,
+        msg_id: u64,
+        dest: SocketAddr,
+        cipher: Option<&DatagramCipher>,
+    ) -> Result<(), VstpError> {
+        let ack_frame = Frame {
+            version: VSTP_VERSION,
+            typ: FrameType::Ack,
+            flags: Flags::empty(),
+            headers: vec![Header {
+                key: b"msg-id".to_vec(),
+                value: msg_id.to_string().into_bytes(),
+            }],
+            payload: Vec::new(),
+        };
+
+        self.send_session(&ack_frame, dest, cipher).await
+    }
+}
+
+/// WELCOME frame issuing a connection ID
+fn welcome_frame(conn_id: ConnectionId) -> Frame {
+    Frame::new(FrameType::Welcome).with_header(CONN_ID_HEADER, &format_connection_id(conn_id))
+}
+
+/// ERR frame telling a peer its connection ID is not known
+fn unknown_connection_frame(conn_id: ConnectionId) -> Frame {
+    error_frame(conn_id, "unknown connection")
+}
+
+/// ERR frame about a connection, sent in the clear
+fn error_frame(conn_id: ConnectionId, message: &str) -> Frame {
+    Frame::new(FrameType::Err)
+        .with_header(CONN_ID_HEADER, &format_connection_id(conn_id))
+        .with_payload(message.as_bytes().to_vec())
+}
+
+/// Extract message ID from frame headers
+fn extract_msg_id(frame: &Frame) -> Option<u64> {
+    for header in &frame.headers {
+        if header.key == b"msg-id" {
+            if let Ok(msg_id) = std::str::from_utf8(&header.value).ok()?.parse::<u64>() {
+                return Some(msg_id);
+            }
+        }
+    }
+    None
+}
+
+struct Acceptor {
+    rx: mpsc::Receiver<VstpUdpConnection>,
+    driver: JoinHandle<()>,
+}
+
+/// VSTP UDP Server
+pub struct VstpUdpServer {
+    shared: Arc<Shared>,
+    acceptor: Mutex<Option<Acceptor>>,
 }
 
 impl VstpUdpServer {
@@ -64,18 +784,33 @@ impl VstpUdpServer {
     {
         info!("Starting UDP server...");
 
+        if let Some(detector) = &detector {
+            let mut blocklist = self.shared.blocklist.write().unwrap_or_else(|e| e.into_inner());
+            blocklist.get_or_insert_with(|| detector.blocklist());
+            *self.shared.detector.write().unwrap_or_else(|e| e.into_inner()) = Some(detector.clone());
+        }
+
         loop {
-            match self.recv().await {
-                Ok((frame, addr)) => {
+            match self.shared.recv_routed().await {
+                Ok(Routed {
+                    frame,
+                    addr,
+                    session_id,
+                    conn_id,
+                    ..
+                }) => {
+                    // Drop a throttled session's early datagrams here, since
+                    // delaying them in their own task would not slow it down
+                    if let Some(detector) = &detector {
+                        if !detector.throttle_admit(session_id) {
+                            debug!("Dropping datagram from throttled session {}", session_id);
+                            continue;
+                        }
+                    }
+
                     let handler = handler.clone();
                     let detector = detector.clone();
-
-                    // Create a session ID for UDP (based on address)
-                    let session_id = {
-                        let mut hasher = DefaultHasher::new();
-                        addr.hash(&mut hasher);
-                        hasher.finish() as u128
-                    };
+                    let shared = self.shared.clone();
 
                     let frame_size = std::mem::size_of_val(&frame) + frame.payload.len();
 
@@ -94,6 +829,9 @@ impl VstpUdpServer {
                                             addr
                                         );
                                     }
+                                    if !shared.apply_responses(detector, conn_id, session_id).await {
+                                        return;
+                                    }
                                 }
                                 Err(e) => {
                                     tracing::error!("Anomaly detection error from {}: {}", addr, e);
@@ -103,7 +841,11 @@ impl VstpUdpServer {
                         }
 
                         // Process frame with handler
+                        let bye = frame.typ == FrameType::Bye;
                         handler(addr, frame).await;
+                        if bye {
+                            shared.end_session(session_id).await;
+                        }
                     });
                 }
                 Err(e) => {
@@ -119,12 +861,7 @@ impl VstpUdpServer {
         let socket = UdpSocket::bind(addr).await?;
         info!("VSTP UDP server bound to {}", addr);
 
-        Ok(Self {
-            socket,
-            config: UdpServerConfig::default(),
-            reassembly: ReassemblyManager::new(),
-            next_session_id: Arc::new(Mutex::new(1)),
-        })
+        Ok(Self::from_parts(socket, UdpServerConfig::default()))
     }
 
     /// Create a new UDP server with custom configuration
@@ -132,113 +869,89 @@ impl VstpUdpServer {
         let socket = UdpSocket::bind(addr).await?;
         info!("VSTP UDP server bound to {} with custom config", addr);
 
-        Ok(Self {
-            socket,
-            config,
-            reassembly: ReassemblyManager::new(),
-            next_session_id: Arc::new(Mutex::new(1)),
-        })
+        Ok(Self::from_parts(socket, config))
+    }
+
+    fn from_parts(socket: UdpSocket, config: UdpServerConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared::new(socket, config)),
+            acceptor: Mutex::new(None),
+        }
+    }
+
+    /// Accept a new client that completed the HELLO/WELCOME handshake
+    ///
+    /// The first call starts a background task that owns the socket from then
+    /// on, so `recv`/`run` must not be used on the same server afterwards.
+    pub async fn accept(&self) -> Result<VstpUdpConnection, VstpError> {
+        let mut acceptor = self.acceptor.lock().await;
+        let acceptor = acceptor.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel(CONNECTION_QUEUE_SIZE);
+            let driver = tokio::spawn(self.shared.clone().drive(tx));
+            Acceptor { rx, driver }
+        });
+
+        acceptor.rx.recv().await.ok_or(VstpError::ConnectionClosed)
+    }
+
+    /// Drop datagrams from banned addresses before they reach a session
+    ///
+    /// When run with a detector and no blocklist set, the server uses the
+    /// detector's.
+    pub fn with_blocklist(self, blocklist: Blocklist) -> Self {
+        *self.shared.blocklist.write().unwrap_or_else(|e| e.into_inner()) = Some(blocklist);
+        self
     }
 
     /// Get the local address this server is bound to
     pub fn local_addr(&self) -> Result<SocketAddr, VstpError> {
-        self.socket.local_addr().map_err(|e| VstpError::Io(e))
+        self.shared.socket.local_addr().map_err(VstpError::Io)
     }
 
-    /// Send a frame to a specific address
+    /// Send a frame to a specific address, encrypted if `dest` has an
+    /// encrypted session
     pub async fn send(&self, frame: Frame, dest: SocketAddr) -> Result<(), VstpError> {
-        let encoded = encode_frame(&frame)?;
-        self.socket.send_to(&encoded, dest).await?;
-        Ok(())
+        let cipher = {
+            let sessions = self.shared.sessions.lock().await;
+            sessions
+                .lookup_addr(&dest)
+                .and_then(|conn_id| sessions.get(conn_id))
+                .and_then(|session| session.cipher())
+        };
+        self.shared.send_session(&frame, dest, cipher.as_deref()).await
     }
 
     /// Receive a frame from any client
     pub async fn recv(&self) -> Result<(Frame, SocketAddr), VstpError> {
-        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE * 2]; // Extra space for headers
-
-        loop {
-            let (len, from_addr) = self.socket.recv_from(&mut buf).await?;
-            let data = &buf[..len];
-            debug!("Received {} bytes from {}", len, from_addr);
-
-            // Try to decode the frame
-            let mut buf = bytes::BytesMut::from(data);
-            match try_decode_frame(&mut buf, 65536) {
-                Ok(Some(frame)) => {
-                    // Check if this is a fragmented frame
-                    if let Some(fragment) = extract_fragment_info(&frame) {
-                        // Handle fragmentation
-                        if let Some(assembled_data) =
-                            self.reassembly.add_fragment(from_addr, fragment).await?
-                        {
-                            // Reassemble the complete frame
-                            let mut complete_frame = frame;
-                            complete_frame.payload = assembled_data;
-                            // Remove fragment headers
-                            complete_frame.headers.retain(|h| {
-                                h.key != b"frag-id"
-                                    && h.key != b"frag-index"
-                                    && h.key != b"frag-total"
-                            });
-
-                            // Send ACK if requested
-                            if complete_frame.flags.contains(Flags::REQ_ACK) {
-                                if let Some(msg_id) = self.extract_msg_id(&complete_frame) {
-                                    let _ = self.send_ack(msg_id, from_addr).await;
-                                }
-                            }
-
-                            return Ok((complete_frame, from_addr));
-                        }
-                        // Fragment received, continue waiting for more
-                        continue;
-                    } else {
-                        // Send ACK if requested
-                        if frame.flags.contains(Flags::REQ_ACK) {
-                            if let Some(msg_id) = self.extract_msg_id(&frame) {
-                                let _ = self.send_ack(msg_id, from_addr).await;
-                            }
-                        }
+        let routed = self.shared.recv_routed().await?;
+        Ok((routed.frame, routed.addr))
+    }
 
-                        return Ok((frame, from_addr));
-                    }
-                }
-                Ok(None) => continue, // Incomplete frame
-                Err(_) => continue,   // Invalid frame
-            }
-        }
+    /// Get the number of active UDP sessions
+    pub async fn session_count(&self) -> usize {
+        self.shared.sessions.lock().await.len()
     }
 
-    /// Extract message ID from frame headers
-    fn extract_msg_id(&self, frame: &Frame) -> Option<u64> {
-        for header in &frame.headers {
-            if header.key == b"msg-id" {
-                if let Ok(msg_id) = std::str::from_utf8(&header.value).ok()?.parse::<u64>() {
-                    return Some(msg_id);
-                }
-            }
-        }
-        None
+    /// Get the number of active reassembly sessions
+    pub async fn reassembly_session_count(&self) -> usize {
+        self.shared.reassembly.session_count().await
     }
 
-    /// Send an ACK for a received message
-    async fn send_ack(&self, msg_id: u64, dest: SocketAddr) -> Result<(), VstpError> {
-        let ack_frame = Frame {
-            version: VSTP_VERSION,
-            typ: FrameType::Ack,
-            flags: Flags::empty(),
-            headers: vec![Header {
-                key: b"msg-id".to_vec(),
-                value: msg_id.to_string().into_bytes(),
-            }],
-            payload: Vec::new(),
-        };
+    /// Get reassembly counters, including evictions
+    pub fn reassembly_metrics(&self) -> ReassemblyMetrics {
+        self.shared.reassembly.metrics()
+    }
 
-        self.send(ack_frame, dest).await
+    /// Get the server configuration
+    pub fn config(&self) -> &UdpServerConfig {
+        &self.shared.config
     }
+}
 
-    /// Get the number of active reassembly sessions
-    pub async fn reassembly_session_count(&self) -> usize {
-        self.reassembly.session_count().await
+impl Drop for VstpUdpServer {
+    fn drop(&mut self) {
+        if let Some(acceptor) = self.acceptor.get_mut() {
+            acceptor.driver.abort();
+        }
     }
 }
@@ -0,0 +1,451 @@
+//! Connection-oriented sessions for the UDP transport
+//!
+//! A client opens a session by sending HELLO. The server answers with WELCOME
+//! carrying a `conn-id` header, and the client echoes that header on every
+//! subsequent datagram so the server can find the session without relying on
+//! the source address alone.
+//!
+//! When a known connection ID shows up from a new address (NAT rebinding or
+//! a changed IP), the server sends a PING with a `path-challenge` token to the
+//! new address. Only after the client echoes the token in a PONG with a
+//! `path-response` header is the session migrated. Datagrams arriving on the
+//! unvalidated path are dropped in the meantime. On encrypted sessions the
+//! challenge and response are sealed under the session keys, and datagrams
+//! from a new address that do not open are ignored, so knowing the connection
+//! ID is not enough to take over a session.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::core::types::{Frame, FrameType, SessionId, VstpError};
+
+use super::crypto::DatagramCipher;
+use super::server::Shared;
+
+/// Header carrying the connection ID issued in WELCOME
+pub const CONN_ID_HEADER: &str = "conn-id";
+
+/// Header carrying the token of a path validation challenge
+pub const PATH_CHALLENGE_HEADER: &str = "path-challenge";
+
+/// Header echoing a path validation token back to the server
+pub const PATH_RESPONSE_HEADER: &str = "path-response";
+
+/// Identifier issued by the server in WELCOME and echoed by the client
+pub type ConnectionId = u64;
+
+/// Duplicate datagrams a session may send within [`DUPLICATE_WINDOW`]
+/// before they are reported as a replay; networks duplicate datagrams now
+/// and then
+pub const DUPLICATE_ALLOWANCE: u32 = 8;
+
+/// Span over which a session's duplicate datagrams are counted
+pub const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Capacity of the per-connection queue used in `accept()` mode
+pub(crate) const CONNECTION_QUEUE_SIZE: usize = 128;
+
+/// Format a connection ID for the `conn-id` header
+pub fn format_connection_id(conn_id: ConnectionId) -> String {
+    format!("{:016x}", conn_id)
+}
+
+/// Parse the `conn-id` header of a frame, if present and well-formed
+pub fn extract_connection_id(frame: &Frame) -> Option<ConnectionId> {
+    frame
+        .get_header(CONN_ID_HEADER)
+        .and_then(|v| ConnectionId::from_str_radix(v, 16).ok())
+}
+
+/// PING frame asking the peer at a new address to prove it owns the session
+pub fn path_challenge_frame(conn_id: ConnectionId, token: u64) -> Frame {
+    Frame::new(FrameType::Ping)
+        .with_header(CONN_ID_HEADER, &format_connection_id(conn_id))
+        .with_header(PATH_CHALLENGE_HEADER, &format!("{:016x}", token))
+}
+
+/// PONG answering a path challenge, or `None` if `frame` is not a challenge
+pub fn path_response_frame(frame: &Frame) -> Option<Frame> {
+    if frame.typ != FrameType::Ping {
+        return None;
+    }
+    let token = frame.get_header(PATH_CHALLENGE_HEADER)?;
+    let conn_id = frame.get_header(CONN_ID_HEADER)?;
+    Some(
+        Frame::new(FrameType::Pong)
+            .with_header(CONN_ID_HEADER, conn_id)
+            .with_header(PATH_RESPONSE_HEADER, token),
+    )
+}
+
+fn extract_path_response(frame: &Frame) -> Option<u64> {
+    if frame.typ != FrameType::Pong {
+        return None;
+    }
+    frame
+        .get_header(PATH_RESPONSE_HEADER)
+        .and_then(|v| u64::from_str_radix(v, 16).ok())
+}
+
+/// An outstanding path validation for a session
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PathChallenge {
+    pub addr: SocketAddr,
+    pub token: u64,
+    pub sent_at: Instant,
+}
+
+/// What to do with a datagram that arrived on a path other than the session's
+pub(crate) enum PathEvent {
+    /// Send this challenge to the new address
+    Challenge(Frame),
+    /// The new address proved ownership; the session should move there
+    Validated,
+    /// Nothing to do, e.g. a challenge is already in flight
+    Pending,
+}
+
+/// Remember recently delivered message IDs so retransmissions are not
+/// handed to the application twice
+#[derive(Debug)]
+pub struct ReliabilityWindow {
+    seen: HashSet<u64>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl ReliabilityWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Whether a message ID is still in the window
+    pub fn contains(&self, msg_id: u64) -> bool {
+        self.seen.contains(&msg_id)
+    }
+
+    /// Record a message ID, returning `false` if it was already in the window
+    pub fn insert(&mut self, msg_id: u64) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.seen.insert(msg_id) {
+            return false;
+        }
+        self.order.push_back(msg_id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Server-side state for one UDP session
+#[derive(Debug)]
+pub(crate) struct UdpSession {
+    pub session_id: SessionId,
+    pub conn_id: ConnectionId,
+    pub peer_addr: Arc<RwLock<SocketAddr>>,
+    /// Whether the peer completed the HELLO/WELCOME handshake
+    pub established: bool,
+    pub created_at: Instant,
+    pub last_seen: Instant,
+    pub window: ReliabilityWindow,
+    /// Queue feeding the `VstpUdpConnection` in `accept()` mode
+    pub tx: Option<mpsc::Sender<Frame>>,
+    /// Path validation in progress for a new peer address
+    pub pending_path: Option<PathChallenge>,
+    /// Number of times the session moved to a new address
+    pub migrations: u32,
+    /// Keys negotiated in the HELLO/WELCOME handshake, if encrypted
+    pub cipher: SharedCipher,
+    /// Start of the current duplicate counting window and the duplicates
+    /// seen in it
+    duplicates: Option<(Instant, u32)>,
+}
+
+/// Session keys shared with the connection handed out by `accept()`, which
+/// must pick up keys if a cleartext session's client runs the handshake
+pub(crate) type SharedCipher = Arc<RwLock<Option<Arc<DatagramCipher>>>>;
+
+impl UdpSession {
+    pub fn new(
+        session_id: SessionId,
+        conn_id: ConnectionId,
+        peer_addr: SocketAddr,
+        window: usize,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            session_id,
+            conn_id,
+            peer_addr: Arc::new(RwLock::new(peer_addr)),
+            established: false,
+            created_at: now,
+            last_seen: now,
+            window: ReliabilityWindow::new(window),
+            tx: None,
+            pending_path: None,
+            migrations: 0,
+            cipher: Arc::new(RwLock::new(None)),
+            duplicates: None,
+        }
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        *self.peer_addr.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn cipher(&self) -> Option<Arc<DatagramCipher>> {
+        self.cipher.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_cipher(&self, cipher: Option<DatagramCipher>) {
+        *self.cipher.write().unwrap_or_else(|e| e.into_inner()) = cipher.map(Arc::new);
+    }
+
+    /// Count a duplicate datagram, returning whether the session has gone
+    /// past [`DUPLICATE_ALLOWANCE`] within [`DUPLICATE_WINDOW`]
+    pub fn note_duplicate(&mut self) -> bool {
+        let now = Instant::now();
+        let (start, count) = self
+            .duplicates
+            .filter(|(start, _)| now.duration_since(*start) <= DUPLICATE_WINDOW)
+            .unwrap_or((now, 0));
+        let count = count.saturating_add(1);
+        self.duplicates = Some((start, count));
+        count > DUPLICATE_ALLOWANCE
+    }
+
+    pub fn is_idle(&self, timeout: Duration) -> bool {
+        self.last_seen.elapsed() > timeout
+    }
+
+    /// Handle a datagram for this session that arrived from `addr`, which is
+    /// not the session's current address
+    pub fn on_new_path(&mut self, frame: &Frame, addr: SocketAddr, timeout: Duration) -> PathEvent {
+        let pending = self
+            .pending_path
+            .filter(|p| p.addr == addr && p.sent_at.elapsed() <= timeout);
+
+        if let (Some(pending), Some(token)) = (pending, extract_path_response(frame)) {
+            if pending.token == token {
+                self.pending_path = None;
+                return PathEvent::Validated;
+            }
+        }
+
+        if pending.is_some() {
+            return PathEvent::Pending;
+        }
+
+        let token = rand::random::<u64>();
+        self.pending_path = Some(PathChallenge {
+            addr,
+            token,
+            sent_at: Instant::now(),
+        });
+        PathEvent::Challenge(path_challenge_frame(self.conn_id, token))
+    }
+}
+
+/// All sessions known to a server, indexed by connection ID and address
+#[derive(Debug, Default)]
+pub(crate) struct UdpSessionTable {
+    by_conn: HashMap<ConnectionId, UdpSession>,
+    by_addr: HashMap<SocketAddr, ConnectionId>,
+}
+
+impl UdpSessionTable {
+    pub fn len(&self) -> usize {
+        self.by_conn.len()
+    }
+
+    pub fn contains(&self, conn_id: ConnectionId) -> bool {
+        self.by_conn.contains_key(&conn_id)
+    }
+
+    pub fn insert(&mut self, session: UdpSession) {
+        self.by_addr.insert(session.peer_addr(), session.conn_id);
+        self.by_conn.insert(session.conn_id, session);
+    }
+
+    pub fn get(&self, conn_id: ConnectionId) -> Option<&UdpSession> {
+        self.by_conn.get(&conn_id)
+    }
+
+    pub fn get_mut(&mut self, conn_id: ConnectionId) -> Option<&mut UdpSession> {
+        self.by_conn.get_mut(&conn_id)
+    }
+
+    pub fn lookup_addr(&self, addr: &SocketAddr) -> Option<ConnectionId> {
+        self.by_addr.get(addr).copied()
+    }
+
+    /// Move a session to a new peer address
+    pub fn migrate(&mut self, conn_id: ConnectionId, new_addr: SocketAddr) -> Option<SocketAddr> {
+        let session = self.by_conn.get_mut(&conn_id)?;
+        let old_addr = session.peer_addr();
+        *session.peer_addr.write().unwrap_or_else(|e| e.into_inner()) = new_addr;
+        session.migrations += 1;
+
+        if self.by_addr.get(&old_addr) == Some(&conn_id) {
+            self.by_addr.remove(&old_addr);
+        }
+        self.by_addr.insert(new_addr, conn_id);
+        Some(old_addr)
+    }
+
+    pub fn remove(&mut self, conn_id: ConnectionId) -> Option<UdpSession> {
+        let session = self.by_conn.remove(&conn_id)?;
+        let addr = session.peer_addr();
+        if self.by_addr.get(&addr) == Some(&conn_id) {
+            self.by_addr.remove(&addr);
+        }
+        Some(session)
+    }
+
+    /// Remove sessions that have been idle longer than `timeout`
+    pub fn expire_idle(&mut self, timeout: Duration) -> Vec<UdpSession> {
+        let idle: Vec<_> = self
+            .by_conn
+            .values()
+            .filter(|s| s.is_idle(timeout))
+            .map(|s| s.conn_id)
+            .collect();
+        idle.into_iter().filter_map(|id| self.remove(id)).collect()
+    }
+}
+
+/// A server-side UDP connection returned by `VstpUdpServer::accept`
+pub struct VstpUdpConnection {
+    shared: Arc<Shared>,
+    rx: mpsc::Receiver<Frame>,
+    session_id: SessionId,
+    conn_id: ConnectionId,
+    peer_addr: Arc<RwLock<SocketAddr>>,
+    cipher: SharedCipher,
+}
+
+impl VstpUdpConnection {
+    pub(crate) fn new(
+        shared: Arc<Shared>,
+        rx: mpsc::Receiver<Frame>,
+        session: &UdpSession,
+    ) -> Self {
+        Self {
+            shared,
+            rx,
+            session_id: session.session_id,
+            conn_id: session.conn_id,
+            peer_addr: session.peer_addr.clone(),
+            cipher: session.cipher.clone(),
+        }
+    }
+
+    /// Send a frame to the peer, encrypted if the session is
+    pub async fn send(&self, frame: Frame) -> Result<(), VstpError> {
+        let cipher = self.cipher.read().unwrap_or_else(|e| e.into_inner()).clone();
+        self.shared
+            .send_session(&frame, self.peer_addr(), cipher.as_deref())
+            .await
+    }
+
+    /// Receive the next frame from the peer, or `None` once the session ends
+    pub async fn recv(&mut self) -> Result<Option<Frame>, VstpError> {
+        Ok(self.rx.recv().await)
+    }
+
+    /// Get the peer address
+    pub fn peer_addr(&self) -> SocketAddr {
+        *self.peer_addr.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get the session ID
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Get the connection ID issued in WELCOME
+    pub fn connection_id(&self) -> ConnectionId {
+        self.conn_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reliability_window_rejects_duplicates() {
+        let mut window = ReliabilityWindow::new(2);
+        assert!(window.insert(1));
+        assert!(!window.insert(1));
+        assert!(window.insert(2));
+        assert!(window.insert(3));
+        // 1 has slid out of the window
+        assert!(window.insert(1));
+    }
+
+    #[test]
+    fn test_connection_id_header_roundtrip() {
+        let frame = Frame::new(crate::core::types::FrameType::Data)
+            .with_header(CONN_ID_HEADER, &format_connection_id(0xdead_beef));
+        assert_eq!(extract_connection_id(&frame), Some(0xdead_beef));
+    }
+
+    #[test]
+    fn test_path_validation_and_migration() {
+        let old_addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let new_addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let mut table = UdpSessionTable::default();
+        table.insert(UdpSession::new(1, 42, old_addr, 8));
+
+        let data = Frame::new(FrameType::Data);
+        let session = table.get_mut(42).unwrap();
+        let PathEvent::Challenge(challenge) = session.on_new_path(&data, new_addr, Duration::from_secs(5)) else {
+            panic!("expected a path challenge");
+        };
+        assert!(matches!(
+            session.on_new_path(&data, new_addr, Duration::from_secs(5)),
+            PathEvent::Pending
+        ));
+
+        let forged = Frame::new(FrameType::Pong).with_header(PATH_RESPONSE_HEADER, "00");
+        assert!(matches!(
+            session.on_new_path(&forged, new_addr, Duration::from_secs(5)),
+            PathEvent::Pending
+        ));
+
+        let response = path_response_frame(&challenge).unwrap();
+        assert!(matches!(
+            session.on_new_path(&response, new_addr, Duration::from_secs(5)),
+            PathEvent::Validated
+        ));
+
+        assert_eq!(table.migrate(42, new_addr), Some(old_addr));
+        assert_eq!(table.lookup_addr(&new_addr), Some(42));
+        assert_eq!(table.lookup_addr(&old_addr), None);
+    }
+
+    #[test]
+    fn test_expire_idle_sessions() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let mut table = UdpSessionTable::default();
+        table.insert(UdpSession::new(1, 42, addr, 8));
+
+        assert!(table.expire_idle(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(table.expire_idle(Duration::from_millis(1)).len(), 1);
+        assert_eq!(table.lookup_addr(&addr), None);
+    }
+}
@@ -57,8 +57,8 @@ mod tests {
         let pool = Pool::new(2);
 
         // Get items
-        let item1: Vec<i32> = pool.get(|| Vec::new()).await;
-        let item2: Vec<i32> = pool.get(|| Vec::new()).await;
+        let item1: Vec<i32> = pool.get(Vec::new).await;
+        let item2: Vec<i32> = pool.get(Vec::new).await;
 
         // Return items
         pool.put(item1).await;
@@ -27,11 +27,11 @@ async fn test_ai_detector_initialization() {
     let detector = AnomalyDetector::new(config);
     println!("✓ AI Detector created successfully");
     
-    let default_detector = AnomalyDetector::default();
+    let _default_detector = AnomalyDetector::default();
     println!("✓ Default detector created successfully");
     
     // Test that detector is enabled
-    assert!(true, "Detector initialized");
+    assert!(detector.get_threat_history(10).await.is_empty(), "Detector initialized");
     println!("✓ AI Detector initialization test passed!\n");
 }
 
@@ -496,3 +496,484 @@ async fn test_ai_detection_both_protocols() {
     println!("✓ AI detection with both protocols test passed!\n");
 }
 
+
+/// Test that bans and threat history survive a restart with a JSONL store
+#[tokio::test]
+async fn test_threat_store_survives_restart() {
+    use vstp::security::ai::{JsonlThreatStore, ThreatStore};
+
+    let path = std::env::temp_dir().join(format!("vstp-detector-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let peer: std::net::SocketAddr = "192.0.2.7:4000".parse().unwrap();
+
+    {
+        let store = Arc::new(JsonlThreatStore::open(&path, 100).await.unwrap());
+        let detector = AnomalyDetector::default().with_store(store);
+        detector
+            .analyze_frame(1, peer, &Frame::new(FrameType::Hello), 32)
+            .await
+            .unwrap();
+        detector.report_unauthorized(1, "probe").await;
+        detector.block_session(1).await;
+        assert!(detector.is_peer_blocked(peer.ip()));
+    }
+
+    let store: Arc<dyn ThreatStore> = Arc::new(JsonlThreatStore::open(&path, 100).await.unwrap());
+    let detector = AnomalyDetector::default().with_store(store);
+    assert!(!detector.is_peer_blocked(peer.ip()));
+    assert_eq!(detector.restore().await.unwrap(), 1);
+    assert!(detector.is_peer_blocked(peer.ip()));
+    let history = detector.get_threat_history(10).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].pattern, AttackPattern::UnauthorizedAccess);
+    assert_eq!(detector.get_session_threats(1).await.len(), 1);
+
+    // Lifting a ban is persisted too
+    detector.unban(peer.ip().into()).await.unwrap();
+    let store = Arc::new(JsonlThreatStore::open(&path, 100).await.unwrap());
+    let detector = AnomalyDetector::default().with_store(store);
+    assert_eq!(detector.restore().await.unwrap(), 0);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Test that bans spread between gossiping nodes
+#[tokio::test]
+async fn test_ban_gossip_between_nodes() {
+    use vstp::security::ai::{GossipConfig, GossipThreatStore, MemoryThreatStore, ThreatStore};
+    use vstp::security::blocklist::BanRecord;
+    use vstp::security::{Blocklist, FrameKeyring};
+
+    let keyring = FrameKeyring::new("cluster-1", b"cluster secret".to_vec());
+    let start = |blocklist: Blocklist, keyring: FrameKeyring| async move {
+        Arc::new(
+            GossipThreatStore::start(
+                Arc::new(MemoryThreatStore::default()),
+                blocklist,
+                GossipConfig::new("127.0.0.1:0", keyring),
+            )
+            .await
+            .unwrap(),
+        )
+    };
+
+    let blocklist_a = Blocklist::default();
+    let blocklist_b = Blocklist::default();
+    let node_a = start(blocklist_a.clone(), keyring.clone()).await;
+    let node_b = start(blocklist_b.clone(), keyring.clone()).await;
+    node_a.add_peer(node_b.local_addr());
+    node_b.add_peer(node_a.local_addr());
+
+    let detector = AnomalyDetector::default()
+        .with_blocklist(blocklist_a)
+        .with_store(node_a.clone());
+    let peer: std::net::SocketAddr = "198.51.100.20:5000".parse().unwrap();
+    detector
+        .analyze_frame(1, peer, &Frame::new(FrameType::Hello), 32)
+        .await
+        .unwrap();
+    detector.block_session(1).await;
+
+    let wait_for = |expected: bool| {
+        let blocklist = blocklist_b.clone();
+        async move {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while blocklist.is_blocked(peer.ip()) != expected {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("gossip did not arrive");
+        }
+    };
+    wait_for(true).await;
+    assert_eq!(node_b.active_bans().await.unwrap().len(), 1);
+
+    detector.unban(peer.ip().into()).await.unwrap();
+    wait_for(false).await;
+
+    // A node without the cluster key cannot inject bans
+    let rogue = start(
+        Blocklist::default(),
+        FrameKeyring::new("cluster-1", b"guessed secret".to_vec()),
+    )
+    .await;
+    rogue.add_peer(node_b.local_addr());
+    let victim = "203.0.113.9".parse::<std::net::IpAddr>().unwrap();
+    rogue
+        .record_ban(&BanRecord {
+            net: victim.into(),
+            expires_at: None,
+            reason: "forged".to_string(),
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!blocklist_b.is_blocked(victim));
+}
+
+/// Test that the detector keeps baselines per authenticated principal
+#[tokio::test]
+async fn test_principal_segmented_baselines() {
+    use vstp::security::ai::Segmentation;
+
+    let detector = AnomalyDetector::default().with_segmentation(Segmentation::Principal);
+    let peer: std::net::SocketAddr = "192.0.2.10:4000".parse().unwrap();
+    detector.set_principal(1, "uploader").await;
+    detector.set_principal(2, "chatter").await;
+
+    for session_id in 1..=3 {
+        detector
+            .analyze_frame(session_id, peer, &Frame::new(FrameType::Data), 128)
+            .await
+            .unwrap();
+    }
+
+    // Session 3 has no principal and only feeds the global baseline
+    let mut segments = detector.baseline_segments().await;
+    segments.sort();
+    assert_eq!(segments, vec!["chatter", "uploader"]);
+}
+
+#[tokio::test]
+async fn test_isolation_forest_in_ensemble() {
+    use vstp::security::ai::forest::IsolationForestConfig;
+    use vstp::security::ai::half_space::HalfSpaceTreesConfig;
+    use vstp::security::ai::{HalfSpaceTrees, IsolationForest};
+
+    let forest = IsolationForest::with_seed(
+        IsolationForestConfig {
+            trees: 50,
+            sample_size: 64,
+            window: 128,
+            sample_interval: Duration::ZERO,
+            ..Default::default()
+        },
+        1,
+    );
+    let half_space = HalfSpaceTrees::with_seed(
+        HalfSpaceTreesConfig {
+            window: 64,
+            sample_interval: Duration::ZERO,
+            ..Default::default()
+        },
+        2,
+    );
+    let detector = AnomalyDetector::default()
+        .with_model(forest, 1.0)
+        .with_model(half_space, 0.0)
+        .with_model_weight("rules", 0.0);
+    assert_eq!(
+        detector.model_weights().await,
+        vec![
+            ("rules".to_string(), 0.0),
+            ("isolation_forest".to_string(), 1.0),
+            ("half_space_trees".to_string(), 0.0),
+        ]
+    );
+
+    let peer: std::net::SocketAddr = "192.0.2.20:4000".parse().unwrap();
+    for i in 0..128u32 {
+        let session_id = (i % 8) as u128 + 1;
+        let size = 120 + (i % 5) as usize * 4;
+        detector
+            .analyze_frame(session_id, peer, &Frame::new(FrameType::Data), size)
+            .await
+            .unwrap();
+    }
+
+    let threats = detector
+        .analyze_frame(100, peer, &Frame::new(FrameType::Data), 60_000)
+        .await
+        .unwrap();
+    let outlier = threats
+        .iter()
+        .find(|t| t.pattern == AttackPattern::AnomalousTraffic)
+        .expect("the forest should flag a 60 KB frame among 128-byte ones");
+    assert!(outlier
+        .indicators
+        .iter()
+        .any(|i| i.starts_with("Model: isolation_forest")));
+    assert!(!outlier
+        .indicators
+        .iter()
+        .any(|i| i.starts_with("Model: half_space_trees")));
+}
+
+/// Test exporting connections and per-frame logs as labelled training data
+#[tokio::test]
+async fn test_training_data_export() {
+    use vstp::security::ai::{ExportFormat, ExportOptions, FrameLog};
+
+    let dir = std::env::temp_dir();
+    let connections = dir.join(format!("vstp-export-{}.csv", std::process::id()));
+    let frames = dir.join(format!("vstp-frames-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&frames);
+
+    let log = FrameLog::open(&frames, ExportOptions::default().with_format(ExportFormat::JsonLines))
+        .await
+        .unwrap();
+    let detector = AnomalyDetector::default().with_frame_log(log);
+    let peer: std::net::SocketAddr = "192.0.2.30:4000".parse().unwrap();
+    for session_id in 1..=2 {
+        detector
+            .analyze_frame(session_id, peer, &Frame::new(FrameType::Hello), 32)
+            .await
+            .unwrap();
+        detector
+            .analyze_frame(session_id, peer, &Frame::new(FrameType::Data).with_payload(b"hi".to_vec()), 40)
+            .await
+            .unwrap();
+    }
+    detector.report_unauthorized(2, "probe").await;
+    assert_eq!(detector.get_all_connections().await.len(), 2);
+
+    let written = detector
+        .export_connections(&connections, ExportOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(written, 2);
+    let csv = std::fs::read_to_string(&connections).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("timestamp,session_id,frame_count,"));
+    assert!(lines[0].ends_with(",is_anomaly,threat_type"));
+    assert!(lines[1].ends_with(",0,normal"));
+    assert!(lines[2].ends_with(",1,unauthorized_access"));
+
+    detector.end_session(1).await;
+    detector.flush_frame_log().await.unwrap();
+    let rows: Vec<serde_json::Value> = std::fs::read_to_string(&frames)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 4);
+    let first = rows.iter().filter(|row| row["session_id"] == 1).collect::<Vec<_>>();
+    assert_eq!(first[0]["sequence_index"], 0);
+    assert_eq!(first[1]["sequence_index"], 1);
+    assert_eq!(first[1]["frame_type"], FrameType::Data as u8);
+    assert_eq!(first[1]["cumulative_byte_count"], 72);
+    assert_eq!(first[1]["threat_type"], "normal");
+
+    let _ = std::fs::remove_file(&connections);
+    let _ = std::fs::remove_file(&frames);
+}
+
+/// Test the learning, shadow and enforcing modes and baseline persistence
+#[tokio::test]
+async fn test_detection_modes_and_baseline_persistence() {
+    use vstp::security::ai::DetectionMode;
+    use vstp::security::replay::ReplayRejection;
+
+    let config = DetectorConfig {
+        enabled: true,
+        min_confidence: 0.5,
+        auto_block_critical: true,
+        learning_mode: true,
+        min_samples: 5,
+    };
+    let detector = AnomalyDetector::new(config.clone());
+    assert_eq!(detector.mode(), DetectionMode::Learning);
+
+    // Learning collects samples but reports and blocks nothing
+    let peer: std::net::SocketAddr = "192.0.2.40:4000".parse().unwrap();
+    for session_id in 1..=5 {
+        let threats = detector
+            .analyze_frame(session_id, peer, &Frame::new(FrameType::Data), 128)
+            .await
+            .unwrap();
+        assert!(threats.is_empty());
+    }
+    assert!(detector.baseline_ready().await);
+    let rejection = ReplayRejection::Duplicate(7);
+    assert!(detector.report_replay(1, &rejection).await.is_none());
+    assert!(detector.get_threat_history(10).await.is_empty());
+
+    // Shadow reports and stores threats without acting on them
+    detector.set_mode(DetectionMode::Shadow);
+    let threat = detector.report_replay(1, &rejection).await.unwrap();
+    assert_eq!(threat.threat_level, vstp::security::ai::ThreatLevel::Critical);
+    assert_eq!(detector.get_threat_history(10).await.len(), 1);
+    assert!(!detector.is_blocked(1).await);
+
+    detector.set_mode(DetectionMode::Enforcing);
+    detector.report_replay(1, &rejection).await.unwrap();
+    assert!(detector.is_blocked(1).await);
+
+    // A saved baseline lets a new detector score from the start
+    let path = std::env::temp_dir().join(format!("vstp-baseline-{}.json", std::process::id()));
+    detector.save_baseline(&path).await.unwrap();
+    let restarted = AnomalyDetector::new(config);
+    assert!(!restarted.baseline_ready().await);
+    assert!(restarted.load_baseline(&path).await.unwrap());
+    assert!(restarted.baseline_ready().await);
+    let _ = std::fs::remove_file(&path);
+    assert!(!restarted.load_baseline(&path).await.unwrap());
+}
+
+/// Test that both servers count frames that fail to decode against the peer
+#[tokio::test]
+async fn test_decode_errors_reach_detector() {
+    use tokio::io::AsyncWriteExt;
+    use vstp::core::frame::encode_frame;
+
+    let config = DetectorConfig {
+        enabled: true,
+        min_confidence: 0.5,
+        auto_block_critical: false,
+        learning_mode: false,
+        min_samples: 0,
+    };
+
+    // UDP: garbage and corrupted datagrams from a peer without a session
+    let udp = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let udp_addr = udp.local_addr().unwrap();
+    let detector = Arc::new(AnomalyDetector::new(config.clone()));
+    let udp_detector = detector.clone();
+    let udp_task = tokio::spawn(async move {
+        let _ = udp
+            .run_with_detector(|_addr, _frame: Frame| async {}, Some(udp_detector))
+            .await;
+    });
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut corrupted = encode_frame(&Frame::new(FrameType::Ping)).unwrap().to_vec();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xff;
+    socket.send_to(b"definitely not a vstp frame", udp_addr).await.unwrap();
+    socket.send_to(&corrupted, udp_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Errors without a session are kept apart from sessions, by address
+    assert!(detector.get_all_connections().await.is_empty());
+    let connections = detector.get_sessionless_stats().await;
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].protocol_errors, 1);
+    assert_eq!(connections[0].crc_errors, 1);
+    assert!(detector
+        .get_threat_history(10)
+        .await
+        .iter()
+        .any(|t| t.pattern == AttackPattern::ManInTheMiddle));
+    udp_task.abort();
+
+    // TCP: a session that sends a frame with a bad CRC
+    let tcp = VstpTcpServer::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+    let detector = Arc::new(AnomalyDetector::new(config));
+    let tcp_detector = detector.clone();
+    let tcp_task = tokio::spawn(async move {
+        let _ = tcp
+            .run_with_detector(|_id: SessionId, _frame: Frame| async {}, Some(tcp_detector))
+            .await;
+    });
+    let mut stream = tokio::net::TcpStream::connect(tcp_addr).await.unwrap();
+    stream
+        .write_all(&encode_frame(&Frame::new(FrameType::Hello)).unwrap())
+        .await
+        .unwrap();
+    stream.write_all(&corrupted).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let connections = detector.get_all_connections().await;
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].frame_count, 1);
+    assert_eq!(connections[0].crc_errors, 1);
+    tcp_task.abort();
+}
+
+/// Test that the response policy escalates per pattern and only acts when
+/// enforcing
+#[tokio::test]
+async fn test_response_policy_actions() {
+    use vstp::security::ai::{
+        DetectionMode, ResponseAction, ResponsePolicy, ResponseRule, ThreatLevel,
+    };
+    use vstp::security::replay::ReplayRejection;
+
+    let policy = ResponsePolicy::new()
+        .with_rule(
+            AttackPattern::UnauthorizedAccess,
+            ResponseRule::new(ThreatLevel::High, ResponseAction::Warn),
+        )
+        .with_rule(
+            AttackPattern::ReplayAttack,
+            ResponseRule::new(
+                ThreatLevel::Critical,
+                ResponseAction::Ban(Some(Duration::from_secs(60))),
+            ),
+        );
+    let detector = AnomalyDetector::default()
+        .with_mode(DetectionMode::Shadow)
+        .with_response_policy(policy);
+    let peer: std::net::SocketAddr = "192.0.2.50:4000".parse().unwrap();
+    detector
+        .analyze_frame(1, peer, &Frame::new(FrameType::Data), 128)
+        .await
+        .unwrap();
+
+    // Shadow mode only logs what it would do
+    let rejection = ReplayRejection::Duplicate(3);
+    detector.report_replay(1, &rejection).await.unwrap();
+    assert!(detector.take_responses(1).is_empty());
+    assert!(!detector.is_peer_blocked(peer.ip()));
+
+    detector.set_mode(DetectionMode::Enforcing);
+    detector.report_unauthorized(1, "no role grants access").await.unwrap();
+    assert_eq!(detector.take_responses(1), vec![ResponseAction::Warn]);
+    // The rule is cooling down for this session
+    detector.report_unauthorized(1, "no role grants access").await.unwrap();
+    assert!(detector.take_responses(1).is_empty());
+
+    // Shadow mode put the replay rule on cooldown too, so a new session
+    // is needed to see the ban
+    detector
+        .analyze_frame(2, peer, &Frame::new(FrameType::Data), 128)
+        .await
+        .unwrap();
+    detector.report_replay(2, &rejection).await.unwrap();
+    assert_eq!(
+        detector.take_responses(2),
+        vec![ResponseAction::Ban(Some(Duration::from_secs(60)))]
+    );
+    assert!(detector.is_blocked(2).await);
+    assert!(detector.is_peer_blocked(peer.ip()));
+}
+
+/// Test that recorded threats reach subscribers and forwarded sinks
+#[tokio::test]
+async fn test_threat_subscription_and_sinks() {
+    use vstp::security::ai::JsonlSink;
+    use vstp::security::replay::ReplayRejection;
+
+    let detector = AnomalyDetector::default();
+    let mut events = detector.subscribe();
+    let path = std::env::temp_dir().join(format!("vstp-threat-sink-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sink = JsonlSink::open(&path).await.unwrap();
+    let _forwarder = detector.forward_to(Arc::new(sink));
+
+    detector
+        .report_replay(9, &ReplayRejection::Duplicate(1))
+        .await
+        .unwrap();
+    let threat = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(threat.pattern, AttackPattern::ReplayAttack);
+    assert_eq!(threat.session_id, Some(9));
+
+    let mut lines = Vec::new();
+    for _ in 0..50 {
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        lines = contents.lines().map(str::to_string).collect();
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"ReplayAttack\""));
+    let _ = std::fs::remove_file(&path);
+}
@@ -94,12 +94,9 @@ async fn test_tcp_bidirectional_communication() {
     let server_handle = tokio::spawn(async move {
         server
             .run(|_session_id: SessionId, frame: Frame| async move {
-                match frame.typ {
-                    FrameType::Data => {
-                        // Echo the data back
-                        println!("TCP Server: Echoing data back to client");
-                    }
-                    _ => {}
+                if frame.typ == FrameType::Data {
+                    // Echo the data back
+                    println!("TCP Server: Echoing data back to client");
                 }
             })
             .await
@@ -363,11 +360,11 @@ async fn test_tcp_multiple_clients() {
     for i in 0..5 {
         let mut client = VstpTcpClient::connect(&format!("127.0.0.1:{}", server_addr.port()))
             .await
-            .expect(&format!("Failed to connect client {}", i));
+            .unwrap_or_else(|_| panic!("Failed to connect client {}", i));
         
-        client.send_hello().await.expect(&format!("Failed to send HELLO from client {}", i));
+        client.send_hello().await.unwrap_or_else(|_| panic!("Failed to send HELLO from client {}", i));
         let payload = format!("Message from client {}", i).as_bytes().to_vec();
-        client.send_data(payload).await.expect(&format!("Failed to send DATA from client {}", i));
+        client.send_data(payload).await.unwrap_or_else(|_| panic!("Failed to send DATA from client {}", i));
         
         clients.push(client);
         println!("Client {} connected and sent data", i);
@@ -377,7 +374,7 @@ async fn test_tcp_multiple_clients() {
 
     // Close all clients
     for (i, client) in clients.iter_mut().enumerate() {
-        client.close().await.expect(&format!("Failed to close client {}", i));
+        client.close().await.unwrap_or_else(|_| panic!("Failed to close client {}", i));
     }
 
     tokio::time::sleep(Duration::from_millis(200)).await;
@@ -409,16 +406,16 @@ async fn test_udp_multiple_clients() {
     let mut clients = Vec::new();
     for i in 0..5 {
         let client = VstpUdpClient::bind("127.0.0.1:0").await
-            .expect(&format!("Failed to bind UDP client {}", i));
+            .unwrap_or_else(|_| panic!("Failed to bind UDP client {}", i));
         
         let hello = Frame::new(FrameType::Hello);
         client.send(hello, server_addr).await
-            .expect(&format!("Failed to send HELLO from client {}", i));
+            .unwrap_or_else(|_| panic!("Failed to send HELLO from client {}", i));
         
         let message = format!("Message from UDP client {}", i);
         let data = Frame::new(FrameType::Data).with_payload(message.as_bytes().to_vec());
         client.send(data, server_addr).await
-            .expect(&format!("Failed to send DATA from client {}", i));
+            .unwrap_or_else(|_| panic!("Failed to send DATA from client {}", i));
         
         clients.push(client);
         println!("UDP Client {} sent data", i);
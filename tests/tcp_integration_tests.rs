@@ -118,3 +118,578 @@ async fn test_tcp_multiple_clients() {
 
     println!("Multiple clients test completed successfully!");
 }
+
+#[tokio::test]
+async fn test_tcp_noise_channel() {
+    use vstp::security::{NoiseConfig, NoiseKeypair, NoisePattern};
+
+    let server_keys = NoiseKeypair::generate().unwrap();
+    let client_keys = NoiseKeypair::generate().unwrap();
+    let server_config = NoiseConfig::new(server_keys.clone()).pin_peer_key(client_keys.public);
+
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_noise(server_config);
+    let addr = server.local_addr().unwrap().to_string();
+
+    let expected_client = client_keys.public;
+    let server_handle = tokio::spawn(async move {
+        for _ in 0..2 {
+            let mut conn = server.accept().await.unwrap();
+            assert_eq!(conn.peer_public_key(), Some(expected_client));
+            let frame = conn.recv().await.unwrap().unwrap();
+            conn.send(Frame::new(FrameType::Data).with_payload(frame.payload))
+                .await
+                .unwrap();
+        }
+        // A client with an unpinned key is refused and never handed out
+        assert!(timeout(Duration::from_secs(2), server.accept()).await.is_err());
+    });
+
+    let large_payload = vec![0x5a; 150_000];
+    for pattern in [NoisePattern::XX, NoisePattern::IK] {
+        let config = NoiseConfig::new(client_keys.clone())
+            .with_pattern(pattern)
+            .with_remote_key(server_keys.public);
+        let mut client = VstpTcpClient::connect_with_noise(&addr, config).await.unwrap();
+        assert_eq!(client.peer_public_key(), Some(server_keys.public));
+
+        client.send_data(large_payload.clone()).await.unwrap();
+        let echo = timeout(Duration::from_secs(5), client.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(echo.payload, large_payload);
+    }
+
+    // With XX the server checks the client key after the last handshake message
+    let stranger = NoiseConfig::new(NoiseKeypair::generate().unwrap());
+    let mut client = VstpTcpClient::connect_with_noise(&addr, stranger).await.unwrap();
+    assert!(timeout(Duration::from_secs(5), client.recv()).await.unwrap().is_err());
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_accept_skips_slow_and_failed_handshakes() {
+    use vstp::security::{NoiseConfig, NoiseKeypair};
+
+    let server_keys = NoiseKeypair::generate().unwrap();
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_noise(NoiseConfig::new(server_keys.clone()));
+    let addr = server.local_addr().unwrap().to_string();
+
+    // One client connects and never starts the handshake, another sends
+    // garbage instead of a Noise HELLO
+    let _silent = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut plain = VstpTcpClient::connect(&addr).await.unwrap();
+    plain.send_hello().await.unwrap();
+
+    let client_config = NoiseConfig::new(NoiseKeypair::generate().unwrap())
+        .with_remote_key(server_keys.public);
+    let client = tokio::spawn({
+        let addr = addr.clone();
+        async move { VstpTcpClient::connect_with_noise(&addr, client_config).await }
+    });
+
+    let conn = timeout(Duration::from_secs(5), server.accept())
+        .await
+        .expect("a stalled handshake must not hold up accept")
+        .unwrap();
+    assert!(conn.peer_public_key().is_some());
+    client.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_authenticated_sessions() {
+    use std::sync::Arc;
+    use vstp::security::auth::{
+        Credentials, HmacChallengeAuthenticator, Principal, SharedTokenAuthenticator,
+    };
+    use vstp::tcp::SessionContext;
+
+    // Shared token through the run loop, with the principal handed to the handler
+    let auth = SharedTokenAuthenticator::new()
+        .with_token("s3cret", Principal::new("sensor-1").with_role("publisher"));
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_authenticator(Arc::new(auth));
+    let addr = server.local_addr().unwrap().to_string();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        server
+            .run_with_context(
+                move |ctx: SessionContext, frame: Frame| {
+                    let tx = tx.clone();
+                    async move {
+                        let principal = ctx.principal.map(|p| p.id.clone());
+                        let _ = tx.send((principal, frame.payload));
+                    }
+                },
+                None,
+            )
+            .await
+            .unwrap();
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    let principal = client
+        .authenticate(&Credentials::Token("s3cret".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(principal, "sensor-1");
+    client.send_data(b"reading".to_vec()).await.unwrap();
+    let (principal, payload) = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal.as_deref(), Some("sensor-1"));
+    assert_eq!(payload, b"reading");
+
+    let mut intruder = VstpTcpClient::connect(&addr).await.unwrap();
+    assert!(intruder
+        .authenticate(&Credentials::Token("guess".to_string()))
+        .await
+        .is_err());
+    // Unauthenticated clients never reach the handler
+    let mut unauthenticated = VstpTcpClient::connect(&addr).await.unwrap();
+    unauthenticated.send_data(b"sneaky".to_vec()).await.unwrap();
+    assert!(timeout(Duration::from_millis(300), rx.recv()).await.is_err());
+
+    // HMAC challenge-response through accept
+    let auth = HmacChallengeAuthenticator::new().with_key(
+        "device-7",
+        b"device-secret".to_vec(),
+        Principal::new("device-7"),
+    );
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_authenticator(Arc::new(auth));
+    let addr = server.local_addr().unwrap().to_string();
+    let server_handle = tokio::spawn(async move {
+        let conn = server.accept().await.unwrap();
+        assert_eq!(conn.principal().map(|p| p.id.as_str()), Some("device-7"));
+        assert!(timeout(Duration::from_secs(2), server.accept()).await.is_err());
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    let principal = client
+        .authenticate(&Credentials::Hmac {
+            key_id: "device-7".to_string(),
+            secret: b"device-secret".to_vec(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(principal, "device-7");
+
+    let mut impostor = VstpTcpClient::connect(&addr).await.unwrap();
+    assert!(impostor
+        .authenticate(&Credentials::Hmac {
+            key_id: "device-7".to_string(),
+            secret: b"guessed".to_vec(),
+        })
+        .await
+        .is_err());
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_jwt_rs256_authentication() {
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use std::sync::Arc;
+    use vstp::security::auth::{Credentials, JwtAuthenticator};
+
+    let jwks = serde_json::from_str(include_str!("fixtures/jwt_rs256_jwks.json")).unwrap();
+    let auth = JwtAuthenticator::new(jwks).with_issuer("vstp-test");
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_authenticator(Arc::new(auth));
+    let addr = server.local_addr().unwrap().to_string();
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        let principal = conn.principal().cloned().unwrap();
+        let frame = conn.recv().await.unwrap().unwrap();
+        (principal, frame.payload)
+    });
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("rs256-test".to_string());
+    let claims = serde_json::json!({
+        "sub": "carol",
+        "iss": "vstp-test",
+        "exp": jsonwebtoken::get_current_timestamp() + 600,
+        "roles": ["operator"],
+    });
+    let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/jwt_rs256_key.pem")).unwrap();
+    let token = encode(&header, &claims, &key).unwrap();
+    // Far larger than a header value can hold
+    assert!(token.len() > 255);
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    let principal = timeout(Duration::from_secs(5), client.authenticate(&Credentials::Bearer(token)))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(principal, "carol");
+    client.send_data(b"signed in".to_vec()).await.unwrap();
+
+    let (principal, payload) = timeout(Duration::from_secs(5), server_handle).await.unwrap().unwrap();
+    assert_eq!(principal.id, "carol");
+    assert!(principal.has_role("operator"));
+    assert_eq!(payload, b"signed in");
+}
+
+#[tokio::test]
+async fn test_tcp_frame_mac() {
+    use vstp::security::mac::{FrameKeyring, SessionMacConfig};
+
+    let master = b"shared master secret";
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_frame_mac(SessionMacConfig::new("s1", master.to_vec()));
+    let addr = server.local_addr().unwrap().to_string();
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        let frame = conn.recv().await.unwrap().unwrap();
+        conn.send(Frame::new(FrameType::Data).with_payload(frame.payload))
+            .await
+            .unwrap();
+        assert!(matches!(
+            conn.recv().await,
+            Err(vstp::VstpError::ForgedFrame(_))
+        ));
+    });
+
+    // The session key is derived from the master secret and the nonce in the
+    // server's WELCOME
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client.enable_session_frame_mac("s1", master).await.unwrap();
+    client.send_data(b"signed".to_vec()).await.unwrap();
+    let echo = timeout(Duration::from_secs(5), client.recv())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(echo.payload, b"signed");
+
+    // Frames signed with a guessed key are rejected
+    client.enable_frame_mac(FrameKeyring::new("s1", b"guessed".to_vec()));
+    client.send_data(b"forged".to_vec()).await.unwrap();
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_run_requires_frame_mac() {
+    use vstp::security::mac::SessionMacConfig;
+
+    let master = b"shared master secret";
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_frame_mac(SessionMacConfig::new("s1", master.to_vec()));
+    let addr = server.local_addr().unwrap().to_string();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        server
+            .run(move |_session_id: SessionId, frame: Frame| {
+                let tx = tx.clone();
+                async move {
+                    if frame.typ == FrameType::Data {
+                        let _ = tx.send(frame.payload);
+                    }
+                }
+            })
+            .await
+            .unwrap();
+    });
+
+    // An unsigned client's frames never reach the handler
+    let mut unsigned = VstpTcpClient::connect(&addr).await.unwrap();
+    unsigned.send_data(b"unsigned".to_vec()).await.unwrap();
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client.enable_session_frame_mac("s1", master).await.unwrap();
+    client.send_data(b"signed".to_vec()).await.unwrap();
+
+    let received = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, b"signed");
+    assert!(timeout(Duration::from_millis(200), rx.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tcp_replay_protection() {
+    use std::sync::Arc;
+    use vstp::security::{AnomalyDetector, AttackPattern, ReplayConfig};
+
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_replay_protection(ReplayConfig::default());
+    let addr = server.local_addr().unwrap().to_string();
+    let detector = Arc::new(AnomalyDetector::default());
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_detector = detector.clone();
+    tokio::spawn(async move {
+        server
+            .run_with_detector(
+                move |_session_id: SessionId, frame: Frame| {
+                    let tx = tx.clone();
+                    async move {
+                        let _ = tx.send(frame.payload);
+                    }
+                },
+                Some(server_detector),
+            )
+            .await
+            .unwrap();
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client.enable_replay_protection(ReplayConfig::default());
+    client.send_data(b"first".to_vec()).await.unwrap();
+    client.send_data(b"second".to_vec()).await.unwrap();
+
+    // Resetting the counter makes the next frame reuse sequence number 1
+    client.enable_replay_protection(ReplayConfig::default());
+    client.send_data(b"replayed".to_vec()).await.unwrap();
+    // A raw frame with no sequence number is rejected too
+    let mut raw = VstpTcpClient::connect(&addr).await.unwrap();
+    raw.send_data(b"unsequenced".to_vec()).await.unwrap();
+
+    for expected in [&b"first"[..], b"second"] {
+        let payload = timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload, expected);
+    }
+    assert!(timeout(Duration::from_millis(300), rx.recv()).await.is_err());
+
+    let replays: Vec<_> = detector
+        .get_threat_history(100)
+        .await
+        .into_iter()
+        .filter(|t| t.pattern == AttackPattern::ReplayAttack)
+        .collect();
+    assert_eq!(replays.len(), 2);
+    assert!(replays.iter().all(|t| t.confidence == 1.0));
+}
+
+#[tokio::test]
+async fn test_tcp_policy_enforcement() {
+    use std::sync::Arc;
+    use vstp::security::auth::{Credentials, Principal, SharedTokenAuthenticator};
+    use vstp::security::{AnomalyDetector, AttackPattern, Policy};
+
+    let policy = Policy::from_json(
+        r#"{
+            "roles": {
+                "publisher": {
+                    "frame_types": ["data"],
+                    "max_payload": 8,
+                    "headers": { "method": ["publish"] }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+    let auth = SharedTokenAuthenticator::new()
+        .with_token("s3cret", Principal::new("sensor-1").with_role("publisher"));
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_authenticator(Arc::new(auth))
+        .with_policy(Arc::new(policy));
+    let addr = server.local_addr().unwrap().to_string();
+    let detector = Arc::new(AnomalyDetector::default());
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_detector = detector.clone();
+    tokio::spawn(async move {
+        server
+            .run_with_detector(
+                move |_session_id: SessionId, frame: Frame| {
+                    let tx = tx.clone();
+                    async move {
+                        let _ = tx.send(frame.payload);
+                    }
+                },
+                Some(server_detector),
+            )
+            .await
+            .unwrap();
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client
+        .authenticate(&Credentials::Token("s3cret".to_string()))
+        .await
+        .unwrap();
+
+    let denied = [
+        Frame::new(FrameType::Data).with_payload(b"much too large".to_vec()),
+        Frame::new(FrameType::Data)
+            .with_header("method", "delete")
+            .with_payload(b"x".to_vec()),
+        Frame::new(FrameType::Ping),
+    ];
+    for frame in denied {
+        client.send(frame).await.unwrap();
+        let reply = timeout(Duration::from_secs(5), client.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.typ, FrameType::Err);
+    }
+
+    // The session survives denials and allowed frames reach the handler
+    client
+        .send(
+            Frame::new(FrameType::Data)
+                .with_header("method", "publish")
+                .with_payload(b"ok".to_vec()),
+        )
+        .await
+        .unwrap();
+    let payload = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(payload, b"ok");
+    assert!(rx.try_recv().is_err());
+
+    let unauthorized = detector
+        .get_threat_history(100)
+        .await
+        .into_iter()
+        .filter(|t| t.pattern == AttackPattern::UnauthorizedAccess)
+        .count();
+    assert_eq!(unauthorized, 3);
+}
+
+#[tokio::test]
+async fn test_tcp_blocked_peer_cannot_reconnect() {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use vstp::security::{AnomalyDetector, IpNet};
+
+    let server = VstpTcpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    let detector = Arc::new(AnomalyDetector::default());
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_detector = detector.clone();
+    tokio::spawn(async move {
+        server
+            .run_with_detector(
+                move |session_id: SessionId, _frame: Frame| {
+                    let tx = tx.clone();
+                    async move {
+                        let _ = tx.send(session_id);
+                    }
+                },
+                Some(server_detector),
+            )
+            .await
+            .unwrap();
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client.send_data(b"hello".to_vec()).await.unwrap();
+    let session_id = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Blocking the session bans its address, closing the open session
+    detector.block_session(session_id).await;
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    assert!(detector.is_peer_blocked(localhost));
+    assert_eq!(detector.blocklist().offenses(localhost), 1);
+    let closed = timeout(Duration::from_secs(5), client.recv()).await.unwrap();
+    assert!(!matches!(closed, Ok(Some(_))));
+
+    // A new connection from the same address is turned away before it gets
+    // a session
+    let mut retry = VstpTcpClient::connect(&addr).await.unwrap();
+    let _ = retry.send_data(b"again".to_vec()).await;
+    let refused = timeout(Duration::from_secs(5), retry.recv()).await.unwrap();
+    assert!(!matches!(refused, Ok(Some(_))));
+    assert!(rx.try_recv().is_err());
+
+    detector.blocklist().unblock(IpNet::from(localhost));
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client.send_data(b"welcome back".to_vec()).await.unwrap();
+    let new_session = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_ne!(new_session, session_id);
+}
+
+#[tokio::test]
+async fn test_tcp_response_policy_closes_session() {
+    use std::sync::Arc;
+    use vstp::security::ai::{
+        ResponseAction, ResponsePolicy, ResponseRule, ThreatLevel,
+    };
+    use vstp::security::{AnomalyDetector, AttackPattern, Policy};
+
+    let policy = Policy::from_json(
+        r#"{
+            "anonymous": ["guest"],
+            "roles": { "guest": { "frame_types": ["hello", "ping", "bye"] } }
+        }"#,
+    )
+    .unwrap();
+    let server = VstpTcpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_policy(Arc::new(policy));
+    let addr = server.local_addr().unwrap().to_string();
+    let detector = Arc::new(AnomalyDetector::default().with_response_policy(
+        ResponsePolicy::new().with_rule(
+            AttackPattern::UnauthorizedAccess,
+            ResponseRule::new(ThreatLevel::High, ResponseAction::Reauthenticate),
+        ),
+    ));
+    let server_detector = detector.clone();
+    tokio::spawn(async move {
+        let _ = server
+            .run_with_detector(|_id: SessionId, _frame: Frame| async {}, Some(server_detector))
+            .await;
+    });
+
+    let mut client = VstpTcpClient::connect(&addr).await.unwrap();
+    client
+        .send(Frame::new(FrameType::Data).with_payload(b"x".to_vec()))
+        .await
+        .unwrap();
+    let mut notices = Vec::new();
+    while let Ok(Some(frame)) = timeout(Duration::from_secs(5), client.recv())
+        .await
+        .unwrap()
+    {
+        assert_eq!(frame.typ, FrameType::Err);
+        notices.push(String::from_utf8(frame.payload).unwrap());
+    }
+    assert_eq!(notices.len(), 2);
+    assert_eq!(notices[1], "re-authentication required");
+
+    // Only the session was closed; the peer may reconnect
+    assert!(VstpTcpClient::connect(&addr).await.is_ok());
+}
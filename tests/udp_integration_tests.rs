@@ -109,6 +109,30 @@ async fn test_udp_fragmentation() {
     server_handle.abort();
 }
 
+#[tokio::test]
+async fn test_udp_fragmentation_with_fec() {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let config = vstp::udp::client::UdpConfig {
+        fec_redundancy: 0.5,
+        ..Default::default()
+    };
+    let client = VstpUdpClient::bind_with_config("127.0.0.1:0", config).await.unwrap();
+
+    let payload: Vec<u8> = (0..6000u32).map(|i| (i % 241) as u8).collect();
+    let data_frame = vstp::Frame::new(FrameType::Data)
+        .with_header("topic", "fec")
+        .with_payload(payload.clone());
+    client.send(data_frame, server_addr).await.unwrap();
+
+    let (frame, _) = timeout(Duration::from_secs(5), server.recv()).await.unwrap().unwrap();
+    assert_eq!(frame.payload, payload);
+    assert_eq!(frame.get_header("topic"), Some("fec"));
+    assert!(frame.get_header("frag-data").is_none());
+    assert_eq!(server.reassembly_metrics().completed, 1);
+}
+
 #[tokio::test]
 async fn test_udp_multiple_clients() {
     // Start a UDP server
@@ -142,3 +166,619 @@ async fn test_udp_multiple_clients() {
     // Stop the server
     server_handle.abort();
 }
+
+#[tokio::test]
+async fn test_udp_accept_connection() {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        let frame = conn.recv().await.unwrap().unwrap();
+        assert_eq!(frame.payload, b"ping over session");
+        assert!(frame.get_header("conn-id").is_none());
+
+        conn.send(vstp::Frame::new(FrameType::Data).with_payload(b"pong".to_vec()))
+            .await
+            .unwrap();
+        conn.connection_id()
+    });
+
+    let mut client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    let conn_id = timeout(Duration::from_secs(5), client.connect(server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(client.connection_id(), Some(conn_id));
+
+    let data_frame = vstp::Frame::new(FrameType::Data).with_payload(b"ping over session".to_vec());
+    client.send(data_frame, server_addr).await.unwrap();
+
+    let (reply, from) = timeout(Duration::from_secs(5), client.recv()).await.unwrap().unwrap();
+    assert_eq!(from, server_addr);
+    assert_eq!(reply.payload, b"pong");
+    assert_eq!(server_handle.await.unwrap(), conn_id);
+}
+
+#[tokio::test]
+async fn test_udp_session_retransmission_delivered_once() {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    let frame = vstp::Frame::new(FrameType::Data)
+        .with_header("msg-id", "7")
+        .with_flag(vstp::Flags::REQ_ACK)
+        .with_payload(b"once".to_vec());
+    client.send(frame.clone(), server_addr).await.unwrap();
+    client.send(frame, server_addr).await.unwrap();
+    client
+        .send(vstp::Frame::new(FrameType::Bye), server_addr)
+        .await
+        .unwrap();
+
+    let (first, _) = timeout(Duration::from_secs(5), server.recv()).await.unwrap().unwrap();
+    assert_eq!(first.payload, b"once");
+    let (second, _) = timeout(Duration::from_secs(5), server.recv()).await.unwrap().unwrap();
+    assert_eq!(second.typ, FrameType::Bye);
+    assert_eq!(server.session_count().await, 0);
+}
+
+#[tokio::test]
+async fn test_udp_idle_session_expires() {
+    let config = vstp::udp::server::UdpServerConfig {
+        session_idle_timeout: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let server = VstpUdpServer::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        // The session expires without traffic and the connection ends
+        timeout(Duration::from_secs(5), conn.recv()).await.unwrap().unwrap()
+    });
+
+    let mut client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server_addr).await.unwrap();
+
+    assert!(server_handle.await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_udp_session_migrates_to_new_address() {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        let first = conn.recv().await.unwrap().unwrap();
+        let first_addr = conn.peer_addr();
+        let second = conn.recv().await.unwrap().unwrap();
+        let second_addr = conn.peer_addr();
+
+        conn.send(vstp::Frame::new(FrameType::Data).with_payload(b"still here".to_vec()))
+            .await
+            .unwrap();
+        (first.payload, first_addr, second.payload, second_addr)
+    });
+
+    let mut client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    let conn_id = client.connect(server_addr).await.unwrap();
+    let old_addr = client.local_addr().unwrap();
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"before".to_vec());
+    client.send(frame, server_addr).await.unwrap();
+
+    // Simulate a NAT rebinding: same session, new source address
+    client.rebind("127.0.0.1:0").await.unwrap();
+    let new_addr = client.local_addr().unwrap();
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"after".to_vec());
+    timeout(Duration::from_secs(5), client.send_with_ack(frame, server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (reply, _) = timeout(Duration::from_secs(5), client.recv()).await.unwrap().unwrap();
+    assert_eq!(reply.payload, b"still here");
+    assert_eq!(client.connection_id(), Some(conn_id));
+
+    let (first, first_addr, second, second_addr) = server_handle.await.unwrap();
+    assert_eq!(first, b"before");
+    assert_eq!(first_addr, old_addr);
+    assert_eq!(second, b"after");
+    assert_eq!(second_addr, new_addr);
+}
+
+#[tokio::test]
+async fn test_udp_encrypted_session() {
+    let config = vstp::udp::server::UdpServerConfig {
+        require_encryption: true,
+        ..Default::default()
+    };
+    let server = VstpUdpServer::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let payload: Vec<u8> = (0..5000u32).map(|i| (i % 233) as u8).collect();
+    let expected = payload.clone();
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        let frame = conn.recv().await.unwrap().unwrap();
+        assert_eq!(frame.payload, expected);
+        assert_eq!(frame.get_header("topic"), Some("telemetry"));
+
+        conn.send(vstp::Frame::new(FrameType::Data).with_payload(b"sealed reply".to_vec()))
+            .await
+            .unwrap();
+        // Keep the server alive until the client has read the reply
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    });
+
+    // A cleartext client is refused
+    let mut plain = VstpUdpClient::bind_with_config(
+        "127.0.0.1:0",
+        vstp::udp::client::UdpConfig {
+            max_retries: 0,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(plain.connect(server_addr).await.is_err());
+
+    let config = vstp::udp::client::UdpConfig {
+        encrypt: true,
+        ..Default::default()
+    };
+    let mut client = VstpUdpClient::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    timeout(Duration::from_secs(5), client.connect(server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let data_frame = vstp::Frame::new(FrameType::Data)
+        .with_header("topic", "telemetry")
+        .with_payload(payload);
+    timeout(Duration::from_secs(5), client.send_with_ack(data_frame, server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (reply, from) = timeout(Duration::from_secs(5), client.recv()).await.unwrap().unwrap();
+    assert_eq!(from, server_addr);
+    assert_eq!(reply.payload, b"sealed reply");
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_udp_blocklist_drops_banned_peers() {
+    use vstp::security::{Blocklist, IpNet};
+
+    let blocklist = Blocklist::default();
+    let loopback: IpNet = "127.0.0.0/8".parse().unwrap();
+    blocklist.block(loopback, None, "test");
+    let server = VstpUdpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_blocklist(blocklist.clone());
+    let server_addr = server.local_addr().unwrap();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_handle = tokio::spawn(async move {
+        server
+            .run(move |_addr, frame| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(frame.payload);
+                }
+            })
+            .await
+            .unwrap();
+    });
+
+    let client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"banned".to_vec());
+    client.send(frame, server_addr).await.unwrap();
+    assert!(timeout(Duration::from_millis(300), rx.recv()).await.is_err());
+
+    blocklist.unblock(loopback);
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"allowed".to_vec());
+    client.send(frame, server_addr).await.unwrap();
+    let payload = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(payload, b"allowed");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_bad_fragment_does_not_fail_recv() {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+
+    // Fragment index outside the fragment count is rejected by reassembly
+    let bad_fragment = vstp::Frame::new(FrameType::Data)
+        .with_header("frag-id", "1")
+        .with_header("frag-index", "9")
+        .with_header("frag-total", "2")
+        .with_payload(b"junk".to_vec());
+    client.send(bad_fragment, server_addr).await.unwrap();
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"after".to_vec());
+    client.send(frame, server_addr).await.unwrap();
+
+    let (frame, _) = timeout(Duration::from_secs(5), server.recv()).await.unwrap().unwrap();
+    assert_eq!(frame.payload, b"after");
+    assert_eq!(server.reassembly_metrics().rejected_fragments, 1);
+}
+
+#[tokio::test]
+async fn test_udp_unkeyed_peer_cannot_migrate_encrypted_session() {
+    use vstp::udp::session::{format_connection_id, CONN_ID_HEADER, PATH_RESPONSE_HEADER};
+
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let (addr_tx, mut addr_rx) = tokio::sync::mpsc::unbounded_channel();
+    let server_handle = tokio::spawn(async move {
+        let mut conn = server.accept().await.unwrap();
+        while let Ok(Some(frame)) = conn.recv().await {
+            let _ = addr_tx.send((frame.payload, conn.peer_addr()));
+        }
+    });
+
+    let config = vstp::udp::client::UdpConfig {
+        encrypt: true,
+        ..Default::default()
+    };
+    let mut client = VstpUdpClient::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    let conn_id = timeout(Duration::from_secs(5), client.connect(server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // The attacker knows the connection ID but not the session keys
+    let attacker = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let conn_header = format_connection_id(conn_id);
+    for frame in [
+        vstp::Frame::new(FrameType::Data).with_header(CONN_ID_HEADER, &conn_header),
+        vstp::Frame::new(FrameType::Pong)
+            .with_header(CONN_ID_HEADER, &conn_header)
+            .with_header(PATH_RESPONSE_HEADER, "0000000000000000"),
+    ] {
+        let encoded = vstp::encode_frame(&frame).unwrap();
+        attacker.send_to(&encoded, server_addr).await.unwrap();
+    }
+    let mut buf = [0u8; 2048];
+    assert!(
+        timeout(Duration::from_millis(300), attacker.recv_from(&mut buf)).await.is_err(),
+        "an unauthenticated datagram must not start path validation"
+    );
+
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"mine".to_vec());
+    timeout(Duration::from_secs(5), client.send_with_ack(frame, server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+    let (payload, peer) = timeout(Duration::from_secs(5), addr_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(payload, b"mine");
+    assert_eq!(peer, client_addr);
+
+    // The real client still migrates by answering the sealed challenge
+    client.rebind("127.0.0.1:0").await.unwrap();
+    let new_addr = client.local_addr().unwrap();
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"moved".to_vec());
+    timeout(Duration::from_secs(5), client.send_with_ack(frame, server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+    let (payload, peer) = timeout(Duration::from_secs(5), addr_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(payload, b"moved");
+    assert_eq!(peer, new_addr);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_forged_reset_does_not_downgrade_encrypted_session() {
+    use vstp::udp::crypto::{self, is_encrypted, HANDSHAKE_HEADER, NOISE_PATTERN};
+    use vstp::udp::session::{format_connection_id, CONN_ID_HEADER};
+
+    // Hand-driven server so the test can send datagrams from its address
+    let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let decode = |buf: &[u8]| {
+        let mut bytes = bytes::BytesMut::from(buf);
+        vstp::try_decode_frame(&mut bytes, 65536).unwrap().unwrap()
+    };
+    let server_handle = tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        let (len, client_addr) = server.recv_from(&mut buf).await.unwrap();
+        let hello = decode(&buf[..len]);
+        let (cipher, message) = crypto::respond(&hello.payload).unwrap();
+        let conn_id = format_connection_id(7);
+        let welcome = vstp::Frame::new(FrameType::Welcome)
+            .with_header(CONN_ID_HEADER, &conn_id)
+            .with_header(HANDSHAKE_HEADER, NOISE_PATTERN)
+            .with_payload(message);
+        server.send_to(&vstp::encode_frame(&welcome).unwrap(), client_addr).await.unwrap();
+
+        // What an off-path attacker spoofing the server's address would send
+        let forged = vstp::Frame::new(FrameType::Err)
+            .with_header(CONN_ID_HEADER, &conn_id)
+            .with_payload(b"unknown connection".to_vec());
+        server.send_to(&vstp::encode_frame(&forged).unwrap(), client_addr).await.unwrap();
+        let real = vstp::Frame::new(FrameType::Data).with_payload(b"real".to_vec());
+        let sealed = cipher.seal(&real, None).unwrap();
+        server.send_to(&vstp::encode_frame(&sealed).unwrap(), client_addr).await.unwrap();
+
+        let (len, _) = server.recv_from(&mut buf).await.unwrap();
+        let next = decode(&buf[..len]);
+        (is_encrypted(&next), cipher.open(&next).unwrap().payload)
+    });
+
+    let config = vstp::udp::client::UdpConfig {
+        encrypt: true,
+        ..Default::default()
+    };
+    let mut client = VstpUdpClient::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    let conn_id = timeout(Duration::from_secs(5), client.connect(server_addr))
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The forged ERR is dropped; the sealed frame behind it is delivered
+    let (frame, _) = timeout(Duration::from_secs(5), client.recv()).await.unwrap().unwrap();
+    assert_eq!(frame.payload, b"real");
+    assert_eq!(client.connection_id(), Some(conn_id));
+
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"secret".to_vec());
+    client.send(frame, server_addr).await.unwrap();
+    let (sealed, payload) = timeout(Duration::from_secs(5), server_handle).await.unwrap().unwrap();
+    assert!(sealed, "the send after a forged reset must still be encrypted");
+    assert_eq!(payload, b"secret");
+}
+
+/// Run a server with a detector, passing the payloads of DATA frames on
+async fn detected_server(
+    detector: std::sync::Arc<vstp::security::AnomalyDetector>,
+) -> (
+    std::net::SocketAddr,
+    tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    tokio::task::JoinHandle<()>,
+) {
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        server
+            .run_with_detector(
+                move |_addr, frame: vstp::Frame| {
+                    let tx = tx.clone();
+                    async move {
+                        if frame.typ == FrameType::Data {
+                            let _ = tx.send(frame.payload);
+                        }
+                    }
+                },
+                Some(detector),
+            )
+            .await
+            .unwrap();
+    });
+    (server_addr, rx, handle)
+}
+
+/// Run the handshake by hand so a test can send raw sealed datagrams
+async fn raw_encrypted_client(
+    server_addr: std::net::SocketAddr,
+) -> (
+    tokio::net::UdpSocket,
+    vstp::udp::crypto::DatagramCipher,
+    vstp::udp::session::ConnectionId,
+) {
+    use vstp::udp::crypto::{self, HANDSHAKE_HEADER, NOISE_PATTERN};
+    use vstp::udp::session::extract_connection_id;
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (state, message) = crypto::initiate().unwrap();
+    let hello = vstp::Frame::new(FrameType::Hello)
+        .with_header(HANDSHAKE_HEADER, NOISE_PATTERN)
+        .with_payload(message);
+    socket.send_to(&vstp::encode_frame(&hello).unwrap(), server_addr).await.unwrap();
+    let mut buf = vec![0u8; 65536];
+    let (len, _) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let welcome = vstp::try_decode_frame(&mut bytes::BytesMut::from(&buf[..len]), 65536)
+        .unwrap()
+        .unwrap();
+    let conn_id = extract_connection_id(&welcome).unwrap();
+    let cipher = crypto::finish(state, &welcome.payload).unwrap();
+    (socket, cipher, conn_id)
+}
+
+async fn replay_threats(
+    detector: &vstp::security::AnomalyDetector,
+) -> Vec<vstp::security::ai::patterns::ThreatDetection> {
+    detector
+        .get_threat_history(100)
+        .await
+        .into_iter()
+        .filter(|t| t.pattern == vstp::security::AttackPattern::ReplayAttack)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_udp_replayed_datagram_is_reported() {
+    use std::sync::Arc;
+    use vstp::security::AnomalyDetector;
+    use vstp::transport::udp::session::DUPLICATE_ALLOWANCE;
+
+    let detector = Arc::new(AnomalyDetector::default());
+    let (server_addr, mut rx, server_handle) = detected_server(detector.clone()).await;
+    let (socket, cipher, conn_id) = raw_encrypted_client(server_addr).await;
+
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"once".to_vec());
+    let sealed = vstp::encode_frame(&cipher.seal(&frame, Some(conn_id)).unwrap()).unwrap();
+    socket.send_to(&sealed, server_addr).await.unwrap();
+    socket.send_to(&sealed, server_addr).await.unwrap();
+
+    let payload = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(payload, b"once");
+    assert!(timeout(Duration::from_millis(300), rx.recv()).await.is_err());
+
+    // The network duplicates datagrams now and then
+    assert!(replay_threats(&detector).await.is_empty());
+
+    for _ in 0..DUPLICATE_ALLOWANCE {
+        socket.send_to(&sealed, server_addr).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(rx.try_recv().is_err());
+
+    let replays = replay_threats(&detector).await;
+    assert_eq!(replays.len(), 1);
+    assert_eq!(replays[0].confidence, 1.0);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_datagram_from_unvalidated_path_does_not_burn_sequence() {
+    use std::sync::Arc;
+    use vstp::security::AnomalyDetector;
+
+    let detector = Arc::new(AnomalyDetector::default());
+    let (server_addr, mut rx, server_handle) = detected_server(detector.clone()).await;
+    let (socket, cipher, conn_id) = raw_encrypted_client(server_addr).await;
+
+    // An attacker who captured the datagram sends it ahead from elsewhere
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"real".to_vec());
+    let sealed = vstp::encode_frame(&cipher.seal(&frame, Some(conn_id)).unwrap()).unwrap();
+    let attacker = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    attacker.send_to(&sealed, server_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    socket.send_to(&sealed, server_addr).await.unwrap();
+    let payload = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(payload, b"real");
+    assert!(replay_threats(&detector).await.is_empty());
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_expired_sessions_end_in_detector() {
+    use std::sync::Arc;
+    use vstp::security::AnomalyDetector;
+
+    let config = vstp::udp::server::UdpServerConfig {
+        session_idle_timeout: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let server = VstpUdpServer::bind_with_config("127.0.0.1:0", config).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let detector = Arc::new(AnomalyDetector::default());
+    let server_detector = detector.clone();
+    let server_handle = tokio::spawn(async move {
+        server
+            .run_with_detector(|_addr, _frame| async {}, Some(server_detector))
+            .await
+            .unwrap();
+    });
+
+    let mut client = VstpUdpClient::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server_addr).await.unwrap();
+    let session_id = timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(stats) = detector.get_all_connections().await.first() {
+                return stats.session_id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    detector.block_session(session_id).await;
+    assert!(detector.is_blocked(session_id).await);
+
+    // Once the session expires the detector forgets it
+    timeout(Duration::from_secs(5), async {
+        while detector.is_blocked(session_id).await {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_spoofed_hello_does_not_rekey_encrypted_session() {
+    use vstp::udp::crypto::{self, HANDSHAKE_HEADER, NOISE_PATTERN};
+
+    // Echo server; every HELLO that opens a session is accepted
+    let server = VstpUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let server_handle = tokio::spawn(async move {
+        loop {
+            let mut conn = server.accept().await.unwrap();
+            tokio::spawn(async move {
+                while let Ok(Some(frame)) = conn.recv().await {
+                    if frame.typ == FrameType::Data {
+                        let reply = vstp::Frame::new(FrameType::Data).with_payload(frame.payload);
+                        let _ = conn.send(reply).await;
+                    }
+                }
+            });
+        }
+    });
+    let (socket, cipher, conn_id) = raw_encrypted_client(server_addr).await;
+
+    // A key exchange spoofed from the client's address, with and without
+    // its connection ID
+    for with_conn_id in [false, true] {
+        let (_, message) = crypto::initiate().unwrap();
+        let mut hello = vstp::Frame::new(FrameType::Hello)
+            .with_header(HANDSHAKE_HEADER, NOISE_PATTERN)
+            .with_payload(message);
+        if with_conn_id {
+            hello = hello.with_header(
+                vstp::udp::session::CONN_ID_HEADER,
+                &vstp::udp::session::format_connection_id(conn_id),
+            );
+        }
+        socket.send_to(&vstp::encode_frame(&hello).unwrap(), server_addr).await.unwrap();
+        let mut buf = vec![0u8; 65536];
+        timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // The real client still round-trips under its original keys
+    let frame = vstp::Frame::new(FrameType::Data).with_payload(b"still mine".to_vec());
+    let sealed = vstp::encode_frame(&cipher.seal(&frame, Some(conn_id)).unwrap()).unwrap();
+    socket.send_to(&sealed, server_addr).await.unwrap();
+    let echo = timeout(Duration::from_secs(5), async {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+            let mut bytes = bytes::BytesMut::from(&buf[..len]);
+            let Ok(Some(reply)) = vstp::try_decode_frame(&mut bytes, 65536) else {
+                continue;
+            };
+            if let Ok(inner) = cipher.open(&reply) {
+                if inner.typ == FrameType::Data {
+                    return inner.payload;
+                }
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(echo, b"still mine");
+
+    server_handle.abort();
+}
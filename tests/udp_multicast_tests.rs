@@ -0,0 +1,147 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::time::timeout;
+use vstp::{
+    types::FrameType,
+    udp::{MulticastConfig, VstpMulticastPublisher, VstpMulticastReceiver},
+    Frame,
+};
+
+#[tokio::test]
+async fn test_multicast_group_delivery() {
+    let group = Ipv4Addr::new(239, 255, 42, 99);
+    let config = MulticastConfig {
+        interface_v4: Ipv4Addr::LOCALHOST,
+        ..Default::default()
+    };
+
+    let mut receiver = VstpMulticastReceiver::bind_with_config("0.0.0.0:0", config.clone())
+        .await
+        .unwrap();
+    receiver.join(IpAddr::V4(group)).unwrap();
+    let port = receiver.local_addr().unwrap().port();
+
+    let publisher = VstpMulticastPublisher::bind_with_config("127.0.0.1:0", config)
+        .await
+        .unwrap();
+    let payload: Vec<u8> = (0..4000u32).map(|i| (i % 199) as u8).collect();
+    publisher
+        .publish(
+            Frame::new(FrameType::Data).with_payload(payload.clone()),
+            SocketAddr::new(IpAddr::V4(group), port),
+        )
+        .await
+        .unwrap();
+
+    let (frame, from) = timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(from, publisher.local_addr().unwrap());
+    assert_eq!(frame.payload, payload);
+    assert_eq!(frame.get_header("mc-seq"), Some("1"));
+
+    receiver.leave(IpAddr::V4(group)).unwrap();
+    assert_eq!(receiver.groups().count(), 0);
+}
+
+#[tokio::test]
+async fn test_multicast_nack_repairs_lost_frame() {
+    let config = MulticastConfig {
+        nack_interval: Duration::from_millis(50),
+        repair: true,
+        repair_receivers: vec!["127.0.0.1/32".parse().unwrap()],
+        ..Default::default()
+    };
+    let mut receiver = VstpMulticastReceiver::bind_with_config("127.0.0.1:0", config.clone())
+        .await
+        .unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+    let publisher = VstpMulticastPublisher::bind_with_config("127.0.0.1:0", config)
+        .await
+        .unwrap();
+
+    // Frame 2 is "lost" by publishing it to an address nobody listens on
+    let nowhere = VstpMulticastReceiver::bind("127.0.0.1:0").await.unwrap();
+    let nowhere_addr = nowhere.local_addr().unwrap();
+    drop(nowhere);
+
+    let frame = |text: &str| Frame::new(FrameType::Data).with_payload(text.as_bytes().to_vec());
+    publisher.publish(frame("one"), receiver_addr).await.unwrap();
+    publisher.publish(frame("two"), nowhere_addr).await.unwrap();
+    publisher.publish(frame("three"), receiver_addr).await.unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0..3 {
+        let (frame, _) = timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.push(String::from_utf8(frame.payload).unwrap());
+    }
+
+    assert_eq!(received, vec!["one", "three", "two"]);
+    assert_eq!(receiver.nacks_sent(), 1);
+    assert_eq!(publisher.repairs_sent(), 1);
+}
+
+#[tokio::test]
+async fn test_multicast_repair_is_not_reflected_to_unknown_sources() {
+    let config = MulticastConfig {
+        repair: true,
+        max_repairs_per_nack: 2,
+        ..Default::default()
+    };
+    let group = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let group_addr = group.local_addr().unwrap();
+    let publisher = VstpMulticastPublisher::bind_with_config("127.0.0.1:0", config)
+        .await
+        .unwrap();
+    let mut buf = [0u8; 2048];
+    for text in ["one", "two", "three"] {
+        let frame = Frame::new(FrameType::Data).with_payload(text.as_bytes().to_vec());
+        publisher.publish(frame, group_addr).await.unwrap();
+        timeout(Duration::from_secs(5), group.recv_from(&mut buf)).await.unwrap().unwrap();
+    }
+
+    // A NACK whose source is not a configured receiver asks for every frame
+    let victim = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let nack = Frame::new(FrameType::Ack).with_header("mc-nack", "1,2,3");
+    victim
+        .send_to(&vstp::encode_frame(&nack).unwrap(), publisher.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    // Only the first two frames are resent, to the group they were published to
+    for expected in ["1", "2"] {
+        let (len, _) = timeout(Duration::from_secs(5), group.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut bytes = bytes::BytesMut::from(&buf[..len]);
+        let frame = vstp::try_decode_frame(&mut bytes, 65536).unwrap().unwrap();
+        assert_eq!(frame.get_header("mc-seq"), Some(expected));
+    }
+    assert!(timeout(Duration::from_millis(300), group.recv_from(&mut buf)).await.is_err());
+    assert!(timeout(Duration::from_millis(100), victim.recv_from(&mut buf)).await.is_err());
+    assert_eq!(publisher.repairs_sent(), 2);
+}
+
+#[tokio::test]
+async fn test_multicast_repair_is_opt_in() {
+    let mut receiver = VstpMulticastReceiver::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+    let publisher = VstpMulticastPublisher::bind("127.0.0.1:0").await.unwrap();
+    let frame = Frame::new(FrameType::Data).with_payload(b"one".to_vec());
+    publisher.publish(frame, receiver_addr).await.unwrap();
+    timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+
+    let nack = Frame::new(FrameType::Ack).with_header("mc-nack", "1");
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&vstp::encode_frame(&nack).unwrap(), publisher.local_addr().unwrap())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 2048];
+    assert!(timeout(Duration::from_millis(300), peer.recv_from(&mut buf)).await.is_err());
+    assert_eq!(publisher.repairs_sent(), 0);
+}